@@ -1,21 +1,33 @@
 use redirs::{config::Config, db::{Database, SessionFactory}, server::Server};
 use std::io;
-use stderrlog::{self, LogLevelNum};
+use stderrlog::LogLevelNum;
 
 fn main() -> io::Result<()> {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("Usage: redirs [/path/to/redis.conf] [--directive value ...]");
+            std::process::exit(1);
+        }
+    };
+
+    // the log level can be changed with CONFIG SET, so the logger lets everything through.
     stderrlog::new()
         .verbosity(LogLevelNum::Trace)
         .init()
         .unwrap();
+    log::set_max_level(config.loglevel.level_filter());
 
-    let config = Config {
-        host: "0.0.0.0".to_string(),
-        port: 5101,
-        databases: 16,
-    };
-
-    let database = Database::new(&config);
-    let mut session_factory = SessionFactory::new(database);
+    let mut session_factory = SessionFactory::new(Database::new(&config));
+    match session_factory.load() {
+        Ok(true) => log::info!("DB loaded from disk"),
+        Ok(false) => (),
+        Err(err) => {
+            log::error!("Cannot load the data: {}", err);
+            std::process::exit(1);
+        }
+    }
     let server = Server::new(&config, &mut session_factory);
     server.run()
 }
@@ -0,0 +1,45 @@
+//! A blocking client, used by a server to send commands to another server, for example to move
+//! keys to another node of the cluster.
+
+use std::io::{BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::value::{Value, ValueRead, ValueWrite};
+
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// Connects to the server. The timeout applies to the connection and to every read and write
+    /// that follows.
+    pub fn connect(host: &str, port: u16, timeout: Duration) -> Result<Self> {
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(Error::ParseError)?;
+        let connection = TcpStream::connect_timeout(&addr, timeout)?;
+        connection.set_read_timeout(Some(timeout))?;
+        connection.set_write_timeout(Some(timeout))?;
+        Ok(Self {
+            reader: BufReader::new(connection),
+        })
+    }
+
+    /// Sends a command and waits for its reply. The error replies are returned as
+    /// [`Value::Err`].
+    pub fn call<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Result<Value> {
+        let request = Value::Array(
+            args.iter()
+                .map(|arg| Value::Blob(arg.as_ref().to_vec().into()))
+                .collect(),
+        );
+        // the request is sent at once, not split in many small segments.
+        let mut buf = vec![];
+        buf.write_value(&request)?;
+        self.reader.get_mut().write_all(&buf)?;
+        self.reader.read_value()
+    }
+}
@@ -1,5 +1,840 @@
+//! The configuration of the server, read from a file with the same syntax as redis.conf: one
+//! directive per line, made of a name and its arguments, like `save 3600 1 300 100`. The
+//! arguments can be quoted, and `#` starts a comment line.
+//!
+//! The directives can also be given on the command line, like `--port 6380 --save ""`, where they
+//! override the ones of the file.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+// How deep the `include` directives can be nested, which is also what stops include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone)]
 pub struct Config {
-    pub host: String,
-    pub port: i16,
+    /// The file the configuration was read from, `None` when there is none.
+    pub config_file: Option<PathBuf>,
+    /// The addresses the server listens on.
+    pub bind: Vec<String>,
+    pub port: u16,
     pub databases: u64,
+    pub loglevel: LogLevel,
+    /// The maximum number of connected clients, the new connections are refused beyond it.
+    pub maxclients: usize,
+    /// How long a client can stay idle before it's disconnected, in seconds. 0 disables it.
+    pub timeout: u64,
+    /// The memory limit of the keys, in bytes. 0 means no limit.
+    pub maxmemory: u64,
+    /// Which keys are evicted once `maxmemory` is reached.
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// The number of keys sampled to pick each evicted key. More samples evict keys closer to
+    /// the best ones, at the price of more work.
+    pub maxmemory_samples: usize,
+    /// The commands taking longer than this, in microseconds, are logged in the slow log. A
+    /// negative value disables the slow log, 0 logs every command.
+    pub slowlog_log_slower_than: i64,
+    /// The number of entries kept in the slow log.
+    pub slowlog_max_len: usize,
+    /// The directory where the RDB file is written.
+    pub dir: String,
+    pub dbfilename: String,
+    /// Save rules, `(seconds, changes)`: a snapshot is taken in the background once at least
+    /// `changes` writes happened in the last `seconds` seconds.
+    pub save: Vec<(u64, u64)>,
+    /// Whether the write commands are logged to the AOF.
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    /// The AOF is rewritten in the background once it grows by this percentage since the last
+    /// rewrite, and is at least `auto_aof_rewrite_min_size` bytes. 0 disables the rewrites.
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+    /// The master replicated by this server, `None` for a master.
+    pub replicaof: Option<(String, u16)>,
+    /// The size of the end of the replication stream kept for the replicas that get
+    /// disconnected, in bytes.
+    pub repl_backlog_size: usize,
+    /// Whether the server is a node of a cluster, serving only the hash slots assigned to it.
+    pub cluster_enabled: bool,
+    /// How long a script runs, in milliseconds, before the other clients are replied BUSY and the
+    /// script can be killed with SCRIPT KILL.
+    pub lua_time_limit: u64,
+    /// The password of the default user, which needs no password when it's `None`.
+    pub requirepass: Option<String>,
+    /// The file where the users are loaded from and saved to with ACL LOAD and ACL SAVE.
+    pub aclfile: Option<String>,
+    /// The number of entries kept in the ACL log.
+    pub acllog_max_len: usize,
+    /// The maximum length of a bulk string in the requests of the clients, in bytes.
+    pub proto_max_bulk_len: usize,
+    /// The replies to the pipelined requests are written at once, unless they grow beyond this
+    /// many bytes. Applies to the new connections.
+    pub output_buffer_ceiling: usize,
+}
+
+/// When the AOF is flushed to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write command.
+    Always,
+    /// Once per second, losing at most one second of writes on a crash.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+/// Which keys are evicted once the memory limit is reached. The `volatile` policies only evict
+/// the keys with an expiry time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    /// Nothing is evicted, the commands that would use more memory are refused instead.
+    NoEviction,
+    /// The least recently used keys are evicted first.
+    AllKeysLru,
+    /// The least frequently used keys are evicted first.
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    /// The keys closest to their expiry time are evicted first.
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    const ALL: [Self; 8] = [
+        Self::NoEviction,
+        Self::AllKeysLru,
+        Self::AllKeysLfu,
+        Self::AllKeysRandom,
+        Self::VolatileLru,
+        Self::VolatileLfu,
+        Self::VolatileRandom,
+        Self::VolatileTtl,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileLru => "volatile-lru",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Returns whether only the keys with an expiry time are evicted.
+    pub fn volatile(self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+}
+
+/// How verbose the logs are, from the most verbose to the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    pub fn level_filter(self) -> log::LevelFilter {
+        match self {
+            Self::Debug => log::LevelFilter::Trace,
+            Self::Verbose => log::LevelFilter::Debug,
+            Self::Notice => log::LevelFilter::Info,
+            Self::Warning => log::LevelFilter::Warn,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config_file: None,
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            databases: 16,
+            loglevel: LogLevel::Notice,
+            maxclients: 10000,
+            timeout: 0,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            replicaof: None,
+            repl_backlog_size: 1024 * 1024,
+            cluster_enabled: false,
+            lua_time_limit: 5000,
+            requirepass: None,
+            aclfile: None,
+            acllog_max_len: 128,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            output_buffer_ceiling: 64 * 1024,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the command line arguments, without the program name: an
+    /// optional configuration file, followed by directives like `--port 6380`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let mut loader = Loader::default();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            let path = PathBuf::from(path);
+            loader.load_file(&path)?;
+            loader.config.config_file = Some(path);
+        }
+
+        while let Some(arg) = args.next() {
+            let name = arg.strip_prefix("--").ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "command line: expected a directive like --port, got '{}'",
+                    arg
+                ))
+            })?;
+            let mut values = vec![];
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            loader.apply(name, &values).map_err(|msg| {
+                Error::InvalidConfig(format!("command line: '--{}': {}", name, msg))
+            })?;
+        }
+        Ok(loader.config)
+    }
+
+    /// Reads the configuration file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut loader = Loader::default();
+        loader.load_file(path)?;
+        loader.config.config_file = Some(path.to_path_buf());
+        Ok(loader.config)
+    }
+
+    /// Applies a directive, given its name and its arguments.
+    pub fn set(&mut self, name: &str, args: &[String]) -> std::result::Result<(), String> {
+        let name = canonical_name(name);
+        let arg = || match args {
+            [arg] => Ok(arg.as_str()),
+            _ => Err(ERR_BAD_DIRECTIVE.to_string()),
+        };
+        match name.as_str() {
+            "bind" => {
+                if args.is_empty() {
+                    return Err(ERR_BAD_DIRECTIVE.to_string());
+                }
+                self.bind = args.to_vec();
+            }
+            "port" => self.port = parse_number(arg()?)?,
+            "databases" => {
+                self.databases = Some(parse_number(arg()?)?)
+                    .filter(|&databases| databases > 0)
+                    .ok_or("Invalid number of databases")?
+            }
+            "loglevel" => {
+                self.loglevel = match arg()?.to_ascii_lowercase().as_str() {
+                    "debug" => LogLevel::Debug,
+                    "verbose" => LogLevel::Verbose,
+                    "notice" => LogLevel::Notice,
+                    "warning" => LogLevel::Warning,
+                    _ => {
+                        return Err("Invalid log level. Must be one of debug, verbose, notice, \
+                                     warning"
+                            .to_string())
+                    }
+                }
+            }
+            "maxclients" => {
+                self.maxclients = Some(parse_number(arg()?)?)
+                    .filter(|&maxclients| maxclients > 0)
+                    .ok_or("Invalid max clients limit")?
+            }
+            "timeout" => self.timeout = parse_number(arg()?)?,
+            "maxmemory" => self.maxmemory = parse_memory(arg()?)?,
+            "maxmemory-policy" => {
+                let name = arg()?.to_ascii_lowercase();
+                self.maxmemory_policy = MaxmemoryPolicy::ALL
+                    .into_iter()
+                    .find(|policy| policy.name() == name)
+                    .ok_or("Invalid maxmemory policy")?
+            }
+            "maxmemory-samples" => {
+                self.maxmemory_samples = Some(parse_number(arg()?)?)
+                    .filter(|samples| (1..=64).contains(samples))
+                    .ok_or("argument must be between 1 and 64 inclusive")?
+            }
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = parse_number(arg()?)?,
+            "slowlog-max-len" => self.slowlog_max_len = parse_number(arg()?)?,
+            "dir" => self.dir = arg()?.to_string(),
+            "dbfilename" => {
+                let filename = arg()?;
+                if filename.is_empty() || filename.contains('/') {
+                    return Err("dbfilename can't be a path, just a filename".to_string());
+                }
+                self.dbfilename = filename.to_string();
+            }
+            "save" => self.save = parse_save(args)?,
+            "appendonly" => self.appendonly = parse_bool(arg()?)?,
+            "appendfilename" => {
+                let filename = arg()?;
+                if filename.is_empty() || filename.contains('/') {
+                    return Err("appendfilename can't be a path, just a filename".to_string());
+                }
+                self.appendfilename = filename.to_string();
+            }
+            "appendfsync" => {
+                self.appendfsync = match arg()?.to_ascii_lowercase().as_str() {
+                    "always" => AppendFsync::Always,
+                    "everysec" => AppendFsync::EverySec,
+                    "no" => AppendFsync::No,
+                    _ => return Err("argument must be 'no', 'always' or 'everysec'".to_string()),
+                }
+            }
+            "auto-aof-rewrite-percentage" => {
+                self.auto_aof_rewrite_percentage = parse_number(arg()?)?
+            }
+            "auto-aof-rewrite-min-size" => self.auto_aof_rewrite_min_size = parse_memory(arg()?)?,
+            "replicaof" => {
+                self.replicaof = match args {
+                    [host, port]
+                        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") =>
+                    {
+                        None
+                    }
+                    [host, port] => Some((host.clone(), parse_number(port)?)),
+                    _ => return Err(ERR_BAD_DIRECTIVE.to_string()),
+                }
+            }
+            "repl-backlog-size" => self.repl_backlog_size = parse_memory(arg()?)? as usize,
+            "cluster-enabled" => self.cluster_enabled = parse_bool(arg()?)?,
+            "lua-time-limit" => self.lua_time_limit = parse_number(arg()?)?,
+            "requirepass" => {
+                let password = arg()?;
+                self.requirepass = (!password.is_empty()).then(|| password.to_string());
+            }
+            "aclfile" => {
+                let path = arg()?;
+                self.aclfile = (!path.is_empty()).then(|| path.to_string());
+            }
+            "acllog-max-len" => self.acllog_max_len = parse_number(arg()?)?,
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = Some(parse_memory(arg()?)?)
+                    .filter(|&len| len >= 1024 * 1024)
+                    .ok_or("proto-max-bulk-len must be 1mb or greater")?
+                    as usize
+            }
+            "output-buffer-ceiling" => {
+                self.output_buffer_ceiling = Some(parse_memory(arg()?)?)
+                    .filter(|&size| size > 0)
+                    .ok_or("output-buffer-ceiling must be greater than 0")?
+                    as usize
+            }
+            _ => return Err(ERR_BAD_DIRECTIVE.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Returns every directive with its arguments, in the order CONFIG GET and CONFIG REWRITE
+    /// list them. A directive without arguments is not set, like `replicaof` on a master.
+    pub fn directives(&self) -> Vec<(&'static str, Vec<String>)> {
+        let one = |value: String| vec![value];
+        let yes_no = |value: bool| one(if value { "yes" } else { "no" }.to_string());
+        let save = if self.save.is_empty() {
+            one(String::new())
+        } else {
+            self.save
+                .iter()
+                .flat_map(|(seconds, changes)| [seconds.to_string(), changes.to_string()])
+                .collect()
+        };
+        let loglevel = match self.loglevel {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        };
+        let appendfsync = match self.appendfsync {
+            AppendFsync::Always => "always",
+            AppendFsync::EverySec => "everysec",
+            AppendFsync::No => "no",
+        };
+        vec![
+            ("bind", self.bind.clone()),
+            ("port", one(self.port.to_string())),
+            ("databases", one(self.databases.to_string())),
+            ("loglevel", one(loglevel.to_string())),
+            ("maxclients", one(self.maxclients.to_string())),
+            ("timeout", one(self.timeout.to_string())),
+            ("maxmemory", one(self.maxmemory.to_string())),
+            (
+                "maxmemory-policy",
+                one(self.maxmemory_policy.name().to_string()),
+            ),
+            ("maxmemory-samples", one(self.maxmemory_samples.to_string())),
+            (
+                "slowlog-log-slower-than",
+                one(self.slowlog_log_slower_than.to_string()),
+            ),
+            ("slowlog-max-len", one(self.slowlog_max_len.to_string())),
+            ("dir", one(self.dir.clone())),
+            ("dbfilename", one(self.dbfilename.clone())),
+            ("save", save),
+            ("appendonly", yes_no(self.appendonly)),
+            ("appendfilename", one(self.appendfilename.clone())),
+            ("appendfsync", one(appendfsync.to_string())),
+            (
+                "auto-aof-rewrite-percentage",
+                one(self.auto_aof_rewrite_percentage.to_string()),
+            ),
+            (
+                "auto-aof-rewrite-min-size",
+                one(self.auto_aof_rewrite_min_size.to_string()),
+            ),
+            (
+                "replicaof",
+                self.replicaof
+                    .iter()
+                    .flat_map(|(host, port)| [host.clone(), port.to_string()])
+                    .collect(),
+            ),
+            ("repl-backlog-size", one(self.repl_backlog_size.to_string())),
+            ("cluster-enabled", yes_no(self.cluster_enabled)),
+            ("lua-time-limit", one(self.lua_time_limit.to_string())),
+            (
+                "requirepass",
+                one(self.requirepass.clone().unwrap_or_default()),
+            ),
+            ("aclfile", one(self.aclfile.clone().unwrap_or_default())),
+            ("acllog-max-len", one(self.acllog_max_len.to_string())),
+            (
+                "proto-max-bulk-len",
+                one(self.proto_max_bulk_len.to_string()),
+            ),
+            (
+                "output-buffer-ceiling",
+                one(self.output_buffer_ceiling.to_string()),
+            ),
+        ]
+    }
+
+    /// Rewrites the configuration file with the current configuration. The comments and the
+    /// order of the directives are kept: the directives are rewritten in place, and the ones
+    /// missing from the file are appended unless they have their default value.
+    pub fn rewrite(&self, path: &Path) -> io::Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let directives = self.directives();
+        let mut rewritten = HashSet::new();
+        let mut lines = vec![];
+        for line in content.lines() {
+            let name = Some(line.trim())
+                .filter(|line| !line.starts_with('#'))
+                .and_then(split_args)
+                .and_then(|args| args.into_iter().next())
+                .map(|name| canonical_name(&name));
+            match directives.iter().find(|(n, _)| Some(*n) == name.as_deref()) {
+                // the directive is written where it's first found, and only there.
+                Some((name, args)) => {
+                    if rewritten.insert(*name) && !args.is_empty() {
+                        lines.push(format_directive(name, args));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let mut signed = lines.iter().any(|line| line == REWRITE_SIGNATURE);
+        for ((name, args), (_, default)) in directives.iter().zip(Config::default().directives()) {
+            if rewritten.contains(name) || args.is_empty() || *args == default {
+                continue;
+            }
+            if !signed {
+                lines.push(REWRITE_SIGNATURE.to_string());
+                signed = true;
+            }
+            lines.push(format_directive(name, args));
+        }
+
+        let temp = path.with_file_name(format!("temp-{}.conf", std::process::id()));
+        let mut file = fs::File::create(&temp)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    }
+}
+
+const ERR_BAD_DIRECTIVE: &str = "Bad directive or wrong number of arguments";
+
+// The line after which CONFIG REWRITE appends the directives missing from the file.
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+/// The directives that can be changed while the server runs, with CONFIG SET.
+pub const MUTABLE_DIRECTIVES: &[&str] = &[
+    "loglevel",
+    "maxclients",
+    "timeout",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "dbfilename",
+    "save",
+    "appendfsync",
+    "auto-aof-rewrite-percentage",
+    "auto-aof-rewrite-min-size",
+    "lua-time-limit",
+    "requirepass",
+    "acllog-max-len",
+    "proto-max-bulk-len",
+    "output-buffer-ceiling",
+];
+
+/// Returns the lowercase name of a directive, resolving the aliases.
+pub fn canonical_name(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    match name.as_str() {
+        "slaveof" => "replicaof".to_string(),
+        "busy-reply-threshold" => "lua-time-limit".to_string(),
+        _ => name,
+    }
+}
+
+/// Formats a directive as a line of the configuration file, quoting the arguments when needed.
+fn format_directive(name: &str, args: &[String]) -> String {
+    let mut line = name.to_string();
+    for arg in args {
+        line.push(' ');
+        let plain = !arg.is_empty()
+            && arg
+                .bytes()
+                .all(|c| c.is_ascii_graphic() && !matches!(c, b'"' | b'\'' | b'\\'));
+        if plain {
+            line.push_str(arg);
+            continue;
+        }
+        line.push('"');
+        for c in arg.chars() {
+            match c {
+                '"' | '\\' => {
+                    line.push('\\');
+                    line.push(c);
+                }
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                '\t' => line.push_str("\\t"),
+                c if c.is_control() => line.push_str(&format!("\\x{:02x}", c as u32)),
+                c => line.push(c),
+            }
+        }
+        line.push('"');
+    }
+    line
+}
+
+/// Reads the configuration files, following the `include` directives.
+#[derive(Default)]
+struct Loader {
+    config: Config,
+    /// Set once a save rule was read: the rules of the file replace the default ones, but the
+    /// `save` directives of the file add up.
+    save_read: bool,
+    depth: usize,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            Error::InvalidConfig(format!("cannot read {}: {}", path.display(), err))
+        })?;
+        for (i, line) in content.lines().enumerate() {
+            let invalid =
+                |msg: &str| Error::InvalidConfig(format!("{}:{}: {}", path.display(), i + 1, msg));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut args = split_args(line).ok_or_else(|| invalid("Unbalanced quotes"))?;
+            let name = args.remove(0);
+            if name.eq_ignore_ascii_case("include") {
+                let [include] = args.as_slice() else {
+                    return Err(invalid(ERR_BAD_DIRECTIVE));
+                };
+                if self.depth == MAX_INCLUDE_DEPTH {
+                    return Err(invalid("Too many nested includes"));
+                }
+                self.depth += 1;
+                self.load_file(Path::new(include))?;
+                self.depth -= 1;
+                continue;
+            }
+            self.apply(&name, &args)
+                .map_err(|msg| invalid(&format!("'{}': {}", line, msg)))?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, name: &str, args: &[String]) -> std::result::Result<(), String> {
+        if !name.eq_ignore_ascii_case("save") || !self.save_read {
+            self.save_read |= name.eq_ignore_ascii_case("save");
+            return self.config.set(name, args);
+        }
+        let previous = std::mem::take(&mut self.config.save);
+        self.config.set(name, args)?;
+        self.config.save.splice(0..0, previous);
+        Ok(())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str) -> std::result::Result<T, String> {
+    arg.parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_bool(arg: &str) -> std::result::Result<bool, String> {
+    match arg.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+/// Parses a size in bytes, with an optional unit: `k`, `m` and `g` are powers of 1000, `kb`,
+/// `mb` and `gb` are powers of 1024.
+pub fn parse_memory(arg: &str) -> std::result::Result<u64, String> {
+    let lower = arg.to_ascii_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+/// Parses the save rules, pairs of seconds and changes. A single empty argument removes them.
+fn parse_save(args: &[String]) -> std::result::Result<Vec<(u64, u64)>, String> {
+    if let [arg] = args {
+        if arg.is_empty() {
+            return Ok(vec![]);
+        }
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err("Invalid save parameters".to_string());
+    }
+    args.chunks(2)
+        .map(|rule| match (rule[0].parse(), rule[1].parse()) {
+            (Ok(seconds), Ok(changes)) => Ok((seconds, changes)),
+            _ => Err("Invalid save parameters".to_string()),
+        })
+        .collect()
+}
+
+/// Splits a line in arguments, like redis does: the arguments are separated by spaces, and can
+/// be quoted with double quotes, understanding escapes like `\n` and `\x41`, or with single
+/// quotes, where only `\'` is an escape. Returns `None` if the quotes are unbalanced.
+pub fn split_args(line: &str) -> Option<Vec<String>> {
+    let args = split_raw_args(line.as_bytes())?;
+    Some(
+        args.iter()
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect(),
+    )
+}
+
+/// Splits a line in binary arguments, like [`split_args`]. Used for the inline commands as well.
+pub fn split_raw_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = vec![];
+        match line[i] {
+            b'"' => {
+                i += 1;
+                loop {
+                    match *line.get(i)? {
+                        b'"' => break,
+                        b'\\'
+                            if line.get(i + 1) == Some(&b'x')
+                                && line.len() > i + 3
+                                && line[i + 2].is_ascii_hexdigit()
+                                && line[i + 3].is_ascii_hexdigit() =>
+                        {
+                            let hex = std::str::from_utf8(&line[i + 2..i + 4]).ok()?;
+                            arg.push(u8::from_str_radix(hex, 16).ok()?);
+                            i += 3;
+                        }
+                        b'\\' => {
+                            i += 1;
+                            arg.push(match *line.get(i)? {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 8,
+                                b'a' => 7,
+                                c => c,
+                            });
+                        }
+                        c => arg.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'\'' => {
+                i += 1;
+                loop {
+                    match *line.get(i)? {
+                        b'\'' => break,
+                        b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                            arg.push(b'\'');
+                            i += 1;
+                        }
+                        c => arg.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => {
+                while i < line.len() && !line[i].is_ascii_whitespace() {
+                    arg.push(line[i]);
+                    i += 1;
+                }
+            }
+        }
+        // a closing quote must be followed by a space.
+        if i < line.len() && !line[i].is_ascii_whitespace() {
+            return None;
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            Some(args(&["save", "3600", "1"])),
+            split_args("  save 3600   1 ")
+        );
+        assert_eq!(Some(args(&["save", ""])), split_args("save \"\""));
+        assert_eq!(
+            Some(args(&["requirepass", "a b\n\"A", "it's"])),
+            split_args(r#"requirepass "a b\n\"\x41" 'it\'s'"#)
+        );
+        assert_eq!(None, split_args("requirepass \"unbalanced"));
+        assert_eq!(None, split_args("requirepass \"a\"b"));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("redirs-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("included.conf");
+        fs::write(&included, "appendonly yes\nappendfsync always\n").unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(
+            &path,
+            format!(
+                "# comment\n\nbind 0.0.0.0 ::1\nPORT 7000\nsave 900 1\nsave 300 10 60 100\n\
+                 include {}\nauto-aof-rewrite-min-size 1mb\nreplicaof 10.0.0.1 6379\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let config = Config::from_args(args(&[
+            path.to_str().unwrap(),
+            "--port",
+            "7001",
+            "--requirepass",
+            "secret",
+        ]))
+        .unwrap();
+        assert_eq!(Some(path.clone()), config.config_file);
+        assert_eq!(args(&["0.0.0.0", "::1"]), config.bind);
+        assert_eq!(7001, config.port);
+        assert_eq!(vec![(900, 1), (300, 10), (60, 100)], config.save);
+        assert!(config.appendonly);
+        assert_eq!(AppendFsync::Always, config.appendfsync);
+        assert_eq!(1024 * 1024, config.auto_aof_rewrite_min_size);
+        assert_eq!(Some(("10.0.0.1".to_string(), 6379)), config.replicaof);
+        assert_eq!(Some("secret".to_string()), config.requirepass);
+
+        let config = Config::from_args(args(&["--save", "", "--loglevel", "debug"])).unwrap();
+        assert!(config.save.is_empty());
+        assert_eq!(LogLevel::Debug, config.loglevel);
+
+        fs::write(&path, "port 7000\nport seventy\n").unwrap();
+        let err = Config::load(&path).unwrap_err().to_string();
+        assert!(
+            err.ends_with(":2: 'port seventy': argument couldn't be parsed into an integer"),
+            "{}",
+            err
+        );
+        fs::write(&path, "maxmemory-bogus 1\n").unwrap();
+        let err = Config::load(&path).unwrap_err().to_string();
+        assert!(
+            err.ends_with(":1: 'maxmemory-bogus 1': Bad directive or wrong number of arguments")
+        );
+        assert!(Config::from_args(args(&["--port"])).is_err());
+        assert!(Config::from_args(args(&["--proto-max-bulk-len", "1kb"])).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
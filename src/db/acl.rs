@@ -0,0 +1,765 @@
+//! Access control: the clients authenticate as a user, which is allowed to run some commands, to
+//! access some keys and to use some pub/sub channels. The users are described with the same rules
+//! as redis, like `on >password ~cache:* +@read -keys`, and can be saved to the ACL file.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::glob::glob_match;
+use crate::value::{Bytes, Value};
+
+use super::command::{
+    get_commands, CommandFlag, CommandSpec, COMMAND_FLAG_ADMIN, COMMAND_FLAG_BLOCKING,
+    COMMAND_FLAG_CONNECTION, COMMAND_FLAG_FAST, COMMAND_FLAG_HASH, COMMAND_FLAG_KEYSPACE,
+    COMMAND_FLAG_LIST, COMMAND_FLAG_PUBSUB, COMMAND_FLAG_READONLY, COMMAND_FLAG_SCRIPTING,
+    COMMAND_FLAG_SET, COMMAND_FLAG_SLOW, COMMAND_FLAG_SORTEDSET, COMMAND_FLAG_STREAM,
+    COMMAND_FLAG_STRING, COMMAND_FLAG_TRANSACTION, COMMAND_FLAG_WRITE,
+};
+use super::now_millis;
+
+pub const DEFAULT_USER: &str = "default";
+
+/// The categories of commands usable in the rules, like `+@read`, and the flag of the commands
+/// in each of them.
+const CATEGORIES: &[(&str, CommandFlag)] = &[
+    ("keyspace", COMMAND_FLAG_KEYSPACE),
+    ("read", COMMAND_FLAG_READONLY),
+    ("write", COMMAND_FLAG_WRITE),
+    ("set", COMMAND_FLAG_SET),
+    ("sortedset", COMMAND_FLAG_SORTEDSET),
+    ("list", COMMAND_FLAG_LIST),
+    ("hash", COMMAND_FLAG_HASH),
+    ("string", COMMAND_FLAG_STRING),
+    ("stream", COMMAND_FLAG_STREAM),
+    ("pubsub", COMMAND_FLAG_PUBSUB),
+    ("admin", COMMAND_FLAG_ADMIN),
+    ("fast", COMMAND_FLAG_FAST),
+    ("slow", COMMAND_FLAG_SLOW),
+    ("blocking", COMMAND_FLAG_BLOCKING),
+    ("connection", COMMAND_FLAG_CONNECTION),
+    ("transaction", COMMAND_FLAG_TRANSACTION),
+    ("scripting", COMMAND_FLAG_SCRIPTING),
+];
+
+/// The entries of the ACL log of the same denial are grouped if they happen within this delay,
+/// in milliseconds.
+const ACL_LOG_GROUPING_MAX_TIME_DELTA: u64 = 60000;
+
+fn hash_password(password: &[u8]) -> String {
+    Sha256::digest(password)
+        .iter()
+        .fold(String::new(), |mut hash, byte| {
+            let _ = write!(hash, "{:02x}", byte);
+            hash
+        })
+}
+
+/// Returns the names of the commands in a category, `None` if the category doesn't exist.
+pub fn category_commands(category: &str) -> Option<Vec<String>> {
+    let commands = get_commands();
+    if category.eq_ignore_ascii_case("all") {
+        return Some(commands.into_iter().map(|spec| spec.name).collect());
+    }
+    let (_, flag) = CATEGORIES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(category))?;
+    Some(
+        commands
+            .into_iter()
+            .filter(|spec| spec.flags.contains(flag))
+            .map(|spec| spec.name)
+            .collect(),
+    )
+}
+
+pub fn categories() -> impl Iterator<Item = &'static str> {
+    CATEGORIES.iter().map(|(name, _)| *name)
+}
+
+/// A pattern of the keys a user can access, for reading, writing, or both.
+#[derive(Debug, Clone, PartialEq)]
+struct KeyPattern {
+    pattern: Bytes,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        let prefix = match (self.read, self.write) {
+            (true, true) => "~".to_string(),
+            (true, false) => "%R~".to_string(),
+            _ => "%W~".to_string(),
+        };
+        prefix + &String::from_utf8_lossy(&self.pattern)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    enabled: bool,
+    nopass: bool,
+    /// The SHA256 of the passwords, in hexadecimal.
+    passwords: Vec<String>,
+    /// The allowed commands, and the allowed subcommands as `COMMAND|SUBCOMMAND`.
+    commands: HashSet<String>,
+    /// The command rules, as they were given since the last `+@all` or `-@all`.
+    command_rules: Vec<String>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<Bytes>,
+}
+
+/// Why a command is denied to a user.
+#[derive(Debug, PartialEq)]
+pub enum Denial {
+    Command(String),
+    Key(Bytes),
+    Channel(Bytes),
+}
+
+impl Denial {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Command(_) => "command",
+            Self::Key(_) => "key",
+            Self::Channel(_) => "channel",
+        }
+    }
+
+    pub fn object(&self) -> String {
+        match self {
+            Self::Command(name) => name.clone(),
+            Self::Key(key) | Self::Channel(key) => String::from_utf8_lossy(key).to_string(),
+        }
+    }
+
+    /// Returns the message replied to the client.
+    pub fn message(&self, user: &str) -> String {
+        match self {
+            Self::Command(name) => format!(
+                "User {} has no permissions to run the '{}' command",
+                user, name
+            ),
+            Self::Key(_) => "No permissions to access a key".to_string(),
+            Self::Channel(_) => "No permissions to access a channel".to_string(),
+        }
+    }
+}
+
+impl User {
+    /// Creates a user with no permissions, which is disabled.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: HashSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// Applies a rule, like `on`, `>password`, `~pattern` or `+@category`.
+    pub fn apply(&mut self, rule: &str) -> std::result::Result<(), String> {
+        let lower = rule.to_ascii_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => *self = Self::new(&self.name),
+            _ => return self.apply_pattern(rule),
+        }
+        Ok(())
+    }
+
+    fn apply_pattern(&mut self, rule: &str) -> std::result::Result<(), String> {
+        let syntax_error = || "Syntax error".to_string();
+        if let Some(password) = rule.strip_prefix('>') {
+            let hash = hash_password(password.as_bytes());
+            if !self.passwords.contains(&hash) {
+                self.passwords.push(hash);
+            }
+            self.nopass = false;
+        } else if let Some(password) = rule.strip_prefix('<') {
+            let hash = hash_password(password.as_bytes());
+            if !self.passwords.contains(&hash) {
+                return Err(
+                    "The password you are trying to remove from the user does not exist"
+                        .to_string(),
+                );
+            }
+            self.passwords.retain(|p| *p != hash);
+        } else if let Some(hash) = rule.strip_prefix('#') {
+            if hash.len() != 64 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(
+                    "The password hash must be exactly 64 characters and contain only \
+                            lowercase hexadecimal characters"
+                        .to_string(),
+                );
+            }
+            let hash = hash.to_ascii_lowercase();
+            if !self.passwords.contains(&hash) {
+                self.passwords.push(hash);
+            }
+            self.nopass = false;
+        } else if let Some(hash) = rule.strip_prefix('!') {
+            let hash = hash.to_ascii_lowercase();
+            if !self.passwords.contains(&hash) {
+                return Err(
+                    "The password you are trying to remove from the user does not exist"
+                        .to_string(),
+                );
+            }
+            self.passwords.retain(|p| *p != hash);
+        } else if let Some(pattern) = rule.strip_prefix('~') {
+            self.add_key_pattern(pattern, true, true);
+        } else if let Some(rest) = rule.strip_prefix('%') {
+            let (access, pattern) = rest.split_once('~').ok_or_else(syntax_error)?;
+            let access = access.to_ascii_uppercase();
+            if access.is_empty() || !access.bytes().all(|c| c == b'R' || c == b'W') {
+                return Err(syntax_error());
+            }
+            self.add_key_pattern(pattern, access.contains('R'), access.contains('W'));
+        } else if let Some(pattern) = rule.strip_prefix('&') {
+            let pattern = Bytes::from(pattern);
+            if !self.channels.contains(&pattern) {
+                self.channels.push(pattern);
+            }
+        } else if let Some(name) = rule.strip_prefix(['+', '-']) {
+            self.apply_command_rule(rule.starts_with('+'), name)?;
+        } else {
+            return Err(syntax_error());
+        }
+        Ok(())
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, read: bool, write: bool) {
+        let pattern = KeyPattern {
+            pattern: Bytes::from(pattern),
+            read,
+            write,
+        };
+        if !self.keys.contains(&pattern) {
+            self.keys.push(pattern);
+        }
+    }
+
+    fn apply_command_rule(&mut self, allow: bool, name: &str) -> std::result::Result<(), String> {
+        let unknown = || "Unknown command or category name in ACL".to_string();
+        let names = match name.strip_prefix('@') {
+            Some(category) => category_commands(category).ok_or_else(unknown)?,
+            None => {
+                let (command, subcommand) = match name.split_once('|') {
+                    Some((command, subcommand)) if !subcommand.is_empty() => {
+                        (command, Some(subcommand))
+                    }
+                    Some(_) => return Err(unknown()),
+                    None => (name, None),
+                };
+                if !get_commands()
+                    .iter()
+                    .any(|spec| spec.name.eq_ignore_ascii_case(command))
+                {
+                    return Err(unknown());
+                }
+                match subcommand {
+                    Some(subcommand) if allow => vec![format!("{}|{}", command, subcommand)],
+                    Some(_) => return Err("Blocking a subcommand is not supported".to_string()),
+                    None => vec![command.to_string()],
+                }
+            }
+        };
+        for name in names {
+            let name = name.to_uppercase();
+            if allow {
+                self.commands.insert(name);
+            } else {
+                // removing a command removes its subcommands as well.
+                let prefix = format!("{}|", name);
+                self.commands
+                    .retain(|allowed| *allowed != name && !allowed.starts_with(&prefix));
+            }
+        }
+
+        let rule = format!("{}{}", if allow { '+' } else { '-' }, name.to_lowercase());
+        if name.eq_ignore_ascii_case("@all") {
+            self.command_rules.clear();
+        }
+        self.command_rules.push(rule);
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn nopass(&self) -> bool {
+        self.nopass
+    }
+
+    /// Checks a password, without checking whether the user is enabled.
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        self.nopass || self.passwords.contains(&hash_password(password))
+    }
+
+    /// Checks that the user can run the command, with its keys and channels.
+    pub fn check(&self, spec: &CommandSpec, args: &[Value]) -> std::result::Result<(), Denial> {
+        let subcommand = match args.first() {
+            Some(Value::Blob(arg) | Value::Simple(arg)) => {
+                format!("{}|{}", spec.name, String::from_utf8_lossy(arg)).to_uppercase()
+            }
+            _ => String::new(),
+        };
+        if !self.commands.contains(&spec.name.to_uppercase())
+            && !self.commands.contains(&subcommand)
+        {
+            return Err(Denial::Command(spec.name.to_lowercase()));
+        }
+
+        let write = spec.flags.contains(&COMMAND_FLAG_WRITE);
+        for key in spec.keys(args) {
+            let allowed = self.keys.iter().any(|pattern| {
+                (if write { pattern.write } else { pattern.read })
+                    && glob_match(&pattern.pattern, key, false)
+            });
+            if !allowed {
+                return Err(Denial::Key(key.to_vec().into()));
+            }
+        }
+
+        let (channels, literal) = match spec.name.to_uppercase().as_str() {
+            "PUBLISH" | "SPUBLISH" => (&args[..1], false),
+            "SUBSCRIBE" | "SSUBSCRIBE" => (args, false),
+            "PSUBSCRIBE" => (args, true),
+            _ => (&args[..0], false),
+        };
+        for channel in channels {
+            let channel = match channel {
+                Value::Blob(channel) | Value::Simple(channel) => channel,
+                _ => continue,
+            };
+            // a pattern is allowed only if it's one of the patterns of the user.
+            let allowed = self.channels.iter().any(|pattern| {
+                if literal {
+                    pattern.as_slice() == b"*" || pattern == channel
+                } else {
+                    glob_match(pattern, channel, false)
+                }
+            });
+            if !allowed {
+                return Err(Denial::Channel(channel.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the user with the rules creating it, as in ACL LIST and in the ACL file.
+    pub fn describe(&self) -> String {
+        let mut rules = vec![format!("user {}", self.name)];
+        rules.push(if self.enabled { "on" } else { "off" }.to_string());
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(self.keys.iter().map(KeyPattern::describe));
+        rules.extend(
+            self.channels
+                .iter()
+                .map(|pattern| format!("&{}", String::from_utf8_lossy(pattern))),
+        );
+        rules.push(self.command_rules.join(" "));
+        rules.join(" ")
+    }
+
+    /// Returns the user as replied by ACL GETUSER.
+    pub fn to_value(&self) -> Value {
+        let mut flags = vec![Value::Blob(if self.enabled { "on" } else { "off" }.into())];
+        if self.nopass {
+            flags.push(Value::Blob("nopass".into()));
+        }
+        let keys: Vec<String> = self.keys.iter().map(KeyPattern::describe).collect();
+        let channels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|pattern| format!("&{}", String::from_utf8_lossy(pattern)))
+            .collect();
+        Value::Array(vec![
+            Value::Blob("flags".into()),
+            Value::Array(flags),
+            Value::Blob("passwords".into()),
+            Value::Array(
+                self.passwords
+                    .iter()
+                    .map(|hash| Value::Blob(hash.as_str().into()))
+                    .collect(),
+            ),
+            Value::Blob("commands".into()),
+            Value::Blob(self.command_rules.join(" ").as_str().into()),
+            Value::Blob("keys".into()),
+            Value::Blob(keys.join(" ").as_str().into()),
+            Value::Blob("channels".into()),
+            Value::Blob(channels.join(" ").as_str().into()),
+            Value::Blob("selectors".into()),
+            Value::Array(vec![]),
+        ])
+    }
+}
+
+/// An entry of the ACL log, the denied commands and the failed authentications.
+struct AclLogEntry {
+    count: u64,
+    reason: &'static str,
+    context: &'static str,
+    object: String,
+    username: String,
+    client_info: String,
+    entry_id: u64,
+    created: u64,
+    updated: u64,
+}
+
+pub struct Acl {
+    users: BTreeMap<String, User>,
+    log: VecDeque<AclLogEntry>,
+    log_max_len: usize,
+    next_entry_id: u64,
+    path: Option<PathBuf>,
+}
+
+impl Acl {
+    pub fn new(config: &Config) -> Self {
+        let mut acl = Self {
+            users: BTreeMap::new(),
+            log: VecDeque::new(),
+            log_max_len: config.acllog_max_len,
+            next_entry_id: 0,
+            path: config.aclfile.as_ref().map(PathBuf::from),
+        };
+        acl.users
+            .insert(DEFAULT_USER.to_string(), Self::default_user());
+        acl.set_requirepass(config.requirepass.as_deref());
+        acl
+    }
+
+    /// The default user, which has every permission and no password until told otherwise.
+    fn default_user() -> User {
+        let mut user = User::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    /// Sets the password of the default user, like the `requirepass` option. `None` removes it.
+    pub fn set_requirepass(&mut self, password: Option<&str>) {
+        let user = self.users.get_mut(DEFAULT_USER).unwrap();
+        user.apply("resetpass").unwrap();
+        match password {
+            Some(password) => user.apply(&format!(">{}", password)).unwrap(),
+            None => user.apply("nopass").unwrap(),
+        }
+    }
+
+    pub fn set_log_max_len(&mut self, max_len: usize) {
+        self.log_max_len = max_len;
+        self.log.truncate(max_len);
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Creates or modifies a user. The rules are applied all or nothing.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> std::result::Result<(), String> {
+        let mut user = self
+            .users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|err| format!("Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+        self.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    pub fn delete_user(&mut self, name: &str) -> bool {
+        self.users.remove(name).is_some()
+    }
+
+    /// Returns the user the credentials authenticate, if they are valid and the user is enabled.
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> Option<&User> {
+        self.users
+            .get(name)
+            .filter(|user| user.enabled && user.check_password(password))
+    }
+
+    /// Adds an entry to the log, or counts it in a recent identical entry.
+    pub fn log(
+        &mut self,
+        reason: &'static str,
+        context: &'static str,
+        object: String,
+        username: &str,
+        client_info: String,
+    ) {
+        let now = now_millis();
+        let similar = self.log.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+                && now.saturating_sub(entry.updated) < ACL_LOG_GROUPING_MAX_TIME_DELTA
+        });
+        if let Some(entry) = similar {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            return;
+        }
+
+        self.log.push_front(AclLogEntry {
+            count: 1,
+            reason,
+            context,
+            object,
+            username: username.to_string(),
+            client_info,
+            entry_id: self.next_entry_id,
+            created: now,
+            updated: now,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(self.log_max_len);
+    }
+
+    pub fn reset_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// Returns the most recent entries of the log, as replied by ACL LOG.
+    pub fn log_entries(&self, count: usize) -> Value {
+        let now = now_millis();
+        Value::Array(
+            self.log
+                .iter()
+                .take(count)
+                .map(|entry| {
+                    let age = now.saturating_sub(entry.created) as f64 / 1000.0;
+                    Value::Array(vec![
+                        Value::Blob("count".into()),
+                        Value::Number(entry.count as i64),
+                        Value::Blob("reason".into()),
+                        Value::Blob(entry.reason.into()),
+                        Value::Blob("context".into()),
+                        Value::Blob(entry.context.into()),
+                        Value::Blob("object".into()),
+                        Value::Blob(entry.object.as_str().into()),
+                        Value::Blob("username".into()),
+                        Value::Blob(entry.username.as_str().into()),
+                        Value::Blob("age-seconds".into()),
+                        Value::Blob(format!("{:.3}", age).as_str().into()),
+                        Value::Blob("client-info".into()),
+                        Value::Blob(entry.client_info.as_str().into()),
+                        Value::Blob("entry-id".into()),
+                        Value::Number(entry.entry_id as i64),
+                        Value::Blob("timestamp-created".into()),
+                        Value::Number(entry.created as i64),
+                        Value::Blob("timestamp-last-updated".into()),
+                        Value::Number(entry.updated as i64),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub fn has_file(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Replaces the users with the ones of the ACL file. Nothing changes if the file is invalid.
+    /// Returns false if there is no ACL file.
+    pub fn load(&mut self) -> Result<bool> {
+        let path = match &self.path {
+            Some(path) if path.exists() => path,
+            _ => return Ok(false),
+        };
+        let content = fs::read_to_string(path)?;
+        let mut users = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let invalid =
+                |msg: &str| Error::InvalidAcl(format!("{}:{}: {}", path.display(), i + 1, msg));
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => continue,
+                Some("user") => (),
+                Some(_) => return Err(invalid("should start with user keyword")),
+            }
+            let name = words
+                .next()
+                .ok_or_else(|| invalid("missing the username"))?;
+            if users.contains_key(name) {
+                return Err(invalid(&format!("Duplicate user '{}' found", name)));
+            }
+            let mut user = User::new(name);
+            for rule in words {
+                user.apply(rule).map_err(|err| {
+                    invalid(&format!("Error in applying operation '{}': {}", rule, err))
+                })?;
+            }
+            users.insert(name.to_string(), user);
+        }
+        users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(Self::default_user);
+        self.users = users;
+        Ok(true)
+    }
+
+    /// Writes the users to the ACL file, replacing it atomically.
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        let mut file = fs::File::create(&tmp)?;
+        for user in self.users.values() {
+            writeln!(file, "{}", user.describe())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Generates a random password of `bits` bits, in hexadecimal.
+pub fn generate_password(bits: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bits.div_ceil(4))
+        .map(|_| format!("{:x}", rng.gen_range(0..16)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(args: &[&str]) -> Vec<Value> {
+        args.iter().map(|arg| Value::Blob((*arg).into())).collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let commands = get_commands();
+        let spec = |name: &str| commands.iter().find(|spec| spec.name == name).unwrap();
+
+        let mut user = User::new("alice");
+        for rule in [
+            "on",
+            ">secret",
+            "~cache:*",
+            "%R~shared:*",
+            "&news.*",
+            "+@read",
+            "-type",
+        ] {
+            user.apply(rule).unwrap();
+        }
+        assert!(user.check_password(b"secret"));
+        assert!(!user.check_password(b"other"));
+        assert_eq!(Ok(()), user.check(spec("GET"), &request(&["cache:1"])));
+        assert_eq!(Ok(()), user.check(spec("GET"), &request(&["shared:1"])));
+        assert_eq!(
+            Err(Denial::Key("other".into())),
+            user.check(spec("GET"), &request(&["other"]))
+        );
+        assert_eq!(
+            Err(Denial::Command("set".into())),
+            user.check(spec("SET"), &request(&["cache:1", "x"]))
+        );
+        assert_eq!(
+            Err(Denial::Command("type".into())),
+            user.check(spec("TYPE"), &request(&["cache:1"]))
+        );
+
+        user.apply("+set").unwrap();
+        assert_eq!(Ok(()), user.check(spec("SET"), &request(&["cache:1", "x"])));
+        assert_eq!(
+            Err(Denial::Key("shared:1".into())),
+            user.check(spec("SET"), &request(&["shared:1", "x"]))
+        );
+
+        user.apply("+publish").unwrap();
+        user.apply("+psubscribe").unwrap();
+        assert_eq!(
+            Ok(()),
+            user.check(spec("PUBLISH"), &request(&["news.1", "x"]))
+        );
+        assert_eq!(
+            Err(Denial::Channel("sport".into())),
+            user.check(spec("PUBLISH"), &request(&["sport", "x"]))
+        );
+        assert_eq!(
+            Ok(()),
+            user.check(spec("PSUBSCRIBE"), &request(&["news.*"]))
+        );
+        assert_eq!(
+            Err(Denial::Channel("news.a*".into())),
+            user.check(spec("PSUBSCRIBE"), &request(&["news.a*"]))
+        );
+
+        user.apply("+cluster|keyslot").unwrap();
+        assert_eq!(
+            Ok(()),
+            user.check(spec("CLUSTER"), &request(&["keyslot", "a"]))
+        );
+        assert_eq!(
+            Err(Denial::Command("cluster".into())),
+            user.check(spec("CLUSTER"), &request(&["nodes"]))
+        );
+
+        assert_eq!(
+            format!(
+                "user alice on #{} ~cache:* %R~shared:* &news.* -@all +@read -type +set \
+                 +publish +psubscribe +cluster|keyslot",
+                hash_password(b"secret")
+            ),
+            user.describe()
+        );
+        assert_eq!(
+            Err("Unknown command or category name in ACL".to_string()),
+            user.apply("+@unknown")
+        );
+        assert_eq!(Err("Syntax error".to_string()), user.apply("bogus"));
+    }
+}
@@ -0,0 +1,717 @@
+//! The append only file: every write command is logged in the RESP protocol, so that the
+//! dataset can be rebuilt on startup by running the commands again.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::{AppendFsync, Config};
+use crate::error::{Error, Result};
+use crate::value::{Bytes, Value, ValueWrite};
+
+use super::object::Object;
+use super::rdb::Entry;
+use super::stream::{PendingEntry, Stream, StreamId};
+use super::Session;
+
+// The maximum number of elements added by a single command of a rewritten AOF, the same as
+// redis, so that the file can be replayed without huge commands.
+const AOF_REWRITE_ITEMS_PER_CMD: usize = 64;
+// How many bytes of a rewrite are buffered before being written to the file.
+const AOF_REWRITE_BUFFER_SIZE: usize = 64 * 1024;
+const AOF_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Builds a command the way it is written to the AOF.
+pub fn command(args: &[&[u8]]) -> Vec<Value> {
+    args.iter()
+        .map(|arg| Value::Blob(Bytes::from(arg.to_vec())))
+        .collect()
+}
+
+/// Builds the XCLAIM that gives the pending entry of a consumer group back to its consumer,
+/// with the same delivery time and count, when replayed.
+pub fn claim_command(key: &[u8], group: &[u8], id: StreamId, entry: &PendingEntry) -> Vec<Value> {
+    command(&[
+        b"XCLAIM",
+        key,
+        group,
+        &entry.consumer,
+        b"0",
+        id.to_string().as_bytes(),
+        b"TIME",
+        entry.delivery_time.to_string().as_bytes(),
+        b"RETRYCOUNT",
+        entry.delivery_count.to_string().as_bytes(),
+        b"FORCE",
+        b"JUSTID",
+    ])
+}
+
+/// The state of the AOF, shared by the clients and the thread rewriting it in the background.
+pub struct Aof {
+    path: PathBuf,
+    fsync: AppendFsync,
+    /// Whether the AOF is turned on by the configuration. The file is only opened once it has
+    /// been loaded, commands are not logged until then.
+    enabled: bool,
+    file: Option<File>,
+    stream: CommandStream,
+    /// The commands logged while the AOF is rewritten in the background, they are appended to
+    /// the rewritten file once it's done.
+    rewrite_buffer: Option<Vec<u8>>,
+    /// The size of the file, and its size after the last rewrite, used to trigger a rewrite once
+    /// the file grows too much.
+    size: u64,
+    base_size: u64,
+    auto_rewrite_percentage: u64,
+    auto_rewrite_min_size: u64,
+    unsynced: bool,
+    last_fsync: Instant,
+}
+
+impl Aof {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            path: Path::new(&config.dir).join(&config.appendfilename),
+            fsync: config.appendfsync,
+            enabled: config.appendonly,
+            file: None,
+            stream: CommandStream::default(),
+            rewrite_buffer: None,
+            size: 0,
+            base_size: 0,
+            auto_rewrite_percentage: config.auto_aof_rewrite_percentage,
+            auto_rewrite_min_size: config.auto_aof_rewrite_min_size,
+            unsynced: false,
+            last_fsync: Instant::now(),
+        }
+    }
+
+    /// Applies the settings that can be changed while the server runs.
+    pub fn configure(&mut self, config: &Config) {
+        self.fsync = config.appendfsync;
+        self.auto_rewrite_percentage = config.auto_aof_rewrite_percentage;
+        self.auto_rewrite_min_size = config.auto_aof_rewrite_min_size;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns whether the write commands are being logged.
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    /// Returns whether the file grew enough since the last rewrite to be rewritten again.
+    pub fn needs_rewrite(&self) -> bool {
+        let growth = self.size.saturating_sub(self.base_size) * 100 / self.base_size.max(1);
+        self.is_open()
+            && !self.rewrite_in_progress()
+            && self.auto_rewrite_percentage > 0
+            && self.size >= self.auto_rewrite_min_size
+            && growth >= self.auto_rewrite_percentage
+    }
+
+    /// Opens the file to start logging the write commands.
+    pub fn open(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.base_size = self.size;
+        self.file = Some(file);
+        self.stream.reset();
+        Ok(())
+    }
+
+    /// Logs the commands run against the database `db`. Inside a transaction, the commands are
+    /// wrapped in MULTI and EXEC so that a transaction is replayed atomically as well.
+    pub fn append(&mut self, db: usize, commands: &[Vec<Value>], in_exec: bool) {
+        let buf = self.stream.encode(db, commands, in_exec);
+        self.write(&buf);
+    }
+
+    /// Ends the transaction started by [`Aof::append`], if any of its commands was logged.
+    pub fn end_exec(&mut self) {
+        if let Some(buf) = self.stream.end_exec() {
+            self.write(&buf);
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        if let Some(rewrite_buffer) = &mut self.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(buf);
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+        // like redis, a failure to write the AOF is not reported to the client: the command has
+        // already been executed anyway.
+        if let Err(err) = file.write_all(buf) {
+            log::error!("Error writing to the AOF: {}", err);
+            return;
+        }
+        self.size += buf.len() as u64;
+        if self.fsync == AppendFsync::Always {
+            if let Err(err) = file.sync_data() {
+                log::error!("Error syncing the AOF: {}", err);
+            }
+        } else {
+            self.unsynced = true;
+        }
+    }
+
+    /// Returns a handle to the file if it's time to sync it, with the `everysec` policy. The
+    /// file is synced by the caller without holding the lock of the AOF, so that the writers
+    /// are not blocked meanwhile.
+    pub fn file_to_sync(&mut self) -> Option<File> {
+        if self.fsync != AppendFsync::EverySec
+            || !self.unsynced
+            || self.last_fsync.elapsed() < AOF_FSYNC_INTERVAL
+        {
+            return None;
+        }
+        let file = self.file.as_ref()?.try_clone().ok()?;
+        self.unsynced = false;
+        self.last_fsync = Instant::now();
+        Some(file)
+    }
+
+    /// Syncs the file right away, whatever the policy.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+            self.unsynced = false;
+            self.last_fsync = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Starts buffering the logged commands, for the rewrite of a snapshot taken right now.
+    pub fn start_rewrite(&mut self) {
+        let mut buffer = vec![];
+        // the rewrite starts in the middle of a transaction, whose first commands are part of
+        // the snapshot.
+        if self.stream.in_multi {
+            write_command(&mut buffer, &command(&[b"MULTI"]));
+        }
+        self.rewrite_buffer = Some(buffer);
+        self.stream.reset();
+    }
+
+    /// Appends the commands logged during the rewrite to the rewritten file at `temp`, then
+    /// replaces the AOF with it.
+    pub fn finish_rewrite(&mut self, temp: &Path) -> io::Result<()> {
+        let buffer = self.rewrite_buffer.take().unwrap_or_default();
+        let mut file = OpenOptions::new().append(true).open(temp)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+        fs::rename(temp, &self.path)?;
+
+        self.base_size = size;
+        if self.file.is_some() {
+            // the buffered commands end with the database currently selected.
+            let selected_db = self.stream.selected_db;
+            self.open()?;
+            self.stream.selected_db = selected_db;
+        }
+        Ok(())
+    }
+
+    pub fn abort_rewrite(&mut self) {
+        self.rewrite_buffer = None;
+    }
+}
+
+/// Encodes the commands propagated by the clients. The commands are preceded by a SELECT when
+/// they run against another database than the previous ones, and the ones run by EXEC are
+/// wrapped in MULTI and EXEC.
+#[derive(Debug, Default)]
+pub struct CommandStream {
+    /// The database selected by the last SELECT written, `None` if the next command has to be
+    /// preceded by a SELECT.
+    selected_db: Option<usize>,
+    /// Set once the MULTI of the running transaction is written.
+    in_multi: bool,
+}
+
+impl CommandStream {
+    /// Encodes the commands run against the database `db`.
+    pub fn encode(&mut self, db: usize, commands: &[Vec<Value>], in_exec: bool) -> Vec<u8> {
+        let mut buf = vec![];
+        if in_exec && !self.in_multi {
+            write_command(&mut buf, &command(&[b"MULTI"]));
+            self.in_multi = true;
+        }
+        if self.selected_db != Some(db) {
+            write_command(&mut buf, &command(&[b"SELECT", db.to_string().as_bytes()]));
+            self.selected_db = Some(db);
+        }
+        for args in commands {
+            write_command(&mut buf, args);
+        }
+        buf
+    }
+
+    /// Returns the EXEC ending the running transaction, if any of its commands was encoded.
+    pub fn end_exec(&mut self) -> Option<Vec<u8>> {
+        if !self.in_multi {
+            return None;
+        }
+        self.in_multi = false;
+        let mut buf = vec![];
+        write_command(&mut buf, &command(&[b"EXEC"]));
+        Some(buf)
+    }
+
+    /// Forgets the selected database, the next command is preceded by a SELECT.
+    pub fn reset(&mut self) {
+        self.selected_db = None;
+    }
+}
+
+pub fn write_command(buf: &mut Vec<u8>, args: &[Value]) {
+    // writing to a vector can't fail.
+    buf.write_value(&Value::Array(args.to_vec())).unwrap();
+}
+
+/// Writes the commands that rebuild the keys of every database, indexed by database number.
+pub fn rewrite(dbs: &[Vec<Entry>], writer: impl Write) -> io::Result<()> {
+    let mut aof = AofWriter {
+        writer,
+        buf: vec![],
+    };
+    for (index, entries) in dbs.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        aof.emit(command(&[b"SELECT", index.to_string().as_bytes()]))?;
+        for (key, value, expire) in entries {
+            aof.rewrite_object(key, value)?;
+            if let Some(when) = expire {
+                aof.emit(command(&[b"PEXPIREAT", key, when.to_string().as_bytes()]))?;
+            }
+        }
+    }
+    aof.writer.write_all(&aof.buf)?;
+    aof.writer.flush()
+}
+
+struct AofWriter<W> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> AofWriter<W> {
+    fn emit(&mut self, args: Vec<Value>) -> io::Result<()> {
+        write_command(&mut self.buf, &args);
+        if self.buf.len() >= AOF_REWRITE_BUFFER_SIZE {
+            self.writer.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Emits `name key items...`, splitting the items in several commands if there are many.
+    fn emit_batches<'a>(
+        &mut self,
+        name: &[u8],
+        key: &[u8],
+        items: impl Iterator<Item = Vec<&'a [u8]>>,
+    ) -> io::Result<()> {
+        let mut items = items.peekable();
+        while items.peek().is_some() {
+            let mut args = vec![name, key];
+            for item in items.by_ref().take(AOF_REWRITE_ITEMS_PER_CMD) {
+                args.extend(item);
+            }
+            self.emit(command(&args))?;
+        }
+        Ok(())
+    }
+
+    fn rewrite_object(&mut self, key: &[u8], value: &Object) -> io::Result<()> {
+        match value {
+            Object::String(s) => self.emit(command(&[b"SET", key, s])),
+            Object::List(list) => {
+                self.emit_batches(b"RPUSH", key, list.iter().map(|item| vec![&item[..]]))
+            }
+            Object::Hash(hash) => self.emit_batches(
+                b"HSET",
+                key,
+                hash.iter()
+                    .map(|(field, value)| vec![&field[..], &value[..]]),
+            ),
+            Object::Set(set) => {
+                let members: Vec<Bytes> = set.iter().map(|member| member.to_owned()).collect();
+                self.emit_batches(b"SADD", key, members.iter().map(|m| vec![&m[..]]))
+            }
+            Object::SortedSet(zset) => {
+                let items: Vec<(String, &Bytes)> = zset
+                    .iter()
+                    .map(|(member, score)| (score.to_string(), member))
+                    .collect();
+                self.emit_batches(
+                    b"ZADD",
+                    key,
+                    items
+                        .iter()
+                        .map(|(score, member)| vec![score.as_bytes(), &member[..]]),
+                )
+            }
+            Object::Stream(stream) => self.rewrite_stream(key, stream),
+        }
+    }
+
+    fn rewrite_stream(&mut self, key: &[u8], stream: &Stream) -> io::Result<()> {
+        if stream.is_empty() {
+            // an empty stream is created by adding an entry that is trimmed right away, XSETID
+            // then restores the last ID.
+            let id = stream.last_id().max(StreamId::new(0, 1)).to_string();
+            self.emit(command(&[
+                b"XADD",
+                key,
+                b"MAXLEN",
+                b"0",
+                id.as_bytes(),
+                b"x",
+                b"y",
+            ]))?;
+        }
+        for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX) {
+            let id = id.to_string();
+            let mut args = vec![&b"XADD"[..], key, id.as_bytes()];
+            for (field, value) in fields.iter() {
+                args.push(field);
+                args.push(value);
+            }
+            self.emit(command(&args))?;
+        }
+        self.emit(command(&[
+            b"XSETID",
+            key,
+            stream.last_id().to_string().as_bytes(),
+            b"ENTRIESADDED",
+            stream.entries_added().to_string().as_bytes(),
+            b"MAXDELETEDID",
+            stream.max_deleted_id().to_string().as_bytes(),
+        ]))?;
+
+        for (name, group) in stream.groups() {
+            let last_id = group.last_id.to_string();
+            let mut args = vec![&b"XGROUP"[..], b"CREATE", key, name, last_id.as_bytes()];
+            let entries_read = group.entries_read.map(|n| n.to_string());
+            if let Some(entries_read) = &entries_read {
+                args.extend([&b"ENTRIESREAD"[..], entries_read.as_bytes()]);
+            }
+            self.emit(command(&args))?;
+
+            for consumer in group.consumers.keys() {
+                self.emit(command(&[
+                    b"XGROUP",
+                    b"CREATECONSUMER",
+                    key,
+                    name,
+                    consumer,
+                ]))?;
+            }
+            for (id, entry) in group.pending.iter() {
+                self.emit(claim_command(key, name, *id, entry))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the commands of the AOF at `path` with the session. A command cut short at the end of
+/// the file, which happens when the server stops in the middle of a write, is discarded along
+/// with the transaction it belongs to, and the file is truncated to the last complete command.
+pub fn replay(path: &Path, session: &mut Session) -> Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(&file);
+
+    // the offset right after the last command that is not part of an unfinished transaction.
+    let mut valid = 0;
+    let truncated = loop {
+        let offset = reader.stream_position()?;
+        if offset == len {
+            break session.transaction.is_some();
+        }
+        let request = match read_command(&mut reader) {
+            Ok(request) => request,
+            Err(Error::Eof) => break true,
+            Err(Error::InvalidAof(msg)) => {
+                return Err(Error::InvalidAof(format!("{} at offset {}", msg, offset)))
+            }
+            Err(err) => return Err(err),
+        };
+
+        if let Value::Err(_, msg) = session.handle_request(Value::Array(request)) {
+            log::warn!("Error replaying the command at offset {}: {}", offset, msg);
+        }
+        if session.transaction.is_none() {
+            valid = reader.stream_position()?;
+        }
+    };
+
+    if truncated {
+        log::warn!(
+            "The AOF ends with an incomplete command, truncating it from {} to {} bytes",
+            len,
+            valid
+        );
+        file.set_len(valid)?;
+    }
+    Ok(())
+}
+
+/// Reads a command, an array of bulk strings. Fails with [`Error::Eof`] if the command is cut
+/// short by the end of the file.
+fn read_command(reader: &mut impl BufRead) -> Result<Vec<Value>> {
+    let len = read_length(reader, b'*')?;
+    let mut args = Vec::with_capacity(len.min(1024));
+    for _ in 0..len {
+        let len = read_length(reader, b'$')?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(Error::InvalidAof("bulk string not terminated".to_string()));
+        }
+        arg.truncate(len);
+        args.push(Value::Blob(Bytes::from(arg)));
+    }
+    if args.is_empty() {
+        return Err(Error::InvalidAof("empty command".to_string()));
+    }
+    Ok(args)
+}
+
+/// Reads a line made of the `prefix` byte followed by a length.
+fn read_length(reader: &mut impl BufRead, prefix: u8) -> Result<usize> {
+    let mut line = vec![];
+    reader.read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(Error::Eof);
+    }
+    if !line.ends_with(b"\r\n") {
+        return Err(Error::InvalidAof("line not terminated by CRLF".to_string()));
+    }
+    line.truncate(line.len() - 2);
+    if line.first() != Some(&prefix) {
+        return Err(Error::InvalidAof(format!(
+            "expected '{}', got '{}'",
+            prefix as char,
+            String::from_utf8_lossy(&line)
+        )));
+    }
+    std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| {
+            Error::InvalidAof(format!(
+                "invalid length in '{}'",
+                String::from_utf8_lossy(&line)
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::command::tests::request;
+    use crate::db::{Database, SessionFactory};
+    use std::thread;
+
+    fn temp_config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("redirs-aof-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Config {
+            dir: dir.to_str().unwrap().to_string(),
+            appendonly: true,
+            ..Config::default()
+        }
+    }
+
+    fn run_all(factory: &SessionFactory, commands: &[&[&str]]) -> Vec<Value> {
+        let mut session = factory.create_session();
+        commands
+            .iter()
+            .map(|args| session.handle_request(request(args)))
+            .collect()
+    }
+
+    /// Reads back everything written by `test_log_and_rewrite`, leaving out what depends on the
+    /// current time.
+    fn dump(factory: &SessionFactory) -> Vec<Value> {
+        run_all(
+            factory,
+            &[
+                &["GET", "s"],
+                &["PEXPIRETIME", "s"],
+                &["PEXPIRETIME", "t"],
+                &["EXISTS", "gone"],
+                &["LRANGE", "l", "0", "-1"],
+                &["HGET", "h", "f"],
+                &["SCARD", "set"],
+                &["ZRANGE", "z", "0", "-1", "WITHSCORES"],
+                &["XRANGE", "st", "-", "+"],
+                &["XPENDING", "st", "g"],
+                &["XINFO", "GROUPS", "st"],
+                &["XINFO", "GROUPS", "empty"],
+                &["SELECT", "2"],
+                &["GET", "other"],
+                &["GET", "x"],
+                &["LRANGE", "l2", "0", "-1"],
+            ],
+        )
+    }
+
+    #[test]
+    fn test_log_and_rewrite() {
+        let config = temp_config("rewrite");
+        let factory = SessionFactory::new(Database::new(&config));
+        assert!(!factory.load().unwrap());
+
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        run(&["SET", "s", "hello"]);
+        run(&["SET", "t", "v", "EX", "100"]);
+        run(&["EXPIRE", "s", "1000"]);
+        run(&["SET", "gone", "v"]);
+        run(&["EXPIRE", "gone", "-1"]);
+        run(&["SET", "s", "not logged", "NX"]);
+        run(&["RPUSH", "l", "a", "b", "c"]);
+        run(&["BLPOP", "l", "0"]);
+        run(&["HSET", "h", "f", "v"]);
+        run(&["SADD", "set", "a", "b", "c"]);
+        run(&["SPOP", "set"]);
+        run(&["ZADD", "z", "1.5", "a", "2", "b"]);
+        run(&["BZPOPMIN", "z", "0"]);
+        run(&["XADD", "st", "*", "f", "1"]);
+        run(&["XADD", "st", "*", "f", "2"]);
+        run(&["XGROUP", "CREATE", "st", "g", "0"]);
+        run(&["XGROUP", "CREATE", "empty", "g", "$", "MKSTREAM"]);
+        run(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "alice",
+            "COUNT",
+            "1",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "st",
+            ">",
+        ]);
+        run(&["XAUTOCLAIM", "st", "g", "bob", "0", "0"]);
+        run(&["SELECT", "2"]);
+        run(&["SET", "other", "1"]);
+        run(&["MULTI"]);
+        run(&["SET", "x", "1"]);
+        run(&["RPUSH", "l2", "q"]);
+        run(&["EXEC"]);
+        let expected = dump(&factory);
+        drop(session);
+
+        let contents = fs::read(Path::new(&config.dir).join("appendonly.aof")).unwrap();
+        let contents = String::from_utf8_lossy(&contents);
+        for logged in [
+            "PEXPIREAT",
+            "LPOP",
+            "SREM",
+            "ZPOPMIN",
+            "XCLAIM",
+            "MULTI",
+            "EXEC",
+        ] {
+            assert!(contents.contains(logged), "{} is not logged", logged);
+        }
+        for rewritten in [
+            "BLPOP",
+            "SPOP",
+            "BZPOPMIN",
+            "XREADGROUP",
+            "XAUTOCLAIM",
+            "not logged",
+        ] {
+            assert!(!contents.contains(rewritten), "{} is logged", rewritten);
+        }
+
+        let factory = SessionFactory::new(Database::new(&config));
+        assert!(factory.load().unwrap());
+        assert_eq!(expected, dump(&factory));
+
+        let rewrite = run_all(&factory, &[&["BGREWRITEAOF"], &["SET", "after", "1"]]);
+        assert_eq!(
+            Value::Simple("Background append only file rewriting started".into()),
+            rewrite[0]
+        );
+        while factory.database().aof_rewrite_in_progress() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        run_all(&factory, &[&["SET", "after", "2"]]);
+
+        let factory = SessionFactory::new(Database::new(&config));
+        assert!(factory.load().unwrap());
+        assert_eq!(expected, dump(&factory));
+        assert_eq!(
+            vec![Value::Blob("2".into())],
+            run_all(&factory, &[&["GET", "after"]])
+        );
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_truncated() {
+        let config = temp_config("truncated");
+        let path = Path::new(&config.dir).join(&config.appendfilename);
+        let set = |key: &str| format!("*3\r\n$3\r\nSET\r\n$1\r\n{}\r\n$1\r\n1\r\n", key);
+
+        // the transaction is not complete, so it's dropped along with the truncated command.
+        let valid = set("a");
+        let contents = format!(
+            "{}*1\r\n$5\r\nMULTI\r\n{}*3\r\n$3\r\nSET\r\n$1\r\nc",
+            valid,
+            set("b")
+        );
+        fs::write(&path, contents).unwrap();
+
+        let factory = SessionFactory::new(Database::new(&config));
+        assert!(factory.load().unwrap());
+        let replies = run_all(
+            &factory,
+            &[&["GET", "a"], &["GET", "b"], &["SET", "d", "1"]],
+        );
+        assert_eq!(Value::Blob("1".into()), replies[0]);
+        assert_eq!(Value::Null, replies[1]);
+        let contents = fs::read(&path).unwrap();
+        let select = "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n";
+        assert_eq!(
+            format!("{}{}{}", valid, select, set("d")).as_bytes(),
+            &contents[..]
+        );
+
+        // a corrupted command in the middle of the file can't be skipped.
+        fs::write(&path, format!("{}+garbage\r\n{}", valid, set("b"))).unwrap();
+        let factory = SessionFactory::new(Database::new(&config));
+        assert!(matches!(factory.load(), Err(Error::InvalidAof(_))));
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+    time::Instant,
+};
+
+use crate::value::{Bytes, Value};
+
+/// Called back when a parked client is woken up, for the command it is blocked on to be retried.
+pub type OnReady = Arc<dyn Fn() + Send + Sync>;
+
+/// A client blocked on one or more keys, waiting for another client to make them ready.
+#[derive(Default)]
+pub struct Waiter {
+    state: Mutex<WaiterState>,
+    cond: Condvar,
+    /// Set for the clients parked without a thread waiting for them.
+    on_ready: Option<OnReady>,
+}
+
+impl std::fmt::Debug for Waiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Waiter")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct WaiterState {
+    ready: bool,
+    /// Set once the client is gone, the command giving up instead of taking what's ready.
+    aborted: bool,
+}
+
+impl Waiter {
+    fn wake(&self) {
+        self.state.lock().unwrap().ready = true;
+        self.cond.notify_one();
+        if let Some(on_ready) = &self.on_ready {
+            on_ready();
+        }
+    }
+
+    fn abort(&self) {
+        self.state.lock().unwrap().aborted = true;
+        self.cond.notify_one();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.lock().unwrap().aborted
+    }
+
+    /// Returns whether the waiter was woken up since the last call, for the parked clients which
+    /// don't [`Waiter::wait`].
+    pub fn take_ready(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().ready)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().ready
+    }
+
+    /// Waits until the waiter is woken up, aborted, or the deadline is reached. Returns false if
+    /// it timed out without being woken up.
+    pub fn wait(&self, deadline: Option<Instant>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.ready && !state.aborted {
+            state = match deadline {
+                None => self.cond.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.cond.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+        state.ready = false;
+        true
+    }
+}
+
+/// The command a parked client is blocked on, retried once the client is woken up or its timeout
+/// expires. Meanwhile, the client doesn't hold a thread.
+pub struct Blocked {
+    pub keys: Vec<Bytes>,
+    pub waiter: Arc<Waiter>,
+    pub deadline: Option<Instant>,
+    /// The command and its arguments, set once the command returns.
+    pub request: Option<(String, Vec<Value>)>,
+}
+
+/// Shared by a session with its connection, for the connection to abort the command the client
+/// is blocked on once the client disconnects. Otherwise, the command would wait forever, or take
+/// an element pushed for the next client blocked on the key, only for it to be lost.
+#[derive(Debug, Default)]
+pub struct AbortHandle {
+    state: Mutex<AbortState>,
+}
+
+#[derive(Debug, Default)]
+struct AbortState {
+    aborted: bool,
+    /// The waiter of the last command of the client which blocked.
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl AbortHandle {
+    /// Aborts the command the client is blocked on, and the ones it would block on after.
+    pub fn abort(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.aborted = true;
+        if let Some(waiter) = &state.waiter {
+            waiter.abort();
+        }
+    }
+
+    /// Makes the waiter the one aborted, aborting it right away if the client is already gone.
+    pub fn watch(&self, waiter: &Arc<Waiter>) {
+        let mut state = self.state.lock().unwrap();
+        if state.aborted {
+            waiter.abort();
+        }
+        state.waiter = Some(waiter.clone());
+    }
+}
+
+/// Keeps track of the clients blocked on each key. The clients blocked on the same key are
+/// served in the order in which they blocked: a key becoming ready only wakes up the first of
+/// them, which passes the turn to the next one once it's done.
+#[derive(Debug, Default)]
+pub struct BlockingRegistry {
+    waiters: Mutex<HashMap<BlockedKey, VecDeque<Arc<Waiter>>>>,
+}
+
+/// A key of a database, identified by its index.
+type BlockedKey = (usize, Bytes);
+
+impl BlockingRegistry {
+    /// Blocks a new client on the keys of the database. A parked client is woken up by calling
+    /// `on_ready`, instead of a thread waiting for it.
+    pub fn register(&self, db: usize, keys: &[Bytes], on_ready: Option<OnReady>) -> Arc<Waiter> {
+        let waiter = Arc::new(Waiter {
+            on_ready,
+            ..Waiter::default()
+        });
+        let mut waiters = self.waiters.lock().unwrap();
+        for key in keys {
+            let queue = waiters.entry((db, key.clone())).or_default();
+            if !queue.iter().any(|w| Arc::ptr_eq(w, &waiter)) {
+                queue.push_back(waiter.clone());
+            }
+        }
+        waiter
+    }
+
+    pub fn unregister(&self, db: usize, keys: &[Bytes], waiter: &Arc<Waiter>) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for key in keys {
+            let entry = (db, key.clone());
+            if let Some(queue) = waiters.get_mut(&entry) {
+                queue.retain(|w| !Arc::ptr_eq(w, waiter));
+                if queue.is_empty() {
+                    waiters.remove(&entry);
+                }
+            }
+        }
+    }
+
+    /// Wakes up the first client blocked on the key, if any.
+    pub fn signal(&self, db: usize, key: &Bytes) {
+        let waiters = self.waiters.lock().unwrap();
+        if let Some(waiter) = waiters.get(&(db, key.clone())).and_then(|q| q.front()) {
+            waiter.wake();
+        }
+    }
+
+    /// Returns the number of clients blocked on any key.
+    pub fn blocked_clients(&self) -> usize {
+        let waiters = self.waiters.lock().unwrap();
+        let mut clients: Vec<*const Waiter> = waiters.values().flatten().map(Arc::as_ptr).collect();
+        clients.sort();
+        clients.dedup();
+        clients.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fifo_wakeup() {
+        let registry = BlockingRegistry::default();
+        let key = Bytes::from("k");
+        let first = registry.register(0, &[key.clone(), Bytes::from("other")], None);
+        let second = registry.register(0, std::slice::from_ref(&key), None);
+        assert_eq!(2, registry.blocked_clients());
+
+        let soon = || Some(Instant::now() + Duration::from_millis(10));
+        registry.signal(1, &key);
+        assert!(!first.wait(soon()));
+        registry.signal(0, &key);
+        assert!(first.wait(soon()));
+        assert!(!second.wait(soon()));
+
+        registry.unregister(0, &[key.clone(), Bytes::from("other")], &first);
+        registry.signal(0, &key);
+        assert!(second.wait(None));
+        registry.unregister(0, std::slice::from_ref(&key), &second);
+        assert_eq!(0, registry.blocked_clients());
+
+        // an aborted waiter stops waiting, even without a deadline.
+        let handle = AbortHandle::default();
+        let waiter = registry.register(0, std::slice::from_ref(&key), None);
+        handle.watch(&waiter);
+        handle.abort();
+        assert!(waiter.wait(None));
+        assert!(waiter.is_aborted());
+        let next = registry.register(0, std::slice::from_ref(&key), None);
+        handle.watch(&next);
+        assert!(next.is_aborted());
+    }
+}
@@ -0,0 +1,294 @@
+//! Cluster mode: the keys are split in 16384 hash slots, each served by one node of the cluster.
+//! A client sending a command to the wrong node is redirected with a MOVED error, and while a
+//! slot is moved to another node, the keys already moved are found with an ASK redirection.
+//!
+//! There is no cluster bus: the nodes don't exchange their configuration, every node has to be
+//! told about the other nodes with CLUSTER MEET and about the owner of every slot with CLUSTER
+//! ADDSLOTS and CLUSTER SETSLOT.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use rand::Rng;
+
+use crate::config::Config;
+
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// The CRC16 variant used by redis to hash the keys (XMODEM).
+fn crc16(buf: &[u8]) -> u16 {
+    buf.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Returns the hash slot of the key. When the key contains a non-empty `{hashtag}`, only the
+/// hashtag is hashed, so that related keys can be stored in the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|&c| c == b'{')
+        .and_then(|start| {
+            let end = key[start + 1..].iter().position(|&c| c == b'}')?;
+            Some(&key[start + 1..start + 1 + end])
+        })
+        .filter(|tag| !tag.is_empty())
+        .unwrap_or(key);
+    crc16(hashed) % CLUSTER_SLOTS
+}
+
+/// Generates a node id, 40 random hexadecimal characters like redis.
+fn new_node_id() -> String {
+    let mut rng = rand::thread_rng();
+    (0..20).fold(String::new(), |mut id, _| {
+        let _ = write!(id, "{:02x}", rng.gen::<u8>());
+        id
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ClusterNode {
+    /// Returns the address of the node, as sent in the redirections.
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Where a command about a slot is served.
+#[derive(Debug)]
+pub enum Route<'a> {
+    /// By this node.
+    Myself,
+    /// By this node, unless the keys were already moved to the given node.
+    Migrating(&'a ClusterNode),
+    /// By the node owning the slot, unless the client was redirected here with ASK.
+    Importing,
+    /// By another node.
+    Moved(&'a ClusterNode),
+    /// By no node at all.
+    Unassigned,
+}
+
+pub struct Cluster {
+    /// The known nodes, this node being the first one. Nodes are never removed, the slots refer
+    /// to them by index.
+    nodes: Vec<ClusterNode>,
+    slots: Vec<Option<usize>>,
+    /// The slots moved from this node, and the nodes they are moved to.
+    migrating: HashMap<u16, usize>,
+    /// The slots moved to this node, and the nodes they are moved from.
+    importing: HashMap<u16, usize>,
+}
+
+impl Cluster {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            nodes: vec![ClusterNode {
+                id: new_node_id(),
+                host: config.bind[0].clone(),
+                port: config.port,
+            }],
+            slots: vec![None; CLUSTER_SLOTS as usize],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[0]
+    }
+
+    pub fn nodes(&self) -> &[ClusterNode] {
+        &self.nodes
+    }
+
+    fn find(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    /// Adds a node, or updates its address if it's already known.
+    pub fn add_node(&mut self, node: ClusterNode) {
+        match self.find(&node.id) {
+            Some(index) => self.nodes[index] = node,
+            None => self.nodes.push(node),
+        }
+    }
+
+    pub fn owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots[slot as usize].map(|index| &self.nodes[index])
+    }
+
+    pub fn migrating(&self, slot: u16) -> Option<&ClusterNode> {
+        self.migrating.get(&slot).map(|&index| &self.nodes[index])
+    }
+
+    pub fn importing(&self, slot: u16) -> Option<&ClusterNode> {
+        self.importing.get(&slot).map(|&index| &self.nodes[index])
+    }
+
+    pub fn route(&self, slot: u16) -> Route<'_> {
+        match self.slots[slot as usize] {
+            Some(0) => match self.migrating(slot) {
+                Some(target) => Route::Migrating(target),
+                None => Route::Myself,
+            },
+            owner => match (self.importing(slot), owner) {
+                (Some(_), _) => Route::Importing,
+                (None, Some(owner)) => Route::Moved(&self.nodes[owner]),
+                (None, None) => Route::Unassigned,
+            },
+        }
+    }
+
+    /// Returns the number of slots with an owner.
+    pub fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// Assigns a slot to the node with the given id, or unassigns it with `None`. Returns false if
+    /// the node is unknown.
+    pub fn assign(&mut self, slot: u16, id: Option<&str>) -> bool {
+        let owner = match id {
+            Some(id) => match self.find(id) {
+                Some(index) => Some(index),
+                None => return false,
+            },
+            None => None,
+        };
+        self.slots[slot as usize] = owner;
+        // once assigned, the slot is no longer moved.
+        if owner == Some(0) {
+            self.importing.remove(&slot);
+        } else {
+            self.migrating.remove(&slot);
+        }
+        true
+    }
+
+    /// Starts moving a slot of this node to the node with the given id. Returns false if the node
+    /// is unknown.
+    pub fn set_migrating(&mut self, slot: u16, id: &str) -> bool {
+        match self.find(id) {
+            Some(index) => {
+                self.migrating.insert(slot, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts moving a slot to this node from the node with the given id. Returns false if the
+    /// node is unknown.
+    pub fn set_importing(&mut self, slot: u16, id: &str) -> bool {
+        match self.find(id) {
+            Some(index) => {
+                self.importing.insert(slot, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops moving the slot, in either direction.
+    pub fn set_stable(&mut self, slot: u16) {
+        self.migrating.remove(&slot);
+        self.importing.remove(&slot);
+    }
+
+    /// Returns the ranges of consecutive slots owned by the node at `index`.
+    pub fn slot_ranges(&self, index: usize) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = vec![];
+        for slot in 0..CLUSTER_SLOTS {
+            if self.slots[slot as usize] != Some(index) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+
+    /// Returns the slots being moved from this node, with the nodes they are moved to.
+    pub fn migrating_slots(&self) -> Vec<(u16, &ClusterNode)> {
+        self.sorted_slots(&self.migrating)
+    }
+
+    /// Returns the slots being moved to this node, with the nodes they are moved from.
+    pub fn importing_slots(&self) -> Vec<(u16, &ClusterNode)> {
+        self.sorted_slots(&self.importing)
+    }
+
+    fn sorted_slots(&self, slots: &HashMap<u16, usize>) -> Vec<(u16, &ClusterNode)> {
+        let mut slots: Vec<_> = slots
+            .iter()
+            .map(|(&slot, &index)| (slot, &self.nodes[index]))
+            .collect();
+        slots.sort_by_key(|(slot, _)| *slot);
+        slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(12182, key_hash_slot(b"foo"));
+        assert_eq!(5061, key_hash_slot(b"bar"));
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_hash_slot(b"user1000"), key_hash_slot(b"x{user1000}y"));
+        // an empty hashtag hashes the whole key, and only the first hashtag counts.
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % 16384);
+        assert_eq!(key_hash_slot(b"{bar"), key_hash_slot(b"foo{{bar}}zap"));
+        assert_eq!(key_hash_slot(b"{foo"), crc16(b"{foo") % 16384);
+    }
+
+    #[test]
+    fn test_route() {
+        let mut cluster = Cluster::new(&Config::default());
+        let myself = cluster.myself().id.clone();
+        cluster.add_node(ClusterNode {
+            id: "other".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 7001,
+        });
+        assert!(matches!(cluster.route(1), Route::Unassigned));
+
+        assert!(cluster.assign(1, Some(&myself)));
+        assert!(cluster.assign(2, Some(&myself)));
+        assert!(cluster.assign(3, Some("other")));
+        assert!(!cluster.assign(4, Some("unknown")));
+        assert!(matches!(cluster.route(1), Route::Myself));
+        assert!(matches!(cluster.route(3), Route::Moved(node) if node.port == 7001));
+        assert_eq!(vec![(1, 2)], cluster.slot_ranges(0));
+        assert_eq!(3, cluster.assigned_slots());
+
+        assert!(cluster.set_migrating(1, "other"));
+        assert!(matches!(cluster.route(1), Route::Migrating(node) if node.id == "other"));
+        assert!(cluster.assign(1, Some("other")));
+        assert!(matches!(cluster.route(1), Route::Moved(_)));
+
+        assert!(cluster.set_importing(3, "other"));
+        assert!(matches!(cluster.route(3), Route::Importing));
+        cluster.set_stable(3);
+        assert!(matches!(cluster.route(3), Route::Moved(_)));
+    }
+}
@@ -1,6 +1,26 @@
-use crate::value::{Bytes, Value};
+mod acl;
+mod cluster;
+mod expire;
+mod hash;
+mod keyspace;
+mod list;
+mod multi;
+mod pubsub;
+mod replication;
+mod script;
+mod server;
+mod set;
+mod sorted_set;
+mod stream;
+mod string;
 
-use super::Session;
+use std::time::{Duration, Instant};
+
+use crate::glob::glob_match;
+use crate::value::{format_double, Bytes, Protocol, Value};
+
+use super::blocking::Blocked;
+use super::{InternalDb, Session};
 
 pub type CommandFlag = &'static str;
 
@@ -11,64 +31,445 @@ pub const COMMAND_FLAG_SLOW: CommandFlag = "slow";
 pub const COMMAND_FLAG_FAST: CommandFlag = "fast";
 pub const COMMAND_FLAG_WRITE: CommandFlag = "write";
 pub const COMMAND_FLAG_CONNECTION: CommandFlag = "connection";
+pub const COMMAND_FLAG_KEYSPACE: CommandFlag = "keyspace";
+pub const COMMAND_FLAG_LIST: CommandFlag = "list";
+pub const COMMAND_FLAG_HASH: CommandFlag = "hash";
+pub const COMMAND_FLAG_SET: CommandFlag = "set";
+pub const COMMAND_FLAG_SORTEDSET: CommandFlag = "sortedset";
+pub const COMMAND_FLAG_STREAM: CommandFlag = "stream";
+pub const COMMAND_FLAG_DENYOOM: CommandFlag = "denyoom";
+pub const COMMAND_FLAG_BLOCKING: CommandFlag = "blocking";
+pub const COMMAND_FLAG_PUBSUB: CommandFlag = "pubsub";
+pub const COMMAND_FLAG_TRANSACTION: CommandFlag = "transaction";
+pub const COMMAND_FLAG_ADMIN: CommandFlag = "admin";
+pub const COMMAND_FLAG_SCRIPTING: CommandFlag = "scripting";
+
+pub type CommandResult = Result<Value, CommandError>;
 
-pub struct CommandSpec<'a> {
+/// Finds the keys of a command in its arguments, which don't include the command name.
+pub type GetKeys = fn(&[Value]) -> Vec<&[u8]>;
+
+pub struct CommandSpec {
     pub name: String,
+    /// Number of arguments, including the command name. A negative number `-n` means the
+    /// command accepts at least `n` arguments.
     pub args_len: i64,
     pub flags: Vec<CommandFlag>,
     pub first_key: i64,
     pub last_key: i64,
     pub key_step: i64,
-    pub handler: fn(&mut Session<'a>, Vec<Value>) -> Result<Value, String>,
+    /// Finds the keys of the commands whose keys can't be found with `first_key`, `last_key` and
+    /// `key_step`, like the ones preceded by their number.
+    pub get_keys: Option<GetKeys>,
+    pub handler: fn(&mut Session, Vec<Value>) -> CommandResult,
+}
+
+impl CommandSpec {
+    /// Returns the keys of the command, found with `get_keys` if the command has it, and with
+    /// `first_key`, `last_key` and `key_step` otherwise. The arguments don't include the command
+    /// name.
+    pub fn keys<'v>(&self, args: &'v [Value]) -> Vec<&'v [u8]> {
+        if let Some(get_keys) = self.get_keys {
+            return get_keys(args);
+        }
+        if self.first_key <= 0 {
+            return vec![];
+        }
+        let last_key = if self.last_key < 0 {
+            args.len() as i64 + 1 + self.last_key
+        } else {
+            self.last_key
+        };
+        (self.first_key..=last_key)
+            .step_by(self.key_step.max(1) as usize)
+            .filter_map(|index| arg_key(args.get(index as usize - 1)?))
+            .collect()
+    }
+
+    /// Checks whether the number of arguments (excluding the command name) is accepted.
+    pub fn check_arity(&self, args: usize) -> bool {
+        let total = args as i64 + 1;
+        if self.args_len >= 0 {
+            total == self.args_len
+        } else {
+            total >= -self.args_len
+        }
+    }
+}
+
+/// Error returned by the command handlers. It is sent to the client as an error reply.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// Generic error, sent with the `ERR` prefix.
+    Err(String),
+    /// Error with a specific prefix, like `WRONGTYPE`.
+    Code(&'static str, String),
+}
+
+impl CommandError {
+    pub fn wrongtype() -> Self {
+        Self::Code("WRONGTYPE", ERR_WRONGTYPE.to_string())
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(msg: &str) -> Self {
+        Self::Err(msg.to_string())
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        Self::Err(msg)
+    }
+}
+
+impl From<CommandError> for Value {
+    fn from(err: CommandError) -> Self {
+        match err {
+            CommandError::Err(msg) => Value::err(msg),
+            CommandError::Code(code, msg) => Value::Err(code.to_string(), msg),
+        }
+    }
 }
 
 const ERR_DB_INDEX: &str = "invalid DB index";
 const ERR_DB_OUTOFRANGE: &str = "DB index is out of range";
-const ERR_INVALID_KEY: &str = "invalid key";
-const ERR_INVALID_VAL: &str = "invalid value";
+const ERR_NOT_INTEGER: &str = "value is not an integer or out of range";
+const ERR_SYNTAX: &str = "syntax error";
+const ERR_WRONGTYPE: &str = "Operation against a key holding the wrong kind of value";
+const ERR_NO_SUCH_KEY: &str = "no such key";
+const ERR_INDEX_OUT_OF_RANGE: &str = "index out of range";
+const ERR_POSITIVE: &str = "value is out of range, must be positive";
+const ERR_OUT_OF_RANGE: &str = "value is out of range";
+const ERR_NOT_FLOAT: &str = "value is not a valid float";
+const ERR_OVERFLOW: &str = "increment or decrement would overflow";
+const ERR_NAN: &str = "increment would produce NaN or Infinity";
+const ERR_TIMEOUT_NEGATIVE: &str = "timeout is negative";
 
-pub fn get_commands<'a>() -> Vec<CommandSpec<'a>> {
-    vec![
+pub fn get_commands() -> Vec<CommandSpec> {
+    let mut commands = vec![
         CommandSpec {
             name: "COMMAND".to_string(),
-            args_len: 1,
+            args_len: -1,
             flags: vec![COMMAND_FLAG_READONLY, COMMAND_FLAG_RANDOM],
-            first_key: 1,
-            last_key: 1,
-            key_step: 1,
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            get_keys: None,
             handler: handle_command,
         },
         CommandSpec {
             name: "SELECT".to_string(),
-            args_len: 1,
+            args_len: 2,
             flags: vec![COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
             first_key: 0,
             last_key: 0,
             key_step: 0,
+            get_keys: None,
             handler: handle_select,
         },
         CommandSpec {
-            name: "GET".to_string(),
-            args_len: 2,
-            flags: vec![COMMAND_FLAG_READONLY, COMMAND_FLAG_RANDOM],
-            first_key: 1,
-            last_key: 1,
-            key_step: 1,
-            handler: handle_get,
+            name: "PING".to_string(),
+            args_len: -1,
+            flags: vec![COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            get_keys: None,
+            handler: handle_ping,
         },
         CommandSpec {
-            name: "SET".to_string(),
-            args_len: 2,
-            flags: vec![COMMAND_FLAG_WRITE, COMMAND_FLAG_STRING, COMMAND_FLAG_SLOW],
-            first_key: 1,
-            last_key: 1,
-            key_step: 1,
-            handler: handle_set,
+            name: "QUIT".to_string(),
+            args_len: -1,
+            flags: vec![COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            get_keys: None,
+            handler: handle_quit,
         },
-    ]
+        CommandSpec {
+            name: "HELLO".to_string(),
+            args_len: -1,
+            flags: vec![COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            get_keys: None,
+            handler: handle_hello,
+        },
+    ];
+    commands.extend(keyspace::get_commands());
+    commands.extend(expire::get_commands());
+    commands.extend(string::get_commands());
+    commands.extend(list::get_commands());
+    commands.extend(hash::get_commands());
+    commands.extend(set::get_commands());
+    commands.extend(sorted_set::get_commands());
+    commands.extend(stream::get_commands());
+    commands.extend(pubsub::get_commands());
+    commands.extend(multi::get_commands());
+    commands.extend(server::get_commands());
+    commands.extend(replication::get_commands());
+    commands.extend(cluster::get_commands());
+    commands.extend(script::get_commands());
+    commands.extend(acl::get_commands());
+    commands
+}
+
+/// Extracts the raw bytes of a string argument.
+fn arg_bytes(arg: Value) -> Result<Bytes, CommandError> {
+    match arg {
+        Value::Simple(s) | Value::Blob(s) => Ok(s),
+        _ => Err(ERR_SYNTAX.into()),
+    }
+}
+
+/// Parses an integer argument, failing with the same message as redis if it's not a valid
+/// 64 bit integer.
+fn arg_i64(arg: &Value) -> Result<i64, CommandError> {
+    match arg {
+        Value::Number(n) => Ok(*n),
+        Value::Simple(s) | Value::Blob(s) => std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| ERR_NOT_INTEGER.into()),
+        _ => Err(ERR_NOT_INTEGER.into()),
+    }
 }
 
-fn handle_command(session: &mut Session, _: Vec<Value>) -> Result<Value, String> {
+/// Returns the argument as an uppercase string, used for matching command options.
+fn arg_option(arg: &Value) -> String {
+    match arg {
+        Value::Simple(s) | Value::Blob(s) => String::from_utf8_lossy(s).to_ascii_uppercase(),
+        Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Returns the argument as a key, if it's a string.
+fn arg_key(arg: &Value) -> Option<&[u8]> {
+    match arg {
+        Value::Simple(key) | Value::Blob(key) => Some(key.as_slice()),
+        _ => None,
+    }
+}
+
+/// Returns the keys preceded by their number, which is the argument at `index`, as in LMPOP or
+/// ZUNION. An invalid number finds no keys, the command rejecting it anyway.
+fn numkeys_keys(args: &[Value], index: usize) -> Vec<&[u8]> {
+    match args.get(index).map(arg_i64) {
+        Some(Ok(numkeys)) if numkeys > 0 => args[index + 1..]
+            .iter()
+            .take(numkeys as usize)
+            .filter_map(arg_key)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Parses a floating point argument. Infinities are accepted, but NaN is not.
+fn arg_f64(arg: &Value) -> Result<f64, CommandError> {
+    match arg {
+        Value::Number(n) => Some(*n as f64),
+        Value::Simple(s) | Value::Blob(s) => std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok()),
+        _ => None,
+    }
+    .filter(|f| !f.is_nan())
+    .ok_or_else(|| ERR_NOT_FLOAT.into())
+}
+
+/// Parses the timeout of the blocking commands, given in seconds. Returns `None` for 0, which
+/// means blocking forever.
+fn parse_timeout(arg: &Value) -> Result<Option<Duration>, CommandError> {
+    let timeout = arg_f64(arg)
+        .ok()
+        .filter(|t| t.is_finite())
+        .ok_or("timeout is not a float or out of range")?;
+    if timeout < 0.0 {
+        return Err(ERR_TIMEOUT_NEGATIVE.into());
+    }
+    Ok(Some(Duration::from_secs_f64(timeout)).filter(|t| !t.is_zero()))
+}
+
+/// Runs `attempt` against the selected database until it returns a reply, blocking the client on
+/// the keys in between. Returns a null reply once the timeout expires.
+///
+/// While the client is blocked, a key holding the wrong type doesn't fail the command: the client
+/// keeps waiting, like redis does. Inside a transaction, the command never blocks. Once the
+/// client is gone, aborted with [`Session::abort_handle`], the command returns a null reply
+/// without taking anything.
+///
+/// The clients of the event loop don't wait on their thread: they are parked, the command
+/// returning right away, to be retried once the client is woken up or the timeout expires.
+fn block_on(
+    session: &mut Session,
+    keys: &[Bytes],
+    timeout: Option<Duration>,
+    mut attempt: impl FnMut(&mut InternalDb) -> Result<Option<Value>, CommandError>,
+) -> CommandResult {
+    if let Some(blocked) = session.blocked.take() {
+        return retry_blocked(session, keys, blocked, attempt);
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut db = session.lock_db();
+    if let Some(reply) = attempt(&mut db)? {
+        return Ok(reply);
+    }
+    if session.executing {
+        return Ok(Value::Null);
+    }
+
+    let waiter = db.block(keys, session.on_ready());
+    if session.on_ready().is_some() {
+        drop(db);
+        session.blocked = Some(Blocked {
+            keys: keys.to_vec(),
+            waiter,
+            deadline,
+            request: None,
+        });
+        return Ok(Value::Null);
+    }
+    session.abort_handle.watch(&waiter);
+    loop {
+        // let the transactions run while the client is blocked.
+        drop(db);
+        session.suspend();
+        let woken = waiter.wait(deadline);
+        session.resume();
+        db = session.lock_db();
+
+        // the client is gone: what's ready is left to the next client blocked on the keys.
+        if waiter.is_aborted() {
+            db.unblock(keys, &waiter);
+            drop(db);
+            session.propagate(vec![]);
+            return Ok(Value::Null);
+        }
+
+        let reply = match attempt(&mut db) {
+            Err(CommandError::Code("WRONGTYPE", _)) => None,
+            Err(err) => {
+                db.unblock(keys, &waiter);
+                return Err(err);
+            }
+            Ok(reply) => reply,
+        };
+        if let Some(reply) = reply {
+            db.unblock(keys, &waiter);
+            return Ok(reply);
+        }
+        if !woken {
+            db.unblock(keys, &waiter);
+            drop(db);
+            // the keys modified while the client was blocked are not its doing.
+            session.propagate(vec![]);
+            return Ok(Value::Null);
+        }
+    }
+}
+
+/// Tries again the command a parked client is blocked on, see [`block_on`]. The client stays
+/// parked until there is something to reply.
+fn retry_blocked(
+    session: &mut Session,
+    keys: &[Bytes],
+    blocked: Blocked,
+    mut attempt: impl FnMut(&mut InternalDb) -> Result<Option<Value>, CommandError>,
+) -> CommandResult {
+    let mut db = session.lock_db();
+    blocked.waiter.take_ready();
+    let reply = match attempt(&mut db) {
+        Err(CommandError::Code("WRONGTYPE", _)) => None,
+        Err(err) => {
+            db.unblock(keys, &blocked.waiter);
+            return Err(err);
+        }
+        Ok(reply) => reply,
+    };
+    if let Some(reply) = reply {
+        db.unblock(keys, &blocked.waiter);
+        return Ok(reply);
+    }
+    if blocked
+        .deadline
+        .is_some_and(|deadline| deadline <= Instant::now())
+    {
+        db.unblock(keys, &blocked.waiter);
+        drop(db);
+        session.propagate(vec![]);
+        return Ok(Value::Null);
+    }
+    drop(db);
+    session.blocked = Some(blocked);
+    Ok(Value::Null)
+}
+
+/// Options shared by the SCAN family of commands.
+struct ScanArgs {
+    cursor: u64,
+    pattern: Option<Bytes>,
+    count: usize,
+    novalues: bool,
+}
+
+impl ScanArgs {
+    /// Parses `cursor [MATCH pattern] [COUNT count]`, plus the flags in `extra` that only some
+    /// commands in the family accept.
+    fn parse(mut args: impl Iterator<Item = Value>, extra: &[&str]) -> Result<Self, CommandError> {
+        let cursor = match args.next() {
+            Some(Value::Simple(s)) | Some(Value::Blob(s)) => std::str::from_utf8(&s)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or("invalid cursor")?,
+            Some(Value::Number(n)) if n >= 0 => n as u64,
+            _ => return Err("invalid cursor".into()),
+        };
+
+        let mut scan = Self {
+            cursor,
+            pattern: None,
+            count: 10,
+            novalues: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg_option(&arg).as_str() {
+                "MATCH" => scan.pattern = Some(arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?),
+                "COUNT" => {
+                    let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                    if count < 1 {
+                        return Err(ERR_SYNTAX.into());
+                    }
+                    scan.count = count as usize;
+                }
+                "NOVALUES" if extra.contains(&"NOVALUES") => scan.novalues = true,
+                _ => return Err(ERR_SYNTAX.into()),
+            }
+        }
+        Ok(scan)
+    }
+
+    fn matches(&self, key: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob_match(pattern, key, false),
+            None => true,
+        }
+    }
+
+    fn reply(cursor: u64, items: Vec<Value>) -> Value {
+        Value::Array(vec![
+            Value::Blob(Bytes::from(&cursor.to_string())),
+            Value::Array(items),
+        ])
+    }
+}
+
+fn handle_command(session: &mut Session, _: Vec<Value>) -> CommandResult {
     Ok(Value::Array(
         session
             .handlers
@@ -80,7 +481,9 @@ fn handle_command(session: &mut Session, _: Vec<Value>) -> Result<Value, String>
                     Value::Array(
                         spec.flags
                             .iter()
-                            .map(|flag| Value::Simple(Bytes::from(*flag)))
+                            .copied()
+                            .chain(spec.get_keys.map(|_| "movablekeys"))
+                            .map(|flag| Value::Simple(Bytes::from(flag)))
                             .collect(),
                     ),
                     Value::Number(spec.first_key),
@@ -92,7 +495,7 @@ fn handle_command(session: &mut Session, _: Vec<Value>) -> Result<Value, String>
     ))
 }
 
-fn handle_select(session: &mut Session, args: Vec<Value>) -> Result<Value, String> {
+fn handle_select(session: &mut Session, args: Vec<Value>) -> CommandResult {
     let mut args = args.into_iter();
 
     let target_db = args
@@ -106,61 +509,252 @@ fn handle_select(session: &mut Session, args: Vec<Value>) -> Result<Value, Strin
             .map_err(|_| ERR_DB_OUTOFRANGE.to_string())?
             .parse::<i64>()
             .map_err(|_| ERR_DB_INDEX.to_string())?,
-        _ => return Err(ERR_DB_INDEX.to_string()),
+        _ => return Err(ERR_DB_INDEX.into()),
     };
 
+    if target_db != 0 && session.db.cluster().is_some() {
+        return Err("SELECT is not allowed in cluster mode".into());
+    }
     session.selected_db = session.db.get(target_db).ok_or(ERR_DB_OUTOFRANGE)?;
 
     Ok(Value::Simple("OK".into()))
 }
 
-fn handle_get(session: &mut Session, args: Vec<Value>) -> Result<Value, String> {
-    let mut args = args.into_iter();
-    let key = args
-        .next()
-        .ok_or("wrong number of arguments for 'get' command")?;
-
-    let key = match key {
-        Value::Simple(s) | Value::Blob(s) => s,
-        _ => return Err(ERR_INVALID_KEY.to_string()),
-    };
+/// Implements `PING [message]`. While subscribed with RESP2, the reply is an array like the
+/// pub/sub messages, so that it can be told apart from them.
+fn handle_ping(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if args.len() > 1 {
+        return Err("wrong number of arguments for 'ping' command".into());
+    }
+    let message = args.into_iter().next().map(arg_bytes).transpose()?;
+    if session.subscriptions.is_subscribed() && session.protocol() == Protocol::Resp2 {
+        return Ok(Value::Array(vec![
+            Value::Blob("pong".into()),
+            Value::Blob(message.unwrap_or_else(|| "".into())),
+        ]));
+    }
+    Ok(message
+        .map(Value::Blob)
+        .unwrap_or_else(|| Value::Simple("PONG".into())))
+}
 
-    Ok(session
-        .selected_db
-        .read()
-        .unwrap()
-        .storage
-        .get(&key)
-        .map(|v| Value::Blob(v.clone()))
-        .unwrap_or(Value::Null))
+fn handle_quit(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    session.quit = true;
+    Ok(Value::Simple("OK".into()))
 }
 
-fn handle_set(session: &mut Session, args: Vec<Value>) -> Result<Value, String> {
+/// Implements `HELLO [protover [AUTH username password] [SETNAME clientname]]`, which switches
+/// the protocol of the client, authenticating it and naming it at the same time. Replies with
+/// the properties of the server.
+fn handle_hello(session: &mut Session, args: Vec<Value>) -> CommandResult {
     let mut args = args.into_iter();
-    let key = args
-        .next()
-        .ok_or("wrong number of arguments for 'set' command")?;
-
-    let key = match key {
-        Value::Simple(s) | Value::Blob(s) => s,
-        _ => return Err(ERR_INVALID_KEY.to_string()),
+    let protocol = match args.next() {
+        Some(version) => match arg_i64(&version)
+            .map_err(|_| "Protocol version is not an integer or out of range")?
+        {
+            2 => Protocol::Resp2,
+            3 => Protocol::Resp3,
+            _ => {
+                return Err(CommandError::Code(
+                    "NOPROTO",
+                    "unsupported protocol version".to_string(),
+                ))
+            }
+        },
+        None => session.protocol(),
     };
 
-    let value = args
-        .next()
-        .ok_or("wrong number of arguments for 'set' command")?;
+    let mut auth = None;
+    let mut name = None;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "AUTH" => {
+                let (user, password) = args.next().zip(args.next()).ok_or_else(|| {
+                    format!("Syntax error in HELLO option '{}'", arg_option(&arg))
+                })?;
+                let user = String::from_utf8_lossy(&arg_bytes(user)?).to_string();
+                auth = Some((user, arg_bytes(password)?));
+            }
+            "SETNAME" => {
+                let value = args.next().ok_or_else(|| {
+                    format!("Syntax error in HELLO option '{}'", arg_option(&arg))
+                })?;
+                name = Some(arg_bytes(value)?);
+            }
+            _ => {
+                return Err(format!(
+                    "Syntax error in HELLO option '{}'",
+                    String::from_utf8_lossy(&arg_bytes(arg)?)
+                )
+                .into())
+            }
+        }
+    }
 
-    let value = match value {
-        Value::Simple(s) | Value::Blob(s) => s,
-        _ => return Err(ERR_INVALID_VAL.to_string()),
+    match auth {
+        Some((user, password)) => acl::authenticate(session, user, &password)?,
+        None if !session.authenticated => {
+            return Err(CommandError::Code(
+                "NOAUTH",
+                "HELLO must be called with the client already authenticated, otherwise the \
+                 HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client \
+                 and select the RESP protocol version at the same time"
+                    .to_string(),
+            ))
+        }
+        None => {}
+    }
+    if let Some(name) = name {
+        if name.iter().any(|c| !(b'!'..=b'~').contains(c)) {
+            return Err(
+                "Client names cannot contain spaces, newlines or special characters.".into(),
+            );
+        }
+        session.name =
+            Some(String::from_utf8_lossy(&name).to_string()).filter(|name| !name.is_empty());
+    }
+    session.subscriber.set_protocol(protocol);
+
+    let field = |name: &str, value: Value| (Value::Blob(name.into()), value);
+    let blob = |value: &str| Value::Blob(value.into());
+    let mode = if session.db.cluster().is_some() {
+        "cluster"
+    } else {
+        "standalone"
+    };
+    let role = if session.db.is_replica() {
+        "replica"
+    } else {
+        "master"
+    };
+    let version = match protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
     };
+    Ok(Value::Map(vec![
+        field("server", blob("redis")),
+        field("version", blob(server::REDIS_VERSION)),
+        field("proto", Value::Number(version)),
+        field("id", Value::Number(session.subscriber.id() as i64)),
+        field("mode", blob(mode)),
+        field("role", blob(role)),
+        field("modules", Value::Array(vec![])),
+    ]))
+}
 
-    session
-        .selected_db
-        .write()
-        .unwrap()
-        .storage
-        .insert(key, value);
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{
+        config::Config,
+        db::{Database, SessionFactory},
+        value::Value,
+    };
 
-    Ok(Value::Simple("OK".into()))
+    /// Builds a request the same way a client would send it.
+    pub fn request(args: &[&str]) -> Value {
+        Value::Array(args.iter().map(|s| Value::Blob((*s).into())).collect())
+    }
+
+    pub fn session_factory() -> SessionFactory {
+        SessionFactory::new(Database::new(&Config {
+            port: 5101,
+            databases: 16,
+            ..Config::default()
+        }))
+    }
+
+    #[test]
+    fn test_arity() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        assert_eq!(
+            Value::err("wrong number of arguments for 'get' command"),
+            session.handle_request(request(&["GET"]))
+        );
+        assert_eq!(
+            Value::err("wrong number of arguments for 'set' command"),
+            session.handle_request(request(&["set", "a"]))
+        );
+        assert_eq!(
+            Value::Simple("OK".into()),
+            session.handle_request(request(&["SET", "a", "b"]))
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let factory = SessionFactory::new(Database::new(&Config {
+            databases: 100,
+            ..Config::default()
+        }));
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        assert_eq!(Value::Simple("OK".into()), run(&["SELECT", "99"]));
+        assert_eq!(
+            Value::err("DB index is out of range"),
+            run(&["SELECT", "100"])
+        );
+    }
+
+    #[test]
+    fn test_hello() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let messages = session.take_messages().unwrap();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            Value::Err(
+                "NOPROTO".to_string(),
+                "unsupported protocol version".to_string()
+            ),
+            run(&["HELLO", "4"])
+        );
+        assert_eq!(
+            Value::Err(
+                "WRONGPASS".to_string(),
+                "invalid username-password pair or user is disabled.".to_string()
+            ),
+            run(&["HELLO", "3", "AUTH", "nobody", "secret"])
+        );
+        assert_eq!(
+            Value::err("Client names cannot contain spaces, newlines or special characters."),
+            run(&["HELLO", "3", "SETNAME", "my client"])
+        );
+        assert_eq!(
+            Value::err("Syntax error in HELLO option 'SETNAME'"),
+            run(&["HELLO", "3", "SETNAME"])
+        );
+
+        let hello = match run(&["HELLO", "3", "SETNAME", "myclient"]) {
+            Value::Map(hello) => hello,
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert!(hello.contains(&(Value::Blob("proto".into()), Value::Number(3))));
+        run(&["HSET", "h", "f", "v"]);
+        assert_eq!(
+            Value::Map(vec![(Value::Blob("f".into()), Value::Blob("v".into()))]),
+            run(&["HGETALL", "h"])
+        );
+        assert_eq!(Value::Null, run(&["GET", "missing"]));
+
+        // the subscribers of the RESP3 clients receive pushes, and can still run any command.
+        run(&["SUBSCRIBE", "a", "b"]);
+        assert_eq!(
+            b">3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n".to_vec(),
+            messages.try_recv().unwrap()
+        );
+        assert_eq!(Value::Null, run(&["GET", "missing"]));
+
+        match run(&["HELLO", "2"]) {
+            Value::Array(hello) => assert_eq!(14, hello.len()),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        run(&["UNSUBSCRIBE"]);
+        assert_eq!(
+            Value::Array(vec![Value::Blob("f".into()), Value::Blob("v".into())]),
+            run(&["HGETALL", "h"])
+        );
+        assert_eq!(Some("myclient"), session.name.as_deref());
+    }
 }
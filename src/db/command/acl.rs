@@ -0,0 +1,459 @@
+use crate::db::acl::{categories, category_commands, generate_password, DEFAULT_USER};
+use crate::db::Session;
+use crate::value::Value;
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_ADMIN,
+    COMMAND_FLAG_CONNECTION, COMMAND_FLAG_FAST, COMMAND_FLAG_SLOW, ERR_POSITIVE, ERR_SYNTAX,
+};
+
+const ERR_NO_ACL_FILE: &str = "This Redis instance is not configured to use an ACL file. You may \
+                               want to specify users via the ACL SETUSER command and then issue \
+                               a CONFIG REWRITE (assuming you have a Redis configuration file \
+                               set) in order to store users in the Redis configuration.";
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: flags.to_vec(),
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        spec(
+            "AUTH",
+            -2,
+            &[COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
+            handle_auth,
+        ),
+        spec(
+            "ACL",
+            -2,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_acl,
+        ),
+    ]
+}
+
+fn arg_string(arg: Value) -> Result<String, CommandError> {
+    Ok(String::from_utf8_lossy(&arg_bytes(arg)?).to_string())
+}
+
+/// Implements `AUTH [username] password`. Without a username, the client authenticates as the
+/// default user, like with the `requirepass` of the older versions of redis.
+fn handle_auth(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let (name, password) = match <[Value; 2]>::try_from(args) {
+        Ok([name, password]) => (arg_string(name)?, arg_bytes(password)?),
+        Err(args) if args.len() == 1 => {
+            let acl = session.db.acl();
+            if acl.user(DEFAULT_USER).is_some_and(|user| user.nopass()) {
+                return Err(
+                    "AUTH <password> called without any password configured for the \
+                            default user. Are you sure your configuration is correct?"
+                        .into(),
+                );
+            }
+            let password = arg_bytes(args.into_iter().next().unwrap())?;
+            (DEFAULT_USER.to_string(), password)
+        }
+        Err(_) => return Err(ERR_SYNTAX.into()),
+    };
+
+    authenticate(session, name, &password)?;
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Authenticates the client as the user, as done by AUTH and HELLO. The failures are logged.
+pub(super) fn authenticate(
+    session: &mut Session,
+    name: String,
+    password: &[u8],
+) -> Result<(), CommandError> {
+    if session.db.acl().authenticate(&name, password).is_none() {
+        let client_info = session.client_info();
+        session
+            .db
+            .acl_mut()
+            .log("auth", "toplevel", "AUTH".to_string(), &name, client_info);
+        return Err(CommandError::Code(
+            "WRONGPASS",
+            "invalid username-password pair or user is disabled.".to_string(),
+        ));
+    }
+    session.user = Some(name);
+    session.authenticated = true;
+    Ok(())
+}
+
+/// Implements the `ACL` subcommands.
+fn handle_acl(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let args: Vec<Value> = args.collect();
+    let arity = |ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(CommandError::from(format!(
+                "wrong number of arguments for 'acl|{}' command",
+                subcommand.to_lowercase()
+            )))
+        }
+    };
+
+    match subcommand.as_str() {
+        "SETUSER" => {
+            arity(!args.is_empty())?;
+            let mut args = args.into_iter();
+            let name = arg_string(args.next().unwrap())?;
+            let rules = args.map(arg_string).collect::<Result<Vec<_>, _>>()?;
+            session.db.acl_mut().set_user(&name, &rules)?;
+            Ok(Value::Simple("OK".into()))
+        }
+        "GETUSER" => {
+            arity(args.len() == 1)?;
+            let name = arg_string(args.into_iter().next().unwrap())?;
+            let acl = session.db.acl();
+            Ok(acl.user(&name).map_or(Value::Null, |user| user.to_value()))
+        }
+        "DELUSER" => {
+            arity(!args.is_empty())?;
+            let names = args
+                .into_iter()
+                .map(arg_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            if names.iter().any(|name| name == DEFAULT_USER) {
+                return Err("The 'default' user cannot be removed".into());
+            }
+            let mut acl = session.db.acl_mut();
+            let deleted = names.iter().filter(|name| acl.delete_user(name)).count();
+            Ok(Value::Number(deleted as i64))
+        }
+        "LIST" | "USERS" => {
+            arity(args.is_empty())?;
+            let acl = session.db.acl();
+            Ok(Value::Array(
+                acl.users()
+                    .map(|user| match subcommand.as_str() {
+                        "LIST" => Value::Blob(user.describe().as_str().into()),
+                        _ => Value::Blob(user.name.as_str().into()),
+                    })
+                    .collect(),
+            ))
+        }
+        "WHOAMI" => {
+            arity(args.is_empty())?;
+            let name = session.user.as_deref().unwrap_or(DEFAULT_USER);
+            Ok(Value::Blob(name.into()))
+        }
+        "CAT" => {
+            arity(args.len() <= 1)?;
+            let names: Vec<String> = match args.into_iter().next() {
+                None => categories().map(str::to_string).collect(),
+                Some(category) => {
+                    let category = arg_string(category)?;
+                    let mut commands = category_commands(&category)
+                        .ok_or_else(|| format!("Unknown category '{}'", category))?;
+                    commands.sort();
+                    commands.iter().map(|name| name.to_lowercase()).collect()
+                }
+            };
+            Ok(Value::Array(
+                names
+                    .iter()
+                    .map(|name| Value::Blob(name.as_str().into()))
+                    .collect(),
+            ))
+        }
+        "LOG" => {
+            arity(args.len() <= 1)?;
+            let count = match args.first() {
+                None => 10,
+                Some(arg) if arg_option(arg) == "RESET" => {
+                    session.db.acl_mut().reset_log();
+                    return Ok(Value::Simple("OK".into()));
+                }
+                Some(arg) => usize::try_from(arg_i64(arg)?).map_err(|_| ERR_POSITIVE)?,
+            };
+            Ok(session.db.acl().log_entries(count))
+        }
+        "LOAD" => {
+            arity(args.is_empty())?;
+            let mut acl = session.db.acl_mut();
+            if !acl.has_file() {
+                return Err(ERR_NO_ACL_FILE.into());
+            }
+            acl.load().map_err(|err| err.to_string())?;
+            Ok(Value::Simple("OK".into()))
+        }
+        "SAVE" => {
+            arity(args.is_empty())?;
+            let acl = session.db.acl();
+            if !acl.has_file() {
+                return Err(ERR_NO_ACL_FILE.into());
+            }
+            acl.save().map_err(|err| {
+                log::warn!("Cannot save the ACL file: {}", err);
+                "There was an error trying to save the ACLs. Please check the server logs for \
+                 more information"
+            })?;
+            Ok(Value::Simple("OK".into()))
+        }
+        "GENPASS" => {
+            arity(args.len() <= 1)?;
+            let bits = match args.first() {
+                Some(arg) => arg_i64(arg)?,
+                None => 256,
+            };
+            if !(1..=4096).contains(&bits) {
+                return Err(
+                    "ACL GENPASS argument must be the number of bits for the output \
+                            password, a positive number up to 4096"
+                        .into(),
+                );
+            }
+            Ok(Value::Blob(
+                generate_password(bits as usize).as_str().into(),
+            ))
+        }
+        "DRYRUN" => {
+            arity(args.len() >= 2)?;
+            let mut args = args.into_iter();
+            let name = arg_string(args.next().unwrap())?;
+            let command = arg_string(args.next().unwrap())?.to_uppercase();
+            let args: Vec<Value> = args.collect();
+            let acl = session.db.acl();
+            let user = acl
+                .user(&name)
+                .ok_or_else(|| format!("User '{}' not found", name))?;
+            let spec = session
+                .handlers
+                .get(&command)
+                .ok_or_else(|| format!("Command '{}' not found", command.to_lowercase()))?;
+            if !spec.check_arity(args.len()) {
+                return Err(format!(
+                    "wrong number of arguments for '{}' command",
+                    command.to_lowercase()
+                )
+                .into());
+            }
+            Ok(match user.check(spec, &args) {
+                Ok(()) => Value::Simple("OK".into()),
+                Err(denial) => Value::Blob(denial.message(&name).as_str().into()),
+            })
+        }
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try ACL HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::command::tests::request;
+    use crate::db::{Database, SessionFactory};
+    use crate::value::Value;
+
+    fn ok() -> Value {
+        Value::Simple("OK".into())
+    }
+
+    fn noperm(msg: &str) -> Value {
+        Value::Err("NOPERM".into(), msg.into())
+    }
+
+    #[test]
+    fn test_auth() {
+        let factory = SessionFactory::new(Database::new(&Config {
+            requirepass: Some("secret".to_string()),
+            ..Config::default()
+        }));
+        let mut session = factory.create_client_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            Value::Err("NOAUTH".into(), "Authentication required.".into()),
+            run(&["GET", "a"])
+        );
+        assert_eq!(
+            Value::Err(
+                "WRONGPASS".into(),
+                "invalid username-password pair or user is disabled.".into()
+            ),
+            run(&["AUTH", "wrong"])
+        );
+        assert_eq!(ok(), run(&["AUTH", "secret"]));
+        assert_eq!(Value::Null, run(&["GET", "a"]));
+        assert_eq!(ok(), run(&["AUTH", "default", "secret"]));
+        assert_eq!(Value::Blob("default".into()), run(&["ACL", "WHOAMI"]));
+
+        let log = match run(&["ACL", "LOG"]) {
+            Value::Array(log) => log,
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert_eq!(1, log.len());
+        match &log[0] {
+            Value::Array(entry) => {
+                assert_eq!(Value::Blob("auth".into()), entry[3]);
+                assert_eq!(Value::Blob("AUTH".into()), entry[7]);
+            }
+            entry => panic!("unexpected entry {:?}", entry),
+        }
+        assert_eq!(ok(), run(&["ACL", "LOG", "RESET"]));
+        assert_eq!(Value::Array(vec![]), run(&["ACL", "LOG"]));
+    }
+
+    #[test]
+    fn test_acl_users() {
+        let factory = SessionFactory::new(Database::new(&Config::default()));
+        let mut admin = factory.create_client_session();
+        let mut session = factory.create_client_session();
+
+        assert_eq!(Value::Null, admin.handle_request(request(&["GET", "a"])));
+        assert_eq!(
+            ok(),
+            admin.handle_request(request(&[
+                "ACL",
+                "SETUSER",
+                "alice",
+                "on",
+                ">pw",
+                "~cache:*",
+                "+@read",
+                "+set",
+                "-type",
+                "+acl|whoami",
+                "+@transaction"
+            ]))
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("alice".into()),
+                Value::Blob("default".into())
+            ]),
+            admin.handle_request(request(&["ACL", "USERS"]))
+        );
+        assert_eq!(
+            Value::Err(
+                "ERR".into(),
+                "Error in ACL SETUSER modifier '+@bogus': Unknown command or category name in ACL"
+                    .into()
+            ),
+            admin.handle_request(request(&["ACL", "SETUSER", "alice", "+@bogus"]))
+        );
+
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        assert_eq!(ok(), run(&["AUTH", "alice", "pw"]));
+        assert_eq!(Value::Blob("alice".into()), run(&["ACL", "WHOAMI"]));
+        assert_eq!(ok(), run(&["SET", "cache:1", "x"]));
+        assert_eq!(Value::Blob("x".into()), run(&["GET", "cache:1"]));
+        assert_eq!(
+            noperm("No permissions to access a key"),
+            run(&["GET", "other"])
+        );
+        assert_eq!(
+            noperm("User alice has no permissions to run the 'type' command"),
+            run(&["TYPE", "cache:1"])
+        );
+        assert_eq!(
+            noperm("User alice has no permissions to run the 'eval' command"),
+            run(&["EVAL", "return 1", "0"])
+        );
+
+        // a denied command aborts the transaction.
+        assert_eq!(ok(), run(&["MULTI"]));
+        assert_eq!(
+            noperm("User alice has no permissions to run the 'del' command"),
+            run(&["DEL", "cache:1"])
+        );
+        assert_eq!(
+            Value::Err(
+                "EXECABORT".into(),
+                "Transaction discarded because of previous errors.".into()
+            ),
+            run(&["EXEC"])
+        );
+
+        assert_eq!(
+            Value::Blob("User alice has no permissions to run the 'del' command".into()),
+            admin.handle_request(request(&["ACL", "DRYRUN", "alice", "DEL", "a"]))
+        );
+        assert_eq!(
+            Value::Err("ERR".into(), "The 'default' user cannot be removed".into()),
+            admin.handle_request(request(&["ACL", "DELUSER", "default"]))
+        );
+        assert_eq!(
+            Value::Number(1),
+            admin.handle_request(request(&["ACL", "DELUSER", "alice", "bob"]))
+        );
+        assert_eq!(
+            Value::Err("NOAUTH".into(), "Authentication required.".into()),
+            session.handle_request(request(&["GET", "cache:1"]))
+        );
+    }
+
+    #[test]
+    fn test_acl_movable_keys() {
+        let factory = SessionFactory::new(Database::new(&Config::default()));
+        let mut admin = factory.create_client_session();
+        let mut session = factory.create_client_session();
+        for args in [
+            &["ACL", "SETUSER", "bob", "on", ">pw", "~cache:*", "+@all"][..],
+            &["RPUSH", "secret", "x"],
+            &["RPUSH", "cache:l", "x"],
+            &["SADD", "sset", "m"],
+            &["ZADD", "sz", "1", "m"],
+            &["ZADD", "cache:z", "1", "m"],
+            &["XADD", "sstream", "1-1", "f", "v"],
+            &["XGROUP", "CREATE", "sstream", "g", "0"],
+        ] {
+            assert!(!matches!(
+                admin.handle_request(request(args)),
+                Value::Err(..)
+            ));
+        }
+
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        assert_eq!(ok(), run(&["AUTH", "bob", "pw"]));
+        let denied = noperm("No permissions to access a key");
+        for args in [
+            &["LMPOP", "1", "secret", "LEFT"][..],
+            &["LMPOP", "2", "cache:l", "secret", "LEFT"],
+            &["BLMPOP", "0", "1", "secret", "LEFT"],
+            &["SINTERCARD", "1", "sset"],
+            &["ZUNION", "1", "sz"],
+            &["ZINTER", "2", "cache:z", "sz"],
+            &["ZDIFF", "1", "sz"],
+            &["ZUNIONSTORE", "cache:x", "1", "sz"],
+            &["ZINTERSTORE", "secret:x", "1", "cache:z"],
+            &["XREAD", "COUNT", "1", "STREAMS", "sstream", "0"],
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "sstream", ">"],
+            &["EVAL", "return 1", "1", "secret"],
+        ] {
+            assert_eq!(denied, run(args), "{:?}", args);
+        }
+        assert_eq!(
+            Value::Number(1),
+            run(&["ZUNIONSTORE", "cache:x", "1", "cache:z"])
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("cache:l".into()),
+                Value::Array(vec![Value::Blob("x".into())])
+            ]),
+            run(&["LMPOP", "1", "cache:l", "LEFT"])
+        );
+        assert_eq!(
+            Value::Number(1),
+            admin.handle_request(request(&["LLEN", "secret"]))
+        );
+    }
+}
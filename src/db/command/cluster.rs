@@ -0,0 +1,783 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::db::aof::command;
+use crate::db::cluster::Cluster;
+use crate::db::rdb;
+use crate::db::{key_hash_slot, now_millis, ClusterNode, Session, CLUSTER_SLOTS};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_ADMIN,
+    COMMAND_FLAG_CONNECTION, COMMAND_FLAG_FAST, COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_SLOW,
+    COMMAND_FLAG_WRITE, ERR_NOT_INTEGER, ERR_SYNTAX,
+};
+
+const ERR_CLUSTER_DISABLED: &str = "This instance has cluster support disabled";
+const ERR_INVALID_SLOT: &str = "Invalid or out of range slot";
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: flags.to_vec(),
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        spec(
+            "CLUSTER",
+            -2,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_cluster,
+        ),
+        spec(
+            "ASKING",
+            1,
+            &[COMMAND_FLAG_FAST, COMMAND_FLAG_CONNECTION],
+            handle_asking,
+        ),
+        // the keys are not routed: MIGRATE moves the keys this node has, and replies NOKEY when
+        // there are none.
+        spec(
+            "MIGRATE",
+            -6,
+            &[COMMAND_FLAG_WRITE, COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_SLOW],
+            handle_migrate,
+        ),
+    ]
+}
+
+fn arg_slot(arg: &Value) -> Result<u16, CommandError> {
+    arg_i64(arg)
+        .ok()
+        .and_then(|slot| u16::try_from(slot).ok())
+        .filter(|&slot| slot < CLUSTER_SLOTS)
+        .ok_or_else(|| ERR_INVALID_SLOT.into())
+}
+
+fn arg_string(arg: Value) -> Result<String, CommandError> {
+    Ok(String::from_utf8_lossy(&arg_bytes(arg)?).to_string())
+}
+
+fn node_reply(node: &ClusterNode) -> Value {
+    Value::Array(vec![
+        Value::Blob(node.host.as_str().into()),
+        Value::Number(node.port as i64),
+        Value::Blob(node.id.as_str().into()),
+    ])
+}
+
+/// Implements the `CLUSTER` subcommands.
+fn handle_cluster(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let args: Vec<Value> = args.collect();
+    if session.db.cluster().is_none() {
+        return Err(ERR_CLUSTER_DISABLED.into());
+    }
+    let arity = |ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(CommandError::from(format!(
+                "wrong number of arguments for 'cluster|{}' command",
+                subcommand.to_lowercase()
+            )))
+        }
+    };
+
+    match subcommand.as_str() {
+        "MYID" => {
+            arity(args.is_empty())?;
+            let cluster = session.db.cluster().unwrap();
+            Ok(Value::Blob(cluster.myself().id.as_str().into()))
+        }
+        "MEET" => {
+            arity(args.len() == 2 || args.len() == 3)?;
+            cluster_meet(session, args)
+        }
+        "ADDSLOTS" | "DELSLOTS" | "ADDSLOTSRANGE" | "DELSLOTSRANGE" => {
+            let ranges = subcommand.ends_with("RANGE");
+            arity(!args.is_empty() && (!ranges || args.len().is_multiple_of(2)))?;
+            let mut slots = args.iter().map(arg_slot).collect::<Result<Vec<_>, _>>()?;
+            if ranges {
+                let bounds = slots;
+                slots = vec![];
+                for range in bounds.chunks(2) {
+                    if range[0] > range[1] {
+                        return Err(format!(
+                            "start slot number {} is greater than end slot number {}",
+                            range[0], range[1]
+                        )
+                        .into());
+                    }
+                    slots.extend(range[0]..=range[1]);
+                }
+            }
+            let mut cluster = session.db.cluster().unwrap();
+            for (i, &slot) in slots.iter().enumerate() {
+                if slots[..i].contains(&slot) {
+                    return Err(format!("Slot {} specified multiple times", slot).into());
+                }
+                let adding = subcommand.starts_with("ADD");
+                match cluster.owner(slot) {
+                    Some(_) if adding => {
+                        return Err(format!("Slot {} is already busy", slot).into())
+                    }
+                    None if !adding => {
+                        return Err(format!("Slot {} is already unassigned", slot).into())
+                    }
+                    _ => (),
+                }
+            }
+            let myself = cluster.myself().id.clone();
+            let owner = subcommand.starts_with("ADD").then_some(myself.as_str());
+            for slot in slots {
+                cluster.assign(slot, owner);
+            }
+            Ok(Value::Simple("OK".into()))
+        }
+        "SETSLOT" => {
+            arity(args.len() == 2 || args.len() == 3)?;
+            cluster_setslot(session, args)
+        }
+        "KEYSLOT" => {
+            arity(args.len() == 1)?;
+            let key = arg_bytes(args.into_iter().next().unwrap())?;
+            Ok(Value::Number(key_hash_slot(&key) as i64))
+        }
+        "COUNTKEYSINSLOT" => {
+            arity(args.len() == 1)?;
+            let slot = arg_slot(&args[0]).map_err(|_| "Invalid slot")?;
+            let count = session.lock_db().keys_in_slot(slot, usize::MAX).len();
+            Ok(Value::Number(count as i64))
+        }
+        "GETKEYSINSLOT" => {
+            arity(args.len() == 2)?;
+            let slot = arg_slot(&args[0]).map_err(|_| "Invalid slot")?;
+            let count =
+                usize::try_from(arg_i64(&args[1])?).map_err(|_| "Invalid number of keys")?;
+            let keys = session.lock_db().keys_in_slot(slot, count);
+            Ok(Value::Array(keys.into_iter().map(Value::Blob).collect()))
+        }
+        "SLOTS" => {
+            arity(args.is_empty())?;
+            let cluster = session.db.cluster().unwrap();
+            let mut slots: Vec<_> = (0..cluster.nodes().len())
+                .flat_map(|index| {
+                    let node = &cluster.nodes()[index];
+                    cluster
+                        .slot_ranges(index)
+                        .into_iter()
+                        .map(move |range| (range, node))
+                })
+                .collect();
+            slots.sort_by_key(|((start, _), _)| *start);
+            Ok(Value::Array(
+                slots
+                    .into_iter()
+                    .map(|((start, end), node)| {
+                        Value::Array(vec![
+                            Value::Number(start as i64),
+                            Value::Number(end as i64),
+                            node_reply(node),
+                        ])
+                    })
+                    .collect(),
+            ))
+        }
+        "SHARDS" => {
+            arity(args.is_empty())?;
+            let offset = session.db.replication_offset() as i64;
+            let cluster = session.db.cluster().unwrap();
+            Ok(Value::Array(
+                cluster
+                    .nodes()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, node)| shard_reply(&cluster, index, node, offset))
+                    .collect(),
+            ))
+        }
+        "NODES" => {
+            arity(args.is_empty())?;
+            let cluster = session.db.cluster().unwrap();
+            Ok(Value::Blob(cluster_nodes(&cluster).as_str().into()))
+        }
+        "INFO" => {
+            arity(args.is_empty())?;
+            let cluster = session.db.cluster().unwrap();
+            let assigned = cluster.assigned_slots();
+            let size = (0..cluster.nodes().len())
+                .filter(|&index| !cluster.slot_ranges(index).is_empty())
+                .count();
+            let state = if assigned == CLUSTER_SLOTS as usize {
+                "ok"
+            } else {
+                "fail"
+            };
+            let info = format!(
+                "cluster_enabled:1\r\n\
+                 cluster_state:{}\r\n\
+                 cluster_slots_assigned:{}\r\n\
+                 cluster_slots_ok:{}\r\n\
+                 cluster_slots_pfail:0\r\n\
+                 cluster_slots_fail:0\r\n\
+                 cluster_known_nodes:{}\r\n\
+                 cluster_size:{}\r\n\
+                 cluster_current_epoch:0\r\n\
+                 cluster_my_epoch:0\r\n",
+                state,
+                assigned,
+                assigned,
+                cluster.nodes().len(),
+                size,
+            );
+            Ok(Value::Blob(info.as_str().into()))
+        }
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try CLUSTER HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+/// Implements `CLUSTER MEET ip port [cluster-bus-port]`. There is no cluster bus: the node is
+/// asked for its id right away, and it doesn't learn about this node.
+fn cluster_meet(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let host = arg_string(args.next().unwrap())?;
+    let port = args.next().unwrap();
+    let port = arg_i64(&port)
+        .ok()
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or_else(|| format!("Invalid base port specified: {}", port))?;
+
+    let invalid = || format!("Invalid node address specified: {}:{}", host, port);
+    let id = Client::connect(&host, port, Duration::from_secs(1))
+        .and_then(|mut client| client.call(&["CLUSTER", "MYID"]))
+        .map_err(|_| invalid())?;
+    let id = match id {
+        Value::Blob(id) => id.into_string().map_err(|_| invalid())?,
+        _ => return Err(invalid().into()),
+    };
+    let mut cluster = session.db.cluster().unwrap();
+    if id != cluster.myself().id {
+        cluster.add_node(ClusterNode { id, host, port });
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `CLUSTER SETSLOT slot IMPORTING|MIGRATING|NODE node-id` and
+/// `CLUSTER SETSLOT slot STABLE`, used to move a slot to another node.
+fn cluster_setslot(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let slot = arg_slot(&args.next().unwrap())?;
+    let action = arg_option(&args.next().unwrap());
+    let id = args.next().map(arg_string).transpose()?;
+
+    let mut cluster = session.db.cluster().unwrap();
+    let myself = cluster.myself().id.clone();
+    let owned = cluster.owner(slot).is_some_and(|owner| owner.id == myself);
+    let unknown = |id: &str| format!("I don't know about node {}", id);
+    match (action.as_str(), id) {
+        ("MIGRATING", Some(id)) => {
+            if !owned {
+                return Err(format!("I'm not the owner of hash slot {}", slot).into());
+            }
+            if id == myself {
+                return Err("I can't migrate a slot to myself".into());
+            }
+            if !cluster.set_migrating(slot, &id) {
+                return Err(unknown(&id).into());
+            }
+        }
+        ("IMPORTING", Some(id)) => {
+            if owned {
+                return Err(format!("I'm already the owner of hash slot {}", slot).into());
+            }
+            if id == myself {
+                return Err("I can't import a slot from myself".into());
+            }
+            if !cluster.set_importing(slot, &id) {
+                return Err(unknown(&id).into());
+            }
+        }
+        ("STABLE", None) => cluster.set_stable(slot),
+        ("NODE", Some(id)) => {
+            if owned && id != myself && !session.lock_db().keys_in_slot(slot, 1).is_empty() {
+                return Err(format!(
+                    "Can't assign hashslot {} to a different node while I still hold keys for \
+                     this hash slot.",
+                    slot
+                )
+                .into());
+            }
+            if !cluster.assign(slot, Some(&id)) {
+                return Err(format!("Unknown node {}", id).into());
+            }
+        }
+        _ => {
+            return Err(
+                "Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP".into(),
+            )
+        }
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+fn shard_reply(cluster: &Cluster, index: usize, node: &ClusterNode, offset: i64) -> Value {
+    let slots = cluster
+        .slot_ranges(index)
+        .into_iter()
+        .flat_map(|(start, end)| [Value::Number(start as i64), Value::Number(end as i64)])
+        .collect();
+    let offset = if index == 0 { offset } else { 0 };
+    Value::Array(vec![
+        Value::Blob("slots".into()),
+        Value::Array(slots),
+        Value::Blob("nodes".into()),
+        Value::Array(vec![Value::Array(vec![
+            Value::Blob("id".into()),
+            Value::Blob(node.id.as_str().into()),
+            Value::Blob("port".into()),
+            Value::Number(node.port as i64),
+            Value::Blob("ip".into()),
+            Value::Blob(node.host.as_str().into()),
+            Value::Blob("endpoint".into()),
+            Value::Blob(node.host.as_str().into()),
+            Value::Blob("role".into()),
+            Value::Blob("master".into()),
+            Value::Blob("replication-offset".into()),
+            Value::Number(offset),
+            Value::Blob("health".into()),
+            Value::Blob("online".into()),
+        ])]),
+    ])
+}
+
+/// Formats the nodes like `CLUSTER NODES`: one line per node, with its address, its flags and
+/// its slots. The slots being moved are listed after the slots of this node.
+fn cluster_nodes(cluster: &Cluster) -> String {
+    let migrating = cluster.migrating_slots();
+    let importing = cluster.importing_slots();
+    let mut nodes = String::new();
+    for (index, node) in cluster.nodes().iter().enumerate() {
+        let flags = if index == 0 {
+            "myself,master"
+        } else {
+            "master"
+        };
+        let _ = write!(
+            nodes,
+            "{} {}@{} {} - 0 0 0 connected",
+            node.id,
+            node.addr(),
+            node.port as u32 + 10000,
+            flags
+        );
+        for (start, end) in cluster.slot_ranges(index) {
+            if start == end {
+                let _ = write!(nodes, " {}", start);
+            } else {
+                let _ = write!(nodes, " {}-{}", start, end);
+            }
+        }
+        if index == 0 {
+            for (slot, target) in &migrating {
+                let _ = write!(nodes, " [{}->-{}]", slot, target.id);
+            }
+            for (slot, source) in &importing {
+                let _ = write!(nodes, " [{}-<-{}]", slot, source.id);
+            }
+        }
+        nodes.push('\n');
+    }
+    nodes
+}
+
+/// Implements `ASKING`: the next command is served even if its slot is still owned by another
+/// node, as long as it's being imported by this node.
+fn handle_asking(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    if session.db.cluster().is_none() {
+        return Err(ERR_CLUSTER_DISABLED.into());
+    }
+    session.asking = true;
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [AUTH password | AUTH2 username password] [KEYS key [key ...]]`. The keys are sent with
+/// RESTORE-ASKING and deleted once the target restored them, unless COPY is given. The database
+/// stays locked meanwhile, so that the keys are not changed while they are moved.
+fn handle_migrate(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let host = arg_string(args.next().unwrap())?;
+    let port = arg_i64(&args.next().unwrap())
+        .ok()
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or(ERR_NOT_INTEGER)?;
+    let key = arg_bytes(args.next().unwrap())?;
+    let target_db = arg_i64(&args.next().unwrap())?;
+    let timeout = arg_i64(&args.next().unwrap())?;
+    let timeout = Duration::from_millis(if timeout <= 0 { 1000 } else { timeout as u64 });
+
+    let mut copy = false;
+    let mut replace = false;
+    let mut auth: Option<Vec<Bytes>> = None;
+    let mut keys = vec![key];
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "COPY" => copy = true,
+            "REPLACE" => replace = true,
+            "AUTH" => {
+                auth = Some(vec![arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?]);
+            }
+            "AUTH2" => {
+                let username = arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?;
+                let password = arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?;
+                auth = Some(vec![username, password]);
+            }
+            "KEYS" => {
+                if !keys[0].is_empty() {
+                    return Err(
+                        "When using MIGRATE KEYS option, the key argument must be set to the \
+                         empty string"
+                            .into(),
+                    );
+                }
+                keys = args.by_ref().map(arg_bytes).collect::<Result<_, _>>()?;
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let now = now_millis();
+    let mut dumps = vec![];
+    for key in keys {
+        if let Some(value) = db.get(&key) {
+            let payload = rdb::dump(value);
+            let ttl = db
+                .get_expire(&key)
+                .map(|when| when.saturating_sub(now).max(1))
+                .unwrap_or(0);
+            dumps.push((key, ttl, payload));
+        }
+    }
+    if dumps.is_empty() {
+        return Ok(Value::Simple("NOKEY".into()));
+    }
+
+    let io_error = |action: &str| {
+        CommandError::Code(
+            "IOERR",
+            format!("error or timeout {} target instance", action),
+        )
+    };
+    let replied_error = |reply: Value| match reply {
+        Value::Err(code, msg) => Err(CommandError::from(format!(
+            "Target instance replied with error: {} {}",
+            code, msg
+        ))),
+        _ => Ok(()),
+    };
+    let mut client = Client::connect(&host, port, timeout).map_err(|_| {
+        CommandError::Code("IOERR", "error or timeout connecting to the client".into())
+    })?;
+    if let Some(auth) = auth {
+        let mut request: Vec<&[u8]> = vec![b"AUTH"];
+        request.extend(auth.iter().map(|arg| arg.as_slice()));
+        replied_error(
+            client
+                .call(&request)
+                .map_err(|_| io_error("reading from"))?,
+        )?;
+    }
+    let target_db = target_db.to_string();
+    replied_error(
+        client
+            .call(&[b"SELECT", target_db.as_bytes()])
+            .map_err(|_| io_error("reading from"))?,
+    )?;
+    for (key, ttl, payload) in &dumps {
+        let ttl = ttl.to_string();
+        let mut request: Vec<&[u8]> = vec![b"RESTORE-ASKING", key, ttl.as_bytes(), payload];
+        if replace {
+            request.push(b"REPLACE");
+        }
+        replied_error(
+            client
+                .call(&request)
+                .map_err(|_| io_error("reading from"))?,
+        )?;
+    }
+
+    if copy {
+        drop(db);
+        session.propagate(vec![]);
+    } else {
+        let mut deleted: Vec<&[u8]> = vec![b"DEL"];
+        for (key, _, _) in &dumps {
+            db.remove(key);
+            deleted.push(key);
+        }
+        drop(db);
+        session.propagate(vec![command(&deleted)]);
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::tests::{start_server, TestClient};
+    use crate::value::Value;
+
+    fn ok() -> Value {
+        Value::Simple("OK".into())
+    }
+
+    fn err(code: &str, msg: &str) -> Value {
+        Value::Err(code.to_string(), msg.to_string())
+    }
+
+    #[test]
+    fn test_resharding() {
+        let base = 25000 + (std::process::id() % 1000) as u16 * 4;
+        let port_a = start_server(base, |config| config.cluster_enabled = true);
+        let port_b = start_server(port_a + 1, |config| config.cluster_enabled = true);
+        let (addr_a, addr_b) = (
+            format!("127.0.0.1:{}", port_a),
+            format!("127.0.0.1:{}", port_b),
+        );
+        let mut a = TestClient::connect(port_a);
+        let mut b = TestClient::connect(port_b);
+        let id = |client: &mut TestClient| match client.call(&["CLUSTER", "MYID"]) {
+            Value::Blob(id) => id.into_string().unwrap(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        let (id_a, id_b) = (id(&mut a), id(&mut b));
+
+        assert_eq!(
+            err("CLUSTERDOWN", "Hash slot not served"),
+            a.call(&["GET", "foo"])
+        );
+        assert_eq!(ok(), a.call(&["CLUSTER", "ADDSLOTSRANGE", "0", "16383"]));
+        assert_eq!(
+            err("ERR", "Slot 3 is already busy"),
+            a.call(&["CLUSTER", "ADDSLOTS", "3"])
+        );
+        assert_eq!(
+            ok(),
+            a.call(&["CLUSTER", "MEET", "127.0.0.1", &port_b.to_string()])
+        );
+        assert_eq!(
+            ok(),
+            b.call(&["CLUSTER", "MEET", "127.0.0.1", &port_a.to_string()])
+        );
+        assert_eq!(
+            ok(),
+            b.call(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_a])
+        );
+
+        assert_eq!(ok(), a.call(&["SET", "foo", "1"]));
+        assert_eq!(ok(), a.call(&["SET", "{foo}x", "2"]));
+        assert_eq!(ok(), a.call(&["SET", "bar", "3"]));
+        assert_eq!(
+            err("CROSSSLOT", "Keys in request don't hash to the same slot"),
+            a.call(&["EXISTS", "foo", "bar"])
+        );
+        assert_eq!(
+            Value::Number(12182),
+            a.call(&["CLUSTER", "KEYSLOT", "{foo}x"])
+        );
+        assert_eq!(
+            err("MOVED", &format!("12182 {}", addr_a)),
+            b.call(&["GET", "foo"])
+        );
+
+        // the slot of foo is moved from a to b, one key at a time.
+        assert_eq!(
+            ok(),
+            b.call(&["CLUSTER", "SETSLOT", "12182", "IMPORTING", &id_a])
+        );
+        assert_eq!(
+            ok(),
+            a.call(&["CLUSTER", "SETSLOT", "12182", "MIGRATING", &id_b])
+        );
+        let port = port_b.to_string();
+        assert_eq!(
+            ok(),
+            a.call(&["MIGRATE", "127.0.0.1", &port, "foo", "0", "1000"])
+        );
+        assert_eq!(
+            err("ASK", &format!("12182 {}", addr_b)),
+            a.call(&["GET", "foo"])
+        );
+        assert_eq!(Value::Blob("2".into()), a.call(&["GET", "{foo}x"]));
+        assert_eq!(
+            err("TRYAGAIN", "Multiple keys request during rehashing of slot"),
+            a.call(&["EXISTS", "foo", "{foo}x"])
+        );
+        assert_eq!(
+            err("MOVED", &format!("12182 {}", addr_a)),
+            b.call(&["GET", "foo"])
+        );
+        assert_eq!(ok(), b.call(&["ASKING"]));
+        assert_eq!(Value::Blob("1".into()), b.call(&["GET", "foo"]));
+
+        assert_eq!(
+            err(
+                "ERR",
+                "Can't assign hashslot 12182 to a different node while I still hold keys for \
+                 this hash slot."
+            ),
+            a.call(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_b])
+        );
+        assert_eq!(
+            ok(),
+            a.call(&[
+                "MIGRATE",
+                "127.0.0.1",
+                &port,
+                "",
+                "0",
+                "1000",
+                "KEYS",
+                "{foo}x"
+            ])
+        );
+        assert_eq!(
+            Value::Simple("NOKEY".into()),
+            a.call(&["MIGRATE", "127.0.0.1", &port, "foo", "0", "1000"])
+        );
+        assert_eq!(
+            Value::Number(0),
+            a.call(&["CLUSTER", "COUNTKEYSINSLOT", "12182"])
+        );
+        assert_eq!(
+            ok(),
+            a.call(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_b])
+        );
+        assert_eq!(
+            ok(),
+            b.call(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_b])
+        );
+
+        assert_eq!(
+            err("MOVED", &format!("12182 {}", addr_b)),
+            a.call(&["GET", "foo"])
+        );
+        assert_eq!(Value::Blob("2".into()), b.call(&["GET", "{foo}x"]));
+        assert_eq!(
+            Value::Number(2),
+            b.call(&["CLUSTER", "COUNTKEYSINSLOT", "12182"])
+        );
+
+        let node = |port: u16, id: &str| {
+            Value::Array(vec![
+                Value::Blob("127.0.0.1".into()),
+                Value::Number(port as i64),
+                Value::Blob(id.into()),
+            ])
+        };
+        assert_eq!(
+            Value::Array(vec![
+                Value::Array(vec![
+                    Value::Number(0),
+                    Value::Number(12181),
+                    node(port_a, &id_a)
+                ]),
+                Value::Array(vec![
+                    Value::Number(12182),
+                    Value::Number(12182),
+                    node(port_b, &id_b)
+                ]),
+                Value::Array(vec![
+                    Value::Number(12183),
+                    Value::Number(16383),
+                    node(port_a, &id_a)
+                ]),
+            ]),
+            a.call(&["CLUSTER", "SLOTS"])
+        );
+        let nodes = format!(
+            "{} {}@{} myself,master - 0 0 0 connected 0-12181 12183-16383\n\
+             {} {}@{} master - 0 0 0 connected 12182\n",
+            id_a,
+            addr_a,
+            port_a as u32 + 10000,
+            id_b,
+            addr_b,
+            port_b as u32 + 10000
+        );
+        assert_eq!(
+            Value::Blob(nodes.as_str().into()),
+            a.call(&["CLUSTER", "NODES"])
+        );
+        assert_eq!(
+            err("ERR", "SELECT is not allowed in cluster mode"),
+            a.call(&["SELECT", "1"])
+        );
+    }
+
+    #[test]
+    fn test_movable_keys() {
+        let base = 39000 + (std::process::id() % 1000) as u16 * 2;
+        let port_a = start_server(base, |config| config.cluster_enabled = true);
+        let port_b = start_server(port_a + 1, |config| config.cluster_enabled = true);
+        let mut a = TestClient::connect(port_a);
+        let mut b = TestClient::connect(port_b);
+        let id_b = match b.call(&["CLUSTER", "MYID"]) {
+            Value::Blob(id) => id.into_string().unwrap(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert_eq!(ok(), a.call(&["CLUSTER", "ADDSLOTSRANGE", "0", "12181"]));
+        assert_eq!(
+            ok(),
+            a.call(&["CLUSTER", "MEET", "127.0.0.1", &port_b.to_string()])
+        );
+        assert_eq!(
+            ok(),
+            a.call(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_b])
+        );
+
+        // the keys preceded by their number, or following STREAMS, are routed like any other.
+        let moved = err("MOVED", &format!("12182 127.0.0.1:{}", port_b));
+        let crossslot = err("CROSSSLOT", "Keys in request don't hash to the same slot");
+        for (args, reply) in [
+            (&["LMPOP", "1", "foo", "LEFT"][..], &moved),
+            (&["BLMPOP", "0", "1", "foo", "LEFT"], &moved),
+            (&["SINTERCARD", "1", "foo"], &moved),
+            (&["ZUNION", "1", "foo"], &moved),
+            (&["ZINTER", "2", "foo", "{foo}x"], &moved),
+            (&["ZDIFF", "1", "foo"], &moved),
+            (&["ZUNIONSTORE", "{foo}d", "1", "foo"], &moved),
+            (&["ZINTERSTORE", "{foo}d", "1", "foo"], &moved),
+            (&["ZDIFFSTORE", "{foo}d", "1", "foo"], &moved),
+            (&["XREAD", "STREAMS", "foo", "0"], &moved),
+            (
+                &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "foo", ">"],
+                &moved,
+            ),
+            (&["EVAL", "return 1", "1", "foo"], &moved),
+            (&["LMPOP", "2", "bar", "foo", "LEFT"], &crossslot),
+            (&["ZUNIONSTORE", "bar", "1", "foo"], &crossslot),
+            (&["XREAD", "STREAMS", "bar", "foo", "0", "0"], &crossslot),
+        ] {
+            assert_eq!(reply, &a.call(args), "{:?}", args);
+        }
+        assert_eq!(Value::Null, a.call(&["LMPOP", "1", "bar", "LEFT"]));
+        assert_eq!(
+            Value::Number(0),
+            a.call(&["ZUNIONSTORE", "{bar}d", "1", "bar"])
+        );
+    }
+}
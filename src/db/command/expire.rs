@@ -0,0 +1,239 @@
+use crate::db::aof::command;
+use crate::db::{now_millis, Session};
+use crate::value::Value;
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandResult, CommandSpec, COMMAND_FLAG_FAST,
+    COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_RANDOM, COMMAND_FLAG_READONLY, COMMAND_FLAG_WRITE,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let write = |name: &str, args_len, handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: vec![COMMAND_FLAG_WRITE, COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_FAST],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        get_keys: None,
+        handler,
+    };
+    let read = |name: &str, handler| CommandSpec {
+        name: name.to_string(),
+        args_len: 2,
+        flags: vec![
+            COMMAND_FLAG_READONLY,
+            COMMAND_FLAG_KEYSPACE,
+            COMMAND_FLAG_RANDOM,
+            COMMAND_FLAG_FAST,
+        ],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        write("EXPIRE", -3, handle_expire),
+        write("PEXPIRE", -3, handle_pexpire),
+        write("EXPIREAT", -3, handle_expireat),
+        write("PEXPIREAT", -3, handle_pexpireat),
+        write("PERSIST", 2, handle_persist),
+        read("EXPIRETIME", handle_expiretime),
+        read("PEXPIRETIME", handle_pexpiretime),
+        read("TTL", handle_ttl),
+        read("PTTL", handle_pttl),
+    ]
+}
+
+fn handle_expire(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    expire_generic(session, args, "expire", now_millis() as i64, 1000)
+}
+
+fn handle_pexpire(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    expire_generic(session, args, "pexpire", now_millis() as i64, 1)
+}
+
+fn handle_expireat(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    expire_generic(session, args, "expireat", 0, 1000)
+}
+
+fn handle_pexpireat(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    expire_generic(session, args, "pexpireat", 0, 1)
+}
+
+/// Implements the EXPIRE family. The expiry time given by the client is multiplied by `unit`
+/// to get milliseconds, then added to `basetime` which is either the current time for relative
+/// expiry or zero for absolute expiry.
+fn expire_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    name: &str,
+    basetime: i64,
+    unit: i64,
+) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let when = arg_i64(&args.next().unwrap())?;
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for arg in args {
+        match arg_option(&arg).as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            option => return Err(format!("Unsupported option {}", option).into()),
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err("NX and XX, GT or LT options at the same time are not compatible".into());
+    }
+    if gt && lt {
+        return Err("GT and LT options at the same time are not compatible".into());
+    }
+
+    let when = when
+        .checked_mul(unit)
+        .and_then(|when| when.checked_add(basetime))
+        .ok_or_else(|| format!("invalid expire time in '{}' command", name))?;
+
+    let mut db = session.lock_db();
+    if !db.contains_key(&key) {
+        return Ok(Value::Number(0));
+    }
+
+    // keys without expiry are treated as having an infinite ttl by GT and LT.
+    let current = db.get_expire(&key).map(|t| t as i64);
+    let rejected = match current {
+        Some(current) => nx || (gt && when <= current) || (lt && when >= current),
+        None => xx || gt,
+    };
+    if rejected {
+        return Ok(Value::Number(0));
+    }
+
+    // the relative expiry times are logged as absolute ones, which don't depend on when the
+    // command is replayed.
+    if when <= now_millis() as i64 {
+        db.remove(&key);
+        drop(db);
+        session.propagate(vec![command(&[b"DEL", &key])]);
+    } else {
+        db.set_expire(&key, when as u64);
+        drop(db);
+        let when = when.to_string();
+        session.propagate(vec![command(&[b"PEXPIREAT", &key, when.as_bytes()])]);
+    }
+    Ok(Value::Number(1))
+}
+
+fn handle_persist(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let persisted = session.lock_db().persist(&key);
+    Ok(Value::Number(persisted as i64))
+}
+
+fn handle_ttl(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    ttl_generic(session, args, |expire| {
+        (expire.saturating_sub(now_millis()) as i64 + 500) / 1000
+    })
+}
+
+fn handle_pttl(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    ttl_generic(session, args, |expire| {
+        expire.saturating_sub(now_millis()) as i64
+    })
+}
+
+fn handle_expiretime(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    ttl_generic(session, args, |expire| (expire as i64 + 500) / 1000)
+}
+
+fn handle_pexpiretime(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    ttl_generic(session, args, |expire| expire as i64)
+}
+
+/// Implements the TTL family. Replies -2 if the key doesn't exist, -1 if it has no expiry, or
+/// the expiry time formatted by `format`.
+fn ttl_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    format: impl Fn(u64) -> i64,
+) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    if !db.contains_key(&key) {
+        return Ok(Value::Number(-2));
+    }
+    Ok(Value::Number(db.get_expire(&key).map(format).unwrap_or(-1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    #[test]
+    fn test_expire() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(Value::Number(0), run(&["EXPIRE", "k", "100"]));
+        run(&["SET", "k", "v"]);
+        assert_eq!(Value::Number(-1), run(&["TTL", "k"]));
+        assert_eq!(Value::Number(-2), run(&["TTL", "missing"]));
+
+        assert_eq!(Value::Number(0), run(&["EXPIRE", "k", "100", "XX"]));
+        assert_eq!(Value::Number(0), run(&["EXPIRE", "k", "100", "GT"]));
+        assert_eq!(Value::Number(1), run(&["EXPIRE", "k", "100", "NX"]));
+        assert_eq!(Value::Number(100), run(&["TTL", "k"]));
+        assert_eq!(Value::Number(0), run(&["EXPIRE", "k", "200", "NX"]));
+        assert_eq!(Value::Number(0), run(&["EXPIRE", "k", "50", "GT"]));
+        assert_eq!(Value::Number(1), run(&["EXPIRE", "k", "50", "LT"]));
+        assert_eq!(Value::Number(50), run(&["TTL", "k"]));
+
+        assert_eq!(Value::Number(1), run(&["PERSIST", "k"]));
+        assert_eq!(Value::Number(0), run(&["PERSIST", "k"]));
+        assert_eq!(Value::Number(-1), run(&["PTTL", "k"]));
+
+        assert_eq!(Value::Number(1), run(&["PEXPIREAT", "k", "33177117420000"]));
+        assert_eq!(Value::Number(33177117420), run(&["EXPIRETIME", "k"]));
+        assert_eq!(Value::Number(33177117420000), run(&["PEXPIRETIME", "k"]));
+
+        assert_eq!(Value::Number(1), run(&["EXPIREAT", "k", "1"]));
+        assert_eq!(Value::Null, run(&["GET", "k"]));
+        assert_eq!(Value::Number(-2), run(&["EXPIRETIME", "k"]));
+    }
+
+    #[test]
+    fn test_expire_options() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["SET", "k", "v"]);
+        assert_eq!(
+            Value::err("NX and XX, GT or LT options at the same time are not compatible"),
+            run(&["EXPIRE", "k", "10", "NX", "GT"])
+        );
+        assert_eq!(
+            Value::err("GT and LT options at the same time are not compatible"),
+            run(&["EXPIRE", "k", "10", "GT", "LT"])
+        );
+        assert_eq!(
+            Value::err("Unsupported option FOO"),
+            run(&["EXPIRE", "k", "10", "FOO"])
+        );
+        assert_eq!(
+            Value::err("invalid expire time in 'expire' command"),
+            run(&["EXPIRE", "k", "9223372036854775807"])
+        );
+        assert_eq!(
+            Value::err("value is not an integer or out of range"),
+            run(&["EXPIRE", "k", "abc"])
+        );
+    }
+}
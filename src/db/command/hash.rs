@@ -0,0 +1,527 @@
+use rand::seq::index::sample;
+
+use crate::db::{Dict, InternalDb, Object, Session};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_f64, arg_i64, arg_option, format_double, CommandError, CommandResult,
+    CommandSpec, ScanArgs, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST, COMMAND_FLAG_HASH,
+    COMMAND_FLAG_RANDOM, COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW, COMMAND_FLAG_WRITE, ERR_NAN,
+    ERR_OUT_OF_RANGE, ERR_OVERFLOW, ERR_SYNTAX,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_HASH]].concat(),
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        get_keys: None,
+        handler,
+    };
+    let write_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST];
+    let read_fast = [COMMAND_FLAG_READONLY, COMMAND_FLAG_FAST];
+    let read_slow = [COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW];
+    let read_random = [
+        COMMAND_FLAG_READONLY,
+        COMMAND_FLAG_RANDOM,
+        COMMAND_FLAG_SLOW,
+    ];
+
+    vec![
+        spec("HSET", -4, &write_fast, handle_hset),
+        spec("HMSET", -4, &write_fast, handle_hmset),
+        spec("HSETNX", 4, &write_fast, handle_hsetnx),
+        spec("HGET", 3, &read_fast, handle_hget),
+        spec("HMGET", -3, &read_fast, handle_hmget),
+        spec(
+            "HDEL",
+            -3,
+            &[COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST],
+            handle_hdel,
+        ),
+        spec("HLEN", 2, &read_fast, handle_hlen),
+        spec("HSTRLEN", 3, &read_fast, handle_hstrlen),
+        spec("HEXISTS", 3, &read_fast, handle_hexists),
+        spec("HKEYS", 2, &read_slow, handle_hkeys),
+        spec("HVALS", 2, &read_slow, handle_hvals),
+        spec("HGETALL", 2, &read_slow, handle_hgetall),
+        spec("HINCRBY", 4, &write_fast, handle_hincrby),
+        spec("HINCRBYFLOAT", 4, &write_fast, handle_hincrbyfloat),
+        spec("HRANDFIELD", -2, &read_random, handle_hrandfield),
+        spec("HSCAN", -3, &read_random, handle_hscan),
+    ]
+}
+
+/// Returns the hash stored at the key, failing if the key holds another type.
+fn get_hash<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+) -> Result<Option<&'a mut Dict<Bytes, Bytes>>, CommandError> {
+    match db.get_mut(key) {
+        None => Ok(None),
+        Some(Object::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Returns the hash stored at the key, creating an empty one if the key doesn't exist.
+fn get_or_create_hash<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+) -> Result<&'a mut Dict<Bytes, Bytes>, CommandError> {
+    match db.get_or_insert_with(key, || Object::Hash(Dict::new())) {
+        Object::Hash(hash) => Ok(hash),
+        _ => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Parses the `key field value [field value ...]` arguments of HSET and HMSET, then sets the
+/// fields. Returns the number of newly created fields.
+fn hset_generic(session: &mut Session, args: Vec<Value>, name: &str) -> Result<i64, CommandError> {
+    if args.len().is_multiple_of(2) {
+        return Err(format!("wrong number of arguments for '{}' command", name).into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    while let (Some(field), Some(value)) = (args.next(), args.next()) {
+        pairs.push((arg_bytes(field)?, arg_bytes(value)?));
+    }
+
+    let mut db = session.lock_db();
+    let hash = get_or_create_hash(&mut db, &key)?;
+    let mut created = 0;
+    let changes = pairs.len();
+    for (field, value) in pairs {
+        if hash.insert(field, value).is_none() {
+            created += 1;
+        }
+    }
+    db.modified(&key, changes);
+    Ok(created)
+}
+
+fn handle_hset(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    Ok(Value::Number(hset_generic(session, args, "hset")?))
+}
+
+fn handle_hmset(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    hset_generic(session, args, "hmset")?;
+    Ok(Value::Simple("OK".into()))
+}
+
+fn handle_hsetnx(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+    let value = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let hash = get_or_create_hash(&mut db, &key)?;
+    if hash.contains_key(&field) {
+        return Ok(Value::Number(0));
+    }
+    hash.insert(field, value);
+    db.modified(&key, 1);
+    Ok(Value::Number(1))
+}
+
+fn handle_hget(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    Ok(get_hash(&mut db, &key)?
+        .and_then(|hash| hash.get(&field))
+        .map(|v| Value::Blob(v.clone()))
+        .unwrap_or(Value::Null))
+}
+
+fn handle_hmget(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let fields = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let hash = get_hash(&mut db, &key)?;
+    Ok(Value::Array(
+        fields
+            .iter()
+            .map(|field| {
+                hash.as_ref()
+                    .and_then(|hash| hash.get(field))
+                    .map(|v| Value::Blob(v.clone()))
+                    .unwrap_or(Value::Null)
+            })
+            .collect(),
+    ))
+}
+
+fn handle_hdel(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let fields = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let hash = match get_hash(&mut db, &key)? {
+        Some(hash) => hash,
+        None => return Ok(Value::Number(0)),
+    };
+    let deleted = fields
+        .iter()
+        .filter(|field| hash.remove(*field).is_some())
+        .count();
+    if hash.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, deleted);
+    Ok(Value::Number(deleted as i64))
+}
+
+fn handle_hlen(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let len = get_hash(&mut db, &key)?.map(|hash| hash.len()).unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_hstrlen(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let len = get_hash(&mut db, &key)?
+        .and_then(|hash| hash.get(&field))
+        .map(|v| v.len())
+        .unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_hexists(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let exists = get_hash(&mut db, &key)?
+        .map(|hash| hash.contains_key(&field))
+        .unwrap_or(false);
+    Ok(Value::Number(exists as i64))
+}
+
+/// Replies with the fields and/or values of the whole hash.
+fn getall_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    fields: bool,
+    values: bool,
+) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let hash = match get_hash(&mut db, &key)? {
+        Some(hash) => hash,
+        None if fields && values => return Ok(Value::Map(vec![])),
+        None => return Ok(Value::Array(vec![])),
+    };
+
+    if fields && values {
+        return Ok(Value::Map(
+            hash.iter()
+                .map(|(field, value)| (Value::Blob(field.clone()), Value::Blob(value.clone())))
+                .collect(),
+        ));
+    }
+    let mut reply = Vec::with_capacity(hash.len());
+    for (field, value) in hash.iter() {
+        if fields {
+            reply.push(Value::Blob(field.clone()));
+        }
+        if values {
+            reply.push(Value::Blob(value.clone()));
+        }
+    }
+    Ok(Value::Array(reply))
+}
+
+fn handle_hkeys(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    getall_generic(session, args, true, false)
+}
+
+fn handle_hvals(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    getall_generic(session, args, false, true)
+}
+
+fn handle_hgetall(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    getall_generic(session, args, true, true)
+}
+
+fn handle_hincrby(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+    let increment = arg_i64(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let current = match get_hash(&mut db, &key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or("hash value is not an integer")?,
+        None => 0,
+    };
+    let result = current.checked_add(increment).ok_or(ERR_OVERFLOW)?;
+    get_or_create_hash(&mut db, &key)?.insert(field, Bytes::from(&result.to_string()));
+    db.modified(&key, 1);
+    Ok(Value::Number(result))
+}
+
+fn handle_hincrbyfloat(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let field = arg_bytes(args.next().unwrap())?;
+    let increment = arg_f64(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let current = match get_hash(&mut db, &key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| !v.is_nan())
+            .ok_or("hash value is not a float")?,
+        None => 0.0,
+    };
+    let result = current + increment;
+    if !result.is_finite() {
+        return Err(ERR_NAN.into());
+    }
+    let result = Bytes::from(&format_double(result));
+    get_or_create_hash(&mut db, &key)?.insert(field, result.clone());
+    db.modified(&key, 1);
+    Ok(Value::Blob(result))
+}
+
+/// Implements `HRANDFIELD key [count [WITHVALUES]]`. A positive count returns distinct fields,
+/// a negative one allows the same field to be returned multiple times.
+fn handle_hrandfield(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if args.len() > 3 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = args.next().map(|count| arg_i64(&count)).transpose()?;
+    let withvalues = match args.next() {
+        Some(arg) if arg_option(&arg) == "WITHVALUES" => true,
+        Some(_) => return Err(ERR_SYNTAX.into()),
+        None => false,
+    };
+
+    let mut db = session.lock_db();
+    let hash = get_hash(&mut db, &key)?;
+    let count = match count {
+        Some(count) => count,
+        None => {
+            return Ok(hash
+                .and_then(|hash| hash.random_entry())
+                .map(|(field, _)| Value::Blob(field.clone()))
+                .unwrap_or(Value::Null))
+        }
+    };
+    if withvalues && !(-i64::MAX / 2..=i64::MAX / 2).contains(&count) {
+        return Err(ERR_OUT_OF_RANGE.into());
+    }
+
+    let hash = match hash {
+        Some(hash) => hash,
+        None => return Ok(Value::Array(vec![])),
+    };
+    let entries: Vec<(&Bytes, &Bytes)> = if count < 0 {
+        (0..count.unsigned_abs())
+            .map(|_| hash.random_entry().unwrap())
+            .collect()
+    } else if count as usize >= hash.len() {
+        hash.iter().collect()
+    } else {
+        sample(&mut rand::thread_rng(), hash.len(), count as usize)
+            .into_iter()
+            .map(|i| hash.get_index(i).unwrap())
+            .collect()
+    };
+
+    let mut reply = Vec::with_capacity(entries.len() * (1 + withvalues as usize));
+    for (field, value) in entries {
+        reply.push(Value::Blob(field.clone()));
+        if withvalues {
+            reply.push(Value::Blob(value.clone()));
+        }
+    }
+    Ok(Value::Array(reply))
+}
+
+/// Implements `HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]`.
+fn handle_hscan(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let scan = ScanArgs::parse(args, &["NOVALUES"])?;
+
+    let mut db = session.lock_db();
+    let hash = match get_hash(&mut db, &key)? {
+        Some(hash) => hash,
+        None => return Ok(ScanArgs::reply(0, vec![])),
+    };
+
+    let (cursor, entries) = hash.scan(scan.cursor, scan.count);
+    let mut items = Vec::new();
+    for (field, value) in entries {
+        if !scan.matches(field) {
+            continue;
+        }
+        items.push(Value::Blob(field.clone()));
+        if !scan.novalues {
+            items.push(Value::Blob(value.clone()));
+        }
+    }
+    Ok(ScanArgs::reply(cursor, items))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    fn blobs(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|v| Value::Blob((*v).into())).collect())
+    }
+
+    #[test]
+    fn test_hash_commands() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            Value::Number(2),
+            run(&["HSET", "h", "name", "jauhar", "age", "20"])
+        );
+        assert_eq!(
+            Value::Number(1),
+            run(&["HSET", "h", "age", "21", "city", "x"])
+        );
+        assert_eq!(
+            Value::err("wrong number of arguments for 'hset' command"),
+            run(&["HSET", "h", "a", "b", "c"])
+        );
+        assert_eq!(Value::Number(0), run(&["HSETNX", "h", "age", "30"]));
+        assert_eq!(Value::Number(1), run(&["HSETNX", "h", "zip", "123"]));
+        assert_eq!(Value::Blob("21".into()), run(&["HGET", "h", "age"]));
+        assert_eq!(Value::Null, run(&["HGET", "h", "missing"]));
+        assert_eq!(
+            Value::Array(vec![Value::Blob("jauhar".into()), Value::Null]),
+            run(&["HMGET", "h", "name", "missing"])
+        );
+        assert_eq!(Value::Number(4), run(&["HLEN", "h"]));
+        assert_eq!(Value::Number(6), run(&["HSTRLEN", "h", "name"]));
+        assert_eq!(Value::Number(1), run(&["HEXISTS", "h", "zip"]));
+        assert_eq!(Value::Number(2), run(&["HDEL", "h", "zip", "city", "none"]));
+        assert_eq!(blobs(&["name", "age"]), run(&["HKEYS", "h"]));
+        assert_eq!(blobs(&["jauhar", "21"]), run(&["HVALS", "h"]));
+        assert_eq!(
+            blobs(&["name", "jauhar", "age", "21"]),
+            run(&["HGETALL", "h"])
+        );
+        assert_eq!(Value::Number(2), run(&["HDEL", "h", "name", "age"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "h"]));
+    }
+
+    #[test]
+    fn test_hincr() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(Value::Number(5), run(&["HINCRBY", "h", "n", "5"]));
+        assert_eq!(Value::Number(2), run(&["HINCRBY", "h", "n", "-3"]));
+        assert_eq!(
+            Value::err("increment or decrement would overflow"),
+            run(&["HINCRBY", "h", "n", "9223372036854775807"])
+        );
+        assert_eq!(
+            Value::Blob("2.5".into()),
+            run(&["HINCRBYFLOAT", "h", "n", "0.5"])
+        );
+        assert_eq!(
+            Value::err("hash value is not an integer"),
+            run(&["HINCRBY", "h", "n", "1"])
+        );
+        assert_eq!(
+            Value::Blob("5000".into()),
+            run(&["HINCRBYFLOAT", "h", "f", "5e3"])
+        );
+        run(&["HSET", "h", "s", "abc"]);
+        assert_eq!(
+            Value::err("hash value is not a float"),
+            run(&["HINCRBYFLOAT", "h", "s", "1"])
+        );
+        assert_eq!(
+            Value::err("increment would produce NaN or Infinity"),
+            run(&["HINCRBYFLOAT", "h", "f", "inf"])
+        );
+    }
+
+    #[test]
+    fn test_hrandfield_hscan() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        let mut args = vec!["HSET", "h"];
+        let fields: Vec<String> = (0..100).map(|i| format!("field:{}", i)).collect();
+        for field in fields.iter() {
+            args.push(field);
+            args.push("v");
+        }
+        run(&args);
+
+        let distinct = match run(&["HRANDFIELD", "h", "50"]) {
+            Value::Array(values) => values,
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert_eq!(50, distinct.iter().collect::<HashSet<_>>().len());
+        match run(&["HRANDFIELD", "h", "-300", "WITHVALUES"]) {
+            Value::Array(values) => assert_eq!(600, values.len()),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(Value::Array(vec![]), run(&["HRANDFIELD", "none", "5"]));
+        assert_eq!(Value::Null, run(&["HRANDFIELD", "none"]));
+
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let reply = run(&["HSCAN", "h", &cursor, "MATCH", "field:1*", "NOVALUES"]);
+            let (next, items) = match reply {
+                Value::Array(mut reply) => match (reply.remove(0), reply.remove(0)) {
+                    (Value::Blob(next), Value::Array(items)) => (next, items),
+                    other => panic!("unexpected reply {:?}", other),
+                },
+                other => panic!("unexpected reply {:?}", other),
+            };
+            seen.extend(items);
+            cursor = next.into_string().unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(11, seen.len());
+        assert_eq!(Value::err("invalid cursor"), run(&["HSCAN", "h", "abc"]));
+        assert_eq!(
+            Value::err("syntax error"),
+            run(&["HSCAN", "h", "0", "COUNT", "0"])
+        );
+    }
+}
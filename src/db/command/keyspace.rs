@@ -0,0 +1,224 @@
+use crate::db::aof::command;
+use crate::db::rdb;
+use crate::db::{now_millis, Session};
+use crate::value::Value;
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_DENYOOM,
+    COMMAND_FLAG_FAST, COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW,
+    COMMAND_FLAG_WRITE, ERR_SYNTAX,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec {
+            name: "DEL".to_string(),
+            args_len: -2,
+            flags: vec![COMMAND_FLAG_WRITE, COMMAND_FLAG_KEYSPACE, COMMAND_FLAG_SLOW],
+            first_key: 1,
+            last_key: -1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_del,
+        },
+        CommandSpec {
+            name: "EXISTS".to_string(),
+            args_len: -2,
+            flags: vec![
+                COMMAND_FLAG_READONLY,
+                COMMAND_FLAG_KEYSPACE,
+                COMMAND_FLAG_FAST,
+            ],
+            first_key: 1,
+            last_key: -1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_exists,
+        },
+        CommandSpec {
+            name: "TYPE".to_string(),
+            args_len: 2,
+            flags: vec![
+                COMMAND_FLAG_READONLY,
+                COMMAND_FLAG_KEYSPACE,
+                COMMAND_FLAG_FAST,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_type,
+        },
+        CommandSpec {
+            name: "DUMP".to_string(),
+            args_len: 2,
+            flags: vec![
+                COMMAND_FLAG_READONLY,
+                COMMAND_FLAG_KEYSPACE,
+                COMMAND_FLAG_SLOW,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_dump,
+        },
+        CommandSpec {
+            name: "RESTORE".to_string(),
+            args_len: -4,
+            flags: vec![
+                COMMAND_FLAG_WRITE,
+                COMMAND_FLAG_DENYOOM,
+                COMMAND_FLAG_KEYSPACE,
+                COMMAND_FLAG_SLOW,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_restore,
+        },
+        // sent by MIGRATE, it's served by a node importing the slot of the key without an ASKING.
+        CommandSpec {
+            name: "RESTORE-ASKING".to_string(),
+            args_len: -4,
+            flags: vec![
+                COMMAND_FLAG_WRITE,
+                COMMAND_FLAG_DENYOOM,
+                COMMAND_FLAG_KEYSPACE,
+                COMMAND_FLAG_SLOW,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_restore,
+        },
+    ]
+}
+
+fn handle_del(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut db = session.lock_db();
+    let mut deleted = 0;
+    for key in args {
+        if db.remove(&arg_bytes(key)?).is_some() {
+            deleted += 1;
+        }
+    }
+    Ok(Value::Number(deleted))
+}
+
+fn handle_exists(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut db = session.lock_db();
+    let mut count = 0;
+    for key in args {
+        if db.contains_key(&arg_bytes(key)?) {
+            count += 1;
+        }
+    }
+    Ok(Value::Number(count))
+}
+
+fn handle_type(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let name = db.get(&key).map(|obj| obj.type_name()).unwrap_or("none");
+    Ok(Value::Simple(name.into()))
+}
+
+fn handle_dump(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    match db.get(&key) {
+        Some(value) => Ok(Value::Blob(rdb::dump(value).into())),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Implements `RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]`.
+/// The idle time and the frequency set how recently and how frequently the key was accessed, as
+/// seen by the eviction.
+fn handle_restore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let ttl = arg_i64(&args.next().unwrap())?;
+    let payload = arg_bytes(args.next().unwrap())?;
+    let mut replace = false;
+    let mut absttl = false;
+    let mut idle = None;
+    let mut frequency = None;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absttl = true,
+            "IDLETIME" => {
+                let seconds = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                if seconds < 0 {
+                    return Err("Invalid IDLETIME value, must be >= 0".into());
+                }
+                idle = Some(seconds as u64);
+            }
+            "FREQ" => {
+                let freq = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                if !(0..=255).contains(&freq) {
+                    return Err("Invalid FREQ value, must be >= 0 and <= 255".into());
+                }
+                frequency = Some(freq as u8);
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+    if ttl < 0 {
+        return Err("Invalid TTL value, must be >= 0".into());
+    }
+
+    let mut db = session.lock_db();
+    if !replace && db.contains_key(&key) {
+        return Err(CommandError::Code(
+            "BUSYKEY",
+            "Target key name already exists.".into(),
+        ));
+    }
+    if !rdb::verify_dump(&payload) {
+        return Err("DUMP payload version or checksum are wrong".into());
+    }
+    let value = rdb::restore(&payload).map_err(|_| "Bad data format")?;
+
+    let when = match ttl {
+        0 => None,
+        ttl if absttl => Some(ttl),
+        ttl => Some(now_millis() as i64 + ttl),
+    };
+    match when {
+        None => {
+            db.insert(key.clone(), value);
+            db.set_access(&key, idle, frequency);
+            drop(db);
+            session.propagate(vec![command(&[
+                b"RESTORE", &key, b"0", &payload, b"REPLACE",
+            ])]);
+        }
+        Some(when) if when <= now_millis() as i64 => {
+            // an expired key is not restored, but it still replaces the existing one.
+            db.remove(&key);
+            drop(db);
+            session.propagate(vec![command(&[b"DEL", &key])]);
+        }
+        Some(when) => {
+            db.insert(key.clone(), value);
+            db.set_expire(&key, when as u64);
+            db.set_access(&key, idle, frequency);
+            drop(db);
+            let when = when.to_string();
+            session.propagate(vec![command(&[
+                b"RESTORE",
+                &key,
+                when.as_bytes(),
+                &payload,
+                b"ABSTTL",
+                b"REPLACE",
+            ])]);
+        }
+    }
+    Ok(Value::Simple("OK".into()))
+}
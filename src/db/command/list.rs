@@ -0,0 +1,971 @@
+use std::time::Duration;
+
+use crate::db::aof::command;
+use crate::db::{InternalDb, Object, QuickList, Session};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_option, block_on, numkeys_keys, parse_timeout, CommandError,
+    CommandResult, CommandSpec, COMMAND_FLAG_BLOCKING, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST,
+    COMMAND_FLAG_LIST, COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW, COMMAND_FLAG_WRITE,
+    ERR_INDEX_OUT_OF_RANGE, ERR_NO_SUCH_KEY, ERR_POSITIVE, ERR_SYNTAX,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], last_key, handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_LIST]].concat(),
+        first_key: 1,
+        last_key,
+        key_step: 1,
+        get_keys: None,
+        handler,
+    };
+    let push = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST];
+    let write_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST];
+    let write_slow = [COMMAND_FLAG_WRITE, COMMAND_FLAG_SLOW];
+    let read_fast = [COMMAND_FLAG_READONLY, COMMAND_FLAG_FAST];
+    let read_slow = [COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW];
+    let r#move = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_SLOW];
+    let blocking = [COMMAND_FLAG_WRITE, COMMAND_FLAG_SLOW, COMMAND_FLAG_BLOCKING];
+    let blocking_move = [
+        COMMAND_FLAG_WRITE,
+        COMMAND_FLAG_DENYOOM,
+        COMMAND_FLAG_SLOW,
+        COMMAND_FLAG_BLOCKING,
+    ];
+
+    vec![
+        spec("LPUSH", -3, &push, 1, handle_lpush),
+        spec("RPUSH", -3, &push, 1, handle_rpush),
+        spec("LPUSHX", -3, &push, 1, handle_lpushx),
+        spec("RPUSHX", -3, &push, 1, handle_rpushx),
+        spec("LINSERT", 5, &r#move, 1, handle_linsert),
+        spec("LPOP", -2, &write_fast, 1, handle_lpop),
+        spec("RPOP", -2, &write_fast, 1, handle_rpop),
+        CommandSpec {
+            get_keys: Some(lmpop_keys),
+            ..spec("LMPOP", -4, &write_slow, 0, handle_lmpop)
+        },
+        spec("LLEN", 2, &read_fast, 1, handle_llen),
+        spec("LINDEX", 3, &read_slow, 1, handle_lindex),
+        spec("LSET", 4, &r#move, 1, handle_lset),
+        spec("LRANGE", 4, &read_slow, 1, handle_lrange),
+        spec("LTRIM", 4, &write_slow, 1, handle_ltrim),
+        spec("LPOS", -3, &read_slow, 1, handle_lpos),
+        spec("LREM", 4, &write_slow, 1, handle_lrem),
+        spec("RPOPLPUSH", 3, &r#move, 2, handle_rpoplpush),
+        spec("LMOVE", 5, &r#move, 2, handle_lmove),
+        spec("BLPOP", -3, &blocking, -2, handle_blpop),
+        spec("BRPOP", -3, &blocking, -2, handle_brpop),
+        CommandSpec {
+            get_keys: Some(blmpop_keys),
+            ..spec("BLMPOP", -5, &blocking, 0, handle_blmpop)
+        },
+        spec("BRPOPLPUSH", 4, &blocking_move, 2, handle_brpoplpush),
+        spec("BLMOVE", 6, &blocking_move, 2, handle_blmove),
+    ]
+}
+
+/// Finds the keys of `LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+fn lmpop_keys(args: &[Value]) -> Vec<&[u8]> {
+    numkeys_keys(args, 0)
+}
+
+/// Finds the keys of `BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+fn blmpop_keys(args: &[Value]) -> Vec<&[u8]> {
+    numkeys_keys(args, 1)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    pub(super) fn parse(arg: &Value) -> Result<Self, CommandError> {
+        match arg_option(arg).as_str() {
+            "LEFT" => Ok(Self::Left),
+            "RIGHT" => Ok(Self::Right),
+            _ => Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Left => b"LEFT",
+            Self::Right => b"RIGHT",
+        }
+    }
+}
+
+/// Returns the list stored at the key, failing if the key holds another type.
+pub(super) fn get_list<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+) -> Result<Option<&'a mut QuickList>, CommandError> {
+    match db.get_mut(key) {
+        None => Ok(None),
+        Some(Object::List(list)) => Ok(Some(list)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Pushes the values to the list at the key, creating the list if needed. Returns the length of
+/// the list after the push.
+pub(super) fn push(
+    db: &mut InternalDb,
+    key: &Bytes,
+    values: impl IntoIterator<Item = Bytes>,
+    end: ListEnd,
+) -> Result<usize, CommandError> {
+    let list = match db.get_or_insert_with(key, || Object::List(QuickList::new())) {
+        Object::List(list) => list,
+        _ => return Err(CommandError::wrongtype()),
+    };
+    let mut pushed = 0;
+    for value in values {
+        match end {
+            ListEnd::Left => list.push_front(value),
+            ListEnd::Right => list.push_back(value),
+        }
+        pushed += 1;
+    }
+    let len = list.len();
+    db.modified(key, pushed);
+    Ok(len)
+}
+
+/// Pops up to `count` elements from the list at the key, deleting the key once the list is
+/// empty. Returns `None` if the key doesn't exist.
+pub(super) fn pop(
+    db: &mut InternalDb,
+    key: &Bytes,
+    end: ListEnd,
+    count: usize,
+) -> Result<Option<Vec<Bytes>>, CommandError> {
+    let list = match get_list(db, key)? {
+        Some(list) => list,
+        None => return Ok(None),
+    };
+
+    let mut values = Vec::with_capacity(count.min(list.len()));
+    while values.len() < count {
+        let value = match end {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        };
+        match value {
+            Some(value) => values.push(value),
+            None => break,
+        }
+    }
+
+    if list.is_empty() {
+        db.remove(key);
+    }
+    db.modified(key, values.len());
+    Ok(Some(values))
+}
+
+/// Pops an element from `source` and pushes it to `destination`. Returns `None` if the source
+/// doesn't exist.
+pub(super) fn move_element(
+    db: &mut InternalDb,
+    source: &Bytes,
+    destination: &Bytes,
+    from: ListEnd,
+    to: ListEnd,
+) -> Result<Option<Bytes>, CommandError> {
+    let value = match get_list(db, source)? {
+        Some(list) => list,
+        None => return Ok(None),
+    };
+    let value = match from {
+        ListEnd::Left => value.front().cloned(),
+        ListEnd::Right => value.back().cloned(),
+    };
+
+    // check the destination before touching the source, so that nothing changes on error.
+    get_list(db, destination)?;
+
+    let value = value.unwrap();
+    pop(db, source, from, 1)?;
+    push(db, destination, [value.clone()], to)?;
+    Ok(Some(value))
+}
+
+/// Converts a possibly negative index, which counts from the end of the list, into an absolute
+/// index. Returns `None` if the index is out of range.
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+/// Converts the `start` and `stop` arguments of LRANGE and LTRIM into an inclusive range of
+/// absolute indices, or `None` if the range is empty.
+pub(super) fn list_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+fn handle_lpush(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    push_generic(session, args, ListEnd::Left, false)
+}
+
+fn handle_rpush(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    push_generic(session, args, ListEnd::Right, false)
+}
+
+fn handle_lpushx(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    push_generic(session, args, ListEnd::Left, true)
+}
+
+fn handle_rpushx(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    push_generic(session, args, ListEnd::Right, true)
+}
+
+fn push_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    end: ListEnd,
+    only_existing: bool,
+) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let values = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    if get_list(&mut db, &key)?.is_none() && only_existing {
+        return Ok(Value::Number(0));
+    }
+    Ok(Value::Number(push(&mut db, &key, values, end)? as i64))
+}
+
+fn handle_lpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    pop_generic(session, args, ListEnd::Left)
+}
+
+fn handle_rpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    pop_generic(session, args, ListEnd::Right)
+}
+
+/// Implements `LPOP key [count]` and `RPOP key [count]`. Without count, a single element is
+/// returned instead of an array.
+fn pop_generic(session: &mut Session, args: Vec<Value>, end: ListEnd) -> CommandResult {
+    if args.len() > 2 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = match args.next() {
+        Some(count) => Some(usize::try_from(arg_i64(&count)?).map_err(|_| ERR_POSITIVE)?),
+        None => None,
+    };
+
+    let mut db = session.lock_db();
+    let values = match pop(&mut db, &key, end, count.unwrap_or(1))? {
+        Some(values) => values,
+        None => return Ok(Value::Null),
+    };
+
+    Ok(match count {
+        Some(_) => Value::Array(values.into_iter().map(Value::Blob).collect()),
+        None => values
+            .into_iter()
+            .next()
+            .map(Value::Blob)
+            .unwrap_or(Value::Null),
+    })
+}
+
+/// Parses the `numkeys key [key ...]` part shared by the LMPOP family. Returns the keys and the
+/// remaining arguments.
+pub(super) fn parse_numkeys(
+    args: Vec<Value>,
+) -> Result<(Vec<Bytes>, std::vec::IntoIter<Value>), CommandError> {
+    let mut args = args.into_iter();
+    let numkeys = arg_i64(&args.next().unwrap())?;
+    if numkeys <= 0 {
+        return Err("numkeys should be greater than 0".into());
+    }
+    if numkeys as usize > args.len() {
+        return Err(ERR_SYNTAX.into());
+    }
+    let keys = args
+        .by_ref()
+        .take(numkeys as usize)
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((keys, args))
+}
+
+/// Parses the optional `COUNT count` at the end of the LMPOP family.
+pub(super) fn parse_mpop_count(
+    mut args: impl Iterator<Item = Value>,
+) -> Result<usize, CommandError> {
+    let count = match args.next() {
+        None => return Ok(1),
+        Some(arg) if arg_option(&arg) == "COUNT" => arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?,
+        Some(_) => return Err(ERR_SYNTAX.into()),
+    };
+    if args.next().is_some() {
+        return Err(ERR_SYNTAX.into());
+    }
+    if count <= 0 {
+        return Err("count should be greater than 0".into());
+    }
+    Ok(count as usize)
+}
+
+/// Pops from the first non empty list among the keys, replying with the key name and the popped
+/// elements.
+pub(super) fn mpop(
+    db: &mut InternalDb,
+    keys: &[Bytes],
+    end: ListEnd,
+    count: usize,
+) -> Result<Option<Value>, CommandError> {
+    for key in keys {
+        if let Some(values) = pop(db, key, end, count)? {
+            return Ok(Some(Value::Array(vec![
+                Value::Blob(key.clone()),
+                Value::Array(values.into_iter().map(Value::Blob).collect()),
+            ])));
+        }
+    }
+    Ok(None)
+}
+
+/// Implements `LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+fn handle_lmpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let (keys, mut args) = parse_numkeys(args)?;
+    let end = ListEnd::parse(&args.next().ok_or(ERR_SYNTAX)?)?;
+    let count = parse_mpop_count(args)?;
+
+    let mut db = session.lock_db();
+    Ok(mpop(&mut db, &keys, end, count)?.unwrap_or(Value::Null))
+}
+
+fn handle_llen(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let len = get_list(&mut db, &key)?.map(|list| list.len()).unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_lindex(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let index = arg_i64(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None => return Ok(Value::Null),
+    };
+    Ok(list_index(index, list.len())
+        .and_then(|i| list.get(i))
+        .map(|v| Value::Blob(v.clone()))
+        .unwrap_or(Value::Null))
+}
+
+fn handle_lset(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let index = arg_i64(&args.next().unwrap())?;
+    let value = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = get_list(&mut db, &key)?.ok_or(ERR_NO_SUCH_KEY)?;
+    let element = list_index(index, list.len())
+        .and_then(|i| list.get_mut(i))
+        .ok_or(ERR_INDEX_OUT_OF_RANGE)?;
+    *element = value;
+    db.modified(&key, 1);
+    Ok(Value::Simple("OK".into()))
+}
+
+fn handle_lrange(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let start = arg_i64(&args.next().unwrap())?;
+    let stop = arg_i64(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None => return Ok(Value::Array(vec![])),
+    };
+    let values = match list_range(start, stop, list.len()) {
+        Some((start, stop)) => list
+            .iter()
+            .skip(start)
+            .take(stop - start + 1)
+            .map(|v| Value::Blob(v.clone()))
+            .collect(),
+        None => vec![],
+    };
+    Ok(Value::Array(values))
+}
+
+fn handle_ltrim(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let start = arg_i64(&args.next().unwrap())?;
+    let stop = arg_i64(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None => return Ok(Value::Simple("OK".into())),
+    };
+    let len = list.len();
+    match list_range(start, stop, len) {
+        Some((start, stop)) => list.trim(start, stop),
+        None => list.trim(1, 0),
+    }
+    let removed = len - list.len();
+    if list.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, removed);
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]`.
+fn handle_lpos(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let element = arg_bytes(args.next().unwrap())?;
+
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    while let Some(arg) = args.next() {
+        let option = arg_option(&arg);
+        let value = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+        match option.as_str() {
+            "RANK" => {
+                if value == 0 {
+                    return Err("RANK can't be zero: use 1 to start from the first match, \
+                        2 from the second ... or use negative to start from the end of the list"
+                        .into());
+                }
+                if value == i64::MIN {
+                    return Err("value is out of range".into());
+                }
+                rank = value;
+            }
+            "COUNT" if value < 0 => return Err("COUNT can't be negative".into()),
+            "COUNT" => count = Some(value as usize),
+            "MAXLEN" if value < 0 => return Err("MAXLEN can't be negative".into()),
+            "MAXLEN" => maxlen = value as usize,
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None if count.is_some() => return Ok(Value::Array(vec![])),
+        None => return Ok(Value::Null),
+    };
+
+    let len = list.len();
+    let limit = if maxlen == 0 { len } else { maxlen.min(len) };
+    let wanted = match count {
+        Some(0) => usize::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    let candidates: Box<dyn Iterator<Item = (usize, &Bytes)>> = if rank > 0 {
+        Box::new(list.iter().enumerate().take(limit))
+    } else {
+        Box::new(
+            list.iter()
+                .rev()
+                .enumerate()
+                .map(|(i, v)| (len - i - 1, v))
+                .take(limit),
+        )
+    };
+    let matches: Vec<Value> = candidates
+        .filter(|(_, v)| **v == element)
+        .skip(rank.unsigned_abs() as usize - 1)
+        .take(wanted)
+        .map(|(i, _)| Value::Number(i as i64))
+        .collect();
+
+    Ok(match count {
+        Some(_) => Value::Array(matches),
+        None => matches.into_iter().next().unwrap_or(Value::Null),
+    })
+}
+
+/// Implements `LREM key count element`. A positive count removes the first matching elements,
+/// a negative one removes the last ones, and zero removes all of them.
+fn handle_lrem(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = arg_i64(&args.next().unwrap())?;
+    let element = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None => return Ok(Value::Number(0)),
+    };
+
+    let total = list.iter().filter(|v| **v == element).count();
+    let limit = if count == 0 {
+        total
+    } else {
+        (count.unsigned_abs() as usize).min(total)
+    };
+    let skip = if count < 0 { total - limit } else { 0 };
+
+    let mut seen = 0;
+    list.retain(|v| {
+        if *v != element {
+            return true;
+        }
+        seen += 1;
+        seen <= skip || seen > skip + limit
+    });
+    if list.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, limit);
+    Ok(Value::Number(limit as i64))
+}
+
+/// Implements `LINSERT key BEFORE|AFTER pivot element`.
+fn handle_linsert(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let after = match arg_option(&args.next().unwrap()).as_str() {
+        "BEFORE" => false,
+        "AFTER" => true,
+        _ => return Err(ERR_SYNTAX.into()),
+    };
+    let pivot = arg_bytes(args.next().unwrap())?;
+    let element = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let list = match get_list(&mut db, &key)? {
+        Some(list) => list,
+        None => return Ok(Value::Number(0)),
+    };
+    let index = match list.iter().position(|v| *v == pivot) {
+        Some(index) => index,
+        None => return Ok(Value::Number(-1)),
+    };
+    list.insert(index + after as usize, element);
+    let len = list.len();
+    db.modified(&key, 1);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_rpoplpush(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let source = arg_bytes(args.next().unwrap())?;
+    let destination = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    Ok(move_element(
+        &mut db,
+        &source,
+        &destination,
+        ListEnd::Right,
+        ListEnd::Left,
+    )?
+    .map(Value::Blob)
+    .unwrap_or(Value::Null))
+}
+
+/// Implements `LMOVE source destination LEFT|RIGHT LEFT|RIGHT`.
+fn handle_lmove(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let source = arg_bytes(args.next().unwrap())?;
+    let destination = arg_bytes(args.next().unwrap())?;
+    let from = ListEnd::parse(&args.next().unwrap())?;
+    let to = ListEnd::parse(&args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    Ok(move_element(&mut db, &source, &destination, from, to)?
+        .map(Value::Blob)
+        .unwrap_or(Value::Null))
+}
+
+fn handle_blpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    blocking_pop(session, args, ListEnd::Left)
+}
+
+fn handle_brpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    blocking_pop(session, args, ListEnd::Right)
+}
+
+/// Implements `BLPOP key [key ...] timeout` and BRPOP, replying with the key name and the popped
+/// element.
+fn blocking_pop(session: &mut Session, mut args: Vec<Value>, end: ListEnd) -> CommandResult {
+    let timeout = parse_timeout(&args.pop().unwrap())?;
+    let keys = args
+        .into_iter()
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let reply = block_on(session, &keys, timeout, |db| {
+        for key in &keys {
+            if let Some(mut values) = pop(db, key, end, 1)? {
+                return Ok(Some(Value::Array(vec![
+                    Value::Blob(key.clone()),
+                    Value::Blob(values.remove(0)),
+                ])));
+            }
+        }
+        Ok(None)
+    })?;
+
+    // the blocking commands are logged as the non blocking command that served them.
+    if let Value::Array(items) = &reply {
+        if let Value::Blob(key) = &items[0] {
+            let name: &[u8] = match end {
+                ListEnd::Left => b"LPOP",
+                ListEnd::Right => b"RPOP",
+            };
+            session.propagate(vec![command(&[name, key])]);
+        }
+    }
+    Ok(reply)
+}
+
+/// Implements `BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+fn handle_blmpop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let timeout = parse_timeout(&args.next().unwrap())?;
+    let (keys, mut args) = parse_numkeys(args.collect())?;
+    let end = ListEnd::parse(&args.next().ok_or(ERR_SYNTAX)?)?;
+    let count = parse_mpop_count(args)?;
+
+    let reply = block_on(session, &keys, timeout, |db| mpop(db, &keys, end, count))?;
+    if let Value::Array(items) = &reply {
+        if let (Value::Blob(key), Value::Array(values)) = (&items[0], &items[1]) {
+            let name: &[u8] = match end {
+                ListEnd::Left => b"LPOP",
+                ListEnd::Right => b"RPOP",
+            };
+            let count = values.len().to_string();
+            session.propagate(vec![command(&[name, key, count.as_bytes()])]);
+        }
+    }
+    Ok(reply)
+}
+
+/// Implements `BRPOPLPUSH source destination timeout`.
+fn handle_brpoplpush(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let source = arg_bytes(args.next().unwrap())?;
+    let destination = arg_bytes(args.next().unwrap())?;
+    let timeout = parse_timeout(&args.next().unwrap())?;
+    blocking_move(
+        session,
+        source,
+        destination,
+        ListEnd::Right,
+        ListEnd::Left,
+        timeout,
+    )
+}
+
+/// Implements `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout`.
+fn handle_blmove(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let source = arg_bytes(args.next().unwrap())?;
+    let destination = arg_bytes(args.next().unwrap())?;
+    let from = ListEnd::parse(&args.next().unwrap())?;
+    let to = ListEnd::parse(&args.next().unwrap())?;
+    let timeout = parse_timeout(&args.next().unwrap())?;
+    blocking_move(session, source, destination, from, to, timeout)
+}
+
+fn blocking_move(
+    session: &mut Session,
+    source: Bytes,
+    destination: Bytes,
+    from: ListEnd,
+    to: ListEnd,
+    timeout: Option<Duration>,
+) -> CommandResult {
+    let reply = block_on(session, std::slice::from_ref(&source), timeout, |db| {
+        Ok(move_element(db, &source, &destination, from, to)?.map(Value::Blob))
+    })?;
+    if reply != Value::Null {
+        session.propagate(vec![command(&[
+            b"LMOVE",
+            &source,
+            &destination,
+            from.as_bytes(),
+            to.as_bytes(),
+        ])]);
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    fn blobs(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|v| Value::Blob((*v).into())).collect())
+    }
+
+    #[test]
+    fn test_push_pop_range() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(Value::Number(0), run(&["LPUSHX", "l", "a"]));
+        assert_eq!(Value::Number(3), run(&["RPUSH", "l", "c", "d", "e"]));
+        assert_eq!(Value::Number(5), run(&["LPUSH", "l", "b", "a"]));
+        assert_eq!(
+            blobs(&["a", "b", "c", "d", "e"]),
+            run(&["LRANGE", "l", "0", "-1"])
+        );
+        assert_eq!(blobs(&["d", "e"]), run(&["LRANGE", "l", "-2", "100"]));
+        assert_eq!(blobs(&[]), run(&["LRANGE", "l", "3", "1"]));
+        assert_eq!(Value::Blob("c".into()), run(&["LINDEX", "l", "-3"]));
+        assert_eq!(Value::Null, run(&["LINDEX", "l", "5"]));
+
+        assert_eq!(Value::Blob("a".into()), run(&["LPOP", "l"]));
+        assert_eq!(blobs(&["e", "d"]), run(&["RPOP", "l", "2"]));
+        assert_eq!(blobs(&[]), run(&["RPOP", "l", "0"]));
+        assert_eq!(
+            Value::err("value is out of range, must be positive"),
+            run(&["RPOP", "l", "-1"])
+        );
+        assert_eq!(blobs(&["b", "c"]), run(&["LPOP", "l", "10"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "l"]));
+        assert_eq!(Value::Null, run(&["LPOP", "l"]));
+        assert_eq!(Value::Null, run(&["LPOP", "l", "1"]));
+    }
+
+    #[test]
+    fn test_wrongtype() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let wrongtype = Value::Err(
+            "WRONGTYPE".to_string(),
+            "Operation against a key holding the wrong kind of value".to_string(),
+        );
+
+        run(&["SET", "s", "v"]);
+        run(&["RPUSH", "l", "v"]);
+        assert_eq!(wrongtype, run(&["LPUSH", "s", "v"]));
+        assert_eq!(wrongtype, run(&["LRANGE", "s", "0", "-1"]));
+        assert_eq!(wrongtype, run(&["GET", "l"]));
+        assert_eq!(wrongtype, run(&["LMOVE", "l", "s", "LEFT", "LEFT"]));
+        assert_eq!(Value::Number(1), run(&["LLEN", "l"]));
+        assert_eq!(Value::Simple("list".into()), run(&["TYPE", "l"]));
+        assert_eq!(Value::Simple("OK".into()), run(&["SET", "l", "v"]));
+        assert_eq!(Value::Simple("string".into()), run(&["TYPE", "l"]));
+    }
+
+    #[test]
+    fn test_modify() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["RPUSH", "l", "a", "b", "a", "c", "a", "b"]);
+        assert_eq!(Value::Number(1), run(&["LREM", "l", "-1", "a"]));
+        assert_eq!(
+            blobs(&["a", "b", "a", "c", "b"]),
+            run(&["LRANGE", "l", "0", "-1"])
+        );
+        assert_eq!(Value::Number(2), run(&["LREM", "l", "0", "a"]));
+        assert_eq!(Value::Number(4), run(&["LINSERT", "l", "AFTER", "c", "x"]));
+        assert_eq!(Value::Number(5), run(&["LINSERT", "l", "before", "b", "y"]));
+        assert_eq!(
+            Value::Number(-1),
+            run(&["LINSERT", "l", "before", "z", "y"])
+        );
+        assert_eq!(
+            Value::Number(0),
+            run(&["LINSERT", "nol", "before", "z", "y"])
+        );
+        assert_eq!(
+            blobs(&["y", "b", "c", "x", "b"]),
+            run(&["LRANGE", "l", "0", "-1"])
+        );
+
+        assert_eq!(Value::Simple("OK".into()), run(&["LSET", "l", "-1", "z"]));
+        assert_eq!(
+            Value::err("index out of range"),
+            run(&["LSET", "l", "5", "z"])
+        );
+        assert_eq!(Value::err("no such key"), run(&["LSET", "nol", "0", "z"]));
+
+        assert_eq!(Value::Simple("OK".into()), run(&["LTRIM", "l", "1", "-2"]));
+        assert_eq!(blobs(&["b", "c", "x"]), run(&["LRANGE", "l", "0", "-1"]));
+        assert_eq!(Value::Simple("OK".into()), run(&["LTRIM", "l", "2", "1"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "l"]));
+    }
+
+    #[test]
+    fn test_lpos() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["RPUSH", "l", "a", "b", "c", "1", "2", "3", "c", "c"]);
+        assert_eq!(Value::Number(2), run(&["LPOS", "l", "c"]));
+        assert_eq!(Value::Number(6), run(&["LPOS", "l", "c", "RANK", "2"]));
+        assert_eq!(Value::Number(7), run(&["LPOS", "l", "c", "RANK", "-1"]));
+        assert_eq!(
+            Value::Array(vec![Value::Number(2), Value::Number(6)]),
+            run(&["LPOS", "l", "c", "COUNT", "2"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Number(7), Value::Number(6), Value::Number(2)]),
+            run(&["LPOS", "l", "c", "RANK", "-1", "COUNT", "0"])
+        );
+        assert_eq!(Value::Null, run(&["LPOS", "l", "c", "MAXLEN", "2"]));
+        assert_eq!(Value::Null, run(&["LPOS", "l", "x"]));
+        assert_eq!(
+            Value::err("COUNT can't be negative"),
+            run(&["LPOS", "l", "c", "COUNT", "-1"])
+        );
+    }
+
+    #[test]
+    fn test_move() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["RPUSH", "src", "a", "b", "c"]);
+        assert_eq!(Value::Blob("c".into()), run(&["RPOPLPUSH", "src", "dst"]));
+        assert_eq!(
+            Value::Blob("a".into()),
+            run(&["LMOVE", "src", "dst", "LEFT", "RIGHT"])
+        );
+        assert_eq!(blobs(&["c", "a"]), run(&["LRANGE", "dst", "0", "-1"]));
+        assert_eq!(
+            Value::Blob("b".into()),
+            run(&["LMOVE", "src", "src", "LEFT", "RIGHT"])
+        );
+        assert_eq!(blobs(&["b"]), run(&["LRANGE", "src", "0", "-1"]));
+        assert_eq!(Value::Null, run(&["LMOVE", "none", "dst", "LEFT", "RIGHT"]));
+
+        assert_eq!(
+            Value::Array(vec![Value::Blob("src".into()), blobs(&["b"])]),
+            run(&["LMPOP", "3", "none", "src", "dst", "LEFT", "COUNT", "5"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Blob("dst".into()), blobs(&["a"])]),
+            run(&["LMPOP", "2", "src", "dst", "RIGHT"])
+        );
+        assert_eq!(
+            Value::err("numkeys should be greater than 0"),
+            run(&["LMPOP", "0", "src", "RIGHT"])
+        );
+        assert_eq!(
+            Value::err("syntax error"),
+            run(&["LMPOP", "3", "src", "RIGHT"])
+        );
+    }
+
+    #[test]
+    fn test_blocking_pop() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["RPUSH", "l", "a"]);
+        assert_eq!(blobs(&["l", "a"]), run(&["BLPOP", "none", "l", "0"]));
+        assert_eq!(Value::Null, run(&["BRPOP", "l", "0.01"]));
+        assert_eq!(
+            Value::err("timeout is negative"),
+            run(&["BLPOP", "l", "-1"])
+        );
+        assert_eq!(
+            Value::err("timeout is not a float or out of range"),
+            run(&["BLPOP", "l", "abc"])
+        );
+
+        // the clients blocked on a key are served in the order they blocked.
+        std::thread::scope(|s| {
+            let first = s.spawn(|| {
+                let mut session = factory.create_session();
+                session.handle_request(request(&["BLPOP", "l", "5"]))
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            let second = s.spawn(|| {
+                let mut session = factory.create_session();
+                session.handle_request(request(&["BLMOVE", "l", "dst", "RIGHT", "LEFT", "5"]))
+            });
+            std::thread::sleep(Duration::from_millis(50));
+
+            let mut session = factory.create_session();
+            session.handle_request(request(&["RPUSH", "l", "x", "y"]));
+            assert_eq!(blobs(&["l", "x"]), first.join().unwrap());
+            assert_eq!(Value::Blob("y".into()), second.join().unwrap());
+            assert_eq!(
+                blobs(&["y"]),
+                session.handle_request(request(&["LRANGE", "dst", "0", "-1"]))
+            );
+        });
+
+        // the element pushed after a blocked client is gone is left in the list.
+        std::thread::scope(|s| {
+            let mut gone = factory.create_session();
+            let abort_handle = gone.abort_handle.clone();
+            let gone = s.spawn(move || gone.handle_request(request(&["BLPOP", "kept", "0"])));
+            std::thread::sleep(Duration::from_millis(50));
+            abort_handle.abort();
+            assert_eq!(Value::Null, gone.join().unwrap());
+
+            let mut session = factory.create_session();
+            assert_eq!(
+                Value::Number(1),
+                session.handle_request(request(&["RPUSH", "kept", "a"]))
+            );
+            assert_eq!(
+                Value::Number(1),
+                session.handle_request(request(&["LLEN", "kept"]))
+            );
+            match session.handle_request(request(&["INFO", "clients"])) {
+                Value::Blob(info) => {
+                    assert!(String::from_utf8_lossy(&info).contains("blocked_clients:0"))
+                }
+                reply => panic!("unexpected reply {:?}", reply),
+            }
+        });
+    }
+}
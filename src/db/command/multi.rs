@@ -0,0 +1,244 @@
+use crate::db::{Session, Transaction, WatchedKey};
+use crate::value::Value;
+
+use super::{
+    arg_bytes, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_FAST, COMMAND_FLAG_SLOW,
+    COMMAND_FLAG_TRANSACTION,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], last_key, handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_TRANSACTION]].concat(),
+        first_key: if last_key == 0 { 0 } else { 1 },
+        last_key,
+        key_step: if last_key == 0 { 0 } else { 1 },
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        spec("MULTI", 1, &[COMMAND_FLAG_FAST], 0, handle_multi),
+        spec("EXEC", 1, &[COMMAND_FLAG_SLOW], 0, handle_exec),
+        spec("DISCARD", 1, &[COMMAND_FLAG_FAST], 0, handle_discard),
+        spec("WATCH", -2, &[COMMAND_FLAG_FAST], -1, handle_watch),
+        spec("UNWATCH", 1, &[COMMAND_FLAG_FAST], 0, handle_unwatch),
+    ]
+}
+
+fn handle_multi(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    if session.transaction.is_some() {
+        return Err("MULTI calls can not be nested".into());
+    }
+    session.transaction = Some(Transaction::default());
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `EXEC`. The queued commands run while holding the exec lock exclusively, so no
+/// other command runs in between them. The transaction is not run at all if one of the watched
+/// keys was modified since it was watched.
+fn handle_exec(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    let transaction = session.transaction.take().ok_or("EXEC without MULTI")?;
+    if transaction.aborted {
+        session.unwatch();
+        return Err(CommandError::Code(
+            "EXECABORT",
+            "Transaction discarded because of previous errors.".to_string(),
+        ));
+    }
+
+    session.exclusive(|session| {
+        let modified = session
+            .watched
+            .iter()
+            .any(|watched| watched.db.write().unwrap().version(&watched.key) != watched.version);
+        session.unwatch();
+        if modified {
+            return Ok(Value::Null);
+        }
+
+        session.executing = true;
+        let replies = transaction
+            .commands
+            .into_iter()
+            .map(|command| session.execute(&command.name, command.args))
+            .collect();
+        session.executing = false;
+        session.db.end_exec();
+        Ok(Value::Array(replies))
+    })
+}
+
+fn handle_discard(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    session.transaction.take().ok_or("DISCARD without MULTI")?;
+    session.unwatch();
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `WATCH key [key ...]`.
+fn handle_watch(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if session.transaction.is_some() {
+        return Err("WATCH inside MULTI is not allowed".into());
+    }
+    for key in args {
+        let key = arg_bytes(key)?;
+        let version = session.lock_db().watch(&key);
+        session.watched.push(WatchedKey {
+            db: session.selected_db.clone(),
+            key,
+            version,
+        });
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+fn handle_unwatch(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    session.unwatch();
+    Ok(Value::Simple("OK".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    fn ok() -> Value {
+        Value::Simple("OK".into())
+    }
+
+    fn queued() -> Value {
+        Value::Simple("QUEUED".into())
+    }
+
+    #[test]
+    fn test_multi_exec() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(ok(), run(&["MULTI"]));
+        assert_eq!(queued(), run(&["SET", "a", "1"]));
+        assert_eq!(queued(), run(&["SET", "a", "2"]));
+        assert_eq!(queued(), run(&["GET", "a"]));
+        assert_eq!(queued(), run(&["LPUSH", "a", "x"]));
+        assert_eq!(queued(), run(&["BLPOP", "l", "0"]));
+        assert_eq!(Value::err("MULTI calls can not be nested"), run(&["MULTI"]));
+        assert_eq!(
+            Value::Array(vec![
+                ok(),
+                ok(),
+                Value::Blob("2".into()),
+                Value::Err(
+                    "WRONGTYPE".into(),
+                    "Operation against a key holding the wrong kind of value".into()
+                ),
+                Value::Null,
+            ]),
+            run(&["EXEC"])
+        );
+        assert_eq!(Value::err("EXEC without MULTI"), run(&["EXEC"]));
+
+        assert_eq!(ok(), run(&["MULTI"]));
+        assert_eq!(queued(), run(&["SET", "a", "3"]));
+        assert_eq!(
+            Value::err("wrong number of arguments for 'get' command"),
+            run(&["GET"])
+        );
+        assert_eq!(
+            Value::Err(
+                "EXECABORT".into(),
+                "Transaction discarded because of previous errors.".into()
+            ),
+            run(&["EXEC"])
+        );
+        assert_eq!(Value::Blob("2".into()), run(&["GET", "a"]));
+
+        assert_eq!(ok(), run(&["MULTI"]));
+        assert_eq!(queued(), run(&["SET", "a", "3"]));
+        assert_eq!(ok(), run(&["DISCARD"]));
+        assert_eq!(Value::err("DISCARD without MULTI"), run(&["DISCARD"]));
+        assert_eq!(Value::Blob("2".into()), run(&["GET", "a"]));
+    }
+
+    #[test]
+    fn test_watch() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut other = factory.create_session();
+
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        run(&["SET", "a", "1"]);
+        run(&["RPUSH", "l", "x"]);
+        assert_eq!(ok(), run(&["WATCH", "a", "l"]));
+
+        // reading the watched keys doesn't count as a modification.
+        other.handle_request(request(&["LRANGE", "l", "0", "-1"]));
+        other.handle_request(request(&["GET", "a"]));
+        assert_eq!(ok(), run(&["MULTI"]));
+        assert_eq!(
+            Value::err("WATCH inside MULTI is not allowed"),
+            run(&["WATCH", "b"])
+        );
+        assert_eq!(queued(), run(&["SET", "a", "2"]));
+        assert_eq!(Value::Array(vec![ok()]), run(&["EXEC"]));
+
+        run(&["WATCH", "l"]);
+        other.handle_request(request(&["RPUSH", "l", "y"]));
+        run(&["MULTI"]);
+        run(&["SET", "a", "3"]);
+        assert_eq!(Value::Null, run(&["EXEC"]));
+        assert_eq!(Value::Blob("2".into()), run(&["GET", "a"]));
+
+        // EXEC unwatches the keys, even when it fails.
+        other.handle_request(request(&["RPUSH", "l", "z"]));
+        run(&["MULTI"]);
+        run(&["SET", "a", "3"]);
+        assert_eq!(Value::Array(vec![ok()]), run(&["EXEC"]));
+
+        run(&["WATCH", "a"]);
+        assert_eq!(ok(), run(&["UNWATCH"]));
+        other.handle_request(request(&["DEL", "a"]));
+        run(&["MULTI"]);
+        assert_eq!(Value::Array(vec![]), run(&["EXEC"]));
+    }
+
+    #[test]
+    fn test_watch_no_op() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut other = factory.create_session();
+
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        run(&["SET", "a", "1"]);
+        run(&["RPUSH", "l", "x"]);
+        run(&["HSET", "h", "f", "v"]);
+        run(&["SADD", "s", "m"]);
+        assert_eq!(ok(), run(&["WATCH", "a", "l", "h", "s", "missing"]));
+
+        // the writes that change nothing don't count as a modification.
+        for args in [
+            &["DEL", "missing"][..],
+            &["LREM", "l", "0", "y"],
+            &["PERSIST", "a"],
+            &["PERSIST", "missing"],
+            &["EXPIRE", "missing", "100"],
+            &["LPUSH", "a", "x"],
+            &["SADD", "l", "x"],
+            &["HDEL", "h", "g"],
+            &["SREM", "s", "n"],
+            &["SADD", "s", "m"],
+        ] {
+            other.handle_request(request(args));
+        }
+        run(&["MULTI"]);
+        run(&["SET", "b", "1"]);
+        assert_eq!(Value::Array(vec![ok()]), run(&["EXEC"]));
+
+        run(&["WATCH", "s"]);
+        other.handle_request(request(&["SREM", "s", "m"]));
+        run(&["MULTI"]);
+        run(&["SET", "b", "2"]);
+        assert_eq!(Value::Null, run(&["EXEC"]));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::db::{ChannelKind, Session};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_FAST,
+    COMMAND_FLAG_PUBSUB, COMMAND_FLAG_SLOW,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_PUBSUB]].concat(),
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+    let slow = [COMMAND_FLAG_SLOW];
+    let fast = [COMMAND_FLAG_FAST];
+
+    vec![
+        spec("SUBSCRIBE", -2, &slow, handle_subscribe),
+        spec("UNSUBSCRIBE", -1, &slow, handle_unsubscribe),
+        spec("PSUBSCRIBE", -2, &slow, handle_psubscribe),
+        spec("PUNSUBSCRIBE", -1, &slow, handle_punsubscribe),
+        spec("SSUBSCRIBE", -2, &slow, handle_ssubscribe),
+        spec("SUNSUBSCRIBE", -1, &slow, handle_sunsubscribe),
+        spec("PUBLISH", 3, &fast, handle_publish),
+        spec("SPUBLISH", 3, &fast, handle_spublish),
+        spec("PUBSUB", -2, &slow, handle_pubsub),
+    ]
+}
+
+fn handle_subscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    subscribe(session, args, ChannelKind::Channel)
+}
+
+fn handle_unsubscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    unsubscribe(session, args, ChannelKind::Channel)
+}
+
+fn handle_psubscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    subscribe(session, args, ChannelKind::Pattern)
+}
+
+fn handle_punsubscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    unsubscribe(session, args, ChannelKind::Pattern)
+}
+
+fn handle_ssubscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    subscribe(session, args, ChannelKind::Shard)
+}
+
+fn handle_sunsubscribe(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    unsubscribe(session, args, ChannelKind::Shard)
+}
+
+/// Replies with one confirmation per channel. Only the last one is returned as the reply of the
+/// command, the others are sent through the client's output before it, so that they are written
+/// in order.
+fn confirmations(session: &Session, mut replies: Vec<Value>) -> CommandResult {
+    let last = replies.pop().unwrap();
+    for reply in replies {
+        session.subscriber.send(reply);
+    }
+    Ok(last)
+}
+
+fn confirmation(kind: &str, channel: Option<Bytes>, count: usize) -> Value {
+    Value::Push(vec![
+        Value::Blob(kind.into()),
+        channel.map(Value::Blob).unwrap_or(Value::Null),
+        Value::Number(count as i64),
+    ])
+}
+
+/// Implements `SUBSCRIBE channel [channel ...]`, PSUBSCRIBE and SSUBSCRIBE.
+fn subscribe(session: &mut Session, args: Vec<Value>, kind: ChannelKind) -> CommandResult {
+    let name = match kind {
+        ChannelKind::Channel => "subscribe",
+        ChannelKind::Pattern => "psubscribe",
+        ChannelKind::Shard => "ssubscribe",
+    };
+
+    let mut replies = vec![];
+    for channel in args {
+        let channel = arg_bytes(channel)?;
+        if session.subscriptions.get_mut(kind).insert(channel.clone()) {
+            session
+                .db
+                .pubsub()
+                .subscribe(kind, &channel, &session.subscriber);
+        }
+        let count = session.subscriptions.count(kind);
+        replies.push(confirmation(name, Some(channel), count));
+    }
+    confirmations(session, replies)
+}
+
+/// Implements `UNSUBSCRIBE [channel ...]`, PUNSUBSCRIBE and SUNSUBSCRIBE. Without arguments, the
+/// client is unsubscribed from all the channels of that kind.
+fn unsubscribe(session: &mut Session, args: Vec<Value>, kind: ChannelKind) -> CommandResult {
+    let name = match kind {
+        ChannelKind::Channel => "unsubscribe",
+        ChannelKind::Pattern => "punsubscribe",
+        ChannelKind::Shard => "sunsubscribe",
+    };
+
+    let channels = if args.is_empty() {
+        let mut channels: Vec<Bytes> = session
+            .subscriptions
+            .get_mut(kind)
+            .iter()
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    } else {
+        args.into_iter().map(arg_bytes).collect::<Result<_, _>>()?
+    };
+    if channels.is_empty() {
+        return Ok(confirmation(name, None, session.subscriptions.count(kind)));
+    }
+
+    let mut replies = vec![];
+    for channel in channels {
+        if session.subscriptions.get_mut(kind).remove(&channel) {
+            session
+                .db
+                .pubsub()
+                .unsubscribe(kind, &channel, &session.subscriber);
+        }
+        let count = session.subscriptions.count(kind);
+        replies.push(confirmation(name, Some(channel), count));
+    }
+    confirmations(session, replies)
+}
+
+/// Implements `PUBLISH channel message`.
+fn handle_publish(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let channel = arg_bytes(args.next().unwrap())?;
+    let message = arg_bytes(args.next().unwrap())?;
+    let received = session.db.pubsub().publish(&channel, &message);
+    Ok(Value::Number(received as i64))
+}
+
+/// Implements `SPUBLISH shardchannel message`.
+fn handle_spublish(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let channel = arg_bytes(args.next().unwrap())?;
+    let message = arg_bytes(args.next().unwrap())?;
+    let received = session.db.pubsub().spublish(&channel, &message);
+    Ok(Value::Number(received as i64))
+}
+
+/// Implements the PUBSUB subcommands: `CHANNELS [pattern]`, `NUMSUB [channel ...]`, `NUMPAT`,
+/// `SHARDCHANNELS [pattern]` and `SHARDNUMSUB [shardchannel ...]`.
+fn handle_pubsub(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let pubsub = session.db.pubsub();
+
+    let channels = |kind, mut args: std::vec::IntoIter<Value>| -> CommandResult {
+        let pattern = args.next().map(arg_bytes).transpose()?;
+        if args.next().is_some() {
+            return Err(wrong_subcommand_arity(&subcommand));
+        }
+        let mut channels = pubsub.channels(kind, pattern.as_deref().map(|p| &p[..]));
+        channels.sort();
+        Ok(Value::Array(
+            channels.into_iter().map(Value::Blob).collect(),
+        ))
+    };
+    let numsub = |kind, args: std::vec::IntoIter<Value>| -> CommandResult {
+        let mut reply = vec![];
+        for channel in args {
+            let channel = arg_bytes(channel)?;
+            let count = pubsub.numsub(kind, &channel);
+            reply.push(Value::Blob(channel));
+            reply.push(Value::Number(count as i64));
+        }
+        Ok(Value::Array(reply))
+    };
+
+    match subcommand.as_str() {
+        "CHANNELS" => channels(ChannelKind::Channel, args),
+        "SHARDCHANNELS" => channels(ChannelKind::Shard, args),
+        "NUMSUB" => numsub(ChannelKind::Channel, args),
+        "SHARDNUMSUB" => numsub(ChannelKind::Shard, args),
+        "NUMPAT" if args.len() == 0 => Ok(Value::Number(pubsub.numpat() as i64)),
+        "NUMPAT" => Err(wrong_subcommand_arity(&subcommand)),
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try PUBSUB HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+fn wrong_subcommand_arity(subcommand: &str) -> CommandError {
+    format!(
+        "wrong number of arguments for 'pubsub|{}' command",
+        subcommand.to_lowercase()
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::command::tests::{request, session_factory};
+    use crate::db::pubsub::tests::decode;
+    use crate::value::Value;
+
+    fn blobs(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|v| Value::Blob((*v).into())).collect())
+    }
+
+    fn confirmation(kind: &str, channel: &str, count: i64) -> Value {
+        Value::Array(vec![
+            Value::Blob(kind.into()),
+            Value::Blob(channel.into()),
+            Value::Number(count),
+        ])
+    }
+
+    #[test]
+    fn test_subscribe_and_publish() {
+        let factory = session_factory();
+        let mut subscriber = factory.create_session();
+        let messages = subscriber.take_messages().unwrap();
+        let mut publisher = factory.create_session();
+
+        assert_eq!(
+            confirmation("subscribe", "b", 2),
+            subscriber.handle_request(request(&["SUBSCRIBE", "a", "b"]))
+        );
+        assert_eq!(
+            confirmation("subscribe", "a", 1),
+            decode(messages.try_recv().unwrap())
+        );
+        assert_eq!(
+            confirmation("psubscribe", "c*", 3),
+            subscriber.handle_request(request(&["PSUBSCRIBE", "c*"]))
+        );
+        assert_eq!(
+            Value::err(
+                "Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are \
+                 allowed in this context"
+            ),
+            subscriber.handle_request(request(&["GET", "a"]))
+        );
+        assert_eq!(
+            blobs(&["pong", ""]),
+            subscriber.handle_request(request(&["PING"]))
+        );
+
+        let mut publish = |args: &[&str]| publisher.handle_request(request(args));
+        assert_eq!(Value::Number(1), publish(&["PUBLISH", "a", "hello"]));
+        assert_eq!(Value::Number(1), publish(&["PUBLISH", "cat", "meow"]));
+        assert_eq!(Value::Number(0), publish(&["PUBLISH", "d", "nobody"]));
+        assert_eq!(
+            blobs(&["message", "a", "hello"]),
+            decode(messages.try_recv().unwrap())
+        );
+        assert_eq!(
+            blobs(&["pmessage", "c*", "cat", "meow"]),
+            decode(messages.try_recv().unwrap())
+        );
+
+        assert_eq!(blobs(&["a", "b"]), publish(&["PUBSUB", "CHANNELS"]));
+        assert_eq!(blobs(&["b"]), publish(&["PUBSUB", "CHANNELS", "b*"]));
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("a".into()),
+                Value::Number(1),
+                Value::Blob("z".into()),
+                Value::Number(0),
+            ]),
+            publish(&["PUBSUB", "NUMSUB", "a", "z"])
+        );
+        assert_eq!(Value::Number(1), publish(&["PUBSUB", "NUMPAT"]));
+
+        assert_eq!(
+            confirmation("unsubscribe", "b", 1),
+            subscriber.handle_request(request(&["UNSUBSCRIBE"]))
+        );
+        assert_eq!(
+            confirmation("unsubscribe", "a", 2),
+            decode(messages.try_recv().unwrap())
+        );
+        assert_eq!(
+            confirmation("punsubscribe", "c*", 0),
+            subscriber.handle_request(request(&["PUNSUBSCRIBE"]))
+        );
+        assert_eq!(
+            Value::Simple("PONG".into()),
+            subscriber.handle_request(request(&["PING"]))
+        );
+
+        // dropping the session unsubscribes it.
+        subscriber.handle_request(request(&["SSUBSCRIBE", "s"]));
+        assert_eq!(
+            Value::Number(1),
+            publisher.handle_request(request(&["SPUBLISH", "s", "x"]))
+        );
+        drop(subscriber);
+        assert_eq!(
+            Value::Number(0),
+            publisher.handle_request(request(&["SPUBLISH", "s", "x"]))
+        );
+    }
+}
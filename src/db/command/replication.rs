@@ -0,0 +1,213 @@
+use std::time::{Duration, Instant};
+
+use crate::db::{LinkState, Session};
+use crate::value::Value;
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandResult, CommandSpec, COMMAND_FLAG_ADMIN,
+    COMMAND_FLAG_CONNECTION, COMMAND_FLAG_FAST, COMMAND_FLAG_SLOW, ERR_NOT_INTEGER, ERR_SYNTAX,
+    ERR_TIMEOUT_NEGATIVE,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: flags.to_vec(),
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        spec(
+            "REPLICAOF",
+            3,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_replicaof,
+        ),
+        spec(
+            "SLAVEOF",
+            3,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_replicaof,
+        ),
+        spec(
+            "REPLCONF",
+            -1,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_replconf,
+        ),
+        spec(
+            "PSYNC",
+            -3,
+            &[COMMAND_FLAG_ADMIN, COMMAND_FLAG_SLOW],
+            handle_psync,
+        ),
+        spec("ROLE", 1, &[COMMAND_FLAG_FAST], handle_role),
+        spec(
+            "WAIT",
+            3,
+            &[COMMAND_FLAG_SLOW, COMMAND_FLAG_CONNECTION],
+            handle_wait,
+        ),
+    ]
+}
+
+/// Implements `REPLICAOF host port` and `REPLICAOF NO ONE`. The connection to the master is made
+/// in the background, see [`crate::replication`].
+fn handle_replicaof(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let [host, port]: [Value; 2] = args.try_into().unwrap();
+    if arg_option(&host) == "NO" && arg_option(&port) == "ONE" {
+        if session.db.replicaof(None) {
+            log::info!("MASTER MODE enabled (user request)");
+        }
+        return Ok(Value::Simple("OK".into()));
+    }
+
+    let host = arg_bytes(host)?
+        .into_string()
+        .map_err(|_| "Invalid master host")?;
+    let port = arg_i64(&port)
+        .ok()
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or("Invalid master port")?;
+    log::info!("REPLICAOF {}:{} enabled (user request)", host, port);
+    if !session.db.replicaof(Some((host, port))) {
+        return Ok(Value::Simple(
+            "OK Already connected to specified master".into(),
+        ));
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `REPLCONF option value [option value ...]`, sent by the replicas during the
+/// handshake. Only the port the replica listens on is used, the other options are accepted for
+/// compatibility.
+fn handle_replconf(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if !args.len().is_multiple_of(2) {
+        return Err(ERR_SYNTAX.into());
+    }
+    for pair in args.chunks(2) {
+        match arg_option(&pair[0]).as_str() {
+            "LISTENING-PORT" => {
+                session.replica_port = arg_i64(&pair[1])
+                    .ok()
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or(ERR_NOT_INTEGER)?;
+            }
+            "IP-ADDRESS" | "CAPA" | "ACK" | "GETACK" | "RDB-ONLY" => (),
+            _ => {
+                let option = arg_bytes(pair[0].clone())?;
+                return Err(format!(
+                    "Unrecognized REPLCONF option: {}",
+                    String::from_utf8_lossy(&option)
+                )
+                .into());
+            }
+        }
+    }
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `PSYNC replicationid offset`. The reply is not sent: the connection is handed over
+/// to the replication, which sends the reply along with the snapshot or the missing part of the
+/// stream, see [`Session::take_replica_sync`].
+fn handle_psync(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let connected = session
+        .db
+        .replication()
+        .master()
+        .is_none_or(|link| link.state == LinkState::Connected);
+    if !connected {
+        return Err("Can't SYNC while not connected with my master".into());
+    }
+
+    let mut args = args.into_iter();
+    let replid = arg_bytes(args.next().unwrap())?;
+    let offset = arg_i64(&args.next().unwrap())?;
+    let replid = String::from_utf8_lossy(&replid).to_string();
+    let addr = session
+        .peer_addr
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    let port = session.replica_port;
+    let sync = session.exclusive(|session| session.db.psync(&replid, offset, addr, port));
+    session.replica_sync = Some(sync);
+    Ok(Value::Null)
+}
+
+/// Implements `ROLE`, replying the role of the server along with its replicas or its master.
+fn handle_role(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    let replication = session.db.replication();
+    let offset = replication.offset() as i64;
+    let reply = match replication.master() {
+        None => vec![
+            Value::Blob("master".into()),
+            Value::Number(offset),
+            Value::Array(
+                replication
+                    .replicas()
+                    .iter()
+                    .map(|replica| {
+                        Value::Array(vec![
+                            Value::Blob(replica.addr.as_str().into()),
+                            Value::Blob(replica.port.to_string().as_str().into()),
+                            Value::Blob(replica.ack_offset.to_string().as_str().into()),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ],
+        Some(link) => vec![
+            Value::Blob("slave".into()),
+            Value::Blob(link.host.as_str().into()),
+            Value::Number(link.port as i64),
+            Value::Blob(link.state.as_str().into()),
+            Value::Number(if link.state == LinkState::Connected {
+                offset
+            } else {
+                -1
+            }),
+        ],
+    };
+    Ok(Value::Array(reply))
+}
+
+/// Implements `WAIT numreplicas timeout`, which blocks until enough replicas acknowledged the
+/// writes of the client, or until the timeout in milliseconds expires. Inside a transaction, it
+/// doesn't block.
+fn handle_wait(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if session.db.is_replica() {
+        return Err(
+            "WAIT cannot be used with replica instances. Please also note that since \
+                    Redis 4.0 if a replica is configured to be writable (which is not the \
+                    default) writes to replicas are just local and are not propagated."
+                .into(),
+        );
+    }
+    let numreplicas = usize::try_from(arg_i64(&args[0])?).unwrap_or(0);
+    let timeout = arg_i64(&args[1])?;
+    if timeout < 0 {
+        return Err(ERR_TIMEOUT_NEGATIVE.into());
+    }
+
+    if session.executing {
+        let acked =
+            session
+                .db
+                .wait_for_replicas(session.write_offset, numreplicas, Some(Instant::now()));
+        return Ok(Value::Number(acked as i64));
+    }
+    let deadline = Some(timeout as u64)
+        .filter(|&timeout| timeout > 0)
+        .map(|timeout| Instant::now() + Duration::from_millis(timeout));
+    session.suspend();
+    let acked = session
+        .db
+        .wait_for_replicas(session.write_offset, numreplicas, deadline);
+    session.resume();
+    Ok(Value::Number(acked as i64))
+}
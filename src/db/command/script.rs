@@ -0,0 +1,335 @@
+use crate::db::Session;
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_option, numkeys_keys, CommandError, CommandResult, CommandSpec,
+    COMMAND_FLAG_SCRIPTING, COMMAND_FLAG_SLOW,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: vec![COMMAND_FLAG_SLOW, COMMAND_FLAG_SCRIPTING],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+
+    let eval = |name: &str, handler| CommandSpec {
+        get_keys: Some(eval_keys),
+        ..spec(name, -3, handler)
+    };
+
+    vec![
+        eval("EVAL", handle_eval),
+        eval("EVALSHA", handle_evalsha),
+        eval("EVAL_RO", handle_eval_ro),
+        eval("EVALSHA_RO", handle_evalsha_ro),
+        spec("SCRIPT", -2, handle_script),
+    ]
+}
+
+/// Finds the keys of `EVAL script numkeys [key [key ...]] [arg [arg ...]]`.
+fn eval_keys(args: &[Value]) -> Vec<&[u8]> {
+    numkeys_keys(args, 1)
+}
+
+fn handle_eval(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    eval_generic(session, args, false, false)
+}
+
+fn handle_evalsha(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    eval_generic(session, args, true, false)
+}
+
+fn handle_eval_ro(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    eval_generic(session, args, false, true)
+}
+
+fn handle_evalsha_ro(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    eval_generic(session, args, true, true)
+}
+
+/// Implements `EVAL script numkeys [key ...] [arg ...]` and its variants, given the script or
+/// its SHA1. The scripts run with EVAL are cached like the ones loaded with SCRIPT LOAD.
+fn eval_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    by_sha: bool,
+    read_only: bool,
+) -> CommandResult {
+    let mut args = args.into_iter();
+    let script = arg_bytes(args.next().unwrap())?;
+    let numkeys = arg_i64(&args.next().unwrap())?;
+    let args = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+    if numkeys < 0 {
+        return Err("Number of keys can't be negative".into());
+    }
+    if numkeys as usize > args.len() {
+        return Err("Number of keys can't be greater than number of args".into());
+    }
+    let mut keys = args;
+    let argv = keys.split_off(numkeys as usize);
+
+    let scripting = session.db.scripting();
+    let (sha, body) = if by_sha {
+        let sha = String::from_utf8_lossy(&script).to_ascii_lowercase();
+        let body = scripting.get(&sha).ok_or_else(|| {
+            CommandError::Code("NOSCRIPT", "No matching script. Please use EVAL.".into())
+        })?;
+        (sha, body)
+    } else {
+        (scripting.load(script.clone())?, script)
+    };
+    Ok(session.exclusive(|session| scripting.run(session, &sha, &body, keys, argv, read_only)))
+}
+
+/// Implements the `SCRIPT` subcommands.
+fn handle_script(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let args: Vec<Bytes> = args.map(arg_bytes).collect::<Result<_, _>>()?;
+    let scripting = session.db.scripting();
+    let arity = |ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(CommandError::from(format!(
+                "wrong number of arguments for 'script|{}' command",
+                subcommand.to_lowercase()
+            )))
+        }
+    };
+
+    match subcommand.as_str() {
+        "LOAD" => {
+            arity(args.len() == 1)?;
+            let sha = scripting.load(args.into_iter().next().unwrap())?;
+            Ok(Value::Blob(sha.as_str().into()))
+        }
+        "EXISTS" => {
+            arity(!args.is_empty())?;
+            Ok(Value::Array(
+                args.iter()
+                    .map(|sha| {
+                        let sha = String::from_utf8_lossy(sha);
+                        Value::Number(scripting.exists(&sha) as i64)
+                    })
+                    .collect(),
+            ))
+        }
+        "FLUSH" => {
+            arity(args.len() <= 1)?;
+            if let Some(mode) = args.first() {
+                if !mode.eq_ignore_ascii_case(b"ASYNC") && !mode.eq_ignore_ascii_case(b"SYNC") {
+                    return Err("SCRIPT FLUSH only support SYNC|ASYNC option".into());
+                }
+            }
+            scripting.flush();
+            Ok(Value::Simple("OK".into()))
+        }
+        "KILL" => {
+            arity(args.is_empty())?;
+            scripting.kill()?;
+            Ok(Value::Simple("OK".into()))
+        }
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try SCRIPT HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::config::Config;
+    use crate::db::command::tests::{request, session_factory};
+    use crate::db::{Database, SessionFactory};
+    use crate::value::Value;
+
+    fn ok() -> Value {
+        Value::Simple("OK".into())
+    }
+
+    #[test]
+    fn test_eval() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            ok(),
+            run(&[
+                "EVAL",
+                "return redis.call('SET', KEYS[1], ARGV[1])",
+                "1",
+                "a",
+                "1"
+            ])
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::Number(2),
+                Value::Blob("1".into()),
+                Value::Number(3),
+                Value::Number(1),
+                Value::Null,
+            ]),
+            run(&[
+                "EVAL",
+                "return {redis.call('HINCRBY', 'h', 'f', 2), ARGV[1], 3.7, true, false, nil, 5}",
+                "0",
+                "1"
+            ])
+        );
+        assert_eq!(
+            Value::Array(vec![ok(), Value::Null]),
+            run(&[
+                "EVAL",
+                "local set = redis.call('SET', 'b', 'x') \
+                 return {redis.status_reply(set.ok), redis.call('GET', 'missing')}",
+                "0"
+            ])
+        );
+
+        // redis.call raises the errors, redis.pcall returns them.
+        let wrongtype = Value::Err(
+            "WRONGTYPE".into(),
+            "Operation against a key holding the wrong kind of value".into(),
+        );
+        assert_eq!(
+            wrongtype,
+            run(&["EVAL", "redis.call('LPUSH', 'a', 'x') return 1", "0"])
+        );
+        assert_eq!(
+            wrongtype,
+            run(&["EVAL", "return redis.pcall('LPUSH', 'a', 'x')", "0"])
+        );
+        assert_eq!(
+            Value::Blob("WRONGTYPE".into()),
+            run(&[
+                "EVAL",
+                "local reply = redis.pcall('LPUSH', 'a', 'x') return string.sub(reply.err, 1, 9)",
+                "0"
+            ])
+        );
+        assert_eq!(
+            Value::Err("MY".into(), "error".into()),
+            run(&["EVAL", "return redis.error_reply('MY error')", "0"])
+        );
+        assert_eq!(
+            Value::err("This Redis command is not allowed from script"),
+            run(&["EVAL", "return redis.call('MULTI')", "0"])
+        );
+        assert!(matches!(
+            run(&["EVAL", "return x +", "0"]),
+            Value::Err(_, msg) if msg.starts_with("Error compiling script")
+        ));
+        assert!(matches!(
+            run(&["EVAL", "error('boom')", "0"]),
+            Value::Err(_, msg) if msg.starts_with("Error running script") && msg.ends_with("boom")
+        ));
+        assert!(matches!(
+            run(&["EVAL", "x = 1", "0"]),
+            Value::Err(_, msg) if msg.ends_with("Script attempted to create global variable 'x'")
+        ));
+        assert_eq!(Value::Null, run(&["EVAL", "return x", "0"]));
+        assert!(matches!(
+            run(&["EVAL", "setmetatable(_G, nil)", "0"]),
+            Value::Err(_, msg) if msg.ends_with("cannot change a protected metatable")
+        ));
+        assert_eq!(
+            Value::err("Number of keys can't be greater than number of args"),
+            run(&["EVAL", "return 1", "2", "a"])
+        );
+
+        // the database selected by the script doesn't leak to the client.
+        assert_eq!(
+            ok(),
+            run(&[
+                "EVAL",
+                "redis.call('SELECT', 1) return redis.call('SET', 'a', 'x')",
+                "0"
+            ])
+        );
+        assert_eq!(Value::Blob("1".into()), run(&["GET", "a"]));
+    }
+
+    #[test]
+    fn test_evalsha() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        let sha = "1b936e3fe509bcbc9cd0664897bbe8fd0cac101b";
+        assert_eq!(
+            Value::Blob(sha.into()),
+            run(&["SCRIPT", "LOAD", "return 'hello'"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Number(1), Value::Number(0)]),
+            run(&["SCRIPT", "EXISTS", &sha.to_uppercase(), "abc"])
+        );
+        assert_eq!(Value::Blob("hello".into()), run(&["EVALSHA", sha, "0"]));
+        assert_eq!(ok(), run(&["SCRIPT", "FLUSH"]));
+        assert_eq!(
+            Value::Err(
+                "NOSCRIPT".into(),
+                "No matching script. Please use EVAL.".into()
+            ),
+            run(&["EVALSHA", sha, "0"])
+        );
+
+        run(&["SET", "a", "1"]);
+        assert_eq!(
+            Value::Blob("1".into()),
+            run(&["EVAL_RO", "return redis.call('GET', KEYS[1])", "1", "a"])
+        );
+        assert_eq!(
+            Value::err("Write commands are not allowed from read-only scripts."),
+            run(&["EVAL_RO", "return redis.call('DEL', KEYS[1])", "1", "a"])
+        );
+        assert_eq!(Value::Number(1), run(&["EXISTS", "a"]));
+    }
+
+    #[test]
+    fn test_script_kill() {
+        let factory = SessionFactory::new(Database::new(&Config {
+            lua_time_limit: 50,
+            ..Config::default()
+        }));
+        let mut session = factory.create_session();
+        let mut other = factory.create_session();
+        assert_eq!(
+            Value::Err(
+                "NOTBUSY".into(),
+                "No scripts in execution right now.".into()
+            ),
+            other.handle_request(request(&["SCRIPT", "KILL"]))
+        );
+
+        thread::scope(|s| {
+            let script = s.spawn(move || {
+                session.handle_request(request(&["EVAL", "while true do end", "0"]))
+            });
+            thread::sleep(Duration::from_millis(200));
+            assert!(matches!(
+                other.handle_request(request(&["GET", "a"])),
+                Value::Err(code, _) if code == "BUSY"
+            ));
+            assert_eq!(ok(), other.handle_request(request(&["SCRIPT", "KILL"])));
+            assert!(matches!(
+                script.join().unwrap(),
+                Value::Err(_, msg) if msg.ends_with("Script killed by user with SCRIPT KILL...")
+            ));
+            assert_eq!(Value::Null, other.handle_request(request(&["GET", "a"])));
+        });
+    }
+}
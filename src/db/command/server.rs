@@ -0,0 +1,758 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use crate::config::{canonical_name, MUTABLE_DIRECTIVES};
+use crate::db::{now_millis, ChannelKind, LinkState, Session};
+use crate::glob::glob_match;
+use crate::value::Value;
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_ADMIN,
+    COMMAND_FLAG_FAST, COMMAND_FLAG_SLOW, ERR_SYNTAX,
+};
+
+const ERR_BGSAVE_IN_PROGRESS: &str = "Background save already in progress";
+const ERR_BGREWRITEAOF_IN_PROGRESS: &str =
+    "Background append only file rewriting already in progress";
+
+/// The version of redis whose behavior is followed, as reported to the clients.
+pub const REDIS_VERSION: &str = "7.0.0";
+
+// The sections of INFO in the order they are listed, and whether they are part of the default
+// sections.
+const INFO_SECTIONS: &[(&str, bool)] = &[
+    ("server", true),
+    ("clients", true),
+    ("memory", true),
+    ("persistence", true),
+    ("stats", true),
+    ("replication", true),
+    ("cluster", true),
+    ("keyspace", true),
+    ("commandstats", false),
+];
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_ADMIN]].concat(),
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        get_keys: None,
+        handler,
+    };
+
+    vec![
+        spec("SAVE", 1, &[COMMAND_FLAG_SLOW], handle_save),
+        spec("BGSAVE", -1, &[COMMAND_FLAG_SLOW], handle_bgsave),
+        spec("SHUTDOWN", -1, &[COMMAND_FLAG_SLOW], handle_shutdown),
+        spec("LASTSAVE", 1, &[COMMAND_FLAG_FAST], handle_lastsave),
+        spec("BGREWRITEAOF", 1, &[COMMAND_FLAG_SLOW], handle_bgrewriteaof),
+        spec("CONFIG", -2, &[COMMAND_FLAG_SLOW], handle_config),
+        spec("SLOWLOG", -2, &[COMMAND_FLAG_SLOW], handle_slowlog),
+        spec("INFO", -1, &[COMMAND_FLAG_SLOW], handle_info),
+    ]
+}
+
+/// Implements `SAVE`, which writes the RDB file while every other client waits.
+fn handle_save(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    if session.db.bgsave_in_progress() {
+        return Err(ERR_BGSAVE_IN_PROGRESS.into());
+    }
+    session
+        .exclusive(|session| session.db.save())
+        .map_err(|err| format!("Failed to save the RDB file: {}", err))?;
+    Ok(Value::Simple("OK".into()))
+}
+
+/// Implements `SHUTDOWN [NOSAVE|SAVE]`, which syncs the AOF, saves the keys if needed and exits.
+/// The server keeps running if they can't be saved. NOSAVE is also accepted while a script is
+/// busy, the exec lock being held by the script then.
+fn handle_shutdown(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let result = match args.as_slice() {
+        [] => session.exclusive(|session| session.db.prepare_shutdown(None)),
+        [option] if arg_option(option) == "SAVE" => {
+            session.exclusive(|session| session.db.prepare_shutdown(Some(true)))
+        }
+        [option] if arg_option(option) == "NOSAVE" => session.db.prepare_shutdown(Some(false)),
+        _ => return Err(ERR_SYNTAX.into()),
+    };
+    if let Err(err) = result {
+        log::error!("Error trying to shut down: {}", err);
+        return Err("Errors trying to SHUTDOWN. Check logs.".into());
+    }
+    log::info!("Redis is now ready to exit, bye bye...");
+    std::process::exit(0);
+}
+
+/// Implements `BGSAVE [SCHEDULE]`. There is nothing that can delay a background save, so
+/// SCHEDULE is accepted but has no effect.
+fn handle_bgsave(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    match args.as_slice() {
+        [] => (),
+        [option] if arg_option(option) == "SCHEDULE" => (),
+        _ => return Err(ERR_SYNTAX.into()),
+    }
+    if !session.exclusive(|session| session.db.bgsave()) {
+        return Err(ERR_BGSAVE_IN_PROGRESS.into());
+    }
+    Ok(Value::Simple("Background saving started".into()))
+}
+
+fn handle_lastsave(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    Ok(Value::Number(session.db.last_save() as i64))
+}
+
+/// Implements `BGREWRITEAOF`, which compacts the AOF into the commands that rebuild the current
+/// keys. The clients keep writing to the old file meanwhile.
+fn handle_bgrewriteaof(session: &mut Session, _: Vec<Value>) -> CommandResult {
+    if !session.exclusive(|session| session.db.bgrewriteaof()) {
+        return Err(ERR_BGREWRITEAOF_IN_PROGRESS.into());
+    }
+    Ok(Value::Simple(
+        "Background append only file rewriting started".into(),
+    ))
+}
+
+fn arg_string(arg: Value) -> Result<String, CommandError> {
+    Ok(String::from_utf8_lossy(&arg_bytes(arg)?).to_string())
+}
+
+fn subcommand_arity(command: &str, subcommand: &str, ok: bool) -> Result<(), CommandError> {
+    if ok {
+        return Ok(());
+    }
+    Err(format!(
+        "wrong number of arguments for '{}|{}' command",
+        command,
+        subcommand.to_lowercase()
+    )
+    .into())
+}
+
+/// Implements the `CONFIG` subcommands.
+fn handle_config(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let args: Vec<Value> = args.collect();
+    let arity = |ok: bool| subcommand_arity("config", &subcommand, ok);
+
+    match subcommand.as_str() {
+        "GET" => {
+            arity(!args.is_empty())?;
+            let patterns = args
+                .into_iter()
+                .map(arg_bytes)
+                .collect::<Result<Vec<_>, _>>()?;
+            let config = session.db.config();
+            let mut reply = vec![];
+            for (name, values) in config.directives() {
+                if patterns
+                    .iter()
+                    .any(|pattern| glob_match(pattern, name.as_bytes(), true))
+                {
+                    reply.push((
+                        Value::Blob(name.into()),
+                        Value::Blob(values.join(" ").as_str().into()),
+                    ));
+                }
+            }
+            Ok(Value::Map(reply))
+        }
+        "SET" => {
+            arity(!args.is_empty() && args.len().is_multiple_of(2))?;
+            session.exclusive(|session| config_set(session, args))?;
+            Ok(Value::Simple("OK".into()))
+        }
+        "RESETSTAT" => {
+            arity(args.is_empty())?;
+            session.db.stats().reset();
+            Ok(Value::Simple("OK".into()))
+        }
+        "REWRITE" => {
+            arity(args.is_empty())?;
+            let config = session.db.config();
+            let path = config
+                .config_file
+                .as_ref()
+                .ok_or("The server is running without a config file")?;
+            config
+                .rewrite(path)
+                .map_err(|err| format!("Rewriting config file: {}", err))?;
+            log::info!("CONFIG REWRITE executed with success.");
+            Ok(Value::Simple("OK".into()))
+        }
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try CONFIG HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+/// Implements `CONFIG SET parameter value [parameter value ...]`. Either every parameter is set,
+/// or none is.
+fn config_set(session: &mut Session, args: Vec<Value>) -> Result<(), CommandError> {
+    let mut config = session.db.config().clone();
+    let known: Vec<&str> = config.directives().iter().map(|(name, _)| *name).collect();
+    let mut names = HashSet::new();
+    let mut args = args.into_iter();
+    while let (Some(name), Some(value)) = (args.next(), args.next()) {
+        let name = canonical_name(&arg_string(name)?);
+        let value = arg_string(value)?;
+        let failed = |msg: &str| {
+            CommandError::from(format!(
+                "CONFIG SET failed (possibly related to argument '{}') - {}",
+                name, msg
+            ))
+        };
+        if !known.contains(&name.as_str()) {
+            return Err(format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            )
+            .into());
+        }
+        if !names.insert(name.clone()) {
+            return Err(failed("duplicate parameter"));
+        }
+        if !MUTABLE_DIRECTIVES.contains(&name.as_str()) {
+            return Err(failed("can't set immutable config"));
+        }
+        // the save rules are given in a single value, like "3600 1 300 100".
+        let values = match name.as_str() {
+            "save" if !value.trim().is_empty() => {
+                value.split_whitespace().map(str::to_string).collect()
+            }
+            _ => vec![value],
+        };
+        config.set(&name, &values).map_err(|msg| failed(&msg))?;
+    }
+    session.db.set_config(config);
+    Ok(())
+}
+
+/// Implements the `SLOWLOG` subcommands.
+fn handle_slowlog(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let subcommand = arg_option(&args.next().unwrap());
+    let args: Vec<Value> = args.collect();
+    let arity = |ok: bool| subcommand_arity("slowlog", &subcommand, ok);
+
+    match subcommand.as_str() {
+        "GET" => {
+            arity(args.len() <= 1)?;
+            let count = match args.first() {
+                Some(count) => arg_i64(count)?,
+                None => 10,
+            };
+            if count < -1 {
+                return Err("count should be greater than or equal to -1".into());
+            }
+            let count = usize::try_from(count).unwrap_or(usize::MAX);
+            Ok(session.db.slowlog().entries(count))
+        }
+        "LEN" => {
+            arity(args.is_empty())?;
+            Ok(Value::Number(session.db.slowlog().len() as i64))
+        }
+        "RESET" => {
+            arity(args.is_empty())?;
+            session.db.slowlog().reset();
+            Ok(Value::Simple("OK".into()))
+        }
+        _ => Err(format!(
+            "unknown subcommand '{}'. Try SLOWLOG HELP.",
+            subcommand.to_lowercase()
+        )
+        .into()),
+    }
+}
+
+/// Implements `INFO [section ...]`. Without sections, the default ones are returned, and
+/// `all` or `everything` return every section. Unlike in redis, `used_memory` is only the
+/// estimated size of the keys and their values, the one compared to `maxmemory`: the memory used
+/// by the server itself, like the buffers of the clients, isn't included.
+fn handle_info(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut sections = HashSet::new();
+    let default_sections = INFO_SECTIONS.iter().filter(|(_, default)| *default);
+    if args.is_empty() {
+        sections.extend(default_sections.map(|(name, _)| name.to_string()));
+    } else {
+        for arg in args {
+            match arg_string(arg)?.to_lowercase().as_str() {
+                "default" => {
+                    sections.extend(default_sections.clone().map(|(name, _)| name.to_string()))
+                }
+                "all" | "everything" => {
+                    sections.extend(INFO_SECTIONS.iter().map(|(name, _)| name.to_string()))
+                }
+                name => {
+                    sections.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    let mut info = String::new();
+    for (name, _) in INFO_SECTIONS
+        .iter()
+        .filter(|(name, _)| sections.contains(*name))
+    {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        let title = name[..1].to_uppercase() + &name[1..];
+        let _ = write!(info, "# {}\r\n", title);
+        for (field, value) in info_section(session, name) {
+            let _ = write!(info, "{}:{}\r\n", field, value);
+        }
+    }
+    Ok(Value::Verbatim("txt".to_string(), info.as_str().into()))
+}
+
+/// Returns the fields of an INFO section, named like the ones of redis.
+fn info_section(session: &Session, name: &str) -> Vec<(String, String)> {
+    let db = session.db;
+    let stats = db.stats();
+    let mut fields: Vec<(String, String)> = vec![];
+    let mut field =
+        |name: &str, value: &dyn ToString| fields.push((name.to_string(), value.to_string()));
+    match name {
+        "server" => {
+            let config = db.config();
+            let uptime = db.uptime().as_secs();
+            field("redis_version", &REDIS_VERSION);
+            field("redirs_version", &env!("CARGO_PKG_VERSION"));
+            let mode = if db.cluster().is_some() {
+                "cluster"
+            } else {
+                "standalone"
+            };
+            field("redis_mode", &mode);
+            field(
+                "os",
+                &format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            );
+            field("arch_bits", &usize::BITS);
+            field("process_id", &std::process::id());
+            field("run_id", &db.run_id());
+            field("tcp_port", &config.port);
+            field("server_time_usec", &(now_millis() * 1000));
+            field("uptime_in_seconds", &uptime);
+            field("uptime_in_days", &(uptime / 86400));
+            field("hz", &10);
+            let executable = std::env::current_exe().unwrap_or_default();
+            field("executable", &executable.display());
+            let config_file = config.config_file.clone().unwrap_or_default();
+            field("config_file", &config_file.display());
+        }
+        "clients" => {
+            field(
+                "connected_clients",
+                &stats.connected_clients.load(Ordering::Relaxed),
+            );
+            field("maxclients", &db.config().maxclients);
+            field("blocked_clients", &db.blocked_clients());
+        }
+        "memory" => {
+            let used = db.used_memory();
+            let peak = stats
+                .used_memory_peak
+                .fetch_max(used, Ordering::Relaxed)
+                .max(used);
+            let config = db.config();
+            field("used_memory", &used);
+            field("used_memory_human", &bytes_to_human(used as u64));
+            field("used_memory_peak", &peak);
+            field("used_memory_peak_human", &bytes_to_human(peak as u64));
+            field("maxmemory", &config.maxmemory);
+            field("maxmemory_human", &bytes_to_human(config.maxmemory));
+            field("maxmemory_policy", &config.maxmemory_policy.name());
+        }
+        "persistence" => {
+            field("loading", &0);
+            field("rdb_changes_since_last_save", &db.changes_since_last_save());
+            field("rdb_bgsave_in_progress", &(db.bgsave_in_progress() as u8));
+            field("rdb_last_save_time", &db.last_save());
+            let status = if db.last_bgsave_ok() { "ok" } else { "err" };
+            field("rdb_last_bgsave_status", &status);
+            field("aof_enabled", &(db.aof_on() as u8));
+            field(
+                "aof_rewrite_in_progress",
+                &(db.aof_rewrite_in_progress() as u8),
+            );
+        }
+        "stats" => {
+            let counter = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+            field(
+                "total_connections_received",
+                &counter(&stats.connections_received),
+            );
+            field(
+                "total_commands_processed",
+                &counter(&stats.commands_processed),
+            );
+            field(
+                "instantaneous_ops_per_sec",
+                &stats.instantaneous_ops_per_sec(),
+            );
+            field(
+                "rejected_connections",
+                &counter(&stats.rejected_connections),
+            );
+            field("expired_keys", &counter(&stats.expired_keys));
+            field("evicted_keys", &counter(&stats.evicted_keys));
+            field("keyspace_hits", &counter(&stats.keyspace_hits));
+            field("keyspace_misses", &counter(&stats.keyspace_misses));
+            let channels = db.pubsub().channels(ChannelKind::Channel, None).len();
+            field("pubsub_channels", &channels);
+            field("pubsub_patterns", &db.pubsub().numpat());
+            field("total_error_replies", &counter(&stats.error_replies));
+        }
+        "replication" => {
+            let replication = db.replication();
+            match replication.master() {
+                None => field("role", &"master"),
+                Some(link) => {
+                    field("role", &"slave");
+                    field("master_host", &link.host);
+                    field("master_port", &link.port);
+                    let status = if link.state == LinkState::Connected {
+                        "up"
+                    } else {
+                        "down"
+                    };
+                    field("master_link_status", &status);
+                    field(
+                        "master_sync_in_progress",
+                        &((link.state == LinkState::Sync) as u8),
+                    );
+                    field("slave_repl_offset", &replication.offset());
+                }
+            }
+            field("connected_slaves", &replication.replicas().len());
+            for (i, replica) in replication.replicas().iter().enumerate() {
+                let value = format!(
+                    "ip={},port={},state=online,offset={}",
+                    replica.addr, replica.port, replica.ack_offset
+                );
+                field(&format!("slave{}", i), &value);
+            }
+            field("master_replid", &replication.replid());
+            field("master_repl_offset", &replication.offset());
+        }
+        "cluster" => field("cluster_enabled", &(db.cluster().is_some() as u8)),
+        "keyspace" => {
+            for (index, keys, expires, avg_ttl) in db.keyspace() {
+                let value = format!("keys={},expires={},avg_ttl={}", keys, expires, avg_ttl);
+                field(&format!("db{}", index), &value);
+            }
+        }
+        "commandstats" => {
+            for (name, command) in stats.command_stats() {
+                let calls = command.calls.load(Ordering::Relaxed);
+                let usec = command.usec.load(Ordering::Relaxed);
+                let value = format!(
+                    "calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                    calls,
+                    usec,
+                    usec as f64 / calls.max(1) as f64,
+                    command.rejected_calls.load(Ordering::Relaxed),
+                    command.failed_calls.load(Ordering::Relaxed)
+                );
+                field(&format!("cmdstat_{}", name.to_lowercase()), &value);
+            }
+        }
+        _ => (),
+    }
+    fields
+}
+
+/// Formats a number of bytes like redis, e.g. `1.50M`.
+fn bytes_to_human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::command::tests::{request, session_factory};
+    use crate::db::{now_millis, Database, SessionFactory};
+    use crate::value::Value;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("redirs-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            dir: dir.to_str().unwrap().to_string(),
+            ..Config::default()
+        };
+
+        let factory = SessionFactory::new(Database::new(&config));
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        run(&["SET", "a", "1"]);
+        run(&["RPUSH", "l", "x", "y"]);
+        run(&["SELECT", "3"]);
+        run(&["HSET", "h", "f", "v"]);
+        run(&["PEXPIRE", "h", "100000"]);
+
+        assert_eq!(Value::Simple("OK".into()), run(&["SAVE"]));
+        let lastsave = run(&["LASTSAVE"]);
+        assert_eq!(Value::Number((now_millis() / 1000) as i64), lastsave);
+        assert_eq!(Value::err("syntax error"), run(&["BGSAVE", "NOW"]));
+
+        run(&["SET", "b", "2"]);
+        assert_eq!(
+            Value::Simple("Background saving started".into()),
+            run(&["BGSAVE", "SCHEDULE"])
+        );
+        while factory.database().bgsave_in_progress() {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let database = Database::new(&config);
+        assert!(database.load().unwrap());
+        let factory = SessionFactory::new(database);
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        assert_eq!(Value::Blob("1".into()), run(&["GET", "a"]));
+        assert_eq!(Value::Number(2), run(&["LLEN", "l"]));
+        run(&["SELECT", "3"]);
+        assert_eq!(Value::Blob("v".into()), run(&["HGET", "h", "f"]));
+        assert_eq!(Value::Blob("2".into()), run(&["GET", "b"]));
+        assert!(matches!(run(&["PTTL", "h"]), Value::Number(ttl) if ttl > 90000));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!Database::new(&config).load().unwrap());
+
+        // the server keeps running when it can't save before shutting down.
+        let factory = SessionFactory::new(Database::new(&config));
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        assert_eq!(Value::err("syntax error"), run(&["SHUTDOWN", "NOW"]));
+        assert_eq!(
+            Value::err("Errors trying to SHUTDOWN. Check logs."),
+            run(&["SHUTDOWN", "SAVE"])
+        );
+        assert_eq!(Value::Simple("PONG".into()), run(&["PING"]));
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = std::env::temp_dir().join(format!("redirs-config-set-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(
+            &path,
+            "# the port\nport 7000\n\n# no snapshots\nsave \"\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+
+        let factory = SessionFactory::new(Database::new(&config));
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let ok = Value::Simple("OK".into());
+        let pair =
+            |name: &str, value: &str| vec![Value::Blob(name.into()), Value::Blob(value.into())];
+
+        assert_eq!(
+            Value::Array([pair("port", "7000"), pair("save", "")].concat()),
+            run(&["CONFIG", "GET", "port", "SAV*"])
+        );
+        assert_eq!(
+            ok,
+            run(&["CONFIG", "SET", "save", "900 1 300 10", "maxmemory", "1mb"])
+        );
+        assert_eq!(
+            Value::Array([pair("maxmemory", "1048576"), pair("save", "900 1 300 10")].concat()),
+            run(&["CONFIG", "GET", "maxmemory", "save"])
+        );
+        assert_eq!(
+            Value::err(
+                "CONFIG SET failed (possibly related to argument 'port') - can't set immutable \
+                 config"
+            ),
+            run(&["CONFIG", "SET", "port", "7001"])
+        );
+        // nothing is set when one of the parameters is invalid.
+        assert_eq!(
+            Value::err(
+                "CONFIG SET failed (possibly related to argument 'timeout') - argument couldn't \
+                 be parsed into an integer"
+            ),
+            run(&["CONFIG", "SET", "slowlog-max-len", "10", "timeout", "soon"])
+        );
+        assert_eq!(
+            Value::Array(pair("slowlog-max-len", "128")),
+            run(&["CONFIG", "GET", "slowlog-max-len"])
+        );
+        assert_eq!(
+            Value::err("Unknown option or number of arguments for CONFIG SET - 'bogus'"),
+            run(&["CONFIG", "SET", "bogus", "1"])
+        );
+
+        assert_eq!(ok, run(&["CONFIG", "SET", "requirepass", "secret pass"]));
+        assert_eq!(ok, run(&["CONFIG", "REWRITE"]));
+        assert_eq!(
+            "# the port\nport 7000\n\n# no snapshots\nsave 900 1 300 10\n\
+             # Generated by CONFIG REWRITE\nmaxmemory 1048576\nrequirepass \"secret pass\"\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        let config = Config::load(&path).unwrap();
+        assert_eq!(Some("secret pass".to_string()), config.requirepass);
+        assert_eq!(vec![(900, 1), (300, 10)], config.save);
+
+        assert!(
+            factory
+                .database()
+                .stats()
+                .commands_processed
+                .load(Ordering::Relaxed)
+                > 0
+        );
+        assert_eq!(ok, run(&["CONFIG", "RESETSTAT"]));
+        // CONFIG RESETSTAT itself is counted.
+        assert_eq!(
+            1,
+            factory
+                .database()
+                .stats()
+                .commands_processed
+                .load(Ordering::Relaxed)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_slowlog() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(Value::Number(0), run(&["SLOWLOG", "LEN"]));
+        run(&["CONFIG", "SET", "slowlog-log-slower-than", "0"]);
+        run(&["SET", "a", &"x".repeat(200)]);
+        let entries = match run(&["SLOWLOG", "GET", "1"]) {
+            Value::Array(entries) => entries,
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert_eq!(1, entries.len());
+        match &entries[0] {
+            Value::Array(entry) => {
+                assert_eq!(Value::Number(0), entry[0]);
+                let truncated = format!("{}... (72 more bytes)", "x".repeat(128));
+                assert_eq!(
+                    Value::Array(vec![
+                        Value::Blob("SET".into()),
+                        Value::Blob("a".into()),
+                        Value::Blob(truncated.as_str().into()),
+                    ]),
+                    entry[3]
+                );
+            }
+            entry => panic!("unexpected entry {:?}", entry),
+        }
+        assert_eq!(Value::Number(2), run(&["SLOWLOG", "LEN"]));
+        assert_eq!(Value::Simple("OK".into()), run(&["SLOWLOG", "RESET"]));
+        // SLOWLOG RESET itself is logged.
+        assert_eq!(Value::Number(1), run(&["SLOWLOG", "LEN"]));
+    }
+
+    #[test]
+    fn test_info() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let info = |reply: Value| match reply {
+            Value::Blob(info) => String::from_utf8(info.to_vec()).unwrap(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        let field = |info: &str, name: &str| {
+            info.split("\r\n")
+                .find_map(|line| line.strip_prefix(&format!("{}:", name)).map(String::from))
+        };
+
+        run(&["SET", "a", "1"]);
+        run(&["SET", "b", "2", "EX", "1000"]);
+        run(&["GET", "a"]);
+        run(&["GET", "missing"]);
+        run(&["GET"]);
+        run(&["LPUSH", "l"]);
+        run(&["SELECT", "2"]);
+        run(&["SET", "c", "3"]);
+
+        let default = info(run(&["INFO"]));
+        assert!(default.starts_with("# Server\r\nredis_version:"));
+        assert!(default.contains("\r\n\r\n# Clients\r\n"));
+        assert!(!default.contains("# Commandstats"));
+        assert_eq!(Some("1".into()), field(&default, "keyspace_hits"));
+        assert_eq!(Some("1".into()), field(&default, "keyspace_misses"));
+        assert_eq!(Some("master".into()), field(&default, "role"));
+        assert_eq!(
+            Some("keys=2,expires=1,avg_ttl=0".into()),
+            field(&default, "db0")
+        );
+        assert_eq!(
+            Some("keys=1,expires=0,avg_ttl=0".into()),
+            field(&default, "db2")
+        );
+        assert_eq!(None, field(&default, "db1"));
+
+        // every element modified by a command counts as a change.
+        let changes = |persistence: Value| {
+            let changes = field(&info(persistence), "rdb_changes_since_last_save").unwrap();
+            changes.parse::<u64>().unwrap()
+        };
+        let before = changes(run(&["INFO", "persistence"]));
+        run(&["HSET", "h", "f1", "v", "f2", "v", "f3", "v"]);
+        run(&["RPUSH", "l", "x", "y"]);
+        run(&["SADD", "s", "m"]);
+        assert_eq!(before + 6, changes(run(&["INFO", "persistence"])));
+
+        let sections = info(run(&["INFO", "keyspace", "COMMANDSTATS"]));
+        assert!(sections.starts_with("# Keyspace\r\ndb0:"));
+        assert!(sections.contains("\r\n\r\n# Commandstats\r\n"));
+        assert!(field(&sections, "cmdstat_set")
+            .unwrap()
+            .starts_with("calls=3,usec="));
+        assert!(field(&sections, "cmdstat_get")
+            .unwrap()
+            .ends_with(",rejected_calls=1,failed_calls=0"));
+        assert_eq!(
+            Some("calls=0,usec=0,usec_per_call=0.00,rejected_calls=1,failed_calls=0".into()),
+            field(&sections, "cmdstat_lpush")
+        );
+        assert_eq!(None, field(&sections, "used_memory"));
+
+        let everything = info(run(&["INFO", "everything"]));
+        assert!(everything.contains("# Commandstats"));
+        assert!(field(&everything, "used_memory").is_some());
+        assert_eq!(
+            Some("noeviction".into()),
+            field(&everything, "maxmemory_policy")
+        );
+        assert_eq!(Value::Blob("".into()), run(&["INFO", "bogus"]));
+    }
+}
@@ -0,0 +1,622 @@
+use rand::seq::index::sample;
+
+use crate::db::aof::command;
+use crate::db::{InternalDb, Object, Session, Set};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_option, numkeys_keys, CommandError, CommandResult, CommandSpec,
+    ScanArgs, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST, COMMAND_FLAG_RANDOM, COMMAND_FLAG_READONLY,
+    COMMAND_FLAG_SET, COMMAND_FLAG_SLOW, COMMAND_FLAG_WRITE, ERR_OUT_OF_RANGE, ERR_POSITIVE,
+    ERR_SYNTAX,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec =
+        |name: &str, args_len, flags: &[&'static str], keys: (i64, i64), handler| CommandSpec {
+            name: name.to_string(),
+            args_len,
+            flags: [flags, &[COMMAND_FLAG_SET]].concat(),
+            first_key: keys.0,
+            last_key: keys.1,
+            key_step: 1,
+            get_keys: None,
+            handler,
+        };
+    let write_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST];
+    let write_slow = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_SLOW];
+    let read_fast = [COMMAND_FLAG_READONLY, COMMAND_FLAG_FAST];
+    let read_slow = [COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW];
+    let read_random = [
+        COMMAND_FLAG_READONLY,
+        COMMAND_FLAG_RANDOM,
+        COMMAND_FLAG_SLOW,
+    ];
+    let pop = [COMMAND_FLAG_WRITE, COMMAND_FLAG_RANDOM, COMMAND_FLAG_FAST];
+
+    vec![
+        spec("SADD", -3, &write_fast, (1, 1), handle_sadd),
+        spec(
+            "SREM",
+            -3,
+            &[COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST],
+            (1, 1),
+            handle_srem,
+        ),
+        spec("SCARD", 2, &read_fast, (1, 1), handle_scard),
+        spec("SMEMBERS", 2, &read_slow, (1, 1), handle_smembers),
+        spec("SISMEMBER", 3, &read_fast, (1, 1), handle_sismember),
+        spec("SMISMEMBER", -3, &read_fast, (1, 1), handle_smismember),
+        spec("SPOP", -2, &pop, (1, 1), handle_spop),
+        spec("SRANDMEMBER", -2, &read_random, (1, 1), handle_srandmember),
+        spec(
+            "SMOVE",
+            4,
+            &[COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST],
+            (1, 2),
+            handle_smove,
+        ),
+        spec("SINTER", -2, &read_slow, (1, -1), handle_sinter),
+        spec("SUNION", -2, &read_slow, (1, -1), handle_sunion),
+        spec("SDIFF", -2, &read_slow, (1, -1), handle_sdiff),
+        spec("SINTERSTORE", -3, &write_slow, (1, -1), handle_sinterstore),
+        spec("SUNIONSTORE", -3, &write_slow, (1, -1), handle_sunionstore),
+        spec("SDIFFSTORE", -3, &write_slow, (1, -1), handle_sdiffstore),
+        CommandSpec {
+            get_keys: Some(sintercard_keys),
+            ..spec("SINTERCARD", -3, &read_slow, (0, 0), handle_sintercard)
+        },
+        spec("SSCAN", -3, &read_random, (1, 1), handle_sscan),
+    ]
+}
+
+/// Finds the keys of `SINTERCARD numkeys key [key ...] [LIMIT limit]`.
+fn sintercard_keys(args: &[Value]) -> Vec<&[u8]> {
+    numkeys_keys(args, 0)
+}
+
+/// Returns the set stored at the key, failing if the key holds another type.
+fn get_set<'a>(db: &'a mut InternalDb, key: &Bytes) -> Result<Option<&'a mut Set>, CommandError> {
+    match db.get_mut(key) {
+        None => Ok(None),
+        Some(Object::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Returns the sets stored at the keys. Missing keys are returned as `None`.
+fn get_sets<'a>(
+    db: &'a mut InternalDb,
+    keys: &[Bytes],
+) -> Result<Vec<Option<&'a Set>>, CommandError> {
+    db.get_many(keys)
+        .into_iter()
+        .map(|obj| match obj {
+            None => Ok(None),
+            Some(Object::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(CommandError::wrongtype()),
+        })
+        .collect()
+}
+
+fn members_reply(members: impl Iterator<Item = Bytes>) -> Value {
+    Value::Set(members.map(Value::Blob).collect())
+}
+
+fn handle_sadd(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let members = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let set = match db.get_or_insert_with(&key, || Object::Set(Set::new())) {
+        Object::Set(set) => set,
+        _ => return Err(CommandError::wrongtype()),
+    };
+    let added = members
+        .into_iter()
+        .filter(|m| set.insert(m.clone()))
+        .count();
+    db.modified(&key, added);
+    Ok(Value::Number(added as i64))
+}
+
+fn handle_srem(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let members = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let set = match get_set(&mut db, &key)? {
+        Some(set) => set,
+        None => return Ok(Value::Number(0)),
+    };
+    let removed = members.iter().filter(|m| set.remove(m)).count();
+    if set.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, removed);
+    Ok(Value::Number(removed as i64))
+}
+
+fn handle_scard(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let len = get_set(&mut db, &key)?.map(|set| set.len()).unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_smembers(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    Ok(match get_set(&mut db, &key)? {
+        Some(set) => members_reply(set.iter()),
+        None => Value::Set(vec![]),
+    })
+}
+
+fn handle_sismember(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let member = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    let found = get_set(&mut db, &key)?
+        .map(|set| set.contains(&member))
+        .unwrap_or(false);
+    Ok(Value::Number(found as i64))
+}
+
+fn handle_smismember(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let members = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let set = get_set(&mut db, &key)?;
+    Ok(Value::Array(
+        members
+            .iter()
+            .map(|m| {
+                let found = set.as_ref().map(|set| set.contains(m)).unwrap_or(false);
+                Value::Number(found as i64)
+            })
+            .collect(),
+    ))
+}
+
+/// Implements `SPOP key [count]`.
+fn handle_spop(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if args.len() > 2 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = match args.next() {
+        Some(count) => Some(usize::try_from(arg_i64(&count)?).map_err(|_| ERR_POSITIVE)?),
+        None => None,
+    };
+
+    let mut db = session.lock_db();
+    let set = match get_set(&mut db, &key)? {
+        Some(set) => set,
+        None if count.is_some() => return Ok(Value::Array(vec![])),
+        None => return Ok(Value::Null),
+    };
+
+    let popped: Vec<Bytes> = (0..count.unwrap_or(1))
+        .map_while(|_| set.pop_random())
+        .collect();
+    if set.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, popped.len());
+    drop(db);
+
+    // the members are picked at random, so the ones that were removed are logged instead.
+    if !popped.is_empty() {
+        let mut args: Vec<&[u8]> = vec![b"SREM", &key];
+        args.extend(popped.iter().map(|member| &member[..]));
+        session.propagate(vec![command(&args)]);
+    }
+
+    Ok(match count {
+        Some(_) => members_reply(popped.into_iter()),
+        None => popped
+            .into_iter()
+            .next()
+            .map(Value::Blob)
+            .unwrap_or(Value::Null),
+    })
+}
+
+/// Implements `SRANDMEMBER key [count]`. A positive count returns distinct members, a negative
+/// one allows the same member to be returned multiple times.
+fn handle_srandmember(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    if args.len() > 2 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = args.next().map(|count| arg_i64(&count)).transpose()?;
+
+    let mut db = session.lock_db();
+    let set = get_set(&mut db, &key)?;
+    let count = match count {
+        Some(count) => count,
+        None => {
+            return Ok(set
+                .and_then(|set| set.random_member())
+                .map(Value::Blob)
+                .unwrap_or(Value::Null))
+        }
+    };
+    if count == i64::MIN {
+        return Err(ERR_OUT_OF_RANGE.into());
+    }
+
+    let set = match set {
+        Some(set) => set,
+        None => return Ok(Value::Array(vec![])),
+    };
+    let members: Vec<Bytes> = if count < 0 {
+        (0..count.unsigned_abs())
+            .map(|_| set.random_member().unwrap())
+            .collect()
+    } else if count as usize >= set.len() {
+        set.iter().collect()
+    } else {
+        sample(&mut rand::thread_rng(), set.len(), count as usize)
+            .into_iter()
+            .map(|i| set.get_index(i).unwrap())
+            .collect()
+    };
+    // the members may repeat, unlike in a set.
+    Ok(Value::Array(members.into_iter().map(Value::Blob).collect()))
+}
+
+fn handle_smove(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let source = arg_bytes(args.next().unwrap())?;
+    let destination = arg_bytes(args.next().unwrap())?;
+    let member = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    get_set(&mut db, &destination)?;
+    let set = match get_set(&mut db, &source)? {
+        Some(set) => set,
+        None => return Ok(Value::Number(0)),
+    };
+    if source == destination {
+        return Ok(Value::Number(set.contains(&member) as i64));
+    }
+    if !set.remove(&member) {
+        return Ok(Value::Number(0));
+    }
+    if set.is_empty() {
+        db.remove(&source);
+    }
+    db.modified(&source, 1);
+
+    if let Object::Set(set) = db.get_or_insert_with(&destination, || Object::Set(Set::new())) {
+        let added = set.insert(member);
+        db.modified(&destination, added as usize);
+    }
+    Ok(Value::Number(1))
+}
+
+#[derive(Clone, Copy)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Computes the intersection, union or difference of the sets stored at the keys. Missing keys
+/// are treated as empty sets.
+fn set_algebra(db: &mut InternalDb, keys: &[Bytes], op: SetOp) -> Result<Set, CommandError> {
+    let sets = get_sets(db, keys)?;
+    let result = match op {
+        SetOp::Inter => {
+            if sets.iter().any(|set| set.is_none()) {
+                return Ok(Set::new());
+            }
+            let mut sets: Vec<&Set> = sets.into_iter().flatten().collect();
+            sets.sort_by_key(|set| set.len());
+            sets[0]
+                .iter()
+                .filter(|m| sets[1..].iter().all(|set| set.contains(m)))
+                .collect()
+        }
+        SetOp::Union => sets
+            .into_iter()
+            .flatten()
+            .flat_map(|set| set.iter())
+            .collect(),
+        SetOp::Diff => match sets[0] {
+            Some(first) => first
+                .iter()
+                .filter(|m| sets[1..].iter().flatten().all(|set| !set.contains(m)))
+                .collect(),
+            None => Set::new(),
+        },
+    };
+    Ok(result)
+}
+
+fn algebra_generic(session: &mut Session, args: Vec<Value>, op: SetOp) -> CommandResult {
+    let keys = args
+        .into_iter()
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut db = session.lock_db();
+    let result = set_algebra(&mut db, &keys, op)?;
+    Ok(members_reply(result.iter()))
+}
+
+fn algebra_store_generic(session: &mut Session, args: Vec<Value>, op: SetOp) -> CommandResult {
+    let mut keys = args
+        .into_iter()
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+    let destination = keys.remove(0);
+
+    let mut db = session.lock_db();
+    let result = set_algebra(&mut db, &keys, op)?;
+    let len = result.len();
+    if result.is_empty() {
+        db.remove(&destination);
+    } else {
+        db.insert(destination, Object::Set(result));
+    }
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_sinter(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_generic(session, args, SetOp::Inter)
+}
+
+fn handle_sunion(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_generic(session, args, SetOp::Union)
+}
+
+fn handle_sdiff(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_generic(session, args, SetOp::Diff)
+}
+
+fn handle_sinterstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_store_generic(session, args, SetOp::Inter)
+}
+
+fn handle_sunionstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_store_generic(session, args, SetOp::Union)
+}
+
+fn handle_sdiffstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    algebra_store_generic(session, args, SetOp::Diff)
+}
+
+/// Implements `SINTERCARD numkeys key [key ...] [LIMIT limit]`.
+fn handle_sintercard(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let numkeys = arg_i64(&args.next().unwrap())?;
+    if numkeys <= 0 {
+        return Err("numkeys should be greater than 0".into());
+    }
+    if numkeys as usize > args.len() {
+        return Err("Number of keys can't be greater than number of args".into());
+    }
+    let keys = args
+        .by_ref()
+        .take(numkeys as usize)
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut limit = 0;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "LIMIT" => {
+                limit = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                if limit < 0 {
+                    return Err("LIMIT can't be negative".into());
+                }
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let sets = get_sets(&mut db, &keys)?;
+    if sets.iter().any(|set| set.is_none()) {
+        return Ok(Value::Number(0));
+    }
+    let mut sets: Vec<&Set> = sets.into_iter().flatten().collect();
+    sets.sort_by_key(|set| set.len());
+
+    let limit = if limit == 0 {
+        usize::MAX
+    } else {
+        limit as usize
+    };
+    let count = sets[0]
+        .iter()
+        .filter(|m| sets[1..].iter().all(|set| set.contains(m)))
+        .take(limit)
+        .count();
+    Ok(Value::Number(count as i64))
+}
+
+/// Implements `SSCAN key cursor [MATCH pattern] [COUNT count]`.
+fn handle_sscan(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let scan = ScanArgs::parse(args, &[])?;
+
+    let mut db = session.lock_db();
+    let set = match get_set(&mut db, &key)? {
+        Some(set) => set,
+        None => return Ok(ScanArgs::reply(0, vec![])),
+    };
+    let (cursor, members) = set.scan(scan.cursor, scan.count);
+    let items = members
+        .into_iter()
+        .filter(|m| scan.matches(m))
+        .map(Value::Blob)
+        .collect();
+    Ok(ScanArgs::reply(cursor, items))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    /// Converts an array reply into a set of strings, since the order of set members is
+    /// unspecified.
+    fn members(value: Value) -> HashSet<String> {
+        match value {
+            Value::Array(values) => values
+                .into_iter()
+                .map(|v| match v {
+                    Value::Blob(b) => b.into_string().unwrap(),
+                    other => panic!("unexpected member {:?}", other),
+                })
+                .collect(),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    fn set_of(values: &[&str]) -> HashSet<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_set_commands() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(Value::Number(3), run(&["SADD", "s", "1", "2", "3", "2"]));
+        assert_eq!(Value::Number(1), run(&["SADD", "s", "a"]));
+        assert_eq!(Value::Number(4), run(&["SCARD", "s"]));
+        assert_eq!(
+            set_of(&["1", "2", "3", "a"]),
+            members(run(&["SMEMBERS", "s"]))
+        );
+        assert_eq!(Value::Number(1), run(&["SISMEMBER", "s", "a"]));
+        assert_eq!(
+            Value::Array(vec![Value::Number(1), Value::Number(0)]),
+            run(&["SMISMEMBER", "s", "2", "4"])
+        );
+        assert_eq!(Value::Number(2), run(&["SREM", "s", "1", "a", "x"]));
+
+        assert_eq!(Value::Number(1), run(&["SMOVE", "s", "d", "2"]));
+        assert_eq!(Value::Number(0), run(&["SMOVE", "s", "d", "2"]));
+        assert_eq!(set_of(&["2"]), members(run(&["SMEMBERS", "d"])));
+
+        assert_eq!(Value::Number(1), run(&["SCARD", "s"]));
+        assert_eq!(Value::Blob("3".into()), run(&["SPOP", "s"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "s"]));
+        assert_eq!(Value::Null, run(&["SPOP", "s"]));
+        assert_eq!(Value::Array(vec![]), run(&["SPOP", "s", "3"]));
+
+        run(&["SADD", "s", "a", "b", "c", "d"]);
+        assert_eq!(4, members(run(&["SPOP", "s", "10"])).len());
+        assert_eq!(Value::Number(0), run(&["EXISTS", "s"]));
+    }
+
+    #[test]
+    fn test_srandmember() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["SADD", "s", "a", "b", "c", "d", "e"]);
+        assert_eq!(3, members(run(&["SRANDMEMBER", "s", "3"])).len());
+        assert_eq!(5, members(run(&["SRANDMEMBER", "s", "10"])).len());
+        match run(&["SRANDMEMBER", "s", "-20"]) {
+            Value::Array(values) => assert_eq!(20, values.len()),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(Value::Array(vec![]), run(&["SRANDMEMBER", "s", "0"]));
+        assert_eq!(Value::Array(vec![]), run(&["SRANDMEMBER", "none", "3"]));
+        assert_eq!(Value::Null, run(&["SRANDMEMBER", "none"]));
+        assert_eq!(Value::Number(5), run(&["SCARD", "s"]));
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["SADD", "a", "1", "2", "3", "x"]);
+        run(&["SADD", "b", "2", "3", "4"]);
+        run(&["SADD", "c", "3", "x", "y"]);
+
+        assert_eq!(set_of(&["3"]), members(run(&["SINTER", "a", "b", "c"])));
+        assert_eq!(set_of(&[]), members(run(&["SINTER", "a", "none"])));
+        assert_eq!(
+            set_of(&["1", "2", "3", "4", "x", "y"]),
+            members(run(&["SUNION", "a", "b", "c", "none"]))
+        );
+        assert_eq!(set_of(&["1"]), members(run(&["SDIFF", "a", "b", "c"])));
+
+        assert_eq!(Value::Number(2), run(&["SINTERSTORE", "dst", "a", "b"]));
+        assert_eq!(set_of(&["2", "3"]), members(run(&["SMEMBERS", "dst"])));
+        assert_eq!(Value::Number(5), run(&["SUNIONSTORE", "dst", "a", "b"]));
+        assert_eq!(Value::Number(0), run(&["SDIFFSTORE", "dst", "none", "a"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "dst"]));
+
+        assert_eq!(Value::Number(2), run(&["SINTERCARD", "2", "a", "b"]));
+        assert_eq!(
+            Value::Number(1),
+            run(&["SINTERCARD", "2", "a", "b", "LIMIT", "1"])
+        );
+        assert_eq!(
+            Value::err("Number of keys can't be greater than number of args"),
+            run(&["SINTERCARD", "3", "a", "b"])
+        );
+
+        run(&["SET", "str", "v"]);
+        assert_eq!(
+            Value::Err(
+                "WRONGTYPE".to_string(),
+                "Operation against a key holding the wrong kind of value".to_string()
+            ),
+            run(&["SUNION", "a", "str"])
+        );
+    }
+
+    #[test]
+    fn test_sscan() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        let members_list: Vec<String> = (0..200).map(|i| format!("m{}", i)).collect();
+        let mut args = vec!["SADD", "s"];
+        args.extend(members_list.iter().map(|m| m.as_str()));
+        run(&args);
+
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let (next, items) = match run(&["SSCAN", "s", &cursor, "COUNT", "7"]) {
+                Value::Array(mut reply) => match (reply.remove(0), reply.remove(0)) {
+                    (Value::Blob(next), items) => (next, members(items)),
+                    other => panic!("unexpected reply {:?}", other),
+                },
+                other => panic!("unexpected reply {:?}", other),
+            };
+            seen.extend(items);
+            cursor = next.into_string().unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(200, seen.len());
+    }
+}
@@ -0,0 +1,1365 @@
+use std::collections::HashMap;
+
+use crate::db::aof::command;
+use crate::db::{InternalDb, LexBound, LexRange, Object, ScoreRange, Session, Set, SortedSet};
+use crate::value::{Bytes, Value};
+
+use super::list::list_range;
+use super::{
+    arg_bytes, arg_f64, arg_i64, arg_key, arg_option, block_on, numkeys_keys, parse_timeout,
+    CommandError, CommandResult, CommandSpec, ScanArgs, COMMAND_FLAG_BLOCKING,
+    COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST, COMMAND_FLAG_RANDOM, COMMAND_FLAG_READONLY,
+    COMMAND_FLAG_SLOW, COMMAND_FLAG_SORTEDSET, COMMAND_FLAG_WRITE, ERR_POSITIVE, ERR_SYNTAX,
+};
+
+const ERR_NOT_FLOAT_RANGE: &str = "min or max is not a float";
+const ERR_NOT_LEX_RANGE: &str = "min or max not valid string range item";
+const ERR_SCORE_NAN: &str = "resulting score is not a number (NaN)";
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec =
+        |name: &str, args_len, flags: &[&'static str], keys: (i64, i64), handler| CommandSpec {
+            name: name.to_string(),
+            args_len,
+            flags: [flags, &[COMMAND_FLAG_SORTEDSET]].concat(),
+            first_key: keys.0,
+            last_key: keys.1,
+            key_step: 1,
+            get_keys: None,
+            handler,
+        };
+    let write_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST];
+    let write_slow = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_SLOW];
+    let remove_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST];
+    let remove_slow = [COMMAND_FLAG_WRITE, COMMAND_FLAG_SLOW];
+    let read_fast = [COMMAND_FLAG_READONLY, COMMAND_FLAG_FAST];
+    let read_slow = [COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW];
+    let blocking = [COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST, COMMAND_FLAG_BLOCKING];
+    let read_random = [
+        COMMAND_FLAG_READONLY,
+        COMMAND_FLAG_RANDOM,
+        COMMAND_FLAG_SLOW,
+    ];
+
+    vec![
+        spec("ZADD", -4, &write_fast, (1, 1), handle_zadd),
+        spec("ZINCRBY", 4, &write_fast, (1, 1), handle_zincrby),
+        spec("ZREM", -3, &remove_fast, (1, 1), handle_zrem),
+        spec("ZCARD", 2, &read_fast, (1, 1), handle_zcard),
+        spec("ZCOUNT", 4, &read_fast, (1, 1), handle_zcount),
+        spec("ZLEXCOUNT", 4, &read_fast, (1, 1), handle_zlexcount),
+        spec("ZSCORE", 3, &read_fast, (1, 1), handle_zscore),
+        spec("ZMSCORE", -3, &read_fast, (1, 1), handle_zmscore),
+        spec("ZRANK", -3, &read_fast, (1, 1), handle_zrank),
+        spec("ZREVRANK", -3, &read_fast, (1, 1), handle_zrevrank),
+        spec("ZRANGE", -4, &read_slow, (1, 1), handle_zrange),
+        spec("ZRANGESTORE", -5, &write_slow, (1, 2), handle_zrangestore),
+        spec("ZREVRANGE", -4, &read_slow, (1, 1), handle_zrevrange),
+        spec(
+            "ZRANGEBYSCORE",
+            -4,
+            &read_slow,
+            (1, 1),
+            handle_zrangebyscore,
+        ),
+        spec(
+            "ZREVRANGEBYSCORE",
+            -4,
+            &read_slow,
+            (1, 1),
+            handle_zrevrangebyscore,
+        ),
+        spec("ZRANGEBYLEX", -4, &read_slow, (1, 1), handle_zrangebylex),
+        spec(
+            "ZREVRANGEBYLEX",
+            -4,
+            &read_slow,
+            (1, 1),
+            handle_zrevrangebylex,
+        ),
+        spec("ZPOPMIN", -2, &remove_fast, (1, 1), handle_zpopmin),
+        spec("ZPOPMAX", -2, &remove_fast, (1, 1), handle_zpopmax),
+        spec("BZPOPMIN", -3, &blocking, (1, -2), handle_bzpopmin),
+        spec("BZPOPMAX", -3, &blocking, (1, -2), handle_bzpopmax),
+        spec(
+            "ZREMRANGEBYRANK",
+            4,
+            &remove_slow,
+            (1, 1),
+            handle_zremrangebyrank,
+        ),
+        spec(
+            "ZREMRANGEBYSCORE",
+            4,
+            &remove_slow,
+            (1, 1),
+            handle_zremrangebyscore,
+        ),
+        spec(
+            "ZREMRANGEBYLEX",
+            4,
+            &remove_slow,
+            (1, 1),
+            handle_zremrangebylex,
+        ),
+        CommandSpec {
+            get_keys: Some(setop_keys),
+            ..spec("ZUNION", -3, &read_slow, (0, 0), handle_zunion)
+        },
+        CommandSpec {
+            get_keys: Some(setop_keys),
+            ..spec("ZINTER", -3, &read_slow, (0, 0), handle_zinter)
+        },
+        CommandSpec {
+            get_keys: Some(setop_keys),
+            ..spec("ZDIFF", -3, &read_slow, (0, 0), handle_zdiff)
+        },
+        CommandSpec {
+            get_keys: Some(setop_store_keys),
+            ..spec("ZUNIONSTORE", -4, &write_slow, (1, 1), handle_zunionstore)
+        },
+        CommandSpec {
+            get_keys: Some(setop_store_keys),
+            ..spec("ZINTERSTORE", -4, &write_slow, (1, 1), handle_zinterstore)
+        },
+        CommandSpec {
+            get_keys: Some(setop_store_keys),
+            ..spec("ZDIFFSTORE", -4, &write_slow, (1, 1), handle_zdiffstore)
+        },
+        spec("ZSCAN", -3, &read_random, (1, 1), handle_zscan),
+    ]
+}
+
+/// Finds the keys of ZUNION, ZINTER and ZDIFF, given as `numkeys key [key ...]`.
+fn setop_keys(args: &[Value]) -> Vec<&[u8]> {
+    numkeys_keys(args, 0)
+}
+
+/// Finds the keys of ZUNIONSTORE, ZINTERSTORE and ZDIFFSTORE: the destination, then the keys
+/// given as `numkeys key [key ...]`.
+fn setop_store_keys(args: &[Value]) -> Vec<&[u8]> {
+    let mut keys: Vec<&[u8]> = args.first().and_then(arg_key).into_iter().collect();
+    keys.extend(numkeys_keys(args, 1));
+    keys
+}
+
+/// Returns the sorted set stored at the key, failing if the key holds another type.
+pub(super) fn get_zset<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+) -> Result<Option<&'a mut SortedSet>, CommandError> {
+    match db.get_mut(key) {
+        None => Ok(None),
+        Some(Object::SortedSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Pops up to `count` members with the lowest scores, or the highest ones with `rev`. The key is
+/// deleted once the sorted set is empty. Returns `None` if the key doesn't exist.
+pub(super) fn pop(
+    db: &mut InternalDb,
+    key: &Bytes,
+    rev: bool,
+    count: usize,
+) -> Result<Option<Vec<(Bytes, f64)>>, CommandError> {
+    let zset = match get_zset(db, key)? {
+        Some(zset) => zset,
+        None => return Ok(None),
+    };
+    let popped: Vec<_> = (0..count).map_while(|_| zset.pop(rev)).collect();
+    if zset.is_empty() {
+        db.remove(key);
+    }
+    db.modified(key, popped.len());
+    Ok(Some(popped))
+}
+
+fn score_reply(score: f64) -> Value {
+    Value::Double(score)
+}
+
+/// Replies with the members, each one followed by its score if `withscores` is set.
+pub(super) fn entries_reply(
+    entries: impl IntoIterator<Item = (Bytes, f64)>,
+    withscores: bool,
+) -> Value {
+    let mut reply = vec![];
+    for (member, score) in entries {
+        reply.push(Value::Blob(member));
+        if withscores {
+            reply.push(score_reply(score));
+        }
+    }
+    Value::Array(reply)
+}
+
+/// Stores the sorted set, replacing any value at the key. An empty sorted set deletes the key
+/// instead. Returns the number of stored members.
+fn store(db: &mut InternalDb, key: Bytes, zset: SortedSet) -> usize {
+    let len = zset.len();
+    if zset.is_empty() {
+        db.remove(&key);
+    } else {
+        db.insert(key, Object::SortedSet(zset));
+    }
+    len
+}
+
+#[derive(Default)]
+struct AddFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+/// Implements `ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]`.
+fn handle_zadd(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter().peekable();
+    let key = arg_bytes(args.next().unwrap())?;
+
+    let mut flags = AddFlags::default();
+    while let Some(arg) = args.peek() {
+        match arg_option(arg).as_str() {
+            "NX" => flags.nx = true,
+            "XX" => flags.xx = true,
+            "GT" => flags.gt = true,
+            "LT" => flags.lt = true,
+            "CH" => flags.ch = true,
+            "INCR" => flags.incr = true,
+            _ => break,
+        }
+        args.next();
+    }
+
+    let args: Vec<Value> = args.collect();
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(ERR_SYNTAX.into());
+    }
+    if flags.nx && flags.xx {
+        return Err("XX and NX options at the same time are not compatible".into());
+    }
+    if [flags.nx, flags.gt, flags.lt]
+        .iter()
+        .filter(|f| **f)
+        .count()
+        > 1
+    {
+        return Err("GT, LT, and/or NX options at the same time are not compatible".into());
+    }
+    if flags.incr && args.len() > 2 {
+        return Err("INCR option supports a single increment-element pair".into());
+    }
+
+    let mut elements = vec![];
+    let mut args = args.into_iter();
+    while let (Some(score), Some(member)) = (args.next(), args.next()) {
+        elements.push((arg_f64(&score)?, arg_bytes(member)?));
+    }
+    zadd_generic(session, key, elements, flags)
+}
+
+fn handle_zincrby(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let increment = arg_f64(&args.next().unwrap())?;
+    let member = arg_bytes(args.next().unwrap())?;
+    let flags = AddFlags {
+        incr: true,
+        ..Default::default()
+    };
+    zadd_generic(session, key, vec![(increment, member)], flags)
+}
+
+fn zadd_generic(
+    session: &mut Session,
+    key: Bytes,
+    elements: Vec<(f64, Bytes)>,
+    flags: AddFlags,
+) -> CommandResult {
+    let mut db = session.lock_db();
+    if get_zset(&mut db, &key)?.is_none() && flags.xx {
+        return Ok(if flags.incr {
+            Value::Null
+        } else {
+            Value::Number(0)
+        });
+    }
+    let zset = match db.get_or_insert_with(&key, || Object::SortedSet(SortedSet::new())) {
+        Object::SortedSet(zset) => zset,
+        _ => return Err(CommandError::wrongtype()),
+    };
+
+    let (mut added, mut updated) = (0, 0);
+    let mut new_score = None;
+    for (score, member) in elements {
+        match zset.score(&member) {
+            Some(_) if flags.nx => (),
+            Some(current) => {
+                let score = if flags.incr { current + score } else { score };
+                if score.is_nan() {
+                    return Err(ERR_SCORE_NAN.into());
+                }
+                if (flags.lt && score >= current) || (flags.gt && score <= current) {
+                    continue;
+                }
+                if score != current {
+                    zset.insert(member, score);
+                    updated += 1;
+                }
+                new_score = Some(score);
+            }
+            None if flags.xx => (),
+            None => {
+                zset.insert(member, score);
+                added += 1;
+                new_score = Some(score);
+            }
+        }
+    }
+    if zset.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, (added + updated) as usize);
+
+    Ok(if flags.incr {
+        new_score.map(score_reply).unwrap_or(Value::Null)
+    } else if flags.ch {
+        Value::Number(added + updated)
+    } else {
+        Value::Number(added)
+    })
+}
+
+fn handle_zrem(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let members = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let zset = match get_zset(&mut db, &key)? {
+        Some(zset) => zset,
+        None => return Ok(Value::Number(0)),
+    };
+    let removed = members.iter().filter(|m| zset.remove(m).is_some()).count();
+    if zset.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, removed);
+    Ok(Value::Number(removed as i64))
+}
+
+fn handle_zcard(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let len = get_zset(&mut db, &key)?.map(|zset| zset.len()).unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+/// Parses one end of a score range, which is exclusive if prefixed with `(`.
+fn parse_score_bound(arg: &Value) -> Result<(f64, bool), CommandError> {
+    let bound = match arg {
+        Value::Simple(s) | Value::Blob(s) => s.clone(),
+        Value::Number(n) => return Ok((*n as f64, false)),
+        _ => return Err(ERR_NOT_FLOAT_RANGE.into()),
+    };
+    let (bound, exclusive) = match bound.strip_prefix(b"(") {
+        Some(bound) => (bound, true),
+        None => (&bound[..], false),
+    };
+    let value = std::str::from_utf8(bound)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| !f.is_nan())
+        .ok_or(ERR_NOT_FLOAT_RANGE)?;
+    Ok((value, exclusive))
+}
+
+fn parse_score_range(min: &Value, max: &Value) -> Result<ScoreRange, CommandError> {
+    let (min, min_exclusive) = parse_score_bound(min)?;
+    let (max, max_exclusive) = parse_score_bound(max)?;
+    Ok(ScoreRange {
+        min,
+        max,
+        min_exclusive,
+        max_exclusive,
+    })
+}
+
+/// Parses one end of a lexicographical range: `-`, `+`, or a member prefixed with `[` if
+/// inclusive or `(` if exclusive.
+fn parse_lex_bound(arg: &Value) -> Result<LexBound, CommandError> {
+    let bound = match arg {
+        Value::Simple(s) | Value::Blob(s) => s,
+        _ => return Err(ERR_NOT_LEX_RANGE.into()),
+    };
+    match bound.first() {
+        Some(b'-') if bound.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if bound.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(Bytes::from(bound[1..].to_vec()))),
+        Some(b'(') => Ok(LexBound::Exclusive(Bytes::from(bound[1..].to_vec()))),
+        _ => Err(ERR_NOT_LEX_RANGE.into()),
+    }
+}
+
+fn parse_lex_range(min: &Value, max: &Value) -> Result<LexRange, CommandError> {
+    Ok(LexRange {
+        min: parse_lex_bound(min)?,
+        max: parse_lex_bound(max)?,
+    })
+}
+
+fn handle_zcount(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args[0].clone())?;
+    let range = parse_score_range(&args[1], &args[2])?;
+    let mut db = session.lock_db();
+    let count = get_zset(&mut db, &key)?
+        .map(|zset| zset.count(&range))
+        .unwrap_or(0);
+    Ok(Value::Number(count as i64))
+}
+
+fn handle_zlexcount(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args[0].clone())?;
+    let range = parse_lex_range(&args[1], &args[2])?;
+    let mut db = session.lock_db();
+    let count = get_zset(&mut db, &key)?
+        .map(|zset| zset.count(&range))
+        .unwrap_or(0);
+    Ok(Value::Number(count as i64))
+}
+
+fn handle_zscore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let member = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    Ok(get_zset(&mut db, &key)?
+        .and_then(|zset| zset.score(&member))
+        .map(score_reply)
+        .unwrap_or(Value::Null))
+}
+
+fn handle_zmscore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let members = args.map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let zset = get_zset(&mut db, &key)?;
+    Ok(Value::Array(
+        members
+            .iter()
+            .map(|m| {
+                zset.as_ref()
+                    .and_then(|zset| zset.score(m))
+                    .map(score_reply)
+                    .unwrap_or(Value::Null)
+            })
+            .collect(),
+    ))
+}
+
+fn handle_zrank(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    rank_generic(session, args, false)
+}
+
+fn handle_zrevrank(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    rank_generic(session, args, true)
+}
+
+/// Implements `ZRANK key member [WITHSCORE]` and `ZREVRANK key member [WITHSCORE]`.
+fn rank_generic(session: &mut Session, args: Vec<Value>, rev: bool) -> CommandResult {
+    if args.len() > 3 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let member = arg_bytes(args.next().unwrap())?;
+    let withscore = match args.next() {
+        Some(arg) if arg_option(&arg) == "WITHSCORE" => true,
+        Some(_) => return Err(ERR_SYNTAX.into()),
+        None => false,
+    };
+
+    let mut db = session.lock_db();
+    let zset = match get_zset(&mut db, &key)? {
+        Some(zset) => zset,
+        None => return Ok(Value::Null),
+    };
+    let rank = match zset.rank(&member, rev) {
+        Some(rank) => Value::Number(rank as i64),
+        None => return Ok(Value::Null),
+    };
+    Ok(if withscore {
+        Value::Array(vec![rank, score_reply(zset.score(&member).unwrap())])
+    } else {
+        rank
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+enum Range {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// Arguments of the unified ZRANGE command, which are shared by the older range commands.
+struct RangeArgs {
+    range: Range,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+    withscores: bool,
+}
+
+impl RangeArgs {
+    /// Parses `start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`, only
+    /// accepting the options listed in `allowed`. The kind of range and direction default to
+    /// `by` and `rev`.
+    fn parse(
+        mut args: impl Iterator<Item = Value>,
+        mut by: RangeBy,
+        mut rev: bool,
+        allowed: &[&str],
+    ) -> Result<Self, CommandError> {
+        let start = args.next().unwrap();
+        let stop = args.next().unwrap();
+
+        let mut limit = None;
+        let mut withscores = false;
+        while let Some(arg) = args.next() {
+            let option = arg_option(&arg);
+            if !allowed.contains(&option.as_str()) {
+                return Err(ERR_SYNTAX.into());
+            }
+            match option.as_str() {
+                "BYSCORE" => by = RangeBy::Score,
+                "BYLEX" => by = RangeBy::Lex,
+                "REV" => rev = true,
+                "WITHSCORES" => withscores = true,
+                "LIMIT" => {
+                    let offset = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                    let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                    limit = Some((offset, count));
+                }
+                _ => return Err(ERR_SYNTAX.into()),
+            }
+        }
+
+        if limit.is_some() && by == RangeBy::Rank {
+            return Err(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                    .into(),
+            );
+        }
+        if withscores && by == RangeBy::Lex {
+            return Err("syntax error, WITHSCORES not supported in combination with BYLEX".into());
+        }
+
+        // score and lex ranges are given from the highest to the lowest when reversed.
+        let (min, max) = if rev {
+            (&stop, &start)
+        } else {
+            (&start, &stop)
+        };
+        let range = match by {
+            RangeBy::Rank => Range::Rank(arg_i64(&start)?, arg_i64(&stop)?),
+            RangeBy::Score => Range::Score(parse_score_range(min, max)?),
+            RangeBy::Lex => Range::Lex(parse_lex_range(min, max)?),
+        };
+        Ok(Self {
+            range,
+            rev,
+            limit,
+            withscores,
+        })
+    }
+
+    /// Returns the members of the sorted set selected by the range.
+    fn select(&self, zset: &SortedSet) -> Vec<(Bytes, f64)> {
+        let (offset, count) = self.limit.unwrap_or((0, -1));
+        if offset < 0 {
+            return vec![];
+        }
+        let (offset, count) = (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        );
+        let to_owned = |(member, score): (&Bytes, f64)| (member.clone(), score);
+
+        match &self.range {
+            Range::Rank(start, stop) => match list_range(*start, *stop, zset.len()) {
+                Some((start, stop)) => zset
+                    .iter_from_rank(start, self.rev)
+                    .take(stop - start + 1)
+                    .map(to_owned)
+                    .collect(),
+                None => vec![],
+            },
+            Range::Score(range) => zset
+                .range(range, self.rev)
+                .skip(offset)
+                .take(count)
+                .map(to_owned)
+                .collect(),
+            Range::Lex(range) => zset
+                .range(range, self.rev)
+                .skip(offset)
+                .take(count)
+                .map(to_owned)
+                .collect(),
+        }
+    }
+}
+
+const ZRANGE_OPTIONS: &[&str] = &["BYSCORE", "BYLEX", "REV", "LIMIT", "WITHSCORES"];
+
+fn range_generic(session: &mut Session, args: Vec<Value>, range: RangeArgs) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let entries = match get_zset(&mut db, &key)? {
+        Some(zset) => range.select(zset),
+        None => vec![],
+    };
+    Ok(entries_reply(entries, range.withscores))
+}
+
+/// Implements `ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`.
+fn handle_zrange(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Rank,
+        false,
+        ZRANGE_OPTIONS,
+    )?;
+    range_generic(session, args, range)
+}
+
+fn handle_zrevrange(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Rank,
+        true,
+        &["WITHSCORES"],
+    )?;
+    range_generic(session, args, range)
+}
+
+fn handle_zrangebyscore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Score,
+        false,
+        &["LIMIT", "WITHSCORES"],
+    )?;
+    range_generic(session, args, range)
+}
+
+fn handle_zrevrangebyscore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Score,
+        true,
+        &["LIMIT", "WITHSCORES"],
+    )?;
+    range_generic(session, args, range)
+}
+
+fn handle_zrangebylex(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Lex,
+        false,
+        &["LIMIT"],
+    )?;
+    range_generic(session, args, range)
+}
+
+fn handle_zrevrangebylex(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = RangeArgs::parse(
+        args.clone().into_iter().skip(1),
+        RangeBy::Lex,
+        true,
+        &["LIMIT"],
+    )?;
+    range_generic(session, args, range)
+}
+
+/// Implements `ZRANGESTORE dst src min max [BYSCORE|BYLEX] [REV] [LIMIT offset count]`.
+fn handle_zrangestore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let destination = arg_bytes(args.next().unwrap())?;
+    let source = arg_bytes(args.next().unwrap())?;
+    let range = RangeArgs::parse(
+        args,
+        RangeBy::Rank,
+        false,
+        &["BYSCORE", "BYLEX", "REV", "LIMIT"],
+    )?;
+
+    let mut db = session.lock_db();
+    let entries = match get_zset(&mut db, &source)? {
+        Some(zset) => range.select(zset),
+        None => vec![],
+    };
+    let zset = entries.into_iter().collect();
+    Ok(Value::Number(store(&mut db, destination, zset) as i64))
+}
+
+fn handle_zpopmin(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    pop_generic(session, args, false)
+}
+
+fn handle_zpopmax(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    pop_generic(session, args, true)
+}
+
+/// Implements `ZPOPMIN key [count]` and `ZPOPMAX key [count]`.
+fn pop_generic(session: &mut Session, args: Vec<Value>, rev: bool) -> CommandResult {
+    if args.len() > 2 {
+        return Err(ERR_SYNTAX.into());
+    }
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let count = match args.next() {
+        Some(count) => usize::try_from(arg_i64(&count)?).map_err(|_| ERR_POSITIVE)?,
+        None => 1,
+    };
+
+    let mut db = session.lock_db();
+    let popped = pop(&mut db, &key, rev, count)?.unwrap_or_default();
+    Ok(entries_reply(popped, true))
+}
+
+fn handle_bzpopmin(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    blocking_pop(session, args, false)
+}
+
+fn handle_bzpopmax(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    blocking_pop(session, args, true)
+}
+
+/// Implements `BZPOPMIN key [key ...] timeout` and BZPOPMAX, replying with the key name, the
+/// popped member and its score.
+fn blocking_pop(session: &mut Session, mut args: Vec<Value>, rev: bool) -> CommandResult {
+    let timeout = parse_timeout(&args.pop().unwrap())?;
+    let keys = args
+        .into_iter()
+        .map(arg_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let reply = block_on(session, &keys, timeout, |db| {
+        for key in &keys {
+            if let Some((member, score)) = pop(db, key, rev, 1)?.and_then(|p| p.into_iter().next())
+            {
+                return Ok(Some(Value::Array(vec![
+                    Value::Blob(key.clone()),
+                    Value::Blob(member),
+                    score_reply(score),
+                ])));
+            }
+        }
+        Ok(None)
+    })?;
+
+    // logged as the non blocking command that served the client.
+    if let Value::Array(items) = &reply {
+        if let Value::Blob(key) = &items[0] {
+            let name: &[u8] = if rev { b"ZPOPMAX" } else { b"ZPOPMIN" };
+            session.propagate(vec![command(&[name, key])]);
+        }
+    }
+    Ok(reply)
+}
+
+fn handle_zremrangebyrank(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = Range::Rank(arg_i64(&args[1])?, arg_i64(&args[2])?);
+    remrange_generic(session, args, range)
+}
+
+fn handle_zremrangebyscore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = Range::Score(parse_score_range(&args[1], &args[2])?);
+    remrange_generic(session, args, range)
+}
+
+fn handle_zremrangebylex(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let range = Range::Lex(parse_lex_range(&args[1], &args[2])?);
+    remrange_generic(session, args, range)
+}
+
+fn remrange_generic(session: &mut Session, args: Vec<Value>, range: Range) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let range = RangeArgs {
+        range,
+        rev: false,
+        limit: None,
+        withscores: false,
+    };
+
+    let mut db = session.lock_db();
+    let zset = match get_zset(&mut db, &key)? {
+        Some(zset) => zset,
+        None => return Ok(Value::Number(0)),
+    };
+    let removed = range.select(zset);
+    for (member, _) in &removed {
+        zset.remove(member);
+    }
+    if zset.is_empty() {
+        db.remove(&key);
+    }
+    db.modified(&key, removed.len());
+    Ok(Value::Number(removed.len() as i64))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN, which is not a valid score.
+            Self::Sum => Some(a + b).filter(|s| !s.is_nan()).unwrap_or(0.0),
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+/// Arguments of ZUNION, ZINTER, ZDIFF and their STORE variants.
+struct SetOpArgs {
+    keys: Vec<Bytes>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    withscores: bool,
+}
+
+impl SetOpArgs {
+    /// Parses `numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX]
+    /// [WITHSCORES]`. ZDIFF doesn't accept weights and aggregation, and the STORE variants
+    /// don't accept WITHSCORES.
+    fn parse(
+        mut args: impl ExactSizeIterator<Item = Value>,
+        name: &str,
+        op: SetOp,
+        store: bool,
+    ) -> Result<Self, CommandError> {
+        let numkeys = arg_i64(&args.next().unwrap())?;
+        if numkeys <= 0 {
+            return Err(format!("at least 1 input key is needed for '{}' command", name).into());
+        }
+        let numkeys = numkeys as usize;
+        if numkeys > args.len() {
+            return Err(ERR_SYNTAX.into());
+        }
+        let keys = args
+            .by_ref()
+            .take(numkeys)
+            .map(arg_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut parsed = Self {
+            keys,
+            weights: vec![1.0; numkeys],
+            aggregate: Aggregate::Sum,
+            withscores: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg_option(&arg).as_str() {
+                "WEIGHTS" if op != SetOp::Diff => {
+                    for weight in parsed.weights.iter_mut() {
+                        let arg = args.next().ok_or(ERR_SYNTAX)?;
+                        *weight = arg_f64(&arg).map_err(|_| "weight value is not a float")?;
+                    }
+                }
+                "AGGREGATE" if op != SetOp::Diff => {
+                    let arg = args.next().ok_or(ERR_SYNTAX)?;
+                    parsed.aggregate = match arg_option(&arg).as_str() {
+                        "SUM" => Aggregate::Sum,
+                        "MIN" => Aggregate::Min,
+                        "MAX" => Aggregate::Max,
+                        _ => return Err(ERR_SYNTAX.into()),
+                    };
+                }
+                "WITHSCORES" if !store => parsed.withscores = true,
+                _ => return Err(ERR_SYNTAX.into()),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// An input of the sorted set operations. Plain sets are accepted too, with all scores being 1.
+enum Input<'a> {
+    Set(&'a Set),
+    SortedSet(&'a SortedSet),
+}
+
+impl<'a> Input<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Set(set) => set.len(),
+            Self::SortedSet(zset) => zset.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Self::Set(set) => set.contains(member).then_some(1.0),
+            Self::SortedSet(zset) => zset.score(member),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + 'a> {
+        match *self {
+            Self::Set(set) => Box::new(set.iter().map(|m| (m, 1.0))),
+            Self::SortedSet(zset) => Box::new(zset.iter().map(|(m, s)| (m.clone(), s))),
+        }
+    }
+}
+
+fn weighted(score: f64, weight: f64) -> f64 {
+    // 0 * inf is NaN, which is not a valid score.
+    Some(score * weight).filter(|s| !s.is_nan()).unwrap_or(0.0)
+}
+
+/// Computes the union, intersection or difference of the inputs. Missing keys are treated as
+/// empty sets.
+fn zset_algebra(
+    db: &mut InternalDb,
+    args: &SetOpArgs,
+    op: SetOp,
+) -> Result<SortedSet, CommandError> {
+    let inputs = db
+        .get_many(&args.keys)
+        .into_iter()
+        .map(|obj| match obj {
+            None => Ok(None),
+            Some(Object::Set(set)) => Ok(Some(Input::Set(set))),
+            Some(Object::SortedSet(zset)) => Ok(Some(Input::SortedSet(zset))),
+            Some(_) => Err(CommandError::wrongtype()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let result = match op {
+        SetOp::Union => {
+            let mut scores: HashMap<Bytes, f64> = HashMap::new();
+            for (input, weight) in inputs.iter().zip(&args.weights) {
+                for (member, score) in input.iter().flat_map(|input| input.iter()) {
+                    let score = weighted(score, *weight);
+                    scores
+                        .entry(member)
+                        .and_modify(|s| *s = args.aggregate.apply(*s, score))
+                        .or_insert(score);
+                }
+            }
+            scores.into_iter().collect()
+        }
+        SetOp::Inter => {
+            if inputs.iter().any(|input| input.is_none()) {
+                return Ok(SortedSet::new());
+            }
+            let mut inputs: Vec<(Input, f64)> = inputs
+                .into_iter()
+                .flatten()
+                .zip(args.weights.clone())
+                .collect();
+            inputs.sort_by_key(|(input, _)| input.len());
+
+            let (first, first_weight) = &inputs[0];
+            first
+                .iter()
+                .filter_map(|(member, score)| {
+                    let mut score = weighted(score, *first_weight);
+                    for (input, weight) in &inputs[1..] {
+                        let other = weighted(input.score(&member)?, *weight);
+                        score = args.aggregate.apply(score, other);
+                    }
+                    Some((member, score))
+                })
+                .collect()
+        }
+        SetOp::Diff => match &inputs[0] {
+            Some(first) => first
+                .iter()
+                .filter(|(member, _)| {
+                    inputs[1..]
+                        .iter()
+                        .flatten()
+                        .all(|input| input.score(member).is_none())
+                })
+                .collect(),
+            None => SortedSet::new(),
+        },
+    };
+    Ok(result)
+}
+
+fn setop_generic(session: &mut Session, args: Vec<Value>, name: &str, op: SetOp) -> CommandResult {
+    let args = SetOpArgs::parse(args.into_iter(), name, op, false)?;
+    let mut db = session.lock_db();
+    let result = zset_algebra(&mut db, &args, op)?;
+    Ok(entries_reply(
+        result.iter().map(|(m, s)| (m.clone(), s)),
+        args.withscores,
+    ))
+}
+
+fn setop_store_generic(
+    session: &mut Session,
+    args: Vec<Value>,
+    name: &str,
+    op: SetOp,
+) -> CommandResult {
+    let mut args = args.into_iter();
+    let destination = arg_bytes(args.next().unwrap())?;
+    let args = SetOpArgs::parse(args, name, op, true)?;
+
+    let mut db = session.lock_db();
+    let result = zset_algebra(&mut db, &args, op)?;
+    Ok(Value::Number(store(&mut db, destination, result) as i64))
+}
+
+fn handle_zunion(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_generic(session, args, "zunion", SetOp::Union)
+}
+
+fn handle_zinter(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_generic(session, args, "zinter", SetOp::Inter)
+}
+
+fn handle_zdiff(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_generic(session, args, "zdiff", SetOp::Diff)
+}
+
+fn handle_zunionstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_store_generic(session, args, "zunionstore", SetOp::Union)
+}
+
+fn handle_zinterstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_store_generic(session, args, "zinterstore", SetOp::Inter)
+}
+
+fn handle_zdiffstore(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    setop_store_generic(session, args, "zdiffstore", SetOp::Diff)
+}
+
+/// Implements `ZSCAN key cursor [MATCH pattern] [COUNT count]`.
+fn handle_zscan(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let scan = ScanArgs::parse(args, &[])?;
+
+    let mut db = session.lock_db();
+    let zset = match get_zset(&mut db, &key)? {
+        Some(zset) => zset,
+        None => return Ok(ScanArgs::reply(0, vec![])),
+    };
+    let (cursor, entries) = zset.scan(scan.cursor, scan.count);
+    let mut items = vec![];
+    for (member, score) in entries {
+        if scan.matches(member) {
+            items.push(Value::Blob(member.clone()));
+            items.push(score_reply(score));
+        }
+    }
+    Ok(ScanArgs::reply(cursor, items))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    fn blobs(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|v| Value::Blob((*v).into())).collect())
+    }
+
+    #[test]
+    fn test_zadd() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            Value::Number(3),
+            run(&["ZADD", "z", "1", "a", "2", "b", "3", "c"])
+        );
+        assert_eq!(Value::Number(0), run(&["ZADD", "z", "NX", "10", "a"]));
+        assert_eq!(Value::Blob("1".into()), run(&["ZSCORE", "z", "a"]));
+        assert_eq!(Value::Number(0), run(&["ZADD", "z", "XX", "1", "d"]));
+        assert_eq!(Value::Null, run(&["ZSCORE", "z", "d"]));
+        assert_eq!(Value::Number(1), run(&["ZADD", "z", "XX", "CH", "5", "a"]));
+        assert_eq!(Value::Number(0), run(&["ZADD", "z", "GT", "CH", "4", "a"]));
+        assert_eq!(Value::Number(1), run(&["ZADD", "z", "LT", "CH", "4", "a"]));
+        assert_eq!(
+            Value::Blob("6.5".into()),
+            run(&["ZADD", "z", "INCR", "2.5", "a"])
+        );
+        assert_eq!(Value::Null, run(&["ZADD", "z", "NX", "INCR", "1", "a"]));
+        assert_eq!(Value::Blob("7".into()), run(&["ZINCRBY", "z", "0.5", "a"]));
+        assert_eq!(
+            Value::Array(vec![Value::Blob("2".into()), Value::Null]),
+            run(&["ZMSCORE", "z", "b", "none"])
+        );
+
+        assert_eq!(
+            Value::err("XX and NX options at the same time are not compatible"),
+            run(&["ZADD", "z", "NX", "XX", "1", "a"])
+        );
+        assert_eq!(
+            Value::err("GT, LT, and/or NX options at the same time are not compatible"),
+            run(&["ZADD", "z", "GT", "LT", "1", "a"])
+        );
+        assert_eq!(
+            Value::err("INCR option supports a single increment-element pair"),
+            run(&["ZADD", "z", "INCR", "1", "a", "2", "b"])
+        );
+        assert_eq!(
+            Value::err("syntax error"),
+            run(&["ZADD", "z", "1", "a", "2"])
+        );
+        assert_eq!(
+            Value::err("value is not a valid float"),
+            run(&["ZADD", "z", "x", "a"])
+        );
+        run(&["ZADD", "z", "inf", "inf"]);
+        assert_eq!(
+            Value::err("resulting score is not a number (NaN)"),
+            run(&["ZINCRBY", "z", "-inf", "inf"])
+        );
+
+        assert_eq!(Value::Number(4), run(&["ZCARD", "z"]));
+        assert_eq!(Value::Number(2), run(&["ZREM", "z", "a", "b", "none"]));
+        assert_eq!(Value::Number(0), run(&["ZADD", "new", "XX", "1", "a"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "new"]));
+    }
+
+    #[test]
+    fn test_zrange() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&[
+            "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+        ]);
+        assert_eq!(blobs(&["a", "b", "c"]), run(&["ZRANGE", "z", "0", "2"]));
+        assert_eq!(blobs(&["e", "d"]), run(&["ZRANGE", "z", "0", "1", "REV"]));
+        assert_eq!(blobs(&["d", "e"]), run(&["ZRANGE", "z", "-2", "-1"]));
+        assert_eq!(blobs(&["b", "a"]), run(&["ZREVRANGE", "z", "3", "10"]));
+        assert_eq!(
+            blobs(&["a", "1", "b", "2"]),
+            run(&["ZRANGE", "z", "0", "1", "WITHSCORES"])
+        );
+
+        assert_eq!(
+            blobs(&["b", "c", "d"]),
+            run(&["ZRANGE", "z", "(1", "4", "BYSCORE"])
+        );
+        assert_eq!(
+            blobs(&["d", "c"]),
+            run(&["ZRANGE", "z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"])
+        );
+        assert_eq!(
+            blobs(&["c", "d", "e"]),
+            run(&["ZRANGEBYSCORE", "z", "3", "+inf"])
+        );
+        assert_eq!(blobs(&["b"]), run(&["ZREVRANGEBYSCORE", "z", "2", "(1"]));
+        assert_eq!(Value::Number(3), run(&["ZCOUNT", "z", "2", "(5"]));
+        assert_eq!(
+            Value::err("min or max is not a float"),
+            run(&["ZCOUNT", "z", "x", "1"])
+        );
+        assert_eq!(
+            Value::err(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+            ),
+            run(&["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"])
+        );
+
+        assert_eq!(Value::Number(2), run(&["ZRANK", "z", "c"]));
+        assert_eq!(Value::Number(0), run(&["ZREVRANK", "z", "e"]));
+        assert_eq!(
+            Value::Array(vec![Value::Number(1), Value::Blob("2".into())]),
+            run(&["ZRANK", "z", "b", "WITHSCORE"])
+        );
+        assert_eq!(Value::Null, run(&["ZRANK", "z", "none"]));
+
+        run(&["ZADD", "lex", "0", "a", "0", "b", "0", "c", "0", "d"]);
+        assert_eq!(
+            blobs(&["b", "c"]),
+            run(&["ZRANGE", "lex", "(a", "[c", "BYLEX"])
+        );
+        assert_eq!(
+            blobs(&["d", "c"]),
+            run(&["ZREVRANGEBYLEX", "lex", "+", "-", "LIMIT", "0", "2"])
+        );
+        assert_eq!(Value::Number(4), run(&["ZLEXCOUNT", "lex", "-", "+"]));
+        assert_eq!(
+            Value::err("min or max not valid string range item"),
+            run(&["ZRANGEBYLEX", "lex", "a", "+"])
+        );
+
+        assert_eq!(
+            Value::Number(2),
+            run(&[
+                "ZRANGESTORE",
+                "dst",
+                "z",
+                "4",
+                "2",
+                "BYSCORE",
+                "REV",
+                "LIMIT",
+                "0",
+                "2"
+            ])
+        );
+        assert_eq!(blobs(&["c", "d"]), run(&["ZRANGE", "dst", "0", "-1"]));
+        assert_eq!(
+            Value::Number(0),
+            run(&["ZRANGESTORE", "dst", "z", "5", "1"])
+        );
+        assert_eq!(Value::Number(0), run(&["EXISTS", "dst"]));
+    }
+
+    #[test]
+    fn test_zpop_and_zremrange() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&[
+            "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+        ]);
+        assert_eq!(blobs(&["a", "1"]), run(&["ZPOPMIN", "z"]));
+        assert_eq!(blobs(&["e", "5", "d", "4"]), run(&["ZPOPMAX", "z", "2"]));
+        assert_eq!(
+            Value::err("value is out of range, must be positive"),
+            run(&["ZPOPMIN", "z", "-1"])
+        );
+        assert_eq!(Value::Array(vec![]), run(&["ZPOPMIN", "none"]));
+
+        run(&["ZADD", "bz", "1", "a", "2", "b"]);
+        assert_eq!(
+            blobs(&["bz", "b", "2"]),
+            run(&["BZPOPMAX", "none", "bz", "0"])
+        );
+        assert_eq!(blobs(&["bz", "a", "1"]), run(&["BZPOPMIN", "bz", "0"]));
+        assert_eq!(Value::Null, run(&["BZPOPMIN", "bz", "0.01"]));
+
+        run(&["ZADD", "z", "1", "a", "4", "d", "5", "e"]);
+        assert_eq!(Value::Number(2), run(&["ZREMRANGEBYRANK", "z", "0", "1"]));
+        assert_eq!(Value::Number(1), run(&["ZREMRANGEBYSCORE", "z", "(3", "4"]));
+        assert_eq!(blobs(&["c", "e"]), run(&["ZRANGE", "z", "0", "-1"]));
+
+        run(&["ZADD", "lex", "0", "a", "0", "b", "0", "c"]);
+        assert_eq!(Value::Number(3), run(&["ZREMRANGEBYLEX", "lex", "-", "+"]));
+        assert_eq!(Value::Number(0), run(&["EXISTS", "lex"]));
+    }
+
+    #[test]
+    fn test_zset_algebra() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["ZADD", "a", "1", "x", "2", "y", "3", "z"]);
+        run(&["ZADD", "b", "10", "y", "20", "z", "30", "w"]);
+        run(&["SADD", "s", "z", "w"]);
+
+        assert_eq!(
+            blobs(&["x", "1", "y", "12", "z", "23", "w", "30"]),
+            run(&["ZUNION", "2", "a", "b", "WITHSCORES"])
+        );
+        assert_eq!(
+            blobs(&["y", "2", "z", "3"]),
+            run(&["ZINTER", "2", "a", "b", "AGGREGATE", "MIN", "WITHSCORES"])
+        );
+        assert_eq!(
+            blobs(&["z", "7"]),
+            run(&[
+                "ZINTER",
+                "3",
+                "a",
+                "b",
+                "s",
+                "WEIGHTS",
+                "2",
+                "0",
+                "1",
+                "WITHSCORES"
+            ])
+        );
+        assert_eq!(blobs(&["x", "y"]), run(&["ZDIFF", "2", "a", "s"]));
+
+        assert_eq!(
+            Value::Number(4),
+            run(&["ZUNIONSTORE", "dst", "2", "a", "b", "AGGREGATE", "MAX"])
+        );
+        assert_eq!(
+            blobs(&["x", "1", "y", "10", "z", "20", "w", "30"]),
+            run(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"])
+        );
+        assert_eq!(
+            Value::Number(0),
+            run(&["ZINTERSTORE", "dst", "2", "a", "none"])
+        );
+        assert_eq!(Value::Number(0), run(&["EXISTS", "dst"]));
+        assert_eq!(Value::Number(1), run(&["ZDIFFSTORE", "dst", "2", "a", "b"]));
+
+        assert_eq!(
+            Value::err("at least 1 input key is needed for 'zunionstore' command"),
+            run(&["ZUNIONSTORE", "dst", "0", "a"])
+        );
+        assert_eq!(
+            Value::err("weight value is not a float"),
+            run(&["ZUNION", "1", "a", "WEIGHTS", "x"])
+        );
+        assert_eq!(
+            Value::err("syntax error"),
+            run(&["ZDIFF", "1", "a", "AGGREGATE", "SUM"])
+        );
+    }
+
+    #[test]
+    fn test_zscan() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["ZADD", "z", "1", "one", "2", "two", "3", "three"]);
+        let reply = run(&["ZSCAN", "z", "0", "MATCH", "t*"]);
+        let items = match reply {
+            Value::Array(mut reply) => reply.remove(1),
+            other => panic!("unexpected reply {:?}", other),
+        };
+        match items {
+            Value::Array(items) => assert_eq!(4, items.len()),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+}
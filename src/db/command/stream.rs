@@ -0,0 +1,1939 @@
+use std::iter::Peekable;
+use std::time::Duration;
+
+use crate::db::aof::{claim_command, command};
+use crate::db::{
+    now_millis, ClaimOptions, ClaimResult, ConsumerGroup, InternalDb, Object, Session, Stream,
+    StreamFields, StreamId, TrimStrategy,
+};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_key, arg_option, block_on, CommandError, CommandResult, CommandSpec,
+    COMMAND_FLAG_BLOCKING, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST, COMMAND_FLAG_READONLY,
+    COMMAND_FLAG_SLOW, COMMAND_FLAG_STREAM, COMMAND_FLAG_WRITE, ERR_NO_SUCH_KEY, ERR_SYNTAX,
+    ERR_TIMEOUT_NEGATIVE,
+};
+
+const ERR_INVALID_ID: &str = "Invalid stream ID specified as stream command argument";
+const ERR_XADD_ID_ZERO: &str = "The ID specified in XADD must be greater than 0-0";
+const ERR_XADD_ID_SMALL: &str =
+    "The ID specified in XADD is equal or smaller than the target stream top item";
+const ERR_XGROUP_NO_KEY: &str = "The XGROUP subcommand requires the key to exist. Note that for \
+    CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    let spec = |name: &str, args_len, flags: &[&'static str], first_key, handler| CommandSpec {
+        name: name.to_string(),
+        args_len,
+        flags: [flags, &[COMMAND_FLAG_STREAM]].concat(),
+        first_key,
+        last_key: first_key,
+        key_step: 1,
+        get_keys: None,
+        handler,
+    };
+    let write_fast = [COMMAND_FLAG_WRITE, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_FAST];
+    let write = [COMMAND_FLAG_WRITE, COMMAND_FLAG_FAST];
+    let write_slow = [COMMAND_FLAG_WRITE, COMMAND_FLAG_SLOW];
+    let read_fast = [COMMAND_FLAG_READONLY, COMMAND_FLAG_FAST];
+    let read_slow = [COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW];
+    let read_blocking = [
+        COMMAND_FLAG_READONLY,
+        COMMAND_FLAG_SLOW,
+        COMMAND_FLAG_BLOCKING,
+    ];
+    let write_blocking = [COMMAND_FLAG_WRITE, COMMAND_FLAG_SLOW, COMMAND_FLAG_BLOCKING];
+
+    vec![
+        spec("XADD", -5, &write_fast, 1, handle_xadd),
+        spec("XLEN", 2, &read_fast, 1, handle_xlen),
+        spec("XRANGE", -4, &read_slow, 1, handle_xrange),
+        spec("XREVRANGE", -4, &read_slow, 1, handle_xrevrange),
+        spec("XDEL", -3, &write, 1, handle_xdel),
+        spec("XTRIM", -4, &write_slow, 1, handle_xtrim),
+        spec("XSETID", -3, &write_fast, 1, handle_xsetid),
+        CommandSpec {
+            get_keys: Some(xread_keys),
+            ..spec("XREAD", -4, &read_blocking, 0, handle_xread)
+        },
+        CommandSpec {
+            get_keys: Some(xreadgroup_keys),
+            ..spec("XREADGROUP", -7, &write_blocking, 0, handle_xreadgroup)
+        },
+        spec("XGROUP", -2, &write_slow, 2, handle_xgroup),
+        spec("XACK", -4, &write, 1, handle_xack),
+        spec("XPENDING", -3, &read_slow, 1, handle_xpending),
+        spec("XCLAIM", -6, &write, 1, handle_xclaim),
+        spec("XAUTOCLAIM", -6, &write, 1, handle_xautoclaim),
+        spec("XINFO", -2, &read_slow, 2, handle_xinfo),
+    ]
+}
+
+/// Finds the keys given after STREAMS, which are followed by as many IDs. The options before it
+/// are searched from `start`.
+fn streams_keys(args: &[Value], start: usize) -> Vec<&[u8]> {
+    let streams = match args
+        .iter()
+        .skip(start)
+        .position(|arg| arg_option(arg) == "STREAMS")
+    {
+        Some(position) => &args[start + position + 1..],
+        None => return vec![],
+    };
+    streams[..streams.len() / 2]
+        .iter()
+        .filter_map(arg_key)
+        .collect()
+}
+
+/// Finds the keys of `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`.
+fn xread_keys(args: &[Value]) -> Vec<&[u8]> {
+    streams_keys(args, 0)
+}
+
+/// Finds the keys of XREADGROUP, skipping `GROUP group consumer` which come first.
+fn xreadgroup_keys(args: &[Value]) -> Vec<&[u8]> {
+    streams_keys(args, 3)
+}
+
+/// Returns the stream stored at the key, failing if the key holds another type.
+pub(super) fn get_stream<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+) -> Result<Option<&'a mut Stream>, CommandError> {
+    match db.get_mut(key) {
+        None => Ok(None),
+        Some(Object::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+/// Returns the consumer group of the stream stored at the key, failing with NOGROUP if either
+/// of them doesn't exist.
+fn get_group<'a>(
+    db: &'a mut InternalDb,
+    key: &Bytes,
+    group: &Bytes,
+) -> Result<&'a mut Stream, CommandError> {
+    match get_stream(db, key)? {
+        Some(stream) if stream.group(group).is_some() => Ok(stream),
+        _ => Err(CommandError::Code(
+            "NOGROUP",
+            format!(
+                "No such key '{}' or consumer group '{}'",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(group)
+            ),
+        )),
+    }
+}
+
+fn no_group_error(key: &Bytes, group: &Bytes) -> CommandError {
+    CommandError::Code(
+        "NOGROUP",
+        format!(
+            "No such consumer group '{}' for key name '{}'",
+            String::from_utf8_lossy(group),
+            String::from_utf8_lossy(key)
+        ),
+    )
+}
+
+/// Parses a stream ID in the form `ms-seq` or `ms`, in which case the sequence defaults to
+/// `missing_seq`. `-` and `+` are the smallest and greatest IDs.
+fn parse_id(arg: &Value, missing_seq: u64) -> Result<StreamId, CommandError> {
+    let arg = match arg {
+        Value::Simple(s) | Value::Blob(s) => s.clone(),
+        Value::Number(n) => Bytes::from(&n.to_string()),
+        _ => return Err(ERR_INVALID_ID.into()),
+    };
+    let parse = |s: &[u8]| -> Option<u64> { std::str::from_utf8(s).ok()?.parse().ok() };
+    match &arg[..] {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        id => {
+            let id = match id.iter().position(|c| *c == b'-') {
+                Some(pos) => parse(&id[..pos]).zip(parse(&id[pos + 1..])),
+                None => parse(id).map(|ms| (ms, missing_seq)),
+            };
+            id.map(|(ms, seq)| StreamId::new(ms, seq))
+                .ok_or_else(|| ERR_INVALID_ID.into())
+        }
+    }
+}
+
+/// Parses the start of an XRANGE interval, which is exclusive if prefixed with `(`.
+fn parse_range_start(arg: &Value) -> Result<StreamId, CommandError> {
+    match exclusive_id(arg) {
+        Some(id) => parse_id(&id, 0)?
+            .next()
+            .ok_or_else(|| "invalid start ID for the interval".into()),
+        None => parse_id(arg, 0),
+    }
+}
+
+/// Parses the end of an XRANGE interval, which is exclusive if prefixed with `(`.
+fn parse_range_end(arg: &Value) -> Result<StreamId, CommandError> {
+    match exclusive_id(arg) {
+        Some(id) => parse_id(&id, u64::MAX)?
+            .prev()
+            .ok_or_else(|| "invalid end ID for the interval".into()),
+        None => parse_id(arg, u64::MAX),
+    }
+}
+
+/// Strips the `(` prefix of an exclusive interval ID. `-` and `+` can't be exclusive.
+fn exclusive_id(arg: &Value) -> Option<Value> {
+    match arg {
+        Value::Simple(s) | Value::Blob(s) => match s.strip_prefix(b"(") {
+            Some(b"-") | Some(b"+") => Some(Value::Blob(Bytes::from("invalid"))),
+            Some(id) => Some(Value::Blob(Bytes::from(id.to_vec()))),
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+fn id_reply(id: StreamId) -> Value {
+    Value::Blob(Bytes::from(&id.to_string()))
+}
+
+fn fields_reply(fields: &StreamFields) -> Value {
+    let mut reply = Vec::with_capacity(fields.len() * 2);
+    for (field, value) in fields {
+        reply.push(Value::Blob(field.clone()));
+        reply.push(Value::Blob(value.clone()));
+    }
+    Value::Array(reply)
+}
+
+fn entry_reply(id: StreamId, fields: &StreamFields) -> Value {
+    Value::Array(vec![id_reply(id), fields_reply(fields)])
+}
+
+/// Replies with a map as a flat array of alternating keys and values.
+fn map_reply(pairs: Vec<(&str, Value)>) -> Value {
+    let mut reply = Vec::with_capacity(pairs.len() * 2);
+    for (key, value) in pairs {
+        reply.push(Value::Blob(Bytes::from(key)));
+        reply.push(value);
+    }
+    Value::Array(reply)
+}
+
+/// Trimming options of XADD and XTRIM.
+struct TrimArgs {
+    strategy: TrimStrategy,
+    limit: Option<usize>,
+}
+
+impl TrimArgs {
+    /// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]`, after the MAXLEN or MINID token
+    /// which is given as `option`.
+    fn parse(
+        option: &str,
+        args: &mut Peekable<impl Iterator<Item = Value>>,
+    ) -> Result<Self, CommandError> {
+        let mut approx = false;
+        let mut threshold = args.next().ok_or(ERR_SYNTAX)?;
+        match arg_option(&threshold).as_str() {
+            "~" => {
+                approx = true;
+                threshold = args.next().ok_or(ERR_SYNTAX)?;
+            }
+            "=" => threshold = args.next().ok_or(ERR_SYNTAX)?,
+            _ => (),
+        }
+        let strategy = match option {
+            "MAXLEN" => {
+                let max = arg_i64(&threshold)?;
+                TrimStrategy::MaxLen(
+                    u64::try_from(max).map_err(|_| "The MAXLEN argument must be >= 0.")?,
+                )
+            }
+            _ => TrimStrategy::MinId(parse_id(&threshold, 0)?),
+        };
+
+        let mut limit = None;
+        if args.peek().map(arg_option).as_deref() == Some("LIMIT") {
+            args.next();
+            let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+            let count = usize::try_from(count).map_err(|_| "The LIMIT argument must be >= 0.")?;
+            if !approx {
+                return Err(
+                    "syntax error, LIMIT cannot be used without the special ~ option".into(),
+                );
+            }
+            limit = Some(count);
+        }
+        Ok(Self { strategy, limit })
+    }
+}
+
+/// Implements `XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field
+/// value [field value ...]`.
+fn handle_xadd(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let args_len = args.len();
+    let mut args = args.into_iter().peekable();
+    let key = arg_bytes(args.next().unwrap())?;
+
+    let mut nomkstream = false;
+    let mut trim = None;
+    while let Some(arg) = args.peek() {
+        match arg_option(arg).as_str() {
+            "NOMKSTREAM" => nomkstream = true,
+            option @ ("MAXLEN" | "MINID") => {
+                args.next();
+                trim = Some(TrimArgs::parse(option, &mut args)?);
+                continue;
+            }
+            _ => break,
+        }
+        args.next();
+    }
+
+    let id = args.next().ok_or(ERR_SYNTAX)?;
+    let rest: Vec<Value> = args.collect();
+    let id_index = args_len - rest.len() - 1;
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err("wrong number of arguments for 'xadd' command".into());
+    }
+    let mut fields = Vec::with_capacity(rest.len() / 2);
+    let mut rest = rest.into_iter();
+    while let (Some(field), Some(value)) = (rest.next(), rest.next()) {
+        fields.push((arg_bytes(field)?, arg_bytes(value)?));
+    }
+
+    let id = AddId::parse(&id)?;
+    if id == AddId::Explicit(StreamId::MIN) {
+        return Err(ERR_XADD_ID_ZERO.into());
+    }
+
+    let mut db = session.lock_db();
+    let last_id = match get_stream(&mut db, &key)? {
+        Some(stream) => stream.last_id(),
+        None if nomkstream => return Ok(Value::Null),
+        None => StreamId::MIN,
+    };
+    let generated = !matches!(id, AddId::Explicit(_));
+    let id = id.resolve(last_id)?;
+
+    let stream = match db.get_or_insert_with(&key, || Object::Stream(Stream::new())) {
+        Object::Stream(stream) => stream,
+        _ => return Err(CommandError::wrongtype()),
+    };
+    stream.insert(id, fields);
+    if let Some(trim) = trim {
+        stream.trim(trim.strategy, trim.limit);
+    }
+    db.modified(&key, 1);
+    // the stream may already exist, so the clients blocked on it are not woken up on their own.
+    db.signal_ready(&key);
+    drop(db);
+
+    // the generated IDs depend on the current time, so the ID is logged instead.
+    if generated {
+        session.rewrite_arg(id_index, Value::Blob(Bytes::from(&id.to_string())));
+    }
+    Ok(id_reply(id))
+}
+
+/// The ID argument of XADD.
+#[derive(Debug, PartialEq, Eq)]
+enum AddId {
+    /// `*`, generated from the current time.
+    Auto,
+    /// `ms-*`, with a generated sequence number.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl AddId {
+    fn parse(arg: &Value) -> Result<Self, CommandError> {
+        match arg {
+            Value::Simple(s) | Value::Blob(s) if &s[..] == b"*" => Ok(Self::Auto),
+            Value::Simple(s) | Value::Blob(s) if s.ends_with(b"-*") => {
+                let ms = Value::Blob(Bytes::from(s[..s.len() - 2].to_vec()));
+                Ok(Self::AutoSeq(parse_id(&ms, 0)?.ms))
+            }
+            arg => Ok(Self::Explicit(parse_id(arg, 0)?)),
+        }
+    }
+
+    /// Returns the ID of the new entry, which must be greater than the last ID of the stream.
+    fn resolve(self, last_id: StreamId) -> Result<StreamId, CommandError> {
+        let id = match self {
+            Self::Auto => {
+                let ms = now_millis();
+                if ms > last_id.ms {
+                    Some(StreamId::new(ms, 0))
+                } else {
+                    last_id.next()
+                }
+            }
+            Self::AutoSeq(ms) if ms == last_id.ms => last_id.next().filter(|id| id.ms == ms),
+            Self::AutoSeq(ms) => Some(StreamId::new(ms, 0)),
+            Self::Explicit(id) => Some(id),
+        };
+        match id {
+            Some(id) if id > last_id => Ok(id),
+            None if self == Self::Auto => Err(
+                "The stream has exhausted the last possible ID, unable to add more items".into(),
+            ),
+            _ => Err(ERR_XADD_ID_SMALL.into()),
+        }
+    }
+}
+
+fn handle_xlen(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+    let len = get_stream(&mut db, &key)?.map(|s| s.len()).unwrap_or(0);
+    Ok(Value::Number(len as i64))
+}
+
+fn handle_xrange(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    range_generic(session, args, false)
+}
+
+fn handle_xrevrange(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    range_generic(session, args, true)
+}
+
+/// Implements `XRANGE key start end [COUNT count]` and `XREVRANGE key end start [COUNT count]`.
+fn range_generic(session: &mut Session, args: Vec<Value>, rev: bool) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let (first, second) = (args.next().unwrap(), args.next().unwrap());
+    let (start, end) = if rev {
+        (parse_range_start(&second)?, parse_range_end(&first)?)
+    } else {
+        (parse_range_start(&first)?, parse_range_end(&second)?)
+    };
+
+    let mut count = usize::MAX;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "COUNT" => {
+                let n = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                count = usize::try_from(n).unwrap_or(0);
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let stream = match get_stream(&mut db, &key)? {
+        Some(stream) => stream,
+        None => return Ok(Value::Array(vec![])),
+    };
+    let range = stream.range(start, end);
+    let entries: Vec<Value> = if rev {
+        range
+            .rev()
+            .take(count)
+            .map(|(id, f)| entry_reply(id, f))
+            .collect()
+    } else {
+        range
+            .take(count)
+            .map(|(id, f)| entry_reply(id, f))
+            .collect()
+    };
+    Ok(Value::Array(entries))
+}
+
+fn handle_xdel(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let ids = args
+        .map(|arg| parse_id(&arg, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let deleted = match get_stream(&mut db, &key)? {
+        Some(stream) => ids.into_iter().filter(|id| stream.remove(*id)).count(),
+        None => 0,
+    };
+    db.modified(&key, deleted);
+    Ok(Value::Number(deleted as i64))
+}
+
+/// Implements `XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]`.
+fn handle_xtrim(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter().peekable();
+    let key = arg_bytes(args.next().unwrap())?;
+    let trim = match arg_option(&args.next().unwrap()).as_str() {
+        option @ ("MAXLEN" | "MINID") => TrimArgs::parse(option, &mut args)?,
+        _ => return Err(ERR_SYNTAX.into()),
+    };
+    if args.next().is_some() {
+        return Err(ERR_SYNTAX.into());
+    }
+
+    let mut db = session.lock_db();
+    let trimmed = match get_stream(&mut db, &key)? {
+        Some(stream) => stream.trim(trim.strategy, trim.limit),
+        None => 0,
+    };
+    db.modified(&key, trimmed);
+    Ok(Value::Number(trimmed as i64))
+}
+
+/// Implements `XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]`.
+fn handle_xsetid(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let last_id = parse_id(&args.next().unwrap(), 0)?;
+
+    let (mut entries_added, mut max_deleted_id) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "ENTRIESADDED" => {
+                let n = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                entries_added =
+                    Some(u64::try_from(n).map_err(|_| "entries_added must be positive")?);
+            }
+            "MAXDELETEDID" => {
+                let id = parse_id(&args.next().ok_or(ERR_SYNTAX)?, 0)?;
+                if last_id < id {
+                    return Err("The ID specified in XSETID is smaller than the provided \
+                        max_deleted_entry_id"
+                        .into());
+                }
+                max_deleted_id = Some(id);
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let stream = get_stream(&mut db, &key)?.ok_or(ERR_NO_SUCH_KEY)?;
+    if stream.last_entry().is_some_and(|(id, _)| last_id < id) {
+        return Err("The ID specified in XSETID is smaller than the target stream top item".into());
+    }
+    if entries_added.is_some_and(|n| n < stream.len() as u64) {
+        return Err(
+            "The entries_added specified in XSETID is smaller than the target stream length".into(),
+        );
+    }
+    stream.restore(
+        last_id,
+        max_deleted_id.unwrap_or(stream.max_deleted_id()),
+        entries_added.unwrap_or(stream.entries_added()),
+    );
+    db.modified(&key, 1);
+    Ok(Value::Simple("OK".into()))
+}
+
+/// The ID given to XREAD and XREADGROUP for each stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ReadId {
+    /// Entries with an ID greater than this one.
+    After(StreamId),
+    /// `$`, only entries added after the command is called.
+    Last,
+    /// `>`, entries never delivered to the consumer group.
+    Undelivered,
+}
+
+/// Arguments of XREAD and XREADGROUP.
+pub(super) struct ReadArgs {
+    pub(super) group: Option<(Bytes, Bytes)>,
+    pub(super) count: usize,
+    pub(super) noack: bool,
+    /// The timeout of `BLOCK`, `Some(None)` blocks forever.
+    pub(super) block: Option<Option<Duration>>,
+    pub(super) keys: Vec<Bytes>,
+    pub(super) ids: Vec<ReadId>,
+}
+
+impl ReadArgs {
+    /// Parses `[GROUP group consumer] [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key
+    /// [key ...] id [id ...]`.
+    fn parse(args: Vec<Value>, xreadgroup: bool) -> Result<Self, CommandError> {
+        let name = if xreadgroup { "xreadgroup" } else { "xread" };
+        let mut args = args.into_iter();
+        let mut parsed = Self {
+            group: None,
+            count: usize::MAX,
+            noack: false,
+            block: None,
+            keys: vec![],
+            ids: vec![],
+        };
+
+        loop {
+            let arg = args.next().ok_or(ERR_SYNTAX)?;
+            match arg_option(&arg).as_str() {
+                "COUNT" => {
+                    let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                    parsed.count = usize::try_from(count)
+                        .ok()
+                        .filter(|c| *c > 0)
+                        .unwrap_or(usize::MAX);
+                }
+                "GROUP" if xreadgroup => {
+                    let group = arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?;
+                    let consumer = arg_bytes(args.next().ok_or(ERR_SYNTAX)?)?;
+                    parsed.group = Some((group, consumer));
+                }
+                "BLOCK" => {
+                    let timeout = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)
+                        .map_err(|_| "timeout is not an integer or out of range")?;
+                    if timeout < 0 {
+                        return Err(ERR_TIMEOUT_NEGATIVE.into());
+                    }
+                    let timeout = Duration::from_millis(timeout as u64);
+                    parsed.block = Some(Some(timeout).filter(|t| !t.is_zero()));
+                }
+                "NOACK" if xreadgroup => parsed.noack = true,
+                "STREAMS" => break,
+                _ => return Err(ERR_SYNTAX.into()),
+            }
+        }
+
+        if args.len() == 0 || !args.len().is_multiple_of(2) {
+            return Err(format!(
+                "Unbalanced '{}' list of streams: for each stream key an ID or '$' must be \
+                 specified.",
+                name
+            )
+            .into());
+        }
+        if xreadgroup && parsed.group.is_none() {
+            return Err("Missing GROUP option for XREADGROUP".into());
+        }
+
+        let numkeys = args.len() / 2;
+        parsed.keys = args
+            .by_ref()
+            .take(numkeys)
+            .map(arg_bytes)
+            .collect::<Result<_, _>>()?;
+        for arg in args {
+            let id = match &arg {
+                Value::Simple(s) | Value::Blob(s) if &s[..] == b"$" => {
+                    if xreadgroup {
+                        return Err("The $ ID is meaningless in the context of XREADGROUP: you \
+                            want to read the history of this consumer by specifying a proper ID, \
+                            or use the > ID to get new messages. The $ ID would just return an \
+                            empty result set."
+                            .into());
+                    }
+                    ReadId::Last
+                }
+                Value::Simple(s) | Value::Blob(s) if &s[..] == b">" => {
+                    if !xreadgroup {
+                        return Err("The > ID can be specified only when calling XREADGROUP \
+                            using the GROUP <group> <consumer> option."
+                            .into());
+                    }
+                    ReadId::Undelivered
+                }
+                arg => ReadId::After(parse_id(arg, 0)?),
+            };
+            parsed.ids.push(id);
+        }
+        Ok(parsed)
+    }
+}
+
+/// Replaces the `$` IDs with the last ID of each stream, so the read only returns entries added
+/// from now on.
+pub(super) fn resolve_last_ids(
+    db: &mut InternalDb,
+    args: &mut ReadArgs,
+) -> Result<(), CommandError> {
+    for (key, id) in args.keys.iter().zip(args.ids.iter_mut()) {
+        if *id == ReadId::Last {
+            let last_id = get_stream(db, key)?
+                .map(|stream| stream.last_id())
+                .unwrap_or_default();
+            *id = ReadId::After(last_id);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entries of XREAD, the `$` IDs must already be resolved. Returns `None` if there
+/// are no entries to read.
+pub(super) fn read_streams(
+    db: &mut InternalDb,
+    args: &ReadArgs,
+) -> Result<Option<Value>, CommandError> {
+    let mut reply = vec![];
+    for (key, id) in args.keys.iter().zip(args.ids.iter()) {
+        let after = match id {
+            ReadId::After(id) => *id,
+            _ => continue,
+        };
+        let stream = match get_stream(db, key)? {
+            Some(stream) => stream,
+            None => continue,
+        };
+        let start = match after.next() {
+            Some(start) => start,
+            None => continue,
+        };
+        let entries: Vec<Value> = stream
+            .range(start, StreamId::MAX)
+            .take(args.count)
+            .map(|(id, f)| entry_reply(id, f))
+            .collect();
+        if !entries.is_empty() {
+            reply.push(Value::Array(vec![
+                Value::Blob(key.clone()),
+                Value::Array(entries),
+            ]));
+        }
+    }
+    Ok((!reply.is_empty()).then_some(Value::Array(reply)))
+}
+
+/// Reads the entries of XREADGROUP. Returns `None` if there are no entries to read, which only
+/// happens when reading undelivered entries, since reading the history of a consumer always
+/// replies with each stream.
+pub(super) fn read_groups(
+    db: &mut InternalDb,
+    args: &ReadArgs,
+) -> Result<Option<Value>, CommandError> {
+    let (group, consumer) = args.group.as_ref().unwrap();
+    for key in args.keys.iter() {
+        if get_stream(db, key)?.and_then(|s| s.group(group)).is_none() {
+            return Err(CommandError::Code(
+                "NOGROUP",
+                format!(
+                    "No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(group)
+                ),
+            ));
+        }
+    }
+
+    let now = now_millis();
+    let mut reply = vec![];
+    for (key, id) in args.keys.iter().zip(args.ids.iter()) {
+        let stream = get_stream(db, key)?.unwrap();
+        let entries: Vec<Value> = match id {
+            ReadId::Undelivered => {
+                let entries = stream.read_group(group, consumer, args.count, args.noack, now);
+                if entries.is_empty() {
+                    continue;
+                }
+                entries
+                    .iter()
+                    .map(|(id, fields)| entry_reply(*id, fields))
+                    .collect()
+            }
+            ReadId::After(after) => stream
+                .read_pending(group, consumer, *after, args.count, now)
+                .into_iter()
+                .map(|(id, fields)| match fields {
+                    Some(fields) => entry_reply(id, &fields),
+                    None => Value::Array(vec![id_reply(id), Value::Null]),
+                })
+                .collect(),
+            ReadId::Last => unreachable!(),
+        };
+        // the entries read are delivered to the consumer, or delivered again.
+        db.modified(key, entries.len());
+        reply.push(Value::Array(vec![
+            Value::Blob(key.clone()),
+            Value::Array(entries),
+        ]));
+    }
+    Ok((!reply.is_empty()).then_some(Value::Array(reply)))
+}
+
+/// Implements `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`.
+fn handle_xread(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let args_len = args.len();
+    let mut args = ReadArgs::parse(args, false)?;
+    let mut db = session.lock_db();
+    resolve_last_ids(&mut db, &mut args)?;
+    let timeout = match args.block {
+        Some(timeout) => timeout,
+        None => return Ok(read_streams(&mut db, &args)?.unwrap_or(Value::Null)),
+    };
+    drop(db);
+
+    // once retried, the read starts from the last IDs of when the client blocked.
+    let first_id = args_len - args.ids.len();
+    for (i, id) in args.ids.iter().enumerate() {
+        if let ReadId::After(id) = id {
+            session.rewrite_retried_arg(first_id + i, id_reply(*id));
+        }
+    }
+
+    block_on(session, &args.keys, timeout, |db| read_streams(db, &args))
+}
+
+/// Implements `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+/// STREAMS key [key ...] id [id ...]`.
+fn handle_xreadgroup(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let args = ReadArgs::parse(args, true)?;
+    let reply = match args.block {
+        Some(timeout) => block_on(session, &args.keys, timeout, |db| read_groups(db, &args))?,
+        None => {
+            let mut db = session.lock_db();
+            read_groups(&mut db, &args)?.unwrap_or(Value::Null)
+        }
+    };
+
+    if session.propagating() {
+        let commands = read_group_commands(&mut session.lock_db(), &args, &reply)?;
+        session.propagate(commands);
+    }
+    Ok(reply)
+}
+
+/// Builds the commands logged in place of XREADGROUP, which would deliver the entries at a
+/// different time when replayed: the delivered entries are claimed by the consumer with their
+/// delivery time and count, then the last ID of the group is set.
+fn read_group_commands(
+    db: &mut InternalDb,
+    args: &ReadArgs,
+    reply: &Value,
+) -> Result<Vec<Vec<Value>>, CommandError> {
+    let (group_name, consumer) = args.group.as_ref().unwrap();
+    let delivered: Vec<(&[u8], StreamId)> = match reply {
+        Value::Array(streams) => streams
+            .iter()
+            .filter_map(|stream| match stream {
+                Value::Array(items) => match (&items[0], &items[1]) {
+                    (Value::Blob(key), Value::Array(entries)) => Some((key, entries)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .flat_map(|(key, entries)| {
+                entries.iter().filter_map(move |entry| match entry {
+                    Value::Array(entry) => Some((&key[..], parse_id(&entry[0], 0).ok()?)),
+                    _ => None,
+                })
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let mut commands = vec![];
+    for key in args.keys.iter() {
+        let stream = match get_stream(db, key)? {
+            Some(stream) => stream,
+            None => continue,
+        };
+        let group = match stream.group(group_name) {
+            Some(group) => group,
+            None => continue,
+        };
+        commands.push(command(&[
+            b"XGROUP",
+            b"CREATECONSUMER",
+            key,
+            group_name,
+            consumer,
+        ]));
+        for (_, id) in delivered.iter().filter(|(k, _)| *k == &key[..]) {
+            // a deleted entry can't be claimed, claiming it would acknowledge it instead.
+            if let (Some(entry), Some(_)) = (group.pending.get(id), stream.get(*id)) {
+                commands.push(claim_command(key, group_name, *id, entry));
+            }
+        }
+        let entries_read = group
+            .entries_read
+            .map(|n| n as i64)
+            .unwrap_or(-1)
+            .to_string();
+        commands.push(command(&[
+            b"XGROUP",
+            b"SETID",
+            key,
+            group_name,
+            group.last_id.to_string().as_bytes(),
+            b"ENTRIESREAD",
+            entries_read.as_bytes(),
+        ]));
+    }
+    Ok(commands)
+}
+
+/// Parses the ID of XGROUP CREATE and SETID, where `$` means the last ID of the stream.
+fn parse_group_id(arg: &Value, stream: Option<&Stream>) -> Result<StreamId, CommandError> {
+    match arg {
+        Value::Simple(s) | Value::Blob(s) if &s[..] == b"$" => {
+            Ok(stream.map(|s| s.last_id()).unwrap_or_default())
+        }
+        arg => parse_id(arg, 0),
+    }
+}
+
+/// Parses the optional `ENTRIESREAD entries-read` of XGROUP CREATE and SETID.
+fn parse_entries_read(
+    mut args: impl Iterator<Item = Value>,
+    mkstream: Option<&mut bool>,
+) -> Result<Option<u64>, CommandError> {
+    let mut entries_read = None;
+    let mut mkstream = mkstream;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "MKSTREAM" if mkstream.is_some() => **mkstream.as_mut().unwrap() = true,
+            "ENTRIESREAD" => {
+                let n = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                if n < 0 && n != -1 {
+                    return Err("value for ENTRIESREAD must be positive or -1".into());
+                }
+                entries_read = u64::try_from(n).ok();
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+    Ok(entries_read)
+}
+
+/// Implements the XGROUP subcommands: CREATE, SETID, DESTROY, CREATECONSUMER and DELCONSUMER.
+fn handle_xgroup(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let subcommand = arg_option(&args[0]);
+    let arity = match subcommand.as_str() {
+        "CREATE" => (4, 7),
+        "SETID" => (4, 6),
+        "DESTROY" => (3, 3),
+        "CREATECONSUMER" | "DELCONSUMER" => (4, 4),
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(&arg_bytes(args[0].clone())?)
+            )
+            .into())
+        }
+    };
+    if args.len() < arity.0 || args.len() > arity.1 {
+        return Err(format!(
+            "wrong number of arguments for 'xgroup|{}' command",
+            subcommand.to_lowercase()
+        )
+        .into());
+    }
+
+    let mut args = args.into_iter().skip(1);
+    let key = arg_bytes(args.next().unwrap())?;
+    let group = arg_bytes(args.next().unwrap())?;
+
+    let mut db = session.lock_db();
+    if subcommand == "CREATE" {
+        let id = args.next().unwrap();
+        let mut mkstream = false;
+        let entries_read = parse_entries_read(args, Some(&mut mkstream))?;
+        let stream = get_stream(&mut db, &key)?;
+        let id = parse_group_id(&id, stream.as_deref())?;
+        if stream.is_none() && !mkstream {
+            return Err(ERR_XGROUP_NO_KEY.into());
+        }
+        let stream = match db.get_or_insert_with(&key, || Object::Stream(Stream::new())) {
+            Object::Stream(stream) => stream,
+            _ => return Err(CommandError::wrongtype()),
+        };
+        if !stream.create_group(group, id, entries_read) {
+            return Err(CommandError::Code(
+                "BUSYGROUP",
+                "Consumer Group name already exists".to_string(),
+            ));
+        }
+        db.modified(&key, 1);
+        return Ok(Value::Simple("OK".into()));
+    }
+
+    let stream = get_stream(&mut db, &key)?.ok_or(ERR_XGROUP_NO_KEY)?;
+    if subcommand == "DESTROY" {
+        let destroyed = stream.destroy_group(&group);
+        db.modified(&key, destroyed as usize);
+        return Ok(Value::Number(destroyed as i64));
+    }
+    if stream.group(&group).is_none() {
+        return Err(no_group_error(&key, &group));
+    }
+
+    let (reply, changes) = match subcommand.as_str() {
+        "SETID" => {
+            let id = args.next().unwrap();
+            let entries_read = parse_entries_read(args, None)?;
+            let id = parse_group_id(&id, Some(stream))?;
+            let group = stream.group_mut(&group).unwrap();
+            group.last_id = id;
+            group.entries_read = entries_read;
+            (Value::Simple("OK".into()), 1)
+        }
+        "CREATECONSUMER" => {
+            let consumer = arg_bytes(args.next().unwrap())?;
+            let group = stream.group_mut(&group).unwrap();
+            let created = group.create_consumer(&consumer, now_millis());
+            (Value::Number(created as i64), created as usize)
+        }
+        "DELCONSUMER" => {
+            let consumer = arg_bytes(args.next().unwrap())?;
+            let group = stream.group_mut(&group).unwrap();
+            let deleted = group.delete_consumer(&consumer);
+            (
+                Value::Number(deleted.unwrap_or(0) as i64),
+                deleted.is_some() as usize,
+            )
+        }
+        _ => unreachable!(),
+    };
+    db.modified(&key, changes);
+    Ok(reply)
+}
+
+/// Implements `XACK key group id [id ...]`.
+fn handle_xack(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let group = arg_bytes(args.next().unwrap())?;
+    let ids = args
+        .map(|arg| parse_id(&arg, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut db = session.lock_db();
+    let group = match get_stream(&mut db, &key)?.and_then(|s| s.group_mut(&group)) {
+        Some(group) => group,
+        None => return Ok(Value::Number(0)),
+    };
+    let acked = ids.into_iter().filter(|id| group.ack(*id)).count();
+    db.modified(&key, acked);
+    Ok(Value::Number(acked as i64))
+}
+
+/// Implements `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`.
+fn handle_xpending(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter().peekable();
+    let key = arg_bytes(args.next().unwrap())?;
+    let group_name = arg_bytes(args.next().unwrap())?;
+
+    let mut min_idle = None;
+    if args.peek().map(arg_option).as_deref() == Some("IDLE") {
+        args.next();
+        min_idle = Some(arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?);
+    }
+    let rest: Vec<Value> = args.collect();
+    let extended = match rest.len() {
+        0 if min_idle.is_none() => None,
+        3 | 4 => Some((
+            parse_range_start(&rest[0])?,
+            parse_range_end(&rest[1])?,
+            usize::try_from(arg_i64(&rest[2])?).unwrap_or(0),
+            rest.get(3).cloned().map(arg_bytes).transpose()?,
+        )),
+        _ => return Err(ERR_SYNTAX.into()),
+    };
+
+    let mut db = session.lock_db();
+    let stream = get_group(&mut db, &key, &group_name)?;
+    let group = stream.group(&group_name).unwrap();
+    let now = now_millis();
+
+    let (start, end, count, consumer) = match extended {
+        Some(extended) => extended,
+        None => {
+            let (first, last) = match (
+                group.pending.keys().next(),
+                group.pending.keys().next_back(),
+            ) {
+                (Some(first), Some(last)) => (*first, *last),
+                _ => {
+                    return Ok(Value::Array(vec![
+                        Value::Number(0),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                    ]))
+                }
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, c)| !c.pending.is_empty())
+                .map(|(name, c)| {
+                    Value::Array(vec![
+                        Value::Blob(name.clone()),
+                        Value::Blob(Bytes::from(&c.pending.len().to_string())),
+                    ])
+                })
+                .collect();
+            return Ok(Value::Array(vec![
+                Value::Number(group.pending.len() as i64),
+                id_reply(first),
+                id_reply(last),
+                Value::Array(consumers),
+            ]));
+        }
+    };
+
+    if start > end {
+        return Ok(Value::Array(vec![]));
+    }
+    let entries = group
+        .pending
+        .range(start..=end)
+        .filter(|(_, entry)| consumer.as_ref().is_none_or(|c| *c == entry.consumer))
+        .filter(|(_, entry)| {
+            min_idle.is_none_or(|min| now.saturating_sub(entry.delivery_time) as i64 >= min)
+        })
+        .take(count)
+        .map(|(id, entry)| {
+            Value::Array(vec![
+                id_reply(*id),
+                Value::Blob(entry.consumer.clone()),
+                Value::Number(now.saturating_sub(entry.delivery_time) as i64),
+                Value::Number(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    Ok(Value::Array(entries))
+}
+
+/// Parses the min-idle-time argument of XCLAIM and XAUTOCLAIM.
+fn parse_min_idle_time(arg: &Value, name: &str) -> Result<u64, CommandError> {
+    let min_idle =
+        arg_i64(arg).map_err(|_| format!("Invalid min-idle-time argument for {}", name))?;
+    Ok(min_idle.max(0) as u64)
+}
+
+/// Implements `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME ms]
+/// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID id]`.
+fn handle_xclaim(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter().peekable();
+    let key = arg_bytes(args.next().unwrap())?;
+    let group_name = arg_bytes(args.next().unwrap())?;
+    let consumer = arg_bytes(args.next().unwrap())?;
+    let mut options = ClaimOptions {
+        min_idle_time: parse_min_idle_time(&args.next().unwrap(), "XCLAIM")?,
+        ..Default::default()
+    };
+
+    let mut ids = vec![];
+    while let Some(id) = args.peek().and_then(|arg| parse_id(arg, 0).ok()) {
+        ids.push(id);
+        args.next();
+    }
+
+    let now = now_millis();
+    let mut last_id = None;
+    while let Some(arg) = args.next() {
+        let option = arg_option(&arg);
+        match option.as_str() {
+            "FORCE" => options.force = true,
+            "JUSTID" => options.justid = true,
+            "IDLE" => {
+                let idle = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                options.delivery_time = Some(now.saturating_sub(idle.max(0) as u64));
+            }
+            "TIME" => {
+                let time = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                options.delivery_time = Some((time.max(0) as u64).min(now));
+            }
+            "RETRYCOUNT" => {
+                let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                options.retry_count = Some(count.max(0) as u64);
+            }
+            "LASTID" => last_id = Some(parse_id(&args.next().ok_or(ERR_SYNTAX)?, 0)?),
+            _ => return Err(format!("Unrecognized XCLAIM option '{}'", option).into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let stream = get_group(&mut db, &key, &group_name)?;
+    if let Some(last_id) = last_id {
+        let group = stream.group_mut(&group_name).unwrap();
+        group.last_id = group.last_id.max(last_id);
+    }
+
+    let mut reply = vec![];
+    let (mut claimed, mut deleted) = (vec![], vec![]);
+    for id in ids {
+        match stream.claim(&group_name, &consumer, id, &options, now) {
+            ClaimResult::Claimed(fields) => {
+                reply.push(if options.justid {
+                    id_reply(id)
+                } else {
+                    entry_reply(id, &fields)
+                });
+                claimed.push(id);
+            }
+            ClaimResult::Deleted => deleted.push(id),
+            ClaimResult::Skipped => (),
+        }
+    }
+
+    let mut commands = claim_commands(stream, &key, &group_name, &claimed, &deleted);
+    let changes = claimed.len() + deleted.len() + last_id.is_some() as usize;
+    if last_id.is_some() {
+        let group = stream.group(&group_name).unwrap();
+        let entries_read = group.entries_read.map(|n| n as i64).unwrap_or(-1);
+        commands.push(command(&[
+            b"XGROUP",
+            b"SETID",
+            &key,
+            &group_name,
+            group.last_id.to_string().as_bytes(),
+            b"ENTRIESREAD",
+            entries_read.to_string().as_bytes(),
+        ]));
+    }
+    db.modified(&key, changes);
+    drop(db);
+    session.propagate(commands);
+    Ok(Value::Array(reply))
+}
+
+/// Builds the commands logged in place of XCLAIM and XAUTOCLAIM, whose outcome depends on how
+/// long the entries have been idle: the claimed entries are claimed again with their delivery
+/// time and count, and the deleted ones are acknowledged.
+fn claim_commands(
+    stream: &Stream,
+    key: &[u8],
+    group_name: &[u8],
+    claimed: &[StreamId],
+    deleted: &[StreamId],
+) -> Vec<Vec<Value>> {
+    let group = stream.group(group_name).unwrap();
+    let mut commands: Vec<Vec<Value>> = claimed
+        .iter()
+        .map(|id| claim_command(key, group_name, *id, &group.pending[id]))
+        .collect();
+    if !deleted.is_empty() {
+        let ids: Vec<String> = deleted.iter().map(|id| id.to_string()).collect();
+        let mut args: Vec<&[u8]> = vec![b"XACK", key, group_name];
+        args.extend(ids.iter().map(|id| id.as_bytes()));
+        commands.push(command(&args));
+    }
+    commands
+}
+
+/// Implements `XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]`.
+fn handle_xautoclaim(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let group_name = arg_bytes(args.next().unwrap())?;
+    let consumer = arg_bytes(args.next().unwrap())?;
+    let mut options = ClaimOptions {
+        min_idle_time: parse_min_idle_time(&args.next().unwrap(), "XAUTOCLAIM")?,
+        ..Default::default()
+    };
+    let start = parse_range_start(&args.next().unwrap())?;
+
+    let mut count = 100;
+    while let Some(arg) = args.next() {
+        match arg_option(&arg).as_str() {
+            "COUNT" => {
+                let n = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                count = usize::try_from(n)
+                    .ok()
+                    .filter(|n| (1..=i64::MAX as usize / 10).contains(n))
+                    .ok_or("COUNT must be > 0")?;
+            }
+            "JUSTID" => options.justid = true,
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let stream = get_group(&mut db, &key, &group_name)?;
+    let now = now_millis();
+
+    // like redis, scan up to 10 times the number of requested entries to bound the work done
+    // when most pending entries are not idle enough.
+    let attempts = count * 10;
+    let ids: Vec<StreamId> = stream
+        .group(&group_name)
+        .unwrap()
+        .pending
+        .range(start..)
+        .map(|(id, _)| *id)
+        .take(attempts + 1)
+        .collect();
+
+    let (mut claimed, mut deleted) = (vec![], vec![]);
+    let (mut claimed_ids, mut deleted_ids) = (vec![], vec![]);
+    let mut scanned = 0;
+    for id in ids.iter().take(attempts) {
+        if claimed.len() >= count {
+            break;
+        }
+        scanned += 1;
+        match stream.claim(&group_name, &consumer, *id, &options, now) {
+            ClaimResult::Claimed(fields) => {
+                claimed.push(if options.justid {
+                    id_reply(*id)
+                } else {
+                    entry_reply(*id, &fields)
+                });
+                claimed_ids.push(*id);
+            }
+            ClaimResult::Deleted => {
+                deleted.push(id_reply(*id));
+                deleted_ids.push(*id);
+            }
+            ClaimResult::Skipped => (),
+        }
+    }
+
+    let commands = claim_commands(stream, &key, &group_name, &claimed_ids, &deleted_ids);
+    db.modified(&key, claimed_ids.len() + deleted_ids.len());
+    drop(db);
+    session.propagate(commands);
+
+    let cursor = ids.get(scanned).copied().unwrap_or_default();
+    Ok(Value::Array(vec![
+        id_reply(cursor),
+        Value::Array(claimed),
+        Value::Array(deleted),
+    ]))
+}
+
+fn optional_number(value: Option<u64>) -> Value {
+    value
+        .map(|n| Value::Number(n as i64))
+        .unwrap_or(Value::Null)
+}
+
+fn group_info(stream: &Stream, name: &Bytes, group: &ConsumerGroup) -> Value {
+    map_reply(vec![
+        ("name", Value::Blob(name.clone())),
+        ("consumers", Value::Number(group.consumers.len() as i64)),
+        ("pending", Value::Number(group.pending.len() as i64)),
+        ("last-delivered-id", id_reply(group.last_id)),
+        ("entries-read", optional_number(group.entries_read)),
+        ("lag", optional_number(stream.lag(group))),
+    ])
+}
+
+fn group_info_full(stream: &Stream, name: &Bytes, group: &ConsumerGroup, count: usize) -> Value {
+    let pending = group
+        .pending
+        .iter()
+        .take(count)
+        .map(|(id, entry)| {
+            Value::Array(vec![
+                id_reply(*id),
+                Value::Blob(entry.consumer.clone()),
+                Value::Number(entry.delivery_time as i64),
+                Value::Number(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    let consumers = group
+        .consumers
+        .iter()
+        .map(|(name, consumer)| {
+            let pending = consumer
+                .pending
+                .iter()
+                .take(count)
+                .map(|id| {
+                    let entry = &group.pending[id];
+                    Value::Array(vec![
+                        id_reply(*id),
+                        Value::Number(entry.delivery_time as i64),
+                        Value::Number(entry.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            map_reply(vec![
+                ("name", Value::Blob(name.clone())),
+                ("seen-time", Value::Number(consumer.seen_time as i64)),
+                (
+                    "active-time",
+                    consumer
+                        .active_time
+                        .map(|t| Value::Number(t as i64))
+                        .unwrap_or(Value::Number(-1)),
+                ),
+                ("pel-count", Value::Number(consumer.pending.len() as i64)),
+                ("pending", Value::Array(pending)),
+            ])
+        })
+        .collect();
+
+    map_reply(vec![
+        ("name", Value::Blob(name.clone())),
+        ("last-delivered-id", id_reply(group.last_id)),
+        ("entries-read", optional_number(group.entries_read)),
+        ("lag", optional_number(stream.lag(group))),
+        ("pel-count", Value::Number(group.pending.len() as i64)),
+        ("pending", Value::Array(pending)),
+        ("consumers", Value::Array(consumers)),
+    ])
+}
+
+/// Implements `XINFO STREAM key [FULL [COUNT count]]`, `XINFO GROUPS key` and `XINFO CONSUMERS
+/// key group`.
+fn handle_xinfo(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let subcommand = arg_option(&args[0]);
+    let arity = match subcommand.as_str() {
+        "STREAM" => (2, 5),
+        "GROUPS" => (2, 2),
+        "CONSUMERS" => (3, 3),
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(&arg_bytes(args[0].clone())?)
+            )
+            .into())
+        }
+    };
+    if args.len() < arity.0 || args.len() > arity.1 {
+        return Err(format!(
+            "wrong number of arguments for 'xinfo|{}' command",
+            subcommand.to_lowercase()
+        )
+        .into());
+    }
+
+    let mut args = args.into_iter().skip(1);
+    let key = arg_bytes(args.next().unwrap())?;
+    let mut db = session.lock_db();
+    let stream = get_stream(&mut db, &key)?.ok_or(ERR_NO_SUCH_KEY)?;
+    let now = now_millis();
+
+    match subcommand.as_str() {
+        "STREAM" => {
+            let mut full = None;
+            while let Some(arg) = args.next() {
+                match arg_option(&arg).as_str() {
+                    "FULL" => full = Some(10),
+                    "COUNT" if full.is_some() => {
+                        let count = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                        full = Some(if count <= 0 {
+                            usize::MAX
+                        } else {
+                            count as usize
+                        });
+                    }
+                    _ => return Err(ERR_SYNTAX.into()),
+                }
+            }
+
+            let mut info = vec![
+                ("length", Value::Number(stream.len() as i64)),
+                ("last-generated-id", id_reply(stream.last_id())),
+                ("max-deleted-entry-id", id_reply(stream.max_deleted_id())),
+                (
+                    "entries-added",
+                    Value::Number(stream.entries_added() as i64),
+                ),
+                ("recorded-first-entry-id", id_reply(stream.first_id())),
+            ];
+            match full {
+                Some(count) => {
+                    let entries = stream
+                        .range(StreamId::MIN, StreamId::MAX)
+                        .take(count)
+                        .map(|(id, f)| entry_reply(id, f))
+                        .collect();
+                    let groups = stream
+                        .groups()
+                        .map(|(name, group)| group_info_full(stream, name, group, count))
+                        .collect();
+                    info.push(("entries", Value::Array(entries)));
+                    info.push(("groups", Value::Array(groups)));
+                }
+                None => {
+                    let entry = |entry: Option<(StreamId, &StreamFields)>| {
+                        entry
+                            .map(|(id, f)| entry_reply(id, f))
+                            .unwrap_or(Value::Null)
+                    };
+                    info.push(("groups", Value::Number(stream.groups().count() as i64)));
+                    info.push(("first-entry", entry(stream.first_entry())));
+                    info.push(("last-entry", entry(stream.last_entry())));
+                }
+            }
+            Ok(map_reply(info))
+        }
+        "GROUPS" => Ok(Value::Array(
+            stream
+                .groups()
+                .map(|(name, group)| group_info(stream, name, group))
+                .collect(),
+        )),
+        "CONSUMERS" => {
+            let group_name = arg_bytes(args.next().unwrap())?;
+            let group = stream
+                .group(&group_name)
+                .ok_or_else(|| no_group_error(&key, &group_name))?;
+            Ok(Value::Array(
+                group
+                    .consumers
+                    .iter()
+                    .map(|(name, consumer)| {
+                        let inactive = consumer
+                            .active_time
+                            .map(|t| now.saturating_sub(t) as i64)
+                            .unwrap_or(-1);
+                        map_reply(vec![
+                            ("name", Value::Blob(name.clone())),
+                            ("pending", Value::Number(consumer.pending.len() as i64)),
+                            (
+                                "idle",
+                                Value::Number(now.saturating_sub(consumer.seen_time) as i64),
+                            ),
+                            ("inactive", Value::Number(inactive)),
+                        ])
+                    })
+                    .collect(),
+            ))
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    fn blob(value: &str) -> Value {
+        Value::Blob(value.into())
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> Value {
+        Value::Array(vec![
+            blob(id),
+            Value::Array(fields.iter().map(|f| blob(f)).collect()),
+        ])
+    }
+
+    #[test]
+    fn test_xadd_and_xrange() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(blob("1-1"), run(&["XADD", "s", "1-1", "a", "1"]));
+        assert_eq!(blob("1-2"), run(&["XADD", "s", "1-*", "b", "2"]));
+        assert_eq!(blob("2-0"), run(&["XADD", "s", "2", "c", "3"]));
+        assert_eq!(
+            Value::err(
+                "The ID specified in XADD is equal or smaller than the target stream top item"
+            ),
+            run(&["XADD", "s", "1-5", "d", "4"])
+        );
+        assert_eq!(
+            Value::err("The ID specified in XADD must be greater than 0-0"),
+            run(&["XADD", "s", "0-0", "d", "4"])
+        );
+        assert_eq!(
+            Value::err("wrong number of arguments for 'xadd' command"),
+            run(&["XADD", "s", "*", "d"])
+        );
+        assert_eq!(
+            Value::Null,
+            run(&["XADD", "none", "NOMKSTREAM", "*", "a", "1"])
+        );
+        assert_eq!(Value::Number(0), run(&["EXISTS", "none"]));
+        assert_eq!(Value::Number(3), run(&["XLEN", "s"]));
+
+        assert_eq!(
+            Value::Array(vec![entry("1-1", &["a", "1"]), entry("1-2", &["b", "2"])]),
+            run(&["XRANGE", "s", "-", "+", "COUNT", "2"])
+        );
+        assert_eq!(
+            Value::Array(vec![entry("2-0", &["c", "3"]), entry("1-2", &["b", "2"])]),
+            run(&["XREVRANGE", "s", "+", "(1-1"])
+        );
+        assert_eq!(
+            Value::Array(vec![entry("1-1", &["a", "1"]), entry("1-2", &["b", "2"])]),
+            run(&["XRANGE", "s", "1", "1"])
+        );
+        assert_eq!(
+            Value::err("Invalid stream ID specified as stream command argument"),
+            run(&["XRANGE", "s", "x", "+"])
+        );
+
+        assert_eq!(Value::Number(1), run(&["XDEL", "s", "1-2", "5-0"]));
+        assert_eq!(
+            blob("3-0"),
+            run(&["XADD", "s", "MAXLEN", "2", "3-0", "d", "4"])
+        );
+        assert_eq!(
+            Value::Array(vec![entry("2-0", &["c", "3"]), entry("3-0", &["d", "4"])]),
+            run(&["XRANGE", "s", "-", "+"])
+        );
+        assert_eq!(Value::Number(1), run(&["XTRIM", "s", "MINID", "=", "3"]));
+        assert_eq!(
+            Value::err("syntax error, LIMIT cannot be used without the special ~ option"),
+            run(&["XTRIM", "s", "MAXLEN", "0", "LIMIT", "1"])
+        );
+        assert_eq!(
+            Value::Number(1),
+            run(&["XTRIM", "s", "MAXLEN", "~", "0", "LIMIT", "5"])
+        );
+        assert_eq!(Value::Number(0), run(&["XLEN", "s"]));
+
+        match run(&["XADD", "s", "*", "e", "5"]) {
+            Value::Blob(id) => assert!(id.into_string().unwrap().ends_with("-0")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xread() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["XADD", "a", "1-0", "f", "1"]);
+        run(&["XADD", "a", "2-0", "f", "2"]);
+        run(&["XADD", "b", "1-0", "g", "1"]);
+
+        assert_eq!(
+            Value::Array(vec![
+                Value::Array(vec![
+                    blob("a"),
+                    Value::Array(vec![entry("2-0", &["f", "2"])])
+                ]),
+                Value::Array(vec![
+                    blob("b"),
+                    Value::Array(vec![entry("1-0", &["g", "1"])])
+                ]),
+            ]),
+            run(&["XREAD", "STREAMS", "a", "b", "1-0", "0"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                blob("a"),
+                Value::Array(vec![entry("1-0", &["f", "1"])])
+            ])]),
+            run(&["XREAD", "COUNT", "1", "STREAMS", "a", "none", "0", "0"])
+        );
+        assert_eq!(Value::Null, run(&["XREAD", "STREAMS", "a", "$"]));
+        assert_eq!(
+            Value::err(
+                "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be \
+                 specified."
+            ),
+            run(&["XREAD", "STREAMS", "a", "b", "0"])
+        );
+    }
+
+    #[test]
+    fn test_xsetid() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let ok = Value::Simple("OK".into());
+
+        assert_eq!(Value::err("no such key"), run(&["XSETID", "s", "1-0"]));
+        run(&["XADD", "s", "1-0", "f", "1"]);
+        run(&["XADD", "s", "2-0", "f", "2"]);
+        assert_eq!(
+            Value::err("The ID specified in XSETID is smaller than the target stream top item"),
+            run(&["XSETID", "s", "1-5"])
+        );
+        assert_eq!(
+            Value::err(
+                "The entries_added specified in XSETID is smaller than the target stream length"
+            ),
+            run(&["XSETID", "s", "5-0", "ENTRIESADDED", "1"])
+        );
+        assert_eq!(
+            Value::err(
+                "The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+            ),
+            run(&["XSETID", "s", "5-0", "MAXDELETEDID", "6-0"])
+        );
+        assert_eq!(
+            Value::err("syntax error"),
+            run(&["XSETID", "s", "5-0", "FOO"])
+        );
+
+        assert_eq!(
+            ok,
+            run(&[
+                "XSETID",
+                "s",
+                "5-0",
+                "ENTRIESADDED",
+                "10",
+                "MAXDELETEDID",
+                "3-0"
+            ])
+        );
+        assert_eq!(
+            Value::err(
+                "The ID specified in XADD is equal or smaller than the target stream top item"
+            ),
+            run(&["XADD", "s", "4-0", "f", "3"])
+        );
+        assert_eq!(blob("5-1"), run(&["XADD", "s", "5-*", "f", "3"]));
+
+        // an emptied stream accepts any ID.
+        run(&["XTRIM", "s", "MAXLEN", "0"]);
+        assert_eq!(ok, run(&["XSETID", "s", "0-1"]));
+        assert_eq!(blob("0-2"), run(&["XADD", "s", "0-*", "f", "4"]));
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        assert_eq!(
+            Value::Simple("OK".into()),
+            run(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"])
+        );
+        assert_eq!(
+            Value::Err(
+                "BUSYGROUP".to_string(),
+                "Consumer Group name already exists".to_string()
+            ),
+            run(&["XGROUP", "CREATE", "s", "g", "$"])
+        );
+        run(&["XADD", "s", "1-0", "f", "1"]);
+        run(&["XADD", "s", "2-0", "f", "2"]);
+        run(&["XADD", "s", "3-0", "f", "3"]);
+
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                blob("s"),
+                Value::Array(vec![entry("1-0", &["f", "1"]), entry("2-0", &["f", "2"])])
+            ])]),
+            run(&[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "alice",
+                "COUNT",
+                "2",
+                "STREAMS",
+                "s",
+                ">"
+            ])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                blob("s"),
+                Value::Array(vec![entry("3-0", &["f", "3"])])
+            ])]),
+            run(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"])
+        );
+        assert_eq!(
+            Value::Null,
+            run(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                blob("s"),
+                Value::Array(vec![entry("2-0", &["f", "2"])])
+            ])]),
+            run(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "1-0"])
+        );
+
+        assert_eq!(
+            Value::Array(vec![
+                Value::Number(3),
+                blob("1-0"),
+                blob("3-0"),
+                Value::Array(vec![
+                    Value::Array(vec![blob("alice"), blob("2")]),
+                    Value::Array(vec![blob("bob"), blob("1")]),
+                ]),
+            ]),
+            run(&["XPENDING", "s", "g"])
+        );
+        match run(&["XPENDING", "s", "g", "-", "+", "10", "alice"]) {
+            Value::Array(entries) => {
+                assert_eq!(2, entries.len());
+                match &entries[1] {
+                    Value::Array(entry) => assert_eq!(Value::Number(2), entry[3]),
+                    other => panic!("unexpected entry {:?}", other),
+                }
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        assert_eq!(
+            Value::Array(vec![blob("1-0")]),
+            run(&["XCLAIM", "s", "g", "bob", "0", "1-0", "JUSTID"])
+        );
+        assert_eq!(
+            Value::Array(vec![]),
+            run(&["XCLAIM", "s", "g", "bob", "3600000", "2-0"])
+        );
+        assert_eq!(
+            Value::Number(2),
+            run(&["XACK", "s", "g", "1-0", "3-0", "9-0"])
+        );
+
+        run(&["XDEL", "s", "2-0"]);
+        assert_eq!(
+            Value::Array(vec![
+                blob("0-0"),
+                Value::Array(vec![]),
+                Value::Array(vec![blob("2-0")]),
+            ]),
+            run(&["XAUTOCLAIM", "s", "g", "bob", "0", "-"])
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::Number(0),
+                Value::Null,
+                Value::Null,
+                Value::Null
+            ]),
+            run(&["XPENDING", "s", "g"])
+        );
+
+        assert_eq!(
+            Value::Err(
+                "NOGROUP".to_string(),
+                "No such key 's' or consumer group 'x' in XREADGROUP with GROUP option".to_string()
+            ),
+            run(&["XREADGROUP", "GROUP", "x", "alice", "STREAMS", "s", ">"])
+        );
+        assert_eq!(
+            Value::Number(1),
+            run(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"])
+        );
+        assert_eq!(
+            Value::Number(0),
+            run(&["XGROUP", "DELCONSUMER", "s", "g", "carol"])
+        );
+        assert_eq!(
+            Value::Simple("OK".into()),
+            run(&["XGROUP", "SETID", "s", "g", "0"])
+        );
+        assert_eq!(Value::Number(1), run(&["XGROUP", "DESTROY", "s", "g"]));
+        assert_eq!(
+            Value::err(
+                "The XGROUP subcommand requires the key to exist. Note that for CREATE you may \
+                 want to use the MKSTREAM option to create an empty stream automatically."
+            ),
+            run(&["XGROUP", "CREATE", "none", "g", "$"])
+        );
+    }
+
+    #[test]
+    fn test_xinfo() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["XADD", "s", "1-0", "f", "1"]);
+        run(&["XADD", "s", "2-0", "f", "2"]);
+        run(&["XGROUP", "CREATE", "s", "g", "0"]);
+        run(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "alice",
+            "COUNT",
+            "1",
+            "STREAMS",
+            "s",
+            ">",
+        ]);
+
+        assert_eq!(
+            Value::Array(vec![
+                blob("length"),
+                Value::Number(2),
+                blob("last-generated-id"),
+                blob("2-0"),
+                blob("max-deleted-entry-id"),
+                blob("0-0"),
+                blob("entries-added"),
+                Value::Number(2),
+                blob("recorded-first-entry-id"),
+                blob("1-0"),
+                blob("groups"),
+                Value::Number(1),
+                blob("first-entry"),
+                entry("1-0", &["f", "1"]),
+                blob("last-entry"),
+                entry("2-0", &["f", "2"]),
+            ]),
+            run(&["XINFO", "STREAM", "s"])
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                blob("name"),
+                blob("g"),
+                blob("consumers"),
+                Value::Number(1),
+                blob("pending"),
+                Value::Number(1),
+                blob("last-delivered-id"),
+                blob("1-0"),
+                blob("entries-read"),
+                Value::Number(1),
+                blob("lag"),
+                Value::Number(1),
+            ])]),
+            run(&["XINFO", "GROUPS", "s"])
+        );
+        match run(&["XINFO", "CONSUMERS", "s", "g"]) {
+            Value::Array(consumers) => assert_eq!(1, consumers.len()),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        match run(&["XINFO", "STREAM", "s", "FULL"]) {
+            Value::Array(info) => assert_eq!(14, info.len()),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(Value::err("no such key"), run(&["XINFO", "STREAM", "none"]));
+    }
+
+    #[test]
+    fn test_xread_block() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+
+        run(&["XADD", "s", "1-1", "a", "1"]);
+        assert_eq!(
+            Value::Null,
+            run(&["XREAD", "BLOCK", "10", "STREAMS", "s", "$"])
+        );
+        assert_eq!(
+            Value::err("timeout is negative"),
+            run(&["XREAD", "BLOCK", "-1", "STREAMS", "s", "$"])
+        );
+        run(&["XGROUP", "CREATE", "s", "g", "$"]);
+
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let mut session = factory.create_session();
+                session.handle_request(request(&["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]))
+            });
+            let group_reader = s.spawn(|| {
+                let mut session = factory.create_session();
+                session.handle_request(request(&[
+                    "XREADGROUP",
+                    "GROUP",
+                    "g",
+                    "c",
+                    "BLOCK",
+                    "5000",
+                    "STREAMS",
+                    "s",
+                    ">",
+                ]))
+            });
+            std::thread::sleep(Duration::from_millis(50));
+
+            let mut session = factory.create_session();
+            session.handle_request(request(&["XADD", "s", "2-1", "b", "2"]));
+            let expected = Value::Array(vec![Value::Array(vec![
+                blob("s"),
+                Value::Array(vec![entry("2-1", &["b", "2"])]),
+            ])]);
+            assert_eq!(expected, reader.join().unwrap());
+            assert_eq!(expected, group_reader.join().unwrap());
+        });
+    }
+}
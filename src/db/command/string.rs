@@ -0,0 +1,235 @@
+use crate::db::aof::command;
+use crate::db::{now_millis, InternalDb, Object, Session};
+use crate::value::{Bytes, Value};
+
+use super::{
+    arg_bytes, arg_i64, arg_option, CommandError, CommandResult, CommandSpec, COMMAND_FLAG_DENYOOM,
+    COMMAND_FLAG_FAST, COMMAND_FLAG_RANDOM, COMMAND_FLAG_READONLY, COMMAND_FLAG_SLOW,
+    COMMAND_FLAG_STRING, COMMAND_FLAG_WRITE, ERR_SYNTAX,
+};
+
+pub fn get_commands() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec {
+            name: "GET".to_string(),
+            args_len: 2,
+            flags: vec![
+                COMMAND_FLAG_READONLY,
+                COMMAND_FLAG_STRING,
+                COMMAND_FLAG_RANDOM,
+                COMMAND_FLAG_FAST,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_get,
+        },
+        CommandSpec {
+            name: "SET".to_string(),
+            args_len: -3,
+            flags: vec![
+                COMMAND_FLAG_WRITE,
+                COMMAND_FLAG_DENYOOM,
+                COMMAND_FLAG_STRING,
+                COMMAND_FLAG_SLOW,
+            ],
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            get_keys: None,
+            handler: handle_set,
+        },
+    ]
+}
+
+/// Returns the string stored at the key, failing if the key holds another type.
+fn get_string<'a>(db: &'a mut InternalDb, key: &Bytes) -> Result<Option<&'a Bytes>, CommandError> {
+    match db.get(key) {
+        None => Ok(None),
+        Some(Object::String(s)) => Ok(Some(s)),
+        Some(_) => Err(CommandError::wrongtype()),
+    }
+}
+
+fn handle_get(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let key = arg_bytes(args.into_iter().next().unwrap())?;
+    let mut db = session.lock_db();
+
+    Ok(get_string(&mut db, &key)?
+        .map(|v| Value::Blob(v.clone()))
+        .unwrap_or(Value::Null))
+}
+
+#[derive(PartialEq, Eq)]
+enum SetCondition {
+    Always,
+    IfNotExists,
+    IfExists,
+}
+
+#[derive(PartialEq, Eq)]
+enum SetExpire {
+    /// Drop the ttl of the key, the default behaviour of SET.
+    Clear,
+    Keep,
+    /// Expire at the given unix time in milliseconds.
+    At(i64),
+}
+
+/// Implements `SET key value [NX | XX] [GET] [EX s | PX ms | EXAT ts | PXAT ts | KEEPTTL]`.
+fn handle_set(session: &mut Session, args: Vec<Value>) -> CommandResult {
+    let mut args = args.into_iter();
+    let key = arg_bytes(args.next().unwrap())?;
+    let value = arg_bytes(args.next().unwrap())?;
+
+    let mut condition = SetCondition::Always;
+    let mut expire = SetExpire::Clear;
+    let mut get = false;
+    while let Some(arg) = args.next() {
+        let option = arg_option(&arg);
+        match option.as_str() {
+            "NX" if condition == SetCondition::Always => condition = SetCondition::IfNotExists,
+            "XX" if condition == SetCondition::Always => condition = SetCondition::IfExists,
+            "GET" if !get => get = true,
+            "KEEPTTL" if expire == SetExpire::Clear => expire = SetExpire::Keep,
+            "EX" | "PX" | "EXAT" | "PXAT" if expire == SetExpire::Clear => {
+                let when = arg_i64(&args.next().ok_or(ERR_SYNTAX)?)?;
+                let (unit, basetime) = match option.as_str() {
+                    "EX" => (1000, now_millis() as i64),
+                    "PX" => (1, now_millis() as i64),
+                    "EXAT" => (1000, 0),
+                    _ => (1, 0),
+                };
+                let when = Some(when)
+                    .filter(|when| *when > 0)
+                    .and_then(|when| when.checked_mul(unit))
+                    .and_then(|when| when.checked_add(basetime))
+                    .ok_or("invalid expire time in 'set' command")?;
+                expire = SetExpire::At(when);
+            }
+            _ => return Err(ERR_SYNTAX.into()),
+        }
+    }
+
+    let mut db = session.lock_db();
+    let reply = if get {
+        get_string(&mut db, &key)?
+            .map(|v| Value::Blob(v.clone()))
+            .unwrap_or(Value::Null)
+    } else {
+        Value::Simple("OK".into())
+    };
+
+    let exists = db.contains_key(&key);
+    let skipped = match condition {
+        SetCondition::Always => false,
+        SetCondition::IfNotExists => exists,
+        SetCondition::IfExists => !exists,
+    };
+    if skipped {
+        return Ok(if get { reply } else { Value::Null });
+    }
+
+    match expire {
+        SetExpire::Clear => {
+            db.insert(key, value.into());
+        }
+        SetExpire::Keep => {
+            db.insert_keepttl(key, value.into());
+        }
+        SetExpire::At(when) if when <= now_millis() as i64 => {
+            db.remove(&key);
+            drop(db);
+            session.propagate(vec![command(&[b"DEL", &key])]);
+        }
+        SetExpire::At(when) => {
+            db.insert(key.clone(), value.clone().into());
+            db.set_expire(&key, when as u64);
+            drop(db);
+            // the expiry time is logged as an absolute time, whatever option was used.
+            let when = when.to_string();
+            session.propagate(vec![command(&[
+                b"SET",
+                &key,
+                &value,
+                b"PXAT",
+                when.as_bytes(),
+            ])]);
+        }
+    }
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::command::tests::{request, session_factory};
+    use crate::value::Value;
+
+    #[test]
+    fn test_set_options() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let ok = Value::Simple("OK".into());
+
+        assert_eq!(Value::Null, run(&["SET", "k", "v1", "XX"]));
+        assert_eq!(ok, run(&["SET", "k", "v1", "NX", "EX", "30"]));
+        assert_eq!(Value::Number(30), run(&["TTL", "k"]));
+        assert_eq!(Value::Null, run(&["SET", "k", "v2", "NX"]));
+        assert_eq!(Value::Blob("v1".into()), run(&["GET", "k"]));
+
+        assert_eq!(ok, run(&["SET", "k", "v2", "xx", "keepttl"]));
+        assert_eq!(Value::Number(30), run(&["TTL", "k"]));
+        assert_eq!(
+            Value::Blob("v2".into()),
+            run(&["SET", "k", "v3", "GET", "PX", "5000"])
+        );
+        assert_eq!(Value::Number(5), run(&["TTL", "k"]));
+        assert_eq!(ok, run(&["SET", "k", "v4"]));
+        assert_eq!(Value::Number(-1), run(&["TTL", "k"]));
+
+        assert_eq!(
+            Value::Blob("v4".into()),
+            run(&["SET", "k", "v5", "NX", "GET"])
+        );
+        assert_eq!(Value::Null, run(&["SET", "other", "v", "XX", "GET"]));
+        assert_eq!(Value::Null, run(&["SET", "other", "v", "GET"]));
+        assert_eq!(Value::Blob("v".into()), run(&["GET", "other"]));
+
+        assert_eq!(ok, run(&["SET", "k", "v", "PXAT", "33177117420000"]));
+        assert_eq!(Value::Number(33177117420000), run(&["PEXPIRETIME", "k"]));
+        assert_eq!(ok, run(&["SET", "k", "v", "EXAT", "1"]));
+        assert_eq!(Value::Null, run(&["GET", "k"]));
+    }
+
+    #[test]
+    fn test_set_invalid_options() {
+        let factory = session_factory();
+        let mut session = factory.create_session();
+        let mut run = |args: &[&str]| session.handle_request(request(args));
+        let syntax_error = Value::err("syntax error");
+
+        assert_eq!(syntax_error, run(&["SET", "k", "v", "NX", "XX"]));
+        assert_eq!(
+            syntax_error,
+            run(&["SET", "k", "v", "EX", "10", "PX", "10"])
+        );
+        assert_eq!(syntax_error, run(&["SET", "k", "v", "KEEPTTL", "EX", "10"]));
+        assert_eq!(syntax_error, run(&["SET", "k", "v", "EX"]));
+        assert_eq!(syntax_error, run(&["SET", "k", "v", "FOO"]));
+        assert_eq!(
+            Value::err("invalid expire time in 'set' command"),
+            run(&["SET", "k", "v", "EX", "0"])
+        );
+        assert_eq!(
+            Value::err("invalid expire time in 'set' command"),
+            run(&["SET", "k", "v", "EX", "9223372036854775807"])
+        );
+        assert_eq!(
+            Value::err("value is not an integer or out of range"),
+            run(&["SET", "k", "v", "PX", "ten"])
+        );
+    }
+}
@@ -1,80 +1,1510 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read},
+    net::{SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering, mpsc::Receiver, Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard,
+        RwLockWriteGuard,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{config::Config, value::Bytes, value::Value};
+use rand::Rng;
 
-use super::command::{get_commands, CommandSpec};
+use crate::config::{Config, MaxmemoryPolicy};
+use crate::{
+    error::Error,
+    value::{Bytes, Protocol, Value},
+};
+
+use super::acl::{Acl, DEFAULT_USER};
+use super::aof::{self, Aof};
+use super::blocking::{AbortHandle, Blocked, BlockingRegistry, OnReady, Waiter};
+use super::cluster::{key_hash_slot, Cluster, ClusterNode, Route};
+use super::command::{
+    get_commands, CommandSpec, COMMAND_FLAG_BLOCKING, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_WRITE,
+};
+use super::dict::Dict;
+use super::evict::{self, EvictionPool, LFU_INIT_VAL};
+use super::multi::{ExecLock, QueuedCommand, Transaction, WatchedKey};
+use super::object::Object;
+use super::pubsub::{ChannelKind, PubSub, Subscriber, Subscriptions};
+use super::rdb::{self, Entry};
+use super::replication::{new_replid, LinkState, ReplicaSync, Replication};
+use super::script::Scripting;
+use super::slowlog::SlowLog;
+use super::stats::Stats;
+
+// Parameters of the active expire cycle. They are the same as the ones used by redis: every
+// cycle samples a few keys with an expiry, and keep going as long as more than a quarter of the
+// sampled keys turned out to be expired and the time budget is not exhausted.
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+const ACTIVE_EXPIRE_CYCLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+// How long to wait before retrying a background save that failed, in seconds.
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+// The estimated memory used by a key besides its name and its value, in bytes.
+const KEY_OVERHEAD: usize = 48;
+
+/// Returns the current unix time in milliseconds.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
 
 pub struct Database {
     dbs: Vec<Arc<RwLock<InternalDb>>>,
+    blocking: Arc<BlockingRegistry>,
+    pubsub: PubSub,
+    exec_lock: ExecLock,
+    /// Shared with the thread writing the background snapshot.
+    saving: Arc<Mutex<SaveState>>,
+    /// Shared with the thread rewriting the AOF in the background.
+    aof: Arc<Mutex<Aof>>,
+    /// Held exclusively by the write commands while they are propagated to the AOF or to the
+    /// replicas, so that the commands are propagated in the same order as they ran.
+    write_lock: ExecLock,
+    replication: Mutex<Replication>,
+    /// Notified when a replica acknowledges the stream, and when the master changes.
+    replication_changed: Condvar,
+    /// The state of the cluster, `None` when the cluster mode is off.
+    cluster: Option<Mutex<Cluster>>,
+    scripting: Scripting,
+    acl: RwLock<Acl>,
+    /// The configuration, as changed by CONFIG SET.
+    config: RwLock<Config>,
+    slowlog: Mutex<SlowLog>,
+    /// Shared with the databases, which count the hits and the expired keys.
+    stats: Arc<Stats>,
+    /// When the server started.
+    started: Instant,
+    /// A random id identifying this run of the server.
+    run_id: String,
+    eviction_pool: Mutex<EvictionPool>,
+}
+
+/// The state of the snapshots written to the RDB file.
+struct SaveState {
+    path: PathBuf,
+    rules: Vec<(u64, u64)>,
+    /// Unix time in seconds of the last successful save.
+    last_save: u64,
+    /// The number of modifications made before the last successful save.
+    dirty_at_last_save: u64,
+    /// Unix time in seconds of the last background save attempt.
+    last_bgsave_try: u64,
+    bgsave_in_progress: bool,
+    last_bgsave_ok: bool,
 }
 
 impl Database {
     pub fn new(config: &Config) -> Self {
-        let n = config.databases.clamp(1, 16);
-        let dbs: Vec<Arc<RwLock<InternalDb>>> = (0..n)
-            .map(|_| Arc::new(RwLock::new(InternalDb::new())))
+        let n = config.databases;
+        let blocking = Arc::new(BlockingRegistry::default());
+        let stats = Arc::new(Stats::default());
+        let dbs: Vec<Arc<RwLock<InternalDb>>> = (0..n as usize)
+            .map(|index| {
+                let db = InternalDb::new(index, blocking.clone(), stats.clone());
+                Arc::new(RwLock::new(db))
+            })
             .collect();
-        Self { dbs }
+        Self {
+            dbs,
+            blocking,
+            pubsub: PubSub::default(),
+            exec_lock: ExecLock::default(),
+            saving: Arc::new(Mutex::new(SaveState {
+                path: Path::new(&config.dir).join(&config.dbfilename),
+                rules: config.save.clone(),
+                last_save: now_millis() / 1000,
+                dirty_at_last_save: 0,
+                last_bgsave_try: 0,
+                bgsave_in_progress: false,
+                last_bgsave_ok: true,
+            })),
+            aof: Arc::new(Mutex::new(Aof::new(config))),
+            write_lock: ExecLock::default(),
+            replication: Mutex::new(Replication::new(config)),
+            replication_changed: Condvar::new(),
+            cluster: config
+                .cluster_enabled
+                .then(|| Mutex::new(Cluster::new(config))),
+            scripting: Scripting::new(config),
+            acl: RwLock::new(Acl::new(config)),
+            config: RwLock::new(config.clone()),
+            slowlog: Mutex::new(SlowLog::default()),
+            stats,
+            started: Instant::now(),
+            run_id: new_replid(),
+            eviction_pool: Mutex::new(EvictionPool::default()),
+        }
+    }
+
+    pub fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    pub fn exec_lock(&self) -> &ExecLock {
+        &self.exec_lock
+    }
+
+    /// Returns the number of clients blocked by a blocking command.
+    pub fn blocked_clients(&self) -> usize {
+        self.blocking.blocked_clients()
     }
 
     pub fn get(&self, index: i64) -> Option<Arc<RwLock<InternalDb>>> {
-        Some(self.dbs.get(index as usize)?.clone())
+        Some(self.dbs.get(usize::try_from(index).ok()?)?.clone())
+    }
+
+    /// Removes expired keys from every database, giving each of them a slice of time so that a
+    /// database with lots of expired keys doesn't block the clients for too long.
+    pub fn active_expire_cycle(&self) {
+        // keys don't expire in the middle of a transaction.
+        self.exec_lock.lock_shared();
+        for db in self.dbs.iter() {
+            db.write()
+                .unwrap()
+                .active_expire_cycle(ACTIVE_EXPIRE_CYCLE_TIME_LIMIT / self.dbs.len() as u32);
+        }
+        self.exec_lock.unlock_shared();
+    }
+
+    /// Returns the number of modifications made since the server started.
+    pub fn dirty(&self) -> u64 {
+        self.dbs.iter().map(|db| db.read().unwrap().dirty).sum()
+    }
+
+    /// Returns the unix time in seconds of the last successful save.
+    pub fn last_save(&self) -> u64 {
+        self.saving.lock().unwrap().last_save
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.saving.lock().unwrap().bgsave_in_progress
+    }
+
+    /// Returns whether the last background save succeeded.
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.saving.lock().unwrap().last_bgsave_ok
+    }
+
+    /// Returns the number of modifications made since the last successful save.
+    pub fn changes_since_last_save(&self) -> u64 {
+        let dirty = self.dirty();
+        dirty.saturating_sub(self.saving.lock().unwrap().dirty_at_last_save)
+    }
+
+    /// Loads the keys of the RDB file, if it exists. Returns false if there is no file. It's
+    /// meant to be called at startup, before accepting clients.
+    pub fn load(&self) -> crate::error::Result<bool> {
+        let path = self.saving.lock().unwrap().path.clone();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        self.load_rdb(BufReader::new(file))?;
+        self.saving.lock().unwrap().dirty_at_last_save = self.dirty();
+        Ok(true)
+    }
+
+    fn load_rdb(&self, reader: impl Read) -> crate::error::Result<()> {
+        rdb::load(reader, |index, (key, value, expire)| {
+            let db = self
+                .dbs
+                .get(index)
+                .ok_or_else(|| Error::InvalidRdb(format!("database {} is out of range", index)))?;
+            let mut db = db.write().unwrap();
+            db.insert(key.clone(), value);
+            if let Some(when) = expire {
+                db.set_expire(&key, when);
+            }
+            Ok(())
+        })
+    }
+
+    /// Copies the keys of every database, along with the number of modifications they include.
+    /// The caller must hold the exec lock exclusively, so that a transaction is either entirely
+    /// in the snapshot or not at all.
+    fn snapshot(&self) -> (Vec<Vec<Entry>>, u64) {
+        let mut dirty = 0;
+        let dbs = self
+            .dbs
+            .iter()
+            .map(|db| {
+                let db = db.read().unwrap();
+                dirty += db.dirty;
+                db.snapshot()
+            })
+            .collect();
+        (dbs, dirty)
+    }
+
+    /// Writes a snapshot to the RDB file, blocking until it's written. The caller must hold the
+    /// exec lock exclusively.
+    pub fn save(&self) -> io::Result<()> {
+        let (dbs, dirty) = self.snapshot();
+        let path = self.saving.lock().unwrap().path.clone();
+        write_snapshot(&path, &dbs)?;
+
+        let mut saving = self.saving.lock().unwrap();
+        saving.last_save = now_millis() / 1000;
+        saving.dirty_at_last_save = dirty;
+        Ok(())
+    }
+
+    /// Writes a snapshot to the RDB file in the background. The keys are copied right away, so
+    /// the clients are only stalled while copying them, not while the file is written. Returns
+    /// false if a background save is already in progress. The caller must hold the exec lock
+    /// exclusively.
+    pub fn bgsave(&self) -> bool {
+        let mut saving = self.saving.lock().unwrap();
+        if saving.bgsave_in_progress {
+            return false;
+        }
+        saving.bgsave_in_progress = true;
+        saving.last_bgsave_try = now_millis() / 1000;
+        let path = saving.path.clone();
+        drop(saving);
+
+        let (dbs, dirty) = self.snapshot();
+        let state = self.saving.clone();
+        thread::spawn(move || {
+            let result = write_snapshot(&path, &dbs);
+            let mut saving = state.lock().unwrap();
+            saving.bgsave_in_progress = false;
+            saving.last_bgsave_ok = result.is_ok();
+            match result {
+                Ok(()) => {
+                    log::info!("Background saving terminated with success");
+                    saving.last_save = now_millis() / 1000;
+                    saving.dirty_at_last_save = dirty;
+                }
+                Err(err) => log::error!("Background saving error: {}", err),
+            }
+        });
+        true
+    }
+
+    /// Starts a background save when one of the save rules is met: enough modifications were
+    /// made, and enough time passed since the last save.
+    pub fn save_if_needed(&self) {
+        let now = now_millis() / 1000;
+        let dirty = self.dirty();
+        let due = {
+            let saving = self.saving.lock().unwrap();
+            let changes = dirty.saturating_sub(saving.dirty_at_last_save);
+            let elapsed = now.saturating_sub(saving.last_save);
+            let can_retry = saving.last_bgsave_ok
+                || now.saturating_sub(saving.last_bgsave_try) > BGSAVE_RETRY_DELAY;
+            !saving.bgsave_in_progress
+                && can_retry
+                && saving
+                    .rules
+                    .iter()
+                    .any(|&(seconds, min_changes)| changes >= min_changes && elapsed >= seconds)
+        };
+        if !due {
+            return;
+        }
+
+        log::info!("Save rule met, saving in the background");
+        self.exec_lock.lock_exclusive();
+        self.bgsave();
+        self.exec_lock.unlock_exclusive();
+    }
+
+    /// Gets the server ready to exit: the AOF is synced, and a snapshot is written to the RDB
+    /// file if `save` is true, or if it's not given and there are save rules. The caller must
+    /// hold the exec lock exclusively when a snapshot is written.
+    pub fn prepare_shutdown(&self, save: Option<bool>) -> io::Result<()> {
+        self.aof().sync()?;
+        if save.unwrap_or_else(|| !self.saving.lock().unwrap().rules.is_empty()) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn aof(&self) -> MutexGuard<'_, Aof> {
+        self.aof.lock().unwrap()
+    }
+
+    /// Returns whether the write commands are logged to the AOF.
+    pub fn aof_on(&self) -> bool {
+        self.aof().is_open()
+    }
+
+    pub fn aof_rewrite_in_progress(&self) -> bool {
+        self.aof().rewrite_in_progress()
+    }
+
+    /// Rewrites the AOF from the current keys, blocking until it's written. The caller must hold
+    /// the exec lock exclusively.
+    pub fn rewrite_aof(&self) -> io::Result<()> {
+        let mut aof = self.aof();
+        let temp = aof
+            .path()
+            .with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+        aof.start_rewrite();
+        let (dbs, _) = self.snapshot();
+        let result = write_aof(&temp, &dbs).and_then(|()| aof.finish_rewrite(&temp));
+        if result.is_err() {
+            aof.abort_rewrite();
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Rewrites the AOF in the background. The commands logged meanwhile are buffered, and
+    /// appended to the rewritten file once it's written. Returns false if a rewrite is already
+    /// in progress. The caller must hold the exec lock exclusively.
+    pub fn bgrewriteaof(&self) -> bool {
+        let mut aof = self.aof();
+        if aof.rewrite_in_progress() {
+            return false;
+        }
+        let temp = aof
+            .path()
+            .with_file_name(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        aof.start_rewrite();
+        drop(aof);
+
+        let (dbs, _) = self.snapshot();
+        let state = self.aof.clone();
+        thread::spawn(move || {
+            let result = write_aof(&temp, &dbs);
+            let mut aof = state.lock().unwrap();
+            match result.and_then(|()| aof.finish_rewrite(&temp)) {
+                Ok(()) => log::info!("Background AOF rewrite finished successfully"),
+                Err(err) => {
+                    log::error!("Background AOF rewrite error: {}", err);
+                    aof.abort_rewrite();
+                    let _ = fs::remove_file(&temp);
+                }
+            }
+        });
+        true
+    }
+
+    /// Syncs the AOF to the disk with the `everysec` policy, and rewrites it in the background
+    /// once it grew too much.
+    pub fn aof_cron(&self) {
+        let (file, needs_rewrite) = {
+            let mut aof = self.aof();
+            (aof.file_to_sync(), aof.needs_rewrite())
+        };
+        if let Some(file) = file {
+            if let Err(err) = file.sync_data() {
+                log::error!("Error syncing the AOF: {}", err);
+            }
+        }
+        if needs_rewrite {
+            log::info!("Starting automatic rewriting of the AOF");
+            self.exec_lock.lock_exclusive();
+            self.bgrewriteaof();
+            self.exec_lock.unlock_exclusive();
+        }
+    }
+
+    pub(super) fn scripting(&self) -> &Scripting {
+        &self.scripting
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap()
+    }
+
+    /// Replaces the configuration, applying the settings that can be changed while the server
+    /// runs. The other settings are expected to be unchanged.
+    pub fn set_config(&self, config: Config) {
+        let mut current = self.config.write().unwrap();
+        {
+            let mut saving = self.saving.lock().unwrap();
+            saving.rules = config.save.clone();
+            saving.path = Path::new(&config.dir).join(&config.dbfilename);
+        }
+        self.aof().configure(&config);
+        self.scripting.set_time_limit(config.lua_time_limit);
+        {
+            let mut acl = self.acl_mut();
+            acl.set_log_max_len(config.acllog_max_len);
+            if config.requirepass != current.requirepass {
+                acl.set_requirepass(config.requirepass.as_deref());
+            }
+        }
+        log::set_max_level(config.loglevel.level_filter());
+        *current = config;
+    }
+
+    pub(super) fn slowlog(&self) -> MutexGuard<'_, SlowLog> {
+        self.slowlog.lock().unwrap()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Returns how long the server has been running.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Returns the number of keys, the number of keys with an expiry time and the estimated
+    /// average time to live in milliseconds of the databases that are not empty, by index.
+    pub fn keyspace(&self) -> Vec<(usize, usize, usize, u64)> {
+        self.dbs
+            .iter()
+            .map(|db| db.read().unwrap())
+            .filter(|db| !db.is_empty())
+            .map(|db| (db.index, db.len(), db.expires.len(), db.avg_ttl))
+            .collect()
+    }
+
+    /// Samples the metrics that are computed over time. It's meant to be called periodically.
+    pub fn track_metrics(&self) {
+        self.stats.track_instantaneous_metrics();
+        let used = self.used_memory();
+        self.stats
+            .used_memory_peak
+            .fetch_max(used, Ordering::Relaxed);
+    }
+
+    /// Returns the memory used by the keys of every database, as estimated for `maxmemory`.
+    pub fn used_memory(&self) -> usize {
+        self.dbs
+            .iter()
+            .map(|db| db.write().unwrap().used_memory())
+            .sum()
+    }
+
+    /// Evicts keys, as chosen by `maxmemory-policy`, until the memory used by the keys is under
+    /// `maxmemory`. Returns false if the memory is still over the limit, because the policy
+    /// doesn't evict anything or because there is nothing left to evict.
+    pub(super) fn perform_evictions(&self) -> bool {
+        let (maxmemory, policy, samples) = {
+            let config = self.config();
+            (
+                config.maxmemory as usize,
+                config.maxmemory_policy,
+                config.maxmemory_samples,
+            )
+        };
+        if maxmemory == 0 {
+            return true;
+        }
+        let mut used = self.used_memory();
+        while used > maxmemory {
+            if policy == MaxmemoryPolicy::NoEviction {
+                return false;
+            }
+            match self.evict_one(policy, samples) {
+                Some(freed) => used = used.saturating_sub(freed),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Evicts the best key to evict under the policy, returning the memory freed.
+    fn evict_one(&self, policy: MaxmemoryPolicy, samples: usize) -> Option<usize> {
+        let volatile = policy.volatile();
+        if matches!(
+            policy,
+            MaxmemoryPolicy::AllKeysRandom | MaxmemoryPolicy::VolatileRandom
+        ) {
+            let start = rand::thread_rng().gen_range(0..self.dbs.len());
+            for i in 0..self.dbs.len() {
+                let index = (start + i) % self.dbs.len();
+                let key = self.dbs[index].read().unwrap().random_key(volatile);
+                if let Some(freed) = key.and_then(|key| self.evict(index, &key, volatile)) {
+                    return Some(freed);
+                }
+            }
+            return None;
+        }
+
+        let mut pool = self.eviction_pool.lock().unwrap();
+        for (index, db) in self.dbs.iter().enumerate() {
+            for (score, key) in db.read().unwrap().eviction_candidates(policy, samples) {
+                pool.insert(score, index, key);
+            }
+        }
+        // the candidates may have been deleted since they were sampled.
+        while let Some((index, key)) = pool.pop() {
+            if let Some(freed) = self.evict(index, &key, volatile) {
+                return Some(freed);
+            }
+        }
+        None
+    }
+
+    /// Deletes a key to free memory, propagating its deletion. Returns the memory freed, or
+    /// `None` if the key doesn't exist anymore.
+    fn evict(&self, index: usize, key: &Bytes, volatile: bool) -> Option<usize> {
+        let propagating = self.propagating();
+        if propagating {
+            self.write_lock.lock_exclusive();
+        }
+        let freed = self.dbs[index].write().unwrap().evict(key, volatile);
+        if propagating {
+            if freed.is_some() {
+                self.propagate(index, &[aof::command(&[b"DEL", key])], false);
+            }
+            self.write_lock.unlock_exclusive();
+        }
+        if freed.is_some() {
+            self.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+        }
+        freed
+    }
+
+    pub(super) fn acl(&self) -> RwLockReadGuard<'_, Acl> {
+        self.acl.read().unwrap()
+    }
+
+    pub(super) fn acl_mut(&self) -> RwLockWriteGuard<'_, Acl> {
+        self.acl.write().unwrap()
+    }
+
+    /// Returns the state of the cluster, `None` when the cluster mode is off.
+    pub(super) fn cluster(&self) -> Option<MutexGuard<'_, Cluster>> {
+        Some(self.cluster.as_ref()?.lock().unwrap())
+    }
+
+    pub(super) fn replication(&self) -> MutexGuard<'_, Replication> {
+        self.replication.lock().unwrap()
+    }
+
+    /// Returns whether this server replicates a master.
+    pub fn is_replica(&self) -> bool {
+        self.replication().master().is_some()
+    }
+
+    /// Returns whether the write commands are propagated, to the AOF or to the replicas.
+    fn propagating(&self) -> bool {
+        self.aof_on() || self.replication().feeding()
+    }
+
+    /// Propagates the commands run against the database `db`. Returns the offset of the
+    /// replication stream once they are sent to the replicas.
+    fn propagate(&self, db: usize, commands: &[Vec<Value>], in_exec: bool) -> u64 {
+        self.aof().append(db, commands, in_exec);
+        let mut replication = self.replication();
+        replication.feed_commands(db, commands, in_exec);
+        replication.offset()
+    }
+
+    /// Ends the transaction propagated by the commands run by EXEC.
+    pub(super) fn end_exec(&self) {
+        self.aof().end_exec();
+        self.replication().end_exec();
+    }
+
+    /// Pings the replicas every once in a while.
+    pub fn replication_cron(&self) {
+        // the ping can't end up in the middle of a transaction.
+        self.exec_lock.lock_shared();
+        self.replication().cron();
+        self.exec_lock.unlock_shared();
+    }
+
+    /// Resolves the PSYNC of a replica: the replica either continues the stream from `offset`,
+    /// or loads a snapshot taken right now and follows the stream from there. The caller must
+    /// hold the exec lock exclusively.
+    pub fn psync(&self, replid: &str, offset: i64, addr: String, port: u16) -> ReplicaSync<'_> {
+        let mut replication = self.replication();
+        let continued = u64::try_from(offset)
+            .ok()
+            .filter(|&offset| replication.can_continue(replid, offset));
+        let (header, snapshot, backlog) = match continued {
+            Some(offset) => {
+                log::info!("Partial resynchronization request from {} accepted", addr);
+                (
+                    format!("+CONTINUE {}\r\n", replication.replid()),
+                    None,
+                    replication.backlog_since(offset),
+                )
+            }
+            None => {
+                log::info!("Full resynchronization requested by replica {}", addr);
+                replication.reset_stream();
+                let header = format!(
+                    "+FULLRESYNC {} {}\r\n",
+                    replication.replid(),
+                    replication.offset()
+                );
+                (header, Some(self.snapshot().0), vec![])
+            }
+        };
+        let (id, feed) = replication.add_replica(addr, port);
+        ReplicaSync {
+            db: self,
+            id,
+            header,
+            snapshot,
+            backlog,
+            feed,
+        }
+    }
+
+    /// Records the offset of the stream acknowledged by a replica. Returns false if the replica
+    /// is no longer connected.
+    pub fn replica_ack(&self, id: u64, offset: u64) -> bool {
+        let acked = self.replication().ack(id, offset);
+        self.replication_changed.notify_all();
+        acked
+    }
+
+    pub fn remove_replica(&self, id: u64) {
+        self.replication().remove_replica(id);
+    }
+
+    /// Waits until `numreplicas` replicas acknowledged the stream up to `offset`, or until the
+    /// deadline. Returns the number of replicas that acknowledged it.
+    pub fn wait_for_replicas(
+        &self,
+        offset: u64,
+        numreplicas: usize,
+        deadline: Option<Instant>,
+    ) -> usize {
+        let mut replication = self.replication();
+        if replication.acked(offset) >= numreplicas {
+            return replication.acked(offset);
+        }
+        replication.request_ack();
+        loop {
+            let acked = replication.acked(offset);
+            if acked >= numreplicas {
+                return acked;
+            }
+            replication = match deadline {
+                None => self.replication_changed.wait(replication).unwrap(),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return acked;
+                    }
+                    self.replication_changed
+                        .wait_timeout(replication, timeout)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Replicates another master, or stops replicating with `None`. Returns false if the master
+    /// didn't change.
+    pub fn replicaof(&self, master: Option<(String, u16)>) -> bool {
+        let changed = self.replication().set_master(master);
+        self.replication_changed.notify_all();
+        changed
+    }
+
+    /// Blocks until the server has a master to connect to, returning its address and the
+    /// generation of the link.
+    pub fn wait_for_master(&self) -> (String, u16, u64) {
+        let mut replication = self.replication();
+        loop {
+            if let Some(master) = replication.master_to_connect() {
+                return master;
+            }
+            replication = self.replication_changed.wait(replication).unwrap();
+        }
+    }
+
+    /// Updates the state of the link with the master. Returns false if the master changed since
+    /// the link was started, the link is then expected to stop.
+    pub fn set_link_state(
+        &self,
+        generation: u64,
+        state: LinkState,
+        connection: Option<TcpStream>,
+    ) -> bool {
+        self.replication()
+            .set_link_state(generation, state, connection)
+    }
+
+    /// Returns the arguments of the PSYNC sent to the master: the history of the stream, and the
+    /// offset to continue from.
+    pub fn psync_args(&self) -> (String, u64) {
+        let replication = self.replication();
+        (replication.replid().to_string(), replication.offset() + 1)
+    }
+
+    /// Replaces every key with the snapshot of the master, then follows its stream from
+    /// `offset`. Returns false if the master changed since the link was started.
+    pub fn full_sync(
+        &self,
+        generation: u64,
+        rdb: &[u8],
+        replid: String,
+        offset: u64,
+    ) -> crate::error::Result<bool> {
+        self.exec_lock.lock_exclusive();
+        let result = self.load_master_snapshot(generation, rdb, replid, offset);
+        self.exec_lock.unlock_exclusive();
+        result
+    }
+
+    fn load_master_snapshot(
+        &self,
+        generation: u64,
+        rdb: &[u8],
+        replid: String,
+        offset: u64,
+    ) -> crate::error::Result<bool> {
+        if self.replication().generation() != generation {
+            return Ok(false);
+        }
+        for db in self.dbs.iter() {
+            db.write().unwrap().clear();
+        }
+        if let Err(err) = self.load_rdb(rdb) {
+            self.replication().discard_history();
+            return Err(err);
+        }
+        self.replication().start_stream(replid, offset);
+        if self.aof_on() {
+            // the AOF has to start from the new keys.
+            self.rewrite_aof()?;
+        }
+        Ok(self.set_link_state(generation, LinkState::Connected, None))
+    }
+
+    /// Continues the stream of the master where it was left. Returns false if the master changed
+    /// since the link was started.
+    pub fn continue_sync(&self, generation: u64, replid: String) -> bool {
+        let mut replication = self.replication();
+        if replication.generation() != generation {
+            return false;
+        }
+        replication.continue_stream(replid);
+        replication.set_link_state(generation, LinkState::Connected, None)
+    }
+
+    /// Forwards the stream received from the master to the replicas of this server. Returns
+    /// false if the master changed since the link was started.
+    pub fn forward_stream(&self, generation: u64, bytes: &[u8]) -> bool {
+        let mut replication = self.replication();
+        if replication.generation() != generation {
+            return false;
+        }
+        replication.feed(bytes);
+        true
+    }
+
+    /// Returns the port this server announces to its master.
+    pub fn announced_port(&self) -> u16 {
+        self.replication().port()
+    }
+
+    /// Returns the offset of the last byte of the replication stream.
+    pub fn replication_offset(&self) -> u64 {
+        self.replication().offset()
+    }
+}
+
+/// Writes the commands rebuilding the keys to a new file.
+fn write_aof(path: &Path, dbs: &[Vec<Entry>]) -> io::Result<()> {
+    let file = File::create(path)?;
+    aof::rewrite(dbs, BufWriter::new(&file))?;
+    file.sync_all()
+}
+
+/// Writes the snapshot to a temporary file first, then renames it, so that the RDB file is
+/// never left half written.
+fn write_snapshot(path: &Path, dbs: &[Vec<Entry>]) -> io::Result<()> {
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let result = File::create(&temp).and_then(|file| {
+        rdb::save(dbs, BufWriter::new(&file))?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
     }
+    result
 }
 
 pub struct InternalDb {
-    pub storage: HashMap<Bytes, Bytes>,
+    index: usize,
+    storage: Dict<Bytes, StoredObject>,
+    expires: Dict<Bytes, u64>,
+    blocking: Arc<BlockingRegistry>,
+    /// The versions of the keys watched by WATCH, bumped every time the key is modified.
+    watched: HashMap<Bytes, KeyVersion>,
+    /// Whether the command holding the lock is a write command. It tells whether accessing a
+    /// value mutably counts as a modification.
+    write_command: bool,
+    /// The number of modifications, used by the save rules.
+    dirty: u64,
+    /// The memory used by the keys and their values, see [`InternalDb::used_memory`].
+    used_memory: usize,
+    /// The keys whose value may have been modified in place since their size was computed.
+    resized: Vec<Bytes>,
+    stats: Arc<Stats>,
+    /// The average time to live of the keys with an expiry time, in milliseconds, estimated from
+    /// the keys sampled by the active expire cycle.
+    avg_ttl: u64,
+}
+
+/// A value of the database, along with what the eviction needs to know about it.
+struct StoredObject {
+    object: Object,
+    /// The estimated memory used by the key and the value.
+    size: usize,
+    /// Unix time in milliseconds of the last access, used by the LRU policies.
+    access_time: u64,
+    /// The logarithmic access frequency used by the LFU policies, see [`evict::lfu_increment`].
+    lfu_counter: u8,
+}
+
+impl StoredObject {
+    fn new(key: &Bytes, object: Object) -> Self {
+        Self {
+            size: stored_size(key, &object),
+            object,
+            access_time: now_millis(),
+            lfu_counter: LFU_INIT_VAL,
+        }
+    }
+
+    fn access(&mut self) {
+        let now = now_millis();
+        self.lfu_counter =
+            evict::lfu_increment(evict::lfu_decay(self.lfu_counter, self.access_time, now));
+        self.access_time = now;
+    }
+}
+
+fn stored_size(key: &Bytes, object: &Object) -> usize {
+    KEY_OVERHEAD + key.len() + object.memory_usage()
+}
+
+#[derive(Debug, Default)]
+struct KeyVersion {
+    version: u64,
+    watchers: usize,
 }
 
 impl InternalDb {
-    fn new() -> Self {
+    fn new(index: usize, blocking: Arc<BlockingRegistry>, stats: Arc<Stats>) -> Self {
         Self {
-            storage: HashMap::new(),
+            index,
+            storage: Dict::new(),
+            expires: Dict::new(),
+            blocking,
+            watched: HashMap::new(),
+            write_command: false,
+            dirty: 0,
+            used_memory: 0,
+            resized: Vec::new(),
+            stats,
+            avg_ttl: 0,
+        }
+    }
+
+    /// Returns the index of the database, as used by SELECT.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    pub fn get(&mut self, key: &Bytes) -> Option<&Object> {
+        self.expire_if_needed(key);
+        self.count_lookup(key);
+        let stored = self.storage.get_mut(key)?;
+        stored.access();
+        Some(&stored.object)
+    }
+
+    /// Returns the values of several keys at once.
+    pub fn get_many(&mut self, keys: &[Bytes]) -> Vec<Option<&Object>> {
+        for key in keys {
+            self.expire_if_needed(key);
+            self.count_lookup(key);
+            if let Some(stored) = self.storage.get_mut(key) {
+                stored.access();
+            }
+        }
+        keys.iter()
+            .map(|key| self.storage.get(key).map(|stored| &stored.object))
+            .collect()
+    }
+
+    /// Returns the value of the key to change it, the caller marking the key with
+    /// [`InternalDb::modified`] if it does.
+    pub fn get_mut(&mut self, key: &Bytes) -> Option<&mut Object> {
+        self.expire_if_needed(key);
+        self.settle_sizes();
+        if self.write_command && self.storage.contains_key(key) {
+            self.resized.push(key.clone());
+        }
+        let stored = self.storage.get_mut(key)?;
+        stored.access();
+        Some(&mut stored.object)
+    }
+
+    /// Returns the value of the key, inserting the value returned by `f` if the key doesn't exist.
+    /// Like with [`InternalDb::get_mut`], the caller marks the key as modified, the value being
+    /// meant to be filled right away.
+    pub fn get_or_insert_with(&mut self, key: &Bytes, f: impl FnOnce() -> Object) -> &mut Object {
+        self.expire_if_needed(key);
+        self.settle_sizes();
+        if !self.storage.contains_key(key) {
+            self.store(key.clone(), f());
+            self.signal_ready(key);
+        }
+        self.resized.push(key.clone());
+        let stored = self.storage.get_mut(key).unwrap();
+        stored.access();
+        &mut stored.object
+    }
+
+    pub fn contains_key(&mut self, key: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        self.storage.contains_key(key)
+    }
+
+    /// Sets the value of the key, discarding its previous expiry time.
+    pub fn insert(&mut self, key: Bytes, value: Object) -> Option<Object> {
+        self.expires.remove(&key);
+        self.signal_ready(&key);
+        self.touch(&key);
+        self.store(key, value)
+    }
+
+    /// Sets the value of the key, keeping its expiry time if it has one.
+    pub fn insert_keepttl(&mut self, key: Bytes, value: Object) -> Option<Object> {
+        self.expire_if_needed(&key);
+        self.signal_ready(&key);
+        self.touch(&key);
+        self.store(key, value)
+    }
+
+    pub fn remove(&mut self, key: &Bytes) -> Option<Object> {
+        if self.expire_if_needed(key) {
+            return None;
+        }
+        self.delete(key)
+    }
+
+    fn delete(&mut self, key: &Bytes) -> Option<Object> {
+        self.expires.remove(key);
+        let stored = self.storage.remove(key)?;
+        self.touch(key);
+        self.used_memory -= stored.size;
+        Some(stored.object)
+    }
+
+    /// Inserts the value, keeping track of the memory used.
+    fn store(&mut self, key: Bytes, value: Object) -> Option<Object> {
+        let stored = StoredObject::new(&key, value);
+        self.used_memory += stored.size;
+        let previous = self.storage.insert(key, stored)?;
+        self.used_memory -= previous.size;
+        Some(previous.object)
+    }
+
+    /// Computes again the size of the values that may have been modified in place.
+    fn settle_sizes(&mut self) {
+        for key in std::mem::take(&mut self.resized) {
+            if let Some(stored) = self.storage.get_mut(&key) {
+                let size = stored_size(&key, &stored.object);
+                self.used_memory = self.used_memory - stored.size + size;
+                stored.size = size;
+            }
+        }
+    }
+
+    /// Returns the memory used by the keys and their values, as estimated for `maxmemory`.
+    pub fn used_memory(&mut self) -> usize {
+        self.settle_sizes();
+        self.used_memory
+    }
+
+    /// Sets the time since the last access of the key, in seconds, and its access frequency, as
+    /// done by RESTORE.
+    pub fn set_access(&mut self, key: &Bytes, idle: Option<u64>, frequency: Option<u8>) {
+        let Some(stored) = self.storage.get_mut(key) else {
+            return;
+        };
+        if let Some(idle) = idle {
+            stored.access_time = now_millis().saturating_sub(idle.saturating_mul(1000));
+        }
+        if let Some(frequency) = frequency {
+            stored.lfu_counter = frequency;
+        }
+    }
+
+    /// Samples keys to evict, returning them with their score under the policy: the higher the
+    /// score, the better the key is to evict. The `volatile` policies only sample the keys with
+    /// an expiry time.
+    fn eviction_candidates(&self, policy: MaxmemoryPolicy, samples: usize) -> Vec<(u64, Bytes)> {
+        let now = now_millis();
+        let sampled = if policy.volatile() {
+            self.expires.len()
+        } else {
+            self.storage.len()
+        };
+        (0..samples.min(sampled))
+            .filter_map(|_| {
+                let key = if policy.volatile() {
+                    self.expires.random_entry()?.0
+                } else {
+                    self.storage.random_entry()?.0
+                };
+                let stored = self.storage.get(key)?;
+                let score = match policy {
+                    MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => {
+                        let counter = evict::lfu_decay(stored.lfu_counter, stored.access_time, now);
+                        (u8::MAX - counter) as u64
+                    }
+                    MaxmemoryPolicy::VolatileTtl => u64::MAX - self.expires.get(key)?,
+                    _ => now.saturating_sub(stored.access_time),
+                };
+                Some((score, key.clone()))
+            })
+            .collect()
+    }
+
+    /// Returns a random key, with an expiry time if `volatile` is set.
+    fn random_key(&self, volatile: bool) -> Option<Bytes> {
+        let key = if volatile {
+            self.expires.random_entry()?.0
+        } else {
+            self.storage.random_entry()?.0
+        };
+        Some(key.clone())
+    }
+
+    /// Deletes the key to free memory, returning the memory freed. With `volatile`, the key is
+    /// only deleted if it has an expiry time.
+    fn evict(&mut self, key: &Bytes, volatile: bool) -> Option<usize> {
+        if volatile && !self.expires.contains_key(key) {
+            return None;
+        }
+        self.settle_sizes();
+        let size = self.storage.get(key)?.size;
+        self.delete(key);
+        Some(size)
+    }
+
+    /// Returns the unix time in milliseconds at which the key will expire, or `None` if the key
+    /// doesn't have an expiry time.
+    pub fn get_expire(&mut self, key: &Bytes) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.expires.get(key).copied()
+    }
+
+    /// Sets the expiry time of an existing key. Returns false if the key doesn't exist.
+    pub fn set_expire(&mut self, key: &Bytes, when: u64) -> bool {
+        if !self.contains_key(key) {
+            return false;
+        }
+        self.touch(key);
+        self.expires.insert(key.clone(), when);
+        true
+    }
+
+    /// Removes the expiry time of the key. Returns false if the key doesn't have one.
+    pub fn persist(&mut self, key: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        if self.expires.remove(key).is_none() {
+            return false;
+        }
+        self.touch(key);
+        true
+    }
+
+    /// Deletes the key if it is already expired. Returns true if the key was deleted.
+    pub fn expire_if_needed(&mut self, key: &Bytes) -> bool {
+        match self.expires.get(key) {
+            Some(&when) if when <= now_millis() => {
+                self.delete(key);
+                self.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Counts a lookup of the read commands as a hit or a miss, for INFO.
+    fn count_lookup(&self, key: &Bytes) {
+        if self.write_command {
+            return;
+        }
+        let counter = if self.storage.contains_key(key) {
+            &self.stats.keyspace_hits
+        } else {
+            &self.stats.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the key as modified, see [`InternalDb::version`].
+    fn touch(&mut self, key: &Bytes) {
+        self.dirty += 1;
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.version += 1;
+        }
+    }
+
+    /// Marks the key as modified by the running command, which changed that many elements of its
+    /// value, each one counting for the save rules. Without changes, nothing is marked: the
+    /// command isn't propagated, and the transactions watching the key can still run.
+    pub fn modified(&mut self, key: &Bytes, changes: usize) {
+        if changes > 0 {
+            self.dirty += changes as u64 - 1;
+            self.touch(key);
+        }
+    }
+
+    /// Sets whether the command about to access the database is a write command. The values
+    /// accessed mutably are only measured again for write commands, since the read commands use
+    /// the same accessors.
+    pub fn set_write_command(&mut self, write_command: bool) {
+        self.write_command = write_command;
+    }
+
+    /// Starts tracking the version of the key, returning its current version.
+    pub fn watch(&mut self, key: &Bytes) -> u64 {
+        self.expire_if_needed(key);
+        let watched = self.watched.entry(key.clone()).or_default();
+        watched.watchers += 1;
+        watched.version
+    }
+
+    pub fn unwatch(&mut self, key: &Bytes) {
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// Returns the version of a watched key, which changes every time the key is modified,
+    /// including when it expires.
+    pub fn version(&mut self, key: &Bytes) -> u64 {
+        self.expire_if_needed(key);
+        self.watched.get(key).map(|w| w.version).unwrap_or(0)
+    }
+
+    /// Wakes up the first client blocked on the key. Keys are signaled automatically when they
+    /// are created, commands that can serve blocked clients without creating a key (e.g. XADD on
+    /// an existing stream) have to do it themselves.
+    pub fn signal_ready(&self, key: &Bytes) {
+        self.blocking.signal(self.index, key);
+    }
+
+    /// Blocks the client on the keys. It has to be called while holding the lock of the
+    /// database, so that it can't miss a key becoming ready.
+    pub fn block(&self, keys: &[Bytes], on_ready: Option<OnReady>) -> Arc<Waiter> {
+        self.blocking.register(self.index, keys, on_ready)
+    }
+
+    /// Unblocks the client, passing the turn to the next client blocked on keys that still exist
+    /// since they may be able to serve it.
+    pub fn unblock(&mut self, keys: &[Bytes], waiter: &Arc<Waiter>) {
+        self.blocking.unregister(self.index, keys, waiter);
+        for key in keys {
+            if self.contains_key(key) {
+                self.signal_ready(key);
+            }
+        }
+    }
+
+    /// Returns up to `count` keys of the hash slot.
+    pub fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes> {
+        self.storage
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key_hash_slot(key) == slot)
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Deletes every key, like FLUSHDB.
+    fn clear(&mut self) {
+        self.dirty += self.storage.len() as u64;
+        for (key, watched) in self.watched.iter_mut() {
+            if self.storage.contains_key(key) {
+                watched.version += 1;
+            }
+        }
+        self.storage.clear();
+        self.expires.clear();
+        self.used_memory = 0;
+        self.resized.clear();
+    }
+
+    /// Copies the keys that are not expired, along with their expiry time.
+    fn snapshot(&self) -> Vec<Entry> {
+        let now = now_millis();
+        self.storage
+            .iter()
+            .filter_map(|(key, stored)| {
+                let expire = self.expires.get(key).copied();
+                match expire {
+                    Some(when) if when <= now => None,
+                    _ => Some((key.clone(), stored.object.clone(), expire)),
+                }
+            })
+            .collect()
+    }
+
+    fn active_expire_cycle(&mut self, time_limit: Duration) {
+        let start = Instant::now();
+        loop {
+            let sampled = ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP.min(self.expires.len());
+            if sampled == 0 {
+                self.avg_ttl = 0;
+                return;
+            }
+
+            let now = now_millis();
+            let mut expired = 0;
+            let mut ttl_sum = 0;
+            for _ in 0..sampled {
+                let (key, &when) = self.expires.random_entry().unwrap();
+                if when <= now {
+                    let key = key.clone();
+                    self.delete(&key);
+                    expired += 1;
+                } else {
+                    ttl_sum += when - now;
+                }
+            }
+            self.stats
+                .expired_keys
+                .fetch_add(expired as u64, Ordering::Relaxed);
+
+            // like redis, the average is smoothed over the cycles.
+            if expired < sampled {
+                let avg_ttl = ttl_sum / (sampled - expired) as u64;
+                self.avg_ttl = if self.avg_ttl == 0 {
+                    avg_ttl
+                } else {
+                    self.avg_ttl / 50 * 49 + avg_ttl / 50
+                };
+            }
+
+            if expired <= sampled / 4 || start.elapsed() > time_limit {
+                return;
+            }
         }
     }
 }
 
 pub struct Session<'a> {
-    pub handlers: HashMap<String, CommandSpec<'a>>,
+    /// The commands, by their name in upper case, shared by the sessions.
+    pub handlers: Arc<HashMap<String, CommandSpec>>,
     pub db: &'a Database,
     pub selected_db: Arc<RwLock<InternalDb>>,
+    /// The output of the client, used to deliver the pub/sub messages.
+    pub subscriber: Subscriber,
+    pub subscriptions: Subscriptions,
+    /// Set by QUIT, the connection is closed once the reply is written.
+    pub quit: bool,
+    /// The queued commands, between MULTI and EXEC.
+    pub transaction: Option<Transaction>,
+    pub watched: Vec<WatchedKey>,
+    /// Set while EXEC runs the queued commands. Blocking commands don't block then.
+    pub executing: bool,
+    /// Set for the session running the commands received from the master, the only one allowed
+    /// to write on a replica.
+    pub master_link: bool,
+    pub peer_addr: Option<SocketAddr>,
+    /// Shared with the connection, to abort the blocking commands once the client is gone.
+    pub abort_handle: Arc<AbortHandle>,
+    /// Set for the clients of the event loop, which are parked while blocked instead of holding
+    /// their thread, see [`Session::retry_blocked`].
+    on_ready: Option<OnReady>,
+    /// The command the parked client is blocked on.
+    pub(super) blocked: Option<Blocked>,
+    /// The arguments of the running command, kept to retry it if the client is parked.
+    retried_args: Option<Vec<Value>>,
+    write_command: bool,
+    /// The commands propagated to the AOF and to the replicas for the running command, `None` if
+    /// it's not propagated.
+    propagated: Option<Vec<Vec<Value>>>,
+    /// The offset of the replication stream after the last write of the client, used by WAIT.
+    pub(super) write_offset: u64,
+    /// The port announced by a replica with REPLCONF listening-port.
+    pub(super) replica_port: u16,
+    /// Set by PSYNC, the connection is then handed over to the replication.
+    pub(super) replica_sync: Option<ReplicaSync<'a>>,
+    /// Set by ASKING, lets the next command access a slot being imported by this node.
+    pub(super) asking: bool,
+    /// The user the client is authenticated as, `None` for the internal sessions which are
+    /// allowed to run anything.
+    pub(super) user: Option<String>,
+    /// Cleared until the client authenticates, when the default user needs a password.
+    pub(super) authenticated: bool,
+    /// Set when the memory used was still over `maxmemory` after the evictions made before the
+    /// running command. The scripts check it before running the commands that use memory.
+    pub(super) oom: bool,
+    /// The name given by the client with HELLO SETNAME.
+    pub(super) name: Option<String>,
+    messages: Option<Receiver<Vec<u8>>>,
 }
 
 pub struct SessionFactory {
     database: Database,
+    handlers: Arc<HashMap<String, CommandSpec>>,
 }
 
 impl SessionFactory {
     pub fn new(database: Database) -> Self {
-        Self { database }
+        let handlers = get_commands()
+            .into_iter()
+            .map(|command| (command.name.to_uppercase(), command))
+            .collect();
+        Self {
+            database,
+            handlers: Arc::new(handlers),
+        }
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
     }
 
-    pub fn create_session(&self) -> Session {
-        let mut handlers = HashMap::new();
-        for command in get_commands() {
-            handlers.insert(command.name.clone().to_uppercase(), command);
+    /// Loads the keys at startup, before accepting clients: from the AOF when it's turned on, and
+    /// from the RDB file otherwise. Returns false if there was nothing to load. The users are
+    /// loaded from the ACL file first, if there is one.
+    pub fn load(&self) -> crate::error::Result<bool> {
+        let database = &self.database;
+        database.acl_mut().load()?;
+        let (enabled, path) = {
+            let aof = database.aof();
+            (aof.enabled(), aof.path().to_path_buf())
+        };
+        if !enabled {
+            return database.load();
         }
 
+        let loaded = if path.exists() {
+            let mut session = self.create_session();
+            aof::replay(&path, &mut session)?;
+            database.saving.lock().unwrap().dirty_at_last_save = database.dirty();
+            true
+        } else {
+            // the AOF starts with the keys of the RDB file, so turning it on doesn't lose them.
+            let loaded = database.load()?;
+            database.rewrite_aof()?;
+            loaded
+        };
+        database.aof().open()?;
+        Ok(loaded)
+    }
+
+    pub fn create_session(&self) -> Session<'_> {
+        let (subscriber, messages) = self.database.pubsub.new_subscriber();
         Session {
             db: &self.database,
             selected_db: self.database.dbs.first().unwrap().clone(),
-            handlers,
+            handlers: self.handlers.clone(),
+            subscriber,
+            subscriptions: Subscriptions::default(),
+            quit: false,
+            transaction: None,
+            watched: vec![],
+            executing: false,
+            master_link: false,
+            peer_addr: None,
+            abort_handle: Arc::default(),
+            on_ready: None,
+            blocked: None,
+            retried_args: None,
+            write_command: false,
+            propagated: None,
+            write_offset: 0,
+            replica_port: 0,
+            replica_sync: None,
+            asking: false,
+            user: None,
+            authenticated: true,
+            oom: false,
+            name: None,
+            messages: Some(messages),
         }
     }
+
+    /// Creates the session of a client connection, authenticated as the default user unless the
+    /// default user needs a password.
+    pub fn create_client_session(&self) -> Session<'_> {
+        let mut session = self.create_session();
+        session.authenticated = self
+            .database
+            .acl()
+            .user(DEFAULT_USER)
+            .is_some_and(|user| user.enabled() && user.nopass());
+        session.user = Some(DEFAULT_USER.to_string());
+        session
+    }
 }
 
+// The commands allowed while the client is subscribed to a channel.
+const SUBSCRIBER_COMMANDS: &[&str] = &[
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "PING",
+    "QUIT",
+];
+
+// The commands allowed before the client authenticates.
+const NOAUTH_COMMANDS: &[&str] = &["AUTH", "HELLO", "QUIT"];
+
+// The commands executed right away between MULTI and EXEC instead of being queued.
+const TRANSACTION_COMMANDS: &[&str] = &["MULTI", "EXEC", "DISCARD", "WATCH", "QUIT"];
+
 impl<'a> Session<'a> {
+    /// Takes the receiving half of the client's output, where the encoded messages published to the
+    /// subscribed channels arrive. The connection is expected to write everything it receives,
+    /// and to send its own replies through [`Session::subscriber`] to keep them in order.
+    pub fn take_messages(&mut self) -> Option<Receiver<Vec<u8>>> {
+        self.messages.take()
+    }
+
+    /// Takes the handshake resolved by PSYNC, once the client turned out to be a replica. The
+    /// reply of PSYNC must not be sent, the connection is expected to send the payload of the
+    /// handshake instead.
+    pub fn take_replica_sync(&mut self) -> Option<ReplicaSync<'a>> {
+        self.replica_sync.take()
+    }
+
     pub fn handle_request(&mut self, request: Value) -> Value {
         let request = match request {
             Value::Array(v) => v,
             _ => return Value::err(format!("Invalid request from client: {:?}", request)),
         };
 
-        if request.is_empty() {}
-
         let mut request = request.into_iter();
         let command = match request.next() {
             Some(command) => command,
@@ -89,23 +1519,680 @@ impl<'a> Session<'a> {
 
         let command = match command.into_string() {
             Ok(v) => v,
-            Err(_) => return Value::err(format!("Invalid command from client")),
+            Err(_) => return Value::err("Invalid command from client"),
         };
 
         let args: Vec<Value> = request.collect();
 
         let command = command.to_uppercase();
-        let handler = match self.handlers.get(&command) {
+        if let Err(err) = self.check_command(&command, &args) {
+            return self.reject(&command, err);
+        }
+
+        if !NOAUTH_COMMANDS.contains(&command.as_str()) {
+            let context = if self.transaction.is_some() {
+                "multi"
+            } else {
+                "toplevel"
+            };
+            if let Err(err) = self.check_acl(&command, &args, context) {
+                return self.reject(&command, err);
+            }
+        }
+
+        if !self.master_link
+            && self.handlers[&command].flags.contains(&COMMAND_FLAG_WRITE)
+            && self.db.is_replica()
+        {
+            let err = Value::Err(
+                "READONLY".to_string(),
+                "You can't write against a read only replica.".to_string(),
+            );
+            return self.reject(&command, err);
+        }
+
+        if let Err(err) = self.check_cluster(&command, &args) {
+            return self.reject(&command, err);
+        }
+
+        // the RESP3 clients can tell the pub/sub messages from the replies.
+        if self.subscriptions.is_subscribed()
+            && self.protocol() == Protocol::Resp2
+            && !SUBSCRIBER_COMMANDS.contains(&command.as_str())
+        {
+            self.db.stats().record_rejected(&command);
+            return Value::err(format!(
+                "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are \
+                 allowed in this context",
+                command.to_lowercase()
+            ));
+        }
+
+        // like redis, the replicas leave the evictions to their master.
+        self.oom = false;
+        if !self.master_link
+            && self.db.config().maxmemory > 0
+            && !self.db.is_replica()
+            && !self.db.scripting().busy()
+        {
+            self.db.exec_lock().lock_shared();
+            self.oom = !self.db.perform_evictions();
+            self.db.exec_lock().unlock_shared();
+            if self.oom
+                && self.handlers[&command]
+                    .flags
+                    .contains(&COMMAND_FLAG_DENYOOM)
+            {
+                let err = Value::Err(
+                    "OOM".to_string(),
+                    "command not allowed when used memory > 'maxmemory'.".to_string(),
+                );
+                return self.reject(&command, err);
+            }
+        }
+
+        if let Some(transaction) = &mut self.transaction {
+            if !TRANSACTION_COMMANDS.contains(&command.as_str()) {
+                transaction.commands.push(QueuedCommand {
+                    name: command,
+                    args,
+                });
+                return Value::Simple("QUEUED".into());
+            }
+        }
+
+        // SCRIPT KILL doesn't wait for the running script, it stops it, and SHUTDOWN NOSAVE exits
+        // without it.
+        let first_arg = |name: &[u8]| match args.first() {
+            Some(Value::Blob(arg) | Value::Simple(arg)) => arg.eq_ignore_ascii_case(name),
+            _ => false,
+        };
+        let skips_busy = (command == "SCRIPT" && first_arg(b"KILL"))
+            || (command == "SHUTDOWN" && args.len() == 1 && first_arg(b"NOSAVE"));
+        if skips_busy {
+            let reply = self.execute(&command, args);
+            return self.downgrade(reply);
+        }
+        if !self.master_link && self.db.scripting().busy() {
+            self.db.stats().record_rejected(&command);
+            return Value::Err(
+                "BUSY".to_string(),
+                "Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN \
+                 NOSAVE."
+                    .to_string(),
+            );
+        }
+
+        let slowlog_threshold = self.db.config().slowlog_log_slower_than;
+        let logged = (slowlog_threshold >= 0).then(|| args.clone());
+        let blocking = self.handlers[&command]
+            .flags
+            .contains(&COMMAND_FLAG_BLOCKING);
+        self.retried_args = (blocking && self.on_ready.is_some()).then(|| args.clone());
+        let started = Instant::now();
+        self.db.exec_lock().lock_shared();
+        let reply = self.execute(&command, args);
+        self.db.exec_lock().unlock_shared();
+
+        let retried_args = self.retried_args.take();
+        if let Some(blocked) = &mut self.blocked {
+            // the reply is the one of the command retried once the client is woken up.
+            blocked.request = retried_args.map(|args| (command, args));
+            return Value::Null;
+        }
+
+        let duration = started.elapsed();
+        if let Some(args) =
+            logged.filter(|_| !blocking && duration.as_micros() >= slowlog_threshold as u128)
+        {
+            let mut request = vec![Value::Blob(command.as_str().into())];
+            request.extend(args);
+            let addr = self
+                .peer_addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_default();
+            let max_len = self.db.config().slowlog_max_len;
+            self.db.slowlog().push(&request, duration, addr, max_len);
+        }
+        self.downgrade(reply)
+    }
+
+    /// Makes the blocking commands park the client instead of holding the thread running them,
+    /// `on_ready` being called once the client is woken up, for the connection to retry the
+    /// command with [`Session::retry_blocked`]. Without it, the command waits on its thread.
+    pub fn set_on_ready(&mut self, on_ready: OnReady) {
+        self.on_ready = Some(on_ready);
+    }
+
+    pub(super) fn on_ready(&self) -> Option<OnReady> {
+        self.on_ready.clone()
+    }
+
+    /// Returns whether the client is parked, blocked on a command. The connection must not send
+    /// it anything else until the command is retried successfully.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.is_some()
+    }
+
+    /// Returns whether the parked client was woken up since the command was last tried.
+    pub fn is_ready(&self) -> bool {
+        self.blocked
+            .as_ref()
+            .is_some_and(|blocked| blocked.waiter.is_ready())
+    }
+
+    /// Returns when the command the parked client is blocked on times out, if it does.
+    pub fn blocked_deadline(&self) -> Option<Instant> {
+        self.blocked.as_ref().and_then(|blocked| blocked.deadline)
+    }
+
+    /// Runs again the command the parked client is blocked on, once the client is woken up or
+    /// the timeout expired. Returns its reply, or `None` if the client is still blocked.
+    pub fn retry_blocked(&mut self) -> Option<Value> {
+        let (command, args) = self.blocked.as_mut()?.request.take()?;
+        self.db.exec_lock().lock_shared();
+        let reply = self.execute(&command, args.clone());
+        self.db.exec_lock().unlock_shared();
+        match &mut self.blocked {
+            Some(blocked) => {
+                blocked.request = Some((command, args));
+                None
+            }
+            None => Some(self.downgrade(reply)),
+        }
+    }
+
+    /// Replaces an argument of the command retried once the parked client is woken up, like the
+    /// IDs resolved when blocking. The index doesn't count the name of the command.
+    pub fn rewrite_retried_arg(&mut self, index: usize, value: Value) {
+        if let Some(args) = &mut self.retried_args {
+            args[index] = value;
+        }
+    }
+
+    /// Rejects the command before it runs, counting it in the stats of the command. A command
+    /// that can't be queued aborts the transaction.
+    fn reject(&mut self, command: &str, err: Value) -> Value {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.aborted = true;
+        }
+        self.db.stats().record_rejected(command);
+        err
+    }
+
+    /// Returns the protocol negotiated by the client with HELLO.
+    pub fn protocol(&self) -> Protocol {
+        self.subscriber.protocol()
+    }
+
+    // The handlers reply with the RESP3 types, received as the closest RESP2 types by the RESP2
+    // clients.
+    fn downgrade(&self, reply: Value) -> Value {
+        match self.protocol() {
+            Protocol::Resp2 => reply.into_resp2(),
+            Protocol::Resp3 => reply,
+        }
+    }
+
+    /// Checks that the command exists and accepts the number of arguments.
+    pub(super) fn check_command(&self, command: &str, args: &[Value]) -> Result<(), Value> {
+        let handler = match self.handlers.get(command) {
             Some(v) => v,
             None => {
-                return Value::err(format!(
+                return Err(Value::err(format!(
                     "unknown command `{}`, with args beginning with: {}",
                     command,
-                    args.get(0).unwrap_or(&Value::Null)
-                ))
+                    args.first().unwrap_or(&Value::Null)
+                )))
+            }
+        };
+
+        if !handler.check_arity(args.len()) {
+            return Err(Value::err(format!(
+                "wrong number of arguments for '{}' command",
+                command.to_lowercase()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the client is authenticated, and that its user is allowed to run the command.
+    /// The denials are logged in the ACL log, with the context the command runs in.
+    pub(super) fn check_acl(
+        &mut self,
+        command: &str,
+        args: &[Value],
+        context: &'static str,
+    ) -> Result<(), Value> {
+        let noauth = || Value::Err("NOAUTH".to_string(), "Authentication required.".to_string());
+        if !self.authenticated {
+            return Err(noauth());
+        }
+        let name = match &self.user {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let denial = match self.db.acl().user(name) {
+            Some(user) => match user.check(&self.handlers[command], args) {
+                Ok(()) => return Ok(()),
+                Err(denial) => denial,
+            },
+            None => {
+                // the user was deleted since the client authenticated.
+                self.authenticated = false;
+                return Err(noauth());
             }
         };
+        self.db.acl_mut().log(
+            denial.reason(),
+            context,
+            denial.object(),
+            name,
+            self.client_info(),
+        );
+        Err(Value::Err("NOPERM".to_string(), denial.message(name)))
+    }
+
+    /// Describes the client, in the ACL log.
+    pub(super) fn client_info(&self) -> String {
+        format!(
+            "addr={} name={} user={} db={}",
+            self.peer_addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_default(),
+            self.name.as_deref().unwrap_or_default(),
+            self.user.as_deref().unwrap_or_default(),
+            self.selected_db.read().unwrap().index()
+        )
+    }
+
+    /// Checks that the keys of the command are served by this node, in cluster mode. Otherwise,
+    /// the client is redirected to the node serving them. The keys are the ones found by
+    /// [`CommandSpec::keys`], which parses the number of keys of commands like LMPOP or ZUNION.
+    pub(super) fn check_cluster(&mut self, command: &str, args: &[Value]) -> Result<(), Value> {
+        let asking = std::mem::take(&mut self.asking) || command == "RESTORE-ASKING";
+        let cluster = match self.db.cluster() {
+            Some(cluster) if !self.master_link => cluster,
+            _ => return Ok(()),
+        };
+        let keys = self.handlers[command].keys(args);
+        let slot = match keys.first() {
+            Some(key) => key_hash_slot(key),
+            None => return Ok(()),
+        };
+        let transaction_slot = self.transaction.as_ref().and_then(|t| t.slot);
+        if keys.iter().any(|key| key_hash_slot(key) != slot)
+            || transaction_slot.is_some_and(|s| s != slot)
+        {
+            return Err(Value::Err(
+                "CROSSSLOT".to_string(),
+                "Keys in request don't hash to the same slot".to_string(),
+            ));
+        }
+        if let Some(transaction) = &mut self.transaction {
+            transaction.slot = Some(slot);
+        }
+
+        let redirect = |code: &str, node: &ClusterNode| {
+            Err(Value::Err(
+                code.to_string(),
+                format!("{} {}", slot, node.addr()),
+            ))
+        };
+        let unassigned = || {
+            Err(Value::Err(
+                "CLUSTERDOWN".to_string(),
+                "Hash slot not served".to_string(),
+            ))
+        };
+        let missing = || {
+            let mut db = self.selected_db.write().unwrap();
+            keys.iter()
+                .filter(|key| !db.contains_key(&Bytes::from(key.to_vec())))
+                .count()
+        };
+        // while a slot is moved, a command needing both keys that were moved and keys that were
+        // not can't be served by either node.
+        let try_again = || {
+            Err(Value::Err(
+                "TRYAGAIN".to_string(),
+                "Multiple keys request during rehashing of slot".to_string(),
+            ))
+        };
+        match cluster.route(slot) {
+            Route::Myself => Ok(()),
+            Route::Moved(owner) => redirect("MOVED", owner),
+            Route::Unassigned => unassigned(),
+            Route::Migrating(target) => match missing() {
+                0 => Ok(()),
+                n if n < keys.len() => try_again(),
+                _ => redirect("ASK", target),
+            },
+            Route::Importing if asking => match missing() {
+                n if n > 0 && n < keys.len() => try_again(),
+                _ => Ok(()),
+            },
+            Route::Importing => match cluster.owner(slot) {
+                Some(owner) => redirect("MOVED", owner),
+                None => unassigned(),
+            },
+        }
+    }
+
+    /// Runs an already checked command. The caller must hold the exec lock.
+    pub fn execute(&mut self, command: &str, args: Vec<Value>) -> Value {
+        let started = Instant::now();
+        let reply = self.run_handler(command, args);
+        // a command the client is parked on is only recorded once it replies.
+        if self.blocked.is_some() {
+            return reply;
+        }
+        let failed = matches!(reply, Value::Err(..));
+        self.db
+            .stats()
+            .record_command(command, started.elapsed(), failed);
+        reply
+    }
+
+    fn run_handler(&mut self, command: &str, args: Vec<Value>) -> Value {
+        let spec = &self.handlers[command];
+        let handler = spec.handler;
+        self.write_command = spec.flags.contains(&COMMAND_FLAG_WRITE);
+        if !self.write_command || !self.db.propagating() {
+            return handler(self, args).unwrap_or_else(Value::from);
+        }
+
+        self.db.write_lock.lock_exclusive();
+        let mut request = vec![Value::Blob(Bytes::from(command))];
+        request.extend(args.iter().cloned());
+        self.propagated = Some(vec![request]);
+        let (index, dirty) = {
+            let db = self.selected_db.read().unwrap();
+            (db.index, db.dirty)
+        };
+
+        let reply = handler(self, args).unwrap_or_else(Value::from);
+
+        // like redis, only the commands that modified the keys are propagated.
+        let commands = self.propagated.take().unwrap_or_default();
+        let modified = self.selected_db.read().unwrap().dirty != dirty;
+        if modified && !commands.is_empty() && !matches!(reply, Value::Err(..)) {
+            self.write_offset = self.db.propagate(index, &commands, self.executing);
+        }
+        self.db.write_lock.unlock_exclusive();
+        reply
+    }
+
+    /// Returns whether the running command is propagated to the AOF and to the replicas.
+    pub fn propagating(&self) -> bool {
+        self.propagated.is_some()
+    }
+
+    /// Replaces the commands propagated for the running command. The commands whose effect
+    /// depends on when they run, like the ones using the current time or picking random
+    /// elements, propagate an equivalent command instead. An empty list propagates nothing.
+    pub fn propagate(&mut self, commands: Vec<Vec<Value>>) {
+        if let Some(propagated) = &mut self.propagated {
+            *propagated = commands;
+        }
+    }
+
+    /// Replaces an argument of the propagated command, the index doesn't count the name of the
+    /// command.
+    pub fn rewrite_arg(&mut self, index: usize, value: Value) {
+        if let Some(propagated) = &mut self.propagated {
+            propagated[0][index + 1] = value;
+        }
+    }
+
+    /// Releases the locks held by the running command while the client is blocked, so that the
+    /// other clients can run their commands meanwhile.
+    pub fn suspend(&self) {
+        if self.propagating() {
+            self.db.write_lock.unlock_exclusive();
+        }
+        self.db.exec_lock().unlock_shared();
+    }
+
+    /// Takes back the locks released by [`Session::suspend`].
+    pub fn resume(&self) {
+        self.db.exec_lock().lock_shared();
+        if self.propagating() {
+            self.db.write_lock.lock_exclusive();
+        }
+    }
+
+    /// Runs `f` holding the exec lock exclusively, so that no other client runs a command
+    /// meanwhile. Like any command, the session must hold the lock shared when calling it.
+    pub fn exclusive<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        // EXEC already holds the lock exclusively.
+        if self.executing {
+            return f(self);
+        }
+        let exec_lock = self.db.exec_lock();
+        exec_lock.unlock_shared();
+        exec_lock.lock_exclusive();
+        let result = f(self);
+        exec_lock.unlock_exclusive();
+        exec_lock.lock_shared();
+        result
+    }
+
+    /// Locks the selected database for the running command.
+    pub fn lock_db(&self) -> RwLockWriteGuard<'_, InternalDb> {
+        let mut db = self.selected_db.write().unwrap();
+        db.set_write_command(self.write_command);
+        db
+    }
+
+    /// Forgets all the keys watched by WATCH.
+    pub fn unwatch(&mut self) {
+        for watched in self.watched.drain(..) {
+            watched.db.write().unwrap().unwatch(&watched.key);
+        }
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.unwatch();
+        if let Some(blocked) = self.blocked.take() {
+            let mut db = self.selected_db.write().unwrap();
+            db.unblock(&blocked.keys, &blocked.waiter);
+        }
+
+        let pubsub = self.db.pubsub();
+        for kind in [
+            ChannelKind::Channel,
+            ChannelKind::Pattern,
+            ChannelKind::Shard,
+        ] {
+            for channel in self.subscriptions.get_mut(kind).drain() {
+                pubsub.unsubscribe(kind, &channel, &self.subscriber);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::command::tests::request;
+    use crate::db::QuickList;
+
+    fn key(s: &str) -> Bytes {
+        Bytes::from(s)
+    }
+
+    #[test]
+    fn test_lazy_expire() {
+        let mut db = InternalDb::new(0, Arc::default(), Arc::default());
+        db.insert(key("a"), key("1").into());
+        db.insert(key("b"), key("2").into());
+
+        assert!(db.set_expire(&key("a"), now_millis() - 1));
+        assert!(db.set_expire(&key("b"), now_millis() + 100_000));
+        assert!(!db.set_expire(&key("c"), now_millis() + 100_000));
+
+        assert_eq!(None, db.get(&key("a")));
+        assert_eq!(Some(&key("2").into()), db.get(&key("b")));
+        assert!(db.get_expire(&key("b")).is_some());
+
+        db.insert(key("b"), key("3").into());
+        assert_eq!(None, db.get_expire(&key("b")));
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let mut db = InternalDb::new(0, Arc::default(), Arc::default());
+        for i in 0..1000 {
+            let k = key(&format!("key:{}", i));
+            db.insert(k.clone(), key("v").into());
+            if i % 2 == 0 {
+                db.set_expire(&k, now_millis() - 1);
+            }
+        }
+
+        // every sampled key is expired, so the cycle keeps going until all of them are removed.
+        db.active_expire_cycle(Duration::from_secs(10));
+        assert_eq!(500, db.len());
+        assert!(db.expires.is_empty());
+    }
+
+    #[test]
+    fn test_used_memory() {
+        let mut db = InternalDb::new(0, Arc::default(), Arc::default());
+        db.set_write_command(true);
+        db.insert(key("a"), key("1").into());
+        let used = db.used_memory();
+        assert!(used > 0);
+
+        // the values modified in place are measured again.
+        let list = db.get_or_insert_with(&key("l"), || Object::List(QuickList::default()));
+        let Object::List(list) = list else { panic!() };
+        for _ in 0..100 {
+            list.push_back(Bytes::from(vec![b'x'; 100]));
+        }
+        assert!(db.used_memory() > used + 100 * 100);
+
+        db.remove(&key("l"));
+        assert_eq!(used, db.used_memory());
+        db.clear();
+        assert_eq!(0, db.used_memory());
+    }
+
+    fn eviction_factory(policy: MaxmemoryPolicy) -> SessionFactory {
+        SessionFactory::new(Database::new(&Config {
+            maxmemory: 100 * 1024,
+            maxmemory_policy: policy,
+            ..Config::default()
+        }))
+    }
+
+    #[test]
+    fn test_eviction() {
+        let value = "x".repeat(1024);
+        for policy in [
+            MaxmemoryPolicy::AllKeysLru,
+            MaxmemoryPolicy::AllKeysLfu,
+            MaxmemoryPolicy::AllKeysRandom,
+        ] {
+            let factory = eviction_factory(policy);
+            let mut session = factory.create_session();
+            for i in 0..500 {
+                let reply = session.handle_request(request(&["SET", &i.to_string(), &value]));
+                assert_eq!(Value::Simple("OK".into()), reply);
+            }
+            let db = factory.database();
+            assert!(db.used_memory() <= 100 * 1024 + 2 * 1024);
+            let evicted = db.stats().evicted_keys.load(Ordering::Relaxed);
+            assert!(evicted > 400);
+            assert_eq!(500 - evicted as usize, db.dbs[0].read().unwrap().len());
+        }
+
+        // only the keys with an expiry time are evicted by the volatile policies.
+        for policy in [
+            MaxmemoryPolicy::VolatileLru,
+            MaxmemoryPolicy::VolatileLfu,
+            MaxmemoryPolicy::VolatileRandom,
+            MaxmemoryPolicy::VolatileTtl,
+        ] {
+            let factory = eviction_factory(policy);
+            let mut session = factory.create_session();
+            for i in 0..50 {
+                session.handle_request(request(&["SET", &format!("persistent:{}", i), &value]));
+            }
+            for i in 0..200 {
+                let key = format!("volatile:{}", i);
+                session.handle_request(request(&["SET", &key, &value, "EX", "1000"]));
+            }
+            for i in 0..50 {
+                let reply =
+                    session.handle_request(request(&["EXISTS", &format!("persistent:{}", i)]));
+                assert_eq!(Value::Number(1), reply);
+            }
+            assert!(factory.database().used_memory() <= 100 * 1024 + 2 * 1024);
+
+            // once there is nothing left to evict, the commands using memory are refused.
+            for i in 0..100 {
+                session.handle_request(request(&["SET", &format!("more:{}", i), &value]));
+            }
+            assert!(matches!(
+                session.handle_request(request(&["SET", "last", &value])),
+                Value::Err(code, _) if code == "OOM"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_noeviction() {
+        let factory = eviction_factory(MaxmemoryPolicy::NoEviction);
+        let mut session = factory.create_session();
+        let value = "x".repeat(1024);
+        let oom = Value::Err(
+            "OOM".into(),
+            "command not allowed when used memory > 'maxmemory'.".into(),
+        );
+        let mut replies =
+            (0..200).map(|i| session.handle_request(request(&["SET", &i.to_string(), &value])));
+        assert!(replies.any(|reply| reply == oom));
+        assert_eq!(
+            0,
+            factory
+                .database()
+                .stats()
+                .evicted_keys
+                .load(Ordering::Relaxed)
+        );
+
+        // the commands that don't use memory still run.
+        assert_eq!(
+            Value::Blob(value.as_str().into()),
+            session.handle_request(request(&["GET", "0"]))
+        );
+
+        // a refused command aborts the transaction.
+        session.handle_request(request(&["MULTI"]));
+        assert_eq!(
+            oom,
+            session.handle_request(request(&["RPUSH", "l", &value]))
+        );
+        assert!(matches!(
+            session.handle_request(request(&["EXEC"])),
+            Value::Err(code, _) if code == "EXECABORT"
+        ));
 
-        (handler.handler)(self, args).unwrap_or_else(|s| Value::err(s))
+        assert_eq!(
+            oom,
+            session.handle_request(request(&[
+                "EVAL",
+                "return redis.call('SET', 'a', 'b')",
+                "0"
+            ]))
+        );
+        assert_eq!(
+            Value::Number(1),
+            session.handle_request(request(&["DEL", "0"]))
+        );
     }
 }
@@ -0,0 +1,220 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+use rand::Rng;
+
+/// A hash map that also keeps its entries in a dense vector, so that a random entry can be
+/// picked in O(1) and the whole map can be scanned incrementally with a cursor.
+///
+/// Removal swaps the last entry into the removed slot. Scanning walks the vector from the end
+/// towards the start, so an entry that exists for the whole duration of a scan is always
+/// returned at least once, even if other entries are inserted or removed in between.
+#[derive(Debug, Clone)]
+pub struct Dict<K, V> {
+    entries: Vec<(K, V)>,
+    index: HashMap<K, usize>,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<K, V> Dict<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = *self.index.get(key)?;
+        Some(&self.entries[i].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = *self.index.get(key)?;
+        Some(&mut self.entries[i].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.index.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(&i) = self.index.get(&key) {
+            return Some(std::mem::replace(&mut self.entries[i].1, value));
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.swap_remove(i);
+        if let Some((moved, _)) = self.entries.get(i) {
+            if let Some(index) = self.index.get_mut::<K>(moved) {
+                *index = i;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        let (k, v) = self.entries.get(index)?;
+        Some((k, v))
+    }
+
+    pub fn random_entry(&self) -> Option<(&K, &V)> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let (k, v) = &self.entries[rand::thread_rng().gen_range(0..self.entries.len())];
+        Some((k, v))
+    }
+
+    /// Returns up to `count` entries starting at `cursor` together with the cursor for the next
+    /// call. A cursor of 0 starts a new scan, and a returned cursor of 0 means the scan is done.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&K, &V)>) {
+        let mut pos = if cursor == 0 {
+            self.entries.len()
+        } else {
+            (cursor as usize).min(self.entries.len())
+        };
+
+        let mut result = Vec::with_capacity(count.min(pos));
+        while pos > 0 && result.len() < count.max(1) {
+            pos -= 1;
+            let (k, v) = &self.entries[pos];
+            result.push((k, v));
+        }
+
+        (pos as u64, result)
+    }
+}
+
+impl<K, V> PartialEq for Dict<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K, V> Eq for Dict<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Eq,
+{
+}
+
+impl<K, V> FromIterator<(K, V)> for Dict<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut dict = Self::new();
+        for (k, v) in iter {
+            dict.insert(k, v);
+        }
+        dict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_insert_remove() {
+        let mut dict = Dict::new();
+        for i in 0..10 {
+            assert_eq!(None, dict.insert(i, i * 10));
+        }
+        assert_eq!(Some(30), dict.insert(3, 33));
+        assert_eq!(10, dict.len());
+
+        assert_eq!(Some(0), dict.remove(&0));
+        assert_eq!(Some(33), dict.remove(&3));
+        assert_eq!(None, dict.remove(&3));
+        assert_eq!(8, dict.len());
+
+        for i in [1, 2, 4, 5, 6, 7, 8, 9] {
+            assert_eq!(Some(&(i * 10)), dict.get(&i));
+        }
+        assert!(!dict.contains_key(&0));
+    }
+
+    #[test]
+    fn test_scan_survives_removal() {
+        let mut dict: Dict<i32, ()> = (0..100).map(|i| (i, ())).collect();
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, entries) = dict.scan(cursor, 7);
+            seen.extend(entries.into_iter().map(|(k, _)| *k));
+            // remove some entries and add others in between iterations.
+            dict.remove(&(next as i32 / 2));
+            dict.insert(1000 + next as i32, ());
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        for i in 0..100 {
+            if dict.contains_key(&i) {
+                assert!(seen.contains(&i), "key {} is not returned by scan", i);
+            }
+        }
+    }
+}
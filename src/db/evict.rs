@@ -0,0 +1,67 @@
+//! The approximations used to pick the keys evicted once `maxmemory` is reached. Like redis, the
+//! keys are not kept in LRU or LFU order: a few random keys are sampled instead, and the best
+//! candidates seen so far are kept in a pool across the evictions.
+
+use crate::value::Bytes;
+
+// The number of candidates kept in the pool.
+const EVICTION_POOL_SIZE: usize = 16;
+
+/// The access counter of a new key, so that it has a chance to be accessed again before being
+/// evicted by the LFU policies.
+pub const LFU_INIT_VAL: u8 = 5;
+// The higher the factor, the more accesses are needed to increment the counter. With 10, the
+// counter saturates after about a million accesses.
+const LFU_LOG_FACTOR: f64 = 10.0;
+// The counter is decremented every this many minutes without access.
+const LFU_DECAY_TIME: u64 = 1;
+
+/// Increments the logarithmic access counter: the higher the counter, the less likely it is to
+/// be incremented.
+pub fn lfu_increment(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+    if rand::random::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// Decays the access counter of a key last accessed at `access_time`, both times being unix
+/// times in milliseconds.
+pub fn lfu_decay(counter: u8, access_time: u64, now: u64) -> u8 {
+    let periods = now.saturating_sub(access_time) / 60_000 / LFU_DECAY_TIME;
+    counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+}
+
+/// The best keys to evict seen so far, with the index of their database.
+#[derive(Default)]
+pub struct EvictionPool {
+    /// Sorted by score, from the best candidate to the worst.
+    candidates: Vec<(u64, usize, Bytes)>,
+}
+
+impl EvictionPool {
+    /// Adds a sampled key, the higher the score the better the key is to evict.
+    pub fn insert(&mut self, score: u64, db: usize, key: Bytes) {
+        self.candidates.retain(|(_, d, k)| *d != db || *k != key);
+        let position = self.candidates.partition_point(|(s, _, _)| *s >= score);
+        if position < EVICTION_POOL_SIZE {
+            self.candidates.insert(position, (score, db, key));
+            self.candidates.truncate(EVICTION_POOL_SIZE);
+        }
+    }
+
+    /// Takes the best candidate out of the pool.
+    pub fn pop(&mut self) -> Option<(usize, Bytes)> {
+        if self.candidates.is_empty() {
+            return None;
+        }
+        let (_, db, key) = self.candidates.remove(0);
+        Some((db, key))
+    }
+}
@@ -0,0 +1,238 @@
+//! The listpack serialization format used by redis to store small collections, as found inside
+//! RDB files.
+
+use crate::value::Bytes;
+
+/// An element of a listpack. Strings that look like integers are stored as integers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListpackValue {
+    Int(i64),
+    Str(Bytes),
+}
+
+impl ListpackValue {
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            Self::Int(n) => Bytes::from(&n.to_string()),
+            Self::Str(s) => s,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(n) => Some(*n),
+            Self::Str(s) => std::str::from_utf8(s).ok()?.parse().ok(),
+        }
+    }
+}
+
+const HEADER_SIZE: usize = 6;
+const EOF: u8 = 0xff;
+
+/// Builds a listpack by appending elements to it.
+pub struct ListpackWriter {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl ListpackWriter {
+    pub fn new() -> Self {
+        Self {
+            buf: vec![0; HEADER_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn push_int(&mut self, value: i64) {
+        let start = self.buf.len();
+        match value {
+            0..=127 => self.buf.push(value as u8),
+            -4096..=4095 => {
+                let value = (value as u64) & 0x1fff;
+                self.buf.push(0xc0 | (value >> 8) as u8);
+                self.buf.push(value as u8);
+            }
+            _ if i16::try_from(value).is_ok() => {
+                self.buf.push(0xf1);
+                self.buf.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -8_388_608..=8_388_607 => {
+                self.buf.push(0xf2);
+                self.buf
+                    .extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(value).is_ok() => {
+                self.buf.push(0xf3);
+                self.buf.extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                self.buf.push(0xf4);
+                self.buf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.finish_entry(start);
+    }
+
+    pub fn push_str(&mut self, value: &[u8]) {
+        let start = self.buf.len();
+        let len = value.len();
+        if len < 64 {
+            self.buf.push(0x80 | len as u8);
+        } else if len < 4096 {
+            self.buf.push(0xe0 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else {
+            self.buf.push(0xf0);
+            self.buf.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.buf.extend_from_slice(value);
+        self.finish_entry(start);
+    }
+
+    /// Appends the length of the entry, which allows walking the listpack backwards.
+    fn finish_entry(&mut self, start: usize) {
+        let len = self.buf.len() - start;
+        // the most significant group of 7 bits comes first, all but the first have the high bit set.
+        let size = backlen_size(len);
+        for i in 0..size {
+            let group = ((len >> (7 * (size - 1 - i))) & 127) as u8;
+            self.buf.push(if i == 0 { group } else { group | 128 });
+        }
+        self.len += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.push(EOF);
+        let total = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&total.to_le_bytes());
+        let len = self.len.min(u16::MAX as usize) as u16;
+        self.buf[4..6].copy_from_slice(&len.to_le_bytes());
+        self.buf
+    }
+}
+
+/// Returns the number of bytes used to encode the length of an entry of `len` bytes.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Decodes all the elements of a listpack. Returns `None` if the listpack is malformed.
+pub fn decode(buf: &[u8]) -> Option<Vec<ListpackValue>> {
+    let total = u32::from_le_bytes(buf.get(0..4)?.try_into().ok()?) as usize;
+    if total != buf.len() || buf.last() != Some(&EOF) {
+        return None;
+    }
+
+    let mut values = vec![];
+    let mut pos = HEADER_SIZE;
+    loop {
+        let start = pos;
+        let byte = *buf.get(pos)?;
+        let take = |pos: usize, n: usize| buf.get(pos..pos + n);
+        let value = match byte {
+            EOF => break,
+            0x00..=0x7f => {
+                pos += 1;
+                ListpackValue::Int(byte as i64)
+            }
+            0x80..=0xbf => {
+                let len = (byte & 0x3f) as usize;
+                let value = take(pos + 1, len)?;
+                pos += 1 + len;
+                ListpackValue::Str(Bytes::from(value.to_vec()))
+            }
+            0xc0..=0xdf => {
+                let value = (((byte & 0x1f) as i64) << 8) | *buf.get(pos + 1)? as i64;
+                pos += 2;
+                ListpackValue::Int(if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                })
+            }
+            0xe0..=0xef => {
+                let len = (((byte & 0x0f) as usize) << 8) | *buf.get(pos + 1)? as usize;
+                let value = take(pos + 2, len)?;
+                pos += 2 + len;
+                ListpackValue::Str(Bytes::from(value.to_vec()))
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(take(pos + 1, 4)?.try_into().ok()?) as usize;
+                let value = take(pos + 5, len)?;
+                pos += 5 + len;
+                ListpackValue::Str(Bytes::from(value.to_vec()))
+            }
+            0xf1 => {
+                let value = i16::from_le_bytes(take(pos + 1, 2)?.try_into().ok()?);
+                pos += 3;
+                ListpackValue::Int(value as i64)
+            }
+            0xf2 => {
+                let bytes = take(pos + 1, 3)?;
+                // sign extend the 24 bits integer.
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                pos += 4;
+                ListpackValue::Int(value as i64)
+            }
+            0xf3 => {
+                let value = i32::from_le_bytes(take(pos + 1, 4)?.try_into().ok()?);
+                pos += 5;
+                ListpackValue::Int(value as i64)
+            }
+            0xf4 => {
+                let value = i64::from_le_bytes(take(pos + 1, 8)?.try_into().ok()?);
+                pos += 9;
+                ListpackValue::Int(value)
+            }
+            _ => return None,
+        };
+        pos += backlen_size(pos - start);
+        values.push(value);
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let ints = [
+            0, 127, 128, -1, -4096, 4095, 4096, -32768, 100_000, -8_388_608,
+        ];
+        let big = [i32::MAX as i64, i64::MIN, i64::MAX];
+        let strings = [vec![], vec![b'a'; 63], vec![b'b'; 64], vec![b'c'; 5000]];
+
+        let mut writer = ListpackWriter::new();
+        for value in ints.iter().chain(big.iter()) {
+            writer.push_int(*value);
+        }
+        for value in strings.iter() {
+            writer.push_str(value);
+        }
+
+        let mut expected: Vec<ListpackValue> = ints
+            .iter()
+            .chain(big.iter())
+            .map(|v| ListpackValue::Int(*v))
+            .collect();
+        expected.extend(strings.into_iter().map(|s| ListpackValue::Str(s.into())));
+        assert_eq!(Some(expected), decode(&writer.finish()));
+    }
+
+    #[test]
+    fn test_backlen() {
+        // a 200 bytes entry is followed by its length in two bytes, the lowest 7 bits last.
+        let mut writer = ListpackWriter::new();
+        writer.push_str(&[b'x'; 198]);
+        let buf = writer.finish();
+        assert_eq!(&[1, 200 & 127 | 128, EOF], &buf[buf.len() - 3..]);
+    }
+}
@@ -1,4 +1,39 @@
+mod acl;
+mod aof;
+mod blocking;
+mod cluster;
 mod command;
+#[allow(clippy::module_inception)]
 mod db;
+mod dict;
+mod evict;
+mod listpack;
+mod multi;
+mod object;
+mod pubsub;
+mod quicklist;
+mod rdb;
+mod replication;
+mod script;
+mod set;
+mod skiplist;
+mod slowlog;
+mod sorted_set;
+mod stats;
+mod stream;
 
-pub use db::{Database, Session, SessionFactory};
+pub use blocking::AbortHandle;
+pub use cluster::{key_hash_slot, ClusterNode, CLUSTER_SLOTS};
+pub use db::{now_millis, Database, InternalDb, Session, SessionFactory};
+pub use dict::Dict;
+pub use multi::{ExecLock, QueuedCommand, Transaction, WatchedKey};
+pub use object::Object;
+pub use pubsub::{ChannelKind, PubSub, Subscriber, Subscriptions};
+pub use quicklist::QuickList;
+pub use replication::{LinkState, ReplicaSync};
+pub use set::Set;
+pub use sorted_set::{LexBound, LexRange, ScoreRange, SortedSet};
+pub use stats::{CommandStats, Stats};
+pub use stream::{
+    ClaimOptions, ClaimResult, ConsumerGroup, Stream, StreamFields, StreamId, TrimStrategy,
+};
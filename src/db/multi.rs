@@ -0,0 +1,113 @@
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+
+use crate::value::{Bytes, Value};
+
+use super::InternalDb;
+
+/// A command queued between MULTI and EXEC.
+#[derive(Debug)]
+pub struct QueuedCommand {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+/// The state of a session between MULTI and EXEC.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<QueuedCommand>,
+    /// Set when a command failed to be queued, EXEC then discards the transaction.
+    pub aborted: bool,
+    /// In cluster mode, the hash slot of the keys of the queued commands, which must all be in
+    /// the same slot.
+    pub slot: Option<u16>,
+}
+
+/// A key watched by WATCH, along with its version at the time it was watched.
+pub struct WatchedKey {
+    pub db: Arc<RwLock<InternalDb>>,
+    pub key: Bytes,
+    pub version: u64,
+}
+
+/// Makes transactions atomic: every command runs holding the lock shared, while EXEC holds it
+/// exclusively for the whole transaction.
+///
+/// Unlike `RwLock`, it's not tied to a guard, so that a client can release its shared hold while
+/// it's blocked by a blocking command and take it back once it's woken up. Writers are preferred,
+/// so EXEC isn't starved by a steady stream of commands.
+#[derive(Debug, Default)]
+pub struct ExecLock {
+    state: Mutex<ExecLockState>,
+    cond: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ExecLockState {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+}
+
+impl ExecLock {
+    pub fn lock_shared(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.writer || state.waiting_writers > 0 {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.readers += 1;
+    }
+
+    pub fn unlock_shared(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.cond.notify_all();
+        }
+    }
+
+    pub fn lock_exclusive(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.waiting_writers += 1;
+        while state.writer || state.readers > 0 {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.waiting_writers -= 1;
+        state.writer = true;
+    }
+
+    pub fn unlock_exclusive(&self) {
+        self.state.lock().unwrap().writer = false;
+        self.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exec_lock() {
+        let lock = ExecLock::default();
+        let exclusive = AtomicBool::new(false);
+
+        lock.lock_shared();
+        lock.lock_shared();
+        thread::scope(|s| {
+            s.spawn(|| {
+                lock.lock_exclusive();
+                exclusive.store(true, Ordering::SeqCst);
+                lock.unlock_exclusive();
+            });
+
+            thread::sleep(Duration::from_millis(20));
+            lock.unlock_shared();
+            thread::sleep(Duration::from_millis(20));
+            assert!(!exclusive.load(Ordering::SeqCst));
+            lock.unlock_shared();
+        });
+        assert!(exclusive.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::value::Bytes;
+
+use super::dict::Dict;
+use super::quicklist::QuickList;
+use super::set::Set;
+use super::sorted_set::SortedSet;
+use super::stream::{Stream, StreamId};
+
+// The memory used by a value is estimated like MEMORY USAGE does: only a few elements of the
+// collections are measured, and the size of the others is extrapolated from them.
+const MEMORY_USAGE_SAMPLES: usize = 5;
+// Rough overheads, in bytes, of an allocated value and of an element of a collection.
+const OBJECT_OVERHEAD: usize = 16;
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// A value stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    String(Bytes),
+    List(QuickList),
+    Hash(Dict<Bytes, Bytes>),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Object {
+    /// Returns the name of the type as reported by the TYPE command.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// Returns an estimation of the memory used by the value, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let elements = match self {
+            Self::String(value) => return OBJECT_OVERHEAD + value.len(),
+            Self::List(list) => sampled_size(list.len(), list.iter().map(|e| e.len())),
+            Self::Hash(hash) => {
+                sampled_size(hash.len(), hash.iter().map(|(f, v)| f.len() + v.len()))
+            }
+            Self::Set(set) => sampled_size(set.len(), set.iter().map(|e| e.len())),
+            Self::SortedSet(zset) => {
+                sampled_size(zset.len(), zset.iter().map(|(e, _)| e.len() + 8))
+            }
+            Self::Stream(stream) => sampled_size(
+                stream.len(),
+                stream
+                    .range(StreamId::MIN, StreamId::MAX)
+                    .map(|(_, fields)| {
+                        16 + fields
+                            .iter()
+                            .map(|(f, v)| f.len() + v.len() + ELEMENT_OVERHEAD)
+                            .sum::<usize>()
+                    }),
+            ),
+        };
+        OBJECT_OVERHEAD + elements
+    }
+}
+
+/// Extrapolates the size of the `len` elements of a collection from the size of its first ones.
+fn sampled_size(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (sampled, total) = sizes
+        .take(MEMORY_USAGE_SAMPLES)
+        .fold((0, 0), |(n, total), size| {
+            (n + 1, total + size + ELEMENT_OVERHEAD)
+        });
+    if sampled == 0 {
+        return 0;
+    }
+    total * len / sampled
+}
+
+impl From<Bytes> for Object {
+    fn from(value: Bytes) -> Self {
+        Self::String(value)
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, OnceLock, RwLock,
+    },
+};
+
+use crate::glob::glob_match;
+use crate::value::{Bytes, Protocol, Value, ValueWrite};
+
+/// The kinds of subscriptions a client can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// A channel, subscribed with SUBSCRIBE.
+    Channel,
+    /// A glob-style pattern matching channels, subscribed with PSUBSCRIBE.
+    Pattern,
+    /// A shard channel, subscribed with SSUBSCRIBE. Without cluster mode, they only differ from
+    /// regular channels in being a separate namespace.
+    Shard,
+}
+
+/// Called with the number of bytes sent to the output of a client, for its connection to write
+/// them.
+struct Notify(Box<dyn Fn(usize) + Send + Sync>);
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Notify")
+    }
+}
+
+/// The sending half of a client's output. Anything sent through it is written to the client's
+/// connection, in order, by the server.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    id: u64,
+    sender: Sender<Vec<u8>>,
+    /// Whether the client negotiated RESP3 with HELLO, shared by the clones of the subscriber.
+    resp3: Arc<AtomicBool>,
+    /// Set by the server for the clients it serves, shared by the clones of the subscriber.
+    notify: Arc<OnceLock<Notify>>,
+}
+
+impl Subscriber {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        if self.resp3.load(Ordering::Relaxed) {
+            Protocol::Resp3
+        } else {
+            Protocol::Resp2
+        }
+    }
+
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.resp3
+            .store(protocol == Protocol::Resp3, Ordering::Relaxed);
+    }
+
+    /// Sends a value to the client. Returns false if the connection is already gone.
+    ///
+    /// The value is encoded right away, with the protocol the client speaks at that time, so
+    /// that switching protocol with HELLO only affects the values sent after it.
+    pub fn send(&self, value: Value) -> bool {
+        let mut buff = vec![];
+        // writing to a vector never fails.
+        buff.write_value_as(&value, self.protocol()).unwrap();
+        let len = buff.len();
+        if self.sender.send(buff).is_err() {
+            return false;
+        }
+        if let Some(notify) = self.notify.get() {
+            (notify.0)(len);
+        }
+        true
+    }
+
+    /// Sets the function called after anything is sent, for the clients whose connection waits
+    /// for their output instead of blocking on it. It can only be set once.
+    pub fn set_notify(&self, notify: impl Fn(usize) + Send + Sync + 'static) {
+        let _ = self.notify.set(Notify(Box::new(notify)));
+    }
+}
+
+type Subscribers = RwLock<HashMap<Bytes, HashMap<u64, Subscriber>>>;
+
+/// The server-wide registry of the subscribed channels.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    next_id: AtomicU64,
+    channels: Subscribers,
+    patterns: Subscribers,
+    shard_channels: Subscribers,
+}
+
+impl PubSub {
+    /// Creates the output of a new client, returning the receiving half for its connection.
+    pub fn new_subscriber(&self) -> (Subscriber, Receiver<Vec<u8>>) {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let subscriber = Subscriber {
+            id,
+            sender,
+            resp3: Arc::default(),
+            notify: Arc::default(),
+        };
+        (subscriber, receiver)
+    }
+
+    fn subscribers(&self, kind: ChannelKind) -> &Subscribers {
+        match kind {
+            ChannelKind::Channel => &self.channels,
+            ChannelKind::Pattern => &self.patterns,
+            ChannelKind::Shard => &self.shard_channels,
+        }
+    }
+
+    pub fn subscribe(&self, kind: ChannelKind, channel: &Bytes, subscriber: &Subscriber) {
+        self.subscribers(kind)
+            .write()
+            .unwrap()
+            .entry(channel.clone())
+            .or_default()
+            .insert(subscriber.id, subscriber.clone());
+    }
+
+    pub fn unsubscribe(&self, kind: ChannelKind, channel: &Bytes, subscriber: &Subscriber) {
+        let mut subscribers = self.subscribers(kind).write().unwrap();
+        if let Some(channel_subscribers) = subscribers.get_mut(channel) {
+            channel_subscribers.remove(&subscriber.id);
+            if channel_subscribers.is_empty() {
+                subscribers.remove(channel);
+            }
+        }
+    }
+
+    /// Sends the message to the clients subscribed to the channel, and to the ones subscribed to
+    /// a pattern matching it. Returns the number of clients that received it.
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let mut received = self.send(ChannelKind::Channel, channel, message);
+        for (pattern, subscribers) in self.patterns.read().unwrap().iter() {
+            if !glob_match(pattern, channel, false) {
+                continue;
+            }
+            let value = Value::Push(vec![
+                Value::Blob("pmessage".into()),
+                Value::Blob(pattern.clone()),
+                Value::Blob(channel.clone()),
+                Value::Blob(message.clone()),
+            ]);
+            for subscriber in subscribers.values() {
+                subscriber.send(value.clone());
+                received += 1;
+            }
+        }
+        received
+    }
+
+    /// Sends the message to the clients subscribed to the shard channel. Returns the number of
+    /// clients that received it.
+    pub fn spublish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        self.send(ChannelKind::Shard, channel, message)
+    }
+
+    fn send(&self, kind: ChannelKind, channel: &Bytes, message: &Bytes) -> usize {
+        let subscribers = self.subscribers(kind).read().unwrap();
+        let subscribers = match subscribers.get(channel) {
+            Some(subscribers) => subscribers,
+            None => return 0,
+        };
+        let kind = match kind {
+            ChannelKind::Shard => "smessage",
+            _ => "message",
+        };
+        let value = Value::Push(vec![
+            Value::Blob(kind.into()),
+            Value::Blob(channel.clone()),
+            Value::Blob(message.clone()),
+        ]);
+        for subscriber in subscribers.values() {
+            subscriber.send(value.clone());
+        }
+        subscribers.len()
+    }
+
+    /// Returns the channels (or patterns) with at least one subscriber, optionally filtered by a
+    /// glob-style pattern.
+    pub fn channels(&self, kind: ChannelKind, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        self.subscribers(kind)
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel, false)))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the number of subscribers of the channel.
+    pub fn numsub(&self, kind: ChannelKind, channel: &Bytes) -> usize {
+        self.subscribers(kind)
+            .read()
+            .unwrap()
+            .get(channel)
+            .map(|subscribers| subscribers.len())
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of patterns with at least one subscriber.
+    pub fn numpat(&self) -> usize {
+        self.patterns.read().unwrap().len()
+    }
+}
+
+/// The subscriptions of a single client.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    pub channels: HashSet<Bytes>,
+    pub patterns: HashSet<Bytes>,
+    pub shard_channels: HashSet<Bytes>,
+}
+
+impl Subscriptions {
+    pub fn get_mut(&mut self, kind: ChannelKind) -> &mut HashSet<Bytes> {
+        match kind {
+            ChannelKind::Channel => &mut self.channels,
+            ChannelKind::Pattern => &mut self.patterns,
+            ChannelKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Returns the number of subscriptions, as reported in the (un)subscribe replies. Shard
+    /// channels are counted separately from the others.
+    pub fn count(&self, kind: ChannelKind) -> usize {
+        match kind {
+            ChannelKind::Shard => self.shard_channels.len(),
+            _ => self.channels.len() + self.patterns.len(),
+        }
+    }
+
+    /// Returns true if the client has any subscription, in which case only a few commands are
+    /// allowed.
+    pub fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::value::ValueRead;
+
+    pub fn decode(message: Vec<u8>) -> Value {
+        message.as_slice().read_value().unwrap()
+    }
+
+    #[test]
+    fn test_publish() {
+        let pubsub = PubSub::default();
+        let (first, first_messages) = pubsub.new_subscriber();
+        let (second, second_messages) = pubsub.new_subscriber();
+        let news = Bytes::from("news.tech");
+
+        pubsub.subscribe(ChannelKind::Channel, &news, &first);
+        pubsub.subscribe(ChannelKind::Pattern, &Bytes::from("news.*"), &second);
+        pubsub.subscribe(ChannelKind::Shard, &news, &second);
+        assert_eq!(2, pubsub.publish(&news, &Bytes::from("hello")));
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("message".into()),
+                Value::Blob("news.tech".into()),
+                Value::Blob("hello".into()),
+            ]),
+            decode(first_messages.try_recv().unwrap())
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("pmessage".into()),
+                Value::Blob("news.*".into()),
+                Value::Blob("news.tech".into()),
+                Value::Blob("hello".into()),
+            ]),
+            decode(second_messages.try_recv().unwrap())
+        );
+        assert!(second_messages.try_recv().is_err());
+
+        assert_eq!(1, pubsub.spublish(&news, &Bytes::from("shard")));
+        pubsub.unsubscribe(ChannelKind::Channel, &news, &first);
+        assert_eq!(1, pubsub.publish(&news, &Bytes::from("again")));
+        assert!(pubsub.channels(ChannelKind::Channel, None).is_empty());
+        assert_eq!(1, pubsub.numpat());
+        assert_eq!(1, pubsub.numsub(ChannelKind::Shard, &news));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+
+use crate::value::Bytes;
+
+// Maximum number of elements in a single node. Small enough that inserting in the middle of a
+// node is cheap, and big enough that the per-node overhead stays small.
+const QUICKLIST_NODE_SIZE: usize = 128;
+
+/// A deque made of a list of small chunks, similar to redis' quicklist. Pushing and popping at
+/// both ends is O(1), and inserting or removing in the middle only needs to shift the elements
+/// of a single chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuickList {
+    nodes: VecDeque<VecDeque<Bytes>>,
+    len: usize,
+}
+
+impl QuickList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, value: Bytes) {
+        match self.nodes.front_mut() {
+            Some(node) if node.len() < QUICKLIST_NODE_SIZE => node.push_front(value),
+            _ => self.nodes.push_front(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: Bytes) {
+        match self.nodes.back_mut() {
+            Some(node) if node.len() < QUICKLIST_NODE_SIZE => node.push_back(value),
+            _ => self.nodes.push_back(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub fn front(&self) -> Option<&Bytes> {
+        self.nodes.front()?.front()
+    }
+
+    pub fn back(&self) -> Option<&Bytes> {
+        self.nodes.back()?.back()
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        let node = self.nodes.front_mut()?;
+        let value = node.pop_front();
+        if node.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        value
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let node = self.nodes.back_mut()?;
+        let value = node.pop_back();
+        if node.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        value
+    }
+
+    /// Returns the node containing the element at `index` and the position of the element
+    /// inside that node. The nodes are walked from whichever end is closer.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+
+        if index < self.len / 2 {
+            let mut index = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if index < node.len() {
+                    return Some((i, index));
+                }
+                index -= node.len();
+            }
+        } else {
+            let mut index = self.len - index - 1;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if index < node.len() {
+                    return Some((i, node.len() - index - 1));
+                }
+                index -= node.len();
+            }
+        }
+        unreachable!("quicklist length doesn't match its nodes")
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Bytes> {
+        let (node, i) = self.locate(index)?;
+        self.nodes[node].get(i)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Bytes> {
+        let (node, i) = self.locate(index)?;
+        self.nodes[node].get_mut(i)
+    }
+
+    /// Inserts the value so that it ends up at `index`. Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: Bytes) {
+        assert!(index <= self.len, "quicklist index out of bounds");
+        if index == 0 {
+            return self.push_front(value);
+        }
+        if index == self.len {
+            return self.push_back(value);
+        }
+
+        let (node, i) = self.locate(index).unwrap();
+        self.nodes[node].insert(i, value);
+        self.len += 1;
+
+        if self.nodes[node].len() > QUICKLIST_NODE_SIZE {
+            let half = self.nodes[node].len() / 2;
+            let tail = self.nodes[node].split_off(half);
+            self.nodes.insert(node + 1, tail);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Bytes> {
+        let (node, i) = self.locate(index)?;
+        let value = self.nodes[node].remove(i);
+        if self.nodes[node].is_empty() {
+            self.nodes.remove(node);
+        }
+        self.len -= 1;
+        value
+    }
+
+    /// Keeps only the elements in the inclusive range `[start, end]`.
+    pub fn trim(&mut self, start: usize, end: usize) {
+        if start > end || start >= self.len {
+            self.nodes.clear();
+            self.len = 0;
+            return;
+        }
+
+        let remove_tail = self.len - end.min(self.len - 1) - 1;
+        for _ in 0..remove_tail {
+            self.pop_back();
+        }
+        for _ in 0..start {
+            self.pop_front();
+        }
+    }
+
+    /// Keeps only the elements for which `f` returns true, in order.
+    pub fn retain(&mut self, mut f: impl FnMut(&Bytes) -> bool) {
+        for node in self.nodes.iter_mut() {
+            let before = node.len();
+            node.retain(&mut f);
+            self.len -= before - node.len();
+        }
+        self.nodes.retain(|node| !node.is_empty());
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Bytes> {
+        self.nodes.iter().flat_map(|node| node.iter())
+    }
+}
+
+impl FromIterator<Bytes> for QuickList {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let mut list = Self::new();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_vec(list: &QuickList) -> Vec<i64> {
+        list.iter()
+            .map(|v| std::str::from_utf8(v).unwrap().parse().unwrap())
+            .collect()
+    }
+
+    fn b(i: i64) -> Bytes {
+        Bytes::from(&i.to_string())
+    }
+
+    #[test]
+    fn test_quicklist_matches_vec() {
+        let mut list = QuickList::new();
+        let mut expected: Vec<i64> = Vec::new();
+
+        for i in 0..1000 {
+            match i % 5 {
+                0 | 1 => {
+                    list.push_back(b(i));
+                    expected.push(i);
+                }
+                2 => {
+                    list.push_front(b(i));
+                    expected.insert(0, i);
+                }
+                3 => {
+                    let index = (i as usize * 7) % (expected.len() + 1);
+                    list.insert(index, b(i));
+                    expected.insert(index, i);
+                }
+                _ => {
+                    let index = (i as usize * 13) % expected.len();
+                    assert_eq!(Some(b(expected.remove(index))), list.remove(index));
+                }
+            }
+        }
+
+        assert_eq!(expected.len(), list.len());
+        assert_eq!(expected, to_vec(&list));
+        for (i, v) in expected.iter().enumerate() {
+            assert_eq!(Some(&b(*v)), list.get(i));
+        }
+
+        list.retain(|v| v.last() != Some(&b'3'));
+        expected.retain(|v| v % 10 != 3);
+        assert_eq!(expected, to_vec(&list));
+
+        list.trim(10, 100);
+        assert_eq!(expected[10..=100].to_vec(), to_vec(&list));
+        assert_eq!(91, list.len());
+    }
+}
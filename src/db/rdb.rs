@@ -0,0 +1,1069 @@
+//! Snapshots of the databases in the RDB file format of redis, so that the files can be read by
+//! redis and its tooling, and the files written by redis can be loaded.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
+
+use crate::error::{Error, Result};
+use crate::value::Bytes;
+
+use super::db::now_millis;
+use super::dict::Dict;
+use super::listpack::{self, ListpackValue, ListpackWriter};
+use super::object::Object;
+use super::quicklist::QuickList;
+use super::sorted_set::SortedSet;
+use super::stream::{Consumer, PendingEntry, Stream, StreamFields, StreamId};
+
+/// The version of the files written. Files up to `RDB_MAX_VERSION` can be loaded, as long as
+/// they only contain the types listed below.
+const RDB_VERSION: u16 = 11;
+const RDB_MAX_VERSION: u16 = 12;
+const REDIS_VERSION: &str = "7.2.0";
+
+const RDB_OPCODE_SLOT_INFO: u8 = 244;
+const RDB_OPCODE_FUNCTION2: u8 = 245;
+const RDB_OPCODE_MODULE_AUX: u8 = 247;
+const RDB_OPCODE_IDLE: u8 = 248;
+const RDB_OPCODE_FREQ: u8 = 249;
+const RDB_OPCODE_AUX: u8 = 250;
+const RDB_OPCODE_RESIZEDB: u8 = 251;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 252;
+const RDB_OPCODE_EXPIRETIME: u8 = 253;
+const RDB_OPCODE_SELECTDB: u8 = 254;
+const RDB_OPCODE_EOF: u8 = 255;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// Special encodings of strings, stored in place of their length.
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+// The same as redis' default stream-node-max-entries.
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// A key of a snapshot, along with its value and expiry time.
+pub type Entry = (Bytes, Object, Option<u64>);
+
+/// Writes the keys of every database, indexed by database number, as an RDB file.
+pub fn save(dbs: &[Vec<Entry>], writer: impl Write) -> io::Result<()> {
+    let mut rdb = RdbWriter {
+        writer,
+        crc: Crc64::default(),
+    };
+    rdb.write_raw(format!("REDIS{:04}", RDB_VERSION).as_bytes())?;
+    rdb.write_aux("redis-ver", REDIS_VERSION.as_bytes())?;
+    rdb.write_aux("redis-bits", b"64")?;
+    rdb.write_aux("ctime", (now_millis() / 1000).to_string().as_bytes())?;
+
+    for (index, entries) in dbs.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        rdb.write_raw(&[RDB_OPCODE_SELECTDB])?;
+        rdb.write_len(index as u64)?;
+        let expires = entries.iter().filter(|(_, _, e)| e.is_some()).count();
+        rdb.write_raw(&[RDB_OPCODE_RESIZEDB])?;
+        rdb.write_len(entries.len() as u64)?;
+        rdb.write_len(expires as u64)?;
+
+        for (key, value, expire) in entries {
+            if let Some(when) = expire {
+                rdb.write_raw(&[RDB_OPCODE_EXPIRETIME_MS])?;
+                rdb.write_raw(&when.to_le_bytes())?;
+            }
+            rdb.write_object(key, value)?;
+        }
+    }
+
+    rdb.write_raw(&[RDB_OPCODE_EOF])?;
+    let checksum = rdb.crc.0;
+    rdb.writer.write_all(&checksum.to_le_bytes())?;
+    rdb.writer.flush()
+}
+
+/// Reads an RDB file, calling `insert` with the database number of every key. The keys that are
+/// already expired are skipped.
+pub fn load(reader: impl Read, mut insert: impl FnMut(usize, Entry) -> Result<()>) -> Result<()> {
+    let mut rdb = RdbReader {
+        reader,
+        crc: Crc64::default(),
+    };
+    let magic = rdb.read_bytes(9)?;
+    if &magic[..5] != b"REDIS" {
+        return Err(invalid("wrong signature"));
+    }
+    let version = std::str::from_utf8(&magic[5..])
+        .ok()
+        .and_then(|v| v.parse::<u16>().ok())
+        .filter(|v| (1..=RDB_MAX_VERSION).contains(v))
+        .ok_or_else(|| invalid("unsupported version"))?;
+
+    let now = now_millis();
+    let mut db = 0;
+    let mut expire = None;
+    loop {
+        match rdb.read_u8()? {
+            RDB_OPCODE_EXPIRETIME_MS => expire = Some(rdb.read_u64()?),
+            RDB_OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(rdb.read_array()?);
+                expire = Some(secs as u64 * 1000);
+            }
+            RDB_OPCODE_FREQ => {
+                rdb.read_u8()?;
+            }
+            RDB_OPCODE_IDLE => {
+                rdb.read_len()?;
+            }
+            RDB_OPCODE_AUX => {
+                rdb.read_string()?;
+                rdb.read_string()?;
+            }
+            RDB_OPCODE_RESIZEDB => {
+                rdb.read_len()?;
+                rdb.read_len()?;
+            }
+            RDB_OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    rdb.read_len()?;
+                }
+            }
+            RDB_OPCODE_SELECTDB => db = rdb.read_len()? as usize,
+            RDB_OPCODE_MODULE_AUX | RDB_OPCODE_FUNCTION2 => {
+                return Err(invalid("modules and functions are not supported"));
+            }
+            RDB_OPCODE_EOF => break,
+            kind => {
+                let key = rdb.read_string()?;
+                let value = rdb.read_object(kind)?;
+                match expire.take() {
+                    Some(when) if when <= now => (),
+                    expire => insert(db, (key, value, expire))?,
+                }
+            }
+        }
+    }
+
+    if version >= 5 {
+        let expected = rdb.crc.0;
+        let mut checksum = [0; 8];
+        rdb.reader.read_exact(&mut checksum).map_err(read_error)?;
+        // a zero checksum means the file was written with checksums disabled.
+        let checksum = u64::from_le_bytes(checksum);
+        if checksum != 0 && checksum != expected {
+            return Err(invalid("wrong checksum"));
+        }
+    }
+    Ok(())
+}
+
+/// Serializes a value the way DUMP does: its type and its value in the RDB format, followed by
+/// the RDB version and a checksum of the whole payload.
+pub fn dump(value: &Object) -> Vec<u8> {
+    let mut rdb = RdbWriter {
+        writer: vec![],
+        crc: Crc64::default(),
+    };
+    // writing to a vector can't fail.
+    rdb.write_raw(&[object_type(value)]).unwrap();
+    rdb.write_value(value).unwrap();
+    rdb.write_raw(&RDB_VERSION.to_le_bytes()).unwrap();
+    let checksum = rdb.crc.0;
+    let mut payload = rdb.writer;
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    payload
+}
+
+/// Checks the version and the checksum of a payload serialized by [`dump`], by this server or by
+/// redis.
+pub fn verify_dump(payload: &[u8]) -> bool {
+    if payload.len() < 10 {
+        return false;
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    let mut crc = Crc64::default();
+    crc.update(body);
+    version <= RDB_MAX_VERSION && crc.0.to_le_bytes() == checksum
+}
+
+/// Deserializes a value serialized by [`dump`]. The payload must be checked with [`verify_dump`]
+/// first.
+pub fn restore(payload: &[u8]) -> Result<Object> {
+    let mut rdb = RdbReader {
+        reader: &payload[..payload.len() - 10],
+        crc: Crc64::default(),
+    };
+    let kind = rdb.read_u8()?;
+    let value = rdb.read_object(kind)?;
+    if !rdb.reader.is_empty() {
+        return Err(invalid("trailing bytes in the DUMP payload"));
+    }
+    Ok(value)
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidRdb(msg.into())
+}
+
+fn read_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("unexpected end of file"),
+        _ => Error::Io(err),
+    }
+}
+
+struct RdbWriter<W> {
+    writer: W,
+    crc: Crc64,
+}
+
+impl<W: Write> RdbWriter<W> {
+    fn write_raw(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.crc.update(buf);
+        self.writer.write_all(buf)
+    }
+
+    fn write_len(&mut self, len: u64) -> io::Result<()> {
+        if len < 1 << 6 {
+            self.write_raw(&[len as u8])
+        } else if len < 1 << 14 {
+            self.write_raw(&[0x40 | (len >> 8) as u8, len as u8])
+        } else if let Ok(len) = u32::try_from(len) {
+            self.write_raw(&[0x80])?;
+            self.write_raw(&len.to_be_bytes())
+        } else {
+            self.write_raw(&[0x81])?;
+            self.write_raw(&len.to_be_bytes())
+        }
+    }
+
+    /// Writes a string, using the integer encodings for the short strings that hold an integer.
+    fn write_string(&mut self, s: &[u8]) -> io::Result<()> {
+        let int = std::str::from_utf8(s)
+            .ok()
+            .filter(|_| s.len() <= 11)
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|n| n.to_string().as_bytes() == s);
+        match int {
+            Some(n) if i8::try_from(n).is_ok() => self.write_raw(&[0xc0, n as i8 as u8]),
+            Some(n) if i16::try_from(n).is_ok() => {
+                self.write_raw(&[0xc1])?;
+                self.write_raw(&(n as i16).to_le_bytes())
+            }
+            Some(n) if i32::try_from(n).is_ok() => {
+                self.write_raw(&[0xc2])?;
+                self.write_raw(&(n as i32).to_le_bytes())
+            }
+            _ => {
+                self.write_len(s.len() as u64)?;
+                self.write_raw(s)
+            }
+        }
+    }
+
+    fn write_aux(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.write_raw(&[RDB_OPCODE_AUX])?;
+        self.write_string(key.as_bytes())?;
+        self.write_string(value)
+    }
+
+    fn write_object(&mut self, key: &[u8], value: &Object) -> io::Result<()> {
+        self.write_raw(&[object_type(value)])?;
+        self.write_string(key)?;
+        self.write_value(value)
+    }
+
+    fn write_value(&mut self, value: &Object) -> io::Result<()> {
+        match value {
+            Object::String(s) => self.write_string(s)?,
+            Object::List(list) => {
+                self.write_len(list.len() as u64)?;
+                for item in list.iter() {
+                    self.write_string(item)?;
+                }
+            }
+            Object::Hash(hash) => {
+                self.write_len(hash.len() as u64)?;
+                for (field, value) in hash.iter() {
+                    self.write_string(field)?;
+                    self.write_string(value)?;
+                }
+            }
+            Object::Set(set) => {
+                self.write_len(set.len() as u64)?;
+                for member in set.iter() {
+                    self.write_string(&member)?;
+                }
+            }
+            Object::SortedSet(zset) => {
+                self.write_len(zset.len() as u64)?;
+                for (member, score) in zset.iter() {
+                    self.write_string(member)?;
+                    self.write_raw(&score.to_le_bytes())?;
+                }
+            }
+            Object::Stream(stream) => self.write_stream(stream)?,
+        }
+        Ok(())
+    }
+
+    fn write_id(&mut self, id: StreamId) -> io::Result<()> {
+        self.write_len(id.ms)?;
+        self.write_len(id.seq)
+    }
+
+    /// Writes the ID as 16 bytes, in big endian so that the IDs sort like the raw bytes.
+    fn write_raw_id(&mut self, id: StreamId) -> io::Result<()> {
+        self.write_raw(&id.ms.to_be_bytes())?;
+        self.write_raw(&id.seq.to_be_bytes())
+    }
+
+    fn write_stream(&mut self, stream: &Stream) -> io::Result<()> {
+        let entries: Vec<_> = stream.range(StreamId::MIN, StreamId::MAX).collect();
+        let nodes: Vec<_> = entries.chunks(STREAM_NODE_MAX_ENTRIES).collect();
+        self.write_len(nodes.len() as u64)?;
+        for node in nodes {
+            let mut master_id = node[0].0.ms.to_be_bytes().to_vec();
+            master_id.extend_from_slice(&node[0].0.seq.to_be_bytes());
+            self.write_string(&master_id)?;
+            self.write_string(&stream_node(node))?;
+        }
+
+        self.write_len(stream.len() as u64)?;
+        self.write_id(stream.last_id())?;
+        self.write_id(stream.first_id())?;
+        self.write_id(stream.max_deleted_id())?;
+        self.write_len(stream.entries_added())?;
+
+        let groups: Vec<_> = stream.groups().collect();
+        self.write_len(groups.len() as u64)?;
+        for (name, group) in groups {
+            self.write_string(name)?;
+            self.write_id(group.last_id)?;
+            // an unknown number of entries read is saved as -1.
+            self.write_len(group.entries_read.unwrap_or(u64::MAX))?;
+
+            self.write_len(group.pending.len() as u64)?;
+            for (id, pending) in group.pending.iter() {
+                self.write_raw_id(*id)?;
+                self.write_raw(&pending.delivery_time.to_le_bytes())?;
+                self.write_len(pending.delivery_count)?;
+            }
+
+            self.write_len(group.consumers.len() as u64)?;
+            for (name, consumer) in group.consumers.iter() {
+                self.write_string(name)?;
+                self.write_raw(&consumer.seen_time.to_le_bytes())?;
+                let active_time = consumer.active_time.map(|t| t as i64).unwrap_or(-1);
+                self.write_raw(&active_time.to_le_bytes())?;
+                self.write_len(consumer.pending.len() as u64)?;
+                for id in consumer.pending.iter() {
+                    self.write_raw_id(*id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the type written before the value.
+fn object_type(value: &Object) -> u8 {
+    match value {
+        Object::String(_) => RDB_TYPE_STRING,
+        Object::List(_) => RDB_TYPE_LIST,
+        Object::Hash(_) => RDB_TYPE_HASH,
+        Object::Set(_) => RDB_TYPE_SET,
+        Object::SortedSet(_) => RDB_TYPE_ZSET_2,
+        Object::Stream(_) => RDB_TYPE_STREAM_LISTPACKS_3,
+    }
+}
+
+/// Encodes entries of a stream as a listpack, the way redis stores the nodes of a stream. The
+/// first entry is the master entry: the other entries only store the difference between their ID
+/// and the master ID, and their values alone when they have the same fields as the master entry.
+fn stream_node(entries: &[(StreamId, &StreamFields)]) -> Vec<u8> {
+    let (master_id, master_fields) = entries[0];
+    let mut lp = ListpackWriter::new();
+    lp.push_int(entries.len() as i64);
+    lp.push_int(0);
+    lp.push_int(master_fields.len() as i64);
+    for (field, _) in master_fields.iter() {
+        lp.push_str(field);
+    }
+    lp.push_int(0);
+
+    for (id, fields) in entries {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields.iter())
+                .all(|((a, _), (b, _))| a == b);
+        lp.push_int(if same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        });
+        lp.push_int(id.ms.wrapping_sub(master_id.ms) as i64);
+        lp.push_int(id.seq.wrapping_sub(master_id.seq) as i64);
+        if same_fields {
+            for (_, value) in fields.iter() {
+                lp.push_str(value);
+            }
+            lp.push_int(fields.len() as i64 + 3);
+        } else {
+            lp.push_int(fields.len() as i64);
+            for (field, value) in fields.iter() {
+                lp.push_str(field);
+                lp.push_str(value);
+            }
+            lp.push_int(fields.len() as i64 * 2 + 4);
+        }
+    }
+    lp.finish()
+}
+
+/// A length, or the special encoding of a string.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+struct RdbReader<R> {
+    reader: R,
+    crc: Crc64,
+}
+
+impl<R: Read> RdbReader<R> {
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>> {
+        // the length comes from the file, so the buffer only grows as the data is actually read.
+        let mut buf = vec![];
+        (&mut self.reader)
+            .take(n as u64)
+            .read_to_end(&mut buf)
+            .map_err(read_error)?;
+        if buf.len() < n {
+            return Err(invalid("unexpected end of file"));
+        }
+        self.crc.update(&buf);
+        Ok(buf)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.reader.read_exact(&mut buf).map_err(read_error)?;
+        self.crc.update(&buf);
+        Ok(buf)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_length(&mut self) -> Result<Length> {
+        let first = self.read_u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Len((first & 0x3f) as u64),
+            1 => Length::Len((((first & 0x3f) as u64) << 8) | self.read_u8()? as u64),
+            3 => Length::Encoded(first & 0x3f),
+            _ => match first {
+                0x80 => Length::Len(u32::from_be_bytes(self.read_array()?) as u64),
+                0x81 => Length::Len(u64::from_be_bytes(self.read_array()?)),
+                _ => return Err(invalid("unknown length encoding")),
+            },
+        })
+    }
+
+    fn read_len(&mut self) -> Result<u64> {
+        match self.read_length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid("unexpected encoded length")),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Bytes> {
+        let int = match self.read_length()? {
+            Length::Len(len) => return Ok(self.read_bytes(len as usize)?.into()),
+            Length::Encoded(RDB_ENC_INT8) => self.read_u8()? as i8 as i64,
+            Length::Encoded(RDB_ENC_INT16) => i16::from_le_bytes(self.read_array()?) as i64,
+            Length::Encoded(RDB_ENC_INT32) => i32::from_le_bytes(self.read_array()?) as i64,
+            Length::Encoded(RDB_ENC_LZF) => {
+                let compressed_len = self.read_len()? as usize;
+                let len = self.read_len()? as usize;
+                let compressed = self.read_bytes(compressed_len)?;
+                return lzf_decompress(&compressed, len)
+                    .map(Bytes::from)
+                    .ok_or_else(|| invalid("corrupted LZF string"));
+            }
+            Length::Encoded(_) => return Err(invalid("unknown string encoding")),
+        };
+        Ok(Bytes::from(&int.to_string()))
+    }
+
+    /// Reads a score of the old sorted set encoding, stored as a string.
+    fn read_string_double(&mut self) -> Result<f64> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let s = self.read_bytes(len as usize)?;
+                parse_double(&s).ok_or_else(|| invalid("invalid double value"))
+            }
+        }
+    }
+
+    fn read_listpack(&mut self) -> Result<Vec<ListpackValue>> {
+        let buf = self.read_string()?;
+        listpack::decode(&buf).ok_or_else(|| invalid("corrupted listpack"))
+    }
+
+    fn read_object(&mut self, kind: u8) -> Result<Object> {
+        let object = match kind {
+            RDB_TYPE_STRING => Object::String(self.read_string()?),
+            RDB_TYPE_LIST => {
+                let len = self.read_len()?;
+                let list = (0..len)
+                    .map(|_| self.read_string())
+                    .collect::<Result<_>>()?;
+                Object::List(list)
+            }
+            RDB_TYPE_SET => {
+                let len = self.read_len()?;
+                let set = (0..len)
+                    .map(|_| self.read_string())
+                    .collect::<Result<_>>()?;
+                Object::Set(set)
+            }
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let len = self.read_len()?;
+                let mut zset = SortedSet::new();
+                for _ in 0..len {
+                    let member = self.read_string()?;
+                    let score = if kind == RDB_TYPE_ZSET {
+                        self.read_string_double()?
+                    } else {
+                        f64::from_le_bytes(self.read_array()?)
+                    };
+                    if score.is_nan() {
+                        return Err(invalid("sorted set score is NaN"));
+                    }
+                    zset.insert(member, score);
+                }
+                Object::SortedSet(zset)
+            }
+            RDB_TYPE_HASH => {
+                let len = self.read_len()?;
+                let mut hash = Dict::new();
+                for _ in 0..len {
+                    let field = self.read_string()?;
+                    let value = self.read_string()?;
+                    hash.insert(field, value);
+                }
+                Object::Hash(hash)
+            }
+            RDB_TYPE_SET_INTSET => {
+                let buf = self.read_string()?;
+                let ints = decode_intset(&buf).ok_or_else(|| invalid("corrupted intset"))?;
+                Object::Set(ints.map(|n| Bytes::from(&n.to_string())).collect())
+            }
+            RDB_TYPE_SET_LISTPACK => {
+                let values = self.read_listpack()?;
+                Object::Set(values.into_iter().map(ListpackValue::into_bytes).collect())
+            }
+            RDB_TYPE_HASH_LISTPACK => {
+                let mut values = self.read_listpack()?.into_iter();
+                let mut hash = Dict::new();
+                while let Some(field) = values.next() {
+                    let value = values.next().ok_or_else(|| invalid("corrupted hash"))?;
+                    hash.insert(field.into_bytes(), value.into_bytes());
+                }
+                Object::Hash(hash)
+            }
+            RDB_TYPE_ZSET_LISTPACK => {
+                let mut values = self.read_listpack()?.into_iter();
+                let mut zset = SortedSet::new();
+                while let Some(member) = values.next() {
+                    let score = match values.next() {
+                        Some(ListpackValue::Int(n)) => Some(n as f64),
+                        Some(ListpackValue::Str(s)) => parse_double(&s),
+                        None => None,
+                    };
+                    let score = score
+                        .filter(|s| !s.is_nan())
+                        .ok_or_else(|| invalid("corrupted sorted set"))?;
+                    zset.insert(member.into_bytes(), score);
+                }
+                Object::SortedSet(zset)
+            }
+            RDB_TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_len()?;
+                let mut list = QuickList::new();
+                for _ in 0..nodes {
+                    let container = self.read_len()?;
+                    if container == QUICKLIST_NODE_CONTAINER_PLAIN {
+                        list.push_back(self.read_string()?);
+                        continue;
+                    }
+                    for value in self.read_listpack()? {
+                        list.push_back(value.into_bytes());
+                    }
+                }
+                Object::List(list)
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => Object::Stream(self.read_stream(kind)?),
+            _ => return Err(invalid(format!("unsupported object type {}", kind))),
+        };
+        Ok(object)
+    }
+
+    fn read_id(&mut self) -> Result<StreamId> {
+        Ok(StreamId::new(self.read_len()?, self.read_len()?))
+    }
+
+    fn read_raw_id(&mut self) -> Result<StreamId> {
+        let ms = u64::from_be_bytes(self.read_array()?);
+        let seq = u64::from_be_bytes(self.read_array()?);
+        Ok(StreamId::new(ms, seq))
+    }
+
+    fn read_stream(&mut self, kind: u8) -> Result<Stream> {
+        let mut stream = Stream::new();
+        let nodes = self.read_len()?;
+        for _ in 0..nodes {
+            let master_id = self.read_string()?;
+            if master_id.len() != 16 {
+                return Err(invalid("stream node key is not a valid ID"));
+            }
+            let master_id = StreamId::new(
+                u64::from_be_bytes(master_id[..8].try_into().unwrap()),
+                u64::from_be_bytes(master_id[8..].try_into().unwrap()),
+            );
+            let values = self.read_listpack()?;
+            read_stream_node(&mut stream, master_id, values)
+                .ok_or_else(|| invalid("corrupted stream node"))?;
+        }
+
+        let length = self.read_len()?;
+        let last_id = self.read_id()?;
+        let (max_deleted_id, entries_added) = if kind >= RDB_TYPE_STREAM_LISTPACKS_2 {
+            let _first_id = self.read_id()?;
+            (self.read_id()?, self.read_len()?)
+        } else {
+            (StreamId::MIN, length)
+        };
+        stream.restore(last_id, max_deleted_id, entries_added);
+
+        let groups = self.read_len()?;
+        for _ in 0..groups {
+            let name = self.read_string()?;
+            let last_id = self.read_id()?;
+            let entries_read = if kind >= RDB_TYPE_STREAM_LISTPACKS_2 {
+                Some(self.read_len()?).filter(|&n| n != u64::MAX)
+            } else {
+                None
+            };
+            if !stream.create_group(name.clone(), last_id, entries_read) {
+                return Err(invalid("duplicated consumer group"));
+            }
+
+            // the entries are only assigned to their consumer once the consumers are read.
+            let mut unassigned = BTreeMap::new();
+            for _ in 0..self.read_len()? {
+                let id = self.read_raw_id()?;
+                let delivery_time = self.read_u64()?;
+                let delivery_count = self.read_len()?;
+                unassigned.insert(id, (delivery_time, delivery_count));
+            }
+
+            let group = stream.group_mut(&name).unwrap();
+            for _ in 0..self.read_len()? {
+                let name = self.read_string()?;
+                let seen_time = self.read_u64()?;
+                let active_time = if kind >= RDB_TYPE_STREAM_LISTPACKS_3 {
+                    Some(self.read_u64()?).filter(|&t| t as i64 != -1)
+                } else {
+                    Some(seen_time)
+                };
+
+                let mut pending = BTreeSet::new();
+                for _ in 0..self.read_len()? {
+                    let id = self.read_raw_id()?;
+                    let (delivery_time, delivery_count) = unassigned
+                        .remove(&id)
+                        .ok_or_else(|| invalid("consumer pending entry not in the group"))?;
+                    group.pending.insert(
+                        id,
+                        PendingEntry {
+                            consumer: name.clone(),
+                            delivery_time,
+                            delivery_count,
+                        },
+                    );
+                    pending.insert(id);
+                }
+                group.consumers.insert(
+                    name,
+                    Consumer {
+                        seen_time,
+                        active_time,
+                        pending,
+                    },
+                );
+            }
+            if !unassigned.is_empty() {
+                return Err(invalid("group pending entry without consumer"));
+            }
+        }
+        Ok(stream)
+    }
+}
+
+/// Inserts the entries of a stream node, see [`stream_node`]. Returns `None` if the node is
+/// malformed.
+fn read_stream_node(
+    stream: &mut Stream,
+    master_id: StreamId,
+    values: Vec<ListpackValue>,
+) -> Option<()> {
+    let mut values = values.into_iter();
+    let next_int = |values: &mut std::vec::IntoIter<ListpackValue>| values.next()?.as_int();
+    let next_bytes = |values: &mut std::vec::IntoIter<ListpackValue>| {
+        values.next().map(ListpackValue::into_bytes)
+    };
+
+    let _count = next_int(&mut values)?;
+    let _deleted = next_int(&mut values)?;
+    let num_fields = next_int(&mut values)?;
+    let master_fields = (0..num_fields)
+        .map(|_| next_bytes(&mut values))
+        .collect::<Option<Vec<_>>>()?;
+    if next_int(&mut values)? != 0 {
+        return None;
+    }
+
+    while let Some(flags) = values.next() {
+        let flags = flags.as_int()?;
+        let ms = master_id.ms.wrapping_add(next_int(&mut values)? as u64);
+        let seq = master_id.seq.wrapping_add(next_int(&mut values)? as u64);
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), next_bytes(&mut values)?)))
+                .collect::<Option<Vec<_>>>()?
+        } else {
+            let num_fields = next_int(&mut values)?;
+            (0..num_fields)
+                .map(|_| Some((next_bytes(&mut values)?, next_bytes(&mut values)?)))
+                .collect::<Option<Vec<_>>>()?
+        };
+        next_int(&mut values)?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            let id = StreamId::new(ms, seq);
+            if id <= stream.last_id() {
+                return None;
+            }
+            stream.insert(id, fields);
+        }
+    }
+    Some(())
+}
+
+fn parse_double(s: &[u8]) -> Option<f64> {
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+/// Decodes the integers of an intset: the size of the integers, their number, and the integers
+/// in little endian.
+fn decode_intset(buf: &[u8]) -> Option<impl Iterator<Item = i64> + '_> {
+    let size = u32::from_le_bytes(buf.get(0..4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(buf.get(4..8)?.try_into().ok()?) as usize;
+    if !matches!(size, 2 | 4 | 8) || buf.len() != 8 + size * len {
+        return None;
+    }
+    Some(buf[8..].chunks(size).map(move |n| match size {
+        2 => i16::from_le_bytes(n.try_into().unwrap()) as i64,
+        4 => i32::from_le_bytes(n.try_into().unwrap()) as i64,
+        _ => i64::from_le_bytes(n.try_into().unwrap()),
+    }))
+}
+
+/// Decompresses a string compressed with LZF, as redis does for the long strings when
+/// rdbcompression is enabled. Returns `None` if the data is corrupted.
+fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // a literal run of ctrl + 1 bytes.
+            out.extend_from_slice(input.get(i..i + ctrl + 1)?);
+            i += ctrl + 1;
+        } else {
+            // a back reference into the output.
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(offset)?;
+            for k in 0..run + 2 {
+                out.push(out[start + k]);
+            }
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+/// The CRC-64 variant used by redis (Jones polynomial, reflected, no final xor).
+#[derive(Default)]
+struct Crc64(u64);
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc64 {
+    fn update(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            self.0 = CRC64_TABLE[((self.0 ^ byte as u64) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Bytes {
+        Bytes::from(s)
+    }
+
+    #[test]
+    fn test_crc64() {
+        let mut crc = Crc64::default();
+        crc.update(b"123456789");
+        assert_eq!(0xe9c6d914c4b8d9ca, crc.0);
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // "abc" as a literal, then a back reference of 6 bytes, 3 bytes back.
+        let compressed = [2, b'a', b'b', b'c', 4 << 5, 2];
+        assert_eq!(Some(b"abcabcabc".to_vec()), lzf_decompress(&compressed, 9));
+        assert_eq!(None, lzf_decompress(&compressed, 8));
+        assert_eq!(None, lzf_decompress(&[4 << 5, 2], 6));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut stream = Stream::new();
+        for i in 1..=250u64 {
+            let mut fields = vec![(bytes("a"), Bytes::from(&i.to_string()))];
+            if i % 7 == 0 {
+                fields.push((bytes("b"), bytes("x")));
+            }
+            stream.insert(StreamId::new(1000 + i / 3, i), fields);
+        }
+        stream.remove(StreamId::new(1001, 3));
+        stream.create_group(bytes("g"), StreamId::new(1001, 4), Some(4));
+        let group = stream.group_mut(b"g").unwrap();
+        group.touch_consumer(&bytes("alice"), 10).active_time = Some(20);
+        group.touch_consumer(&bytes("bob"), 30);
+        for (id, consumer) in [(1, "alice"), (4, "bob")] {
+            let id = StreamId::new(1000 + id / 3, id);
+            group.pending.insert(
+                id,
+                PendingEntry {
+                    consumer: bytes(consumer),
+                    delivery_time: 15,
+                    delivery_count: 2,
+                },
+            );
+            group
+                .consumers
+                .get_mut(consumer.as_bytes())
+                .unwrap()
+                .pending
+                .insert(id);
+        }
+
+        let long = Bytes::from(vec![b'x'; 20000]);
+        let dbs: Vec<Vec<Entry>> = vec![
+            vec![
+                (bytes("s"), Object::String(bytes("hello")), None),
+                (bytes("n"), Object::String(bytes("-12345")), Some(u64::MAX)),
+                (bytes("long"), Object::String(long), None),
+                (bytes("expired"), Object::String(bytes("x")), Some(1)),
+                (
+                    bytes("l"),
+                    Object::List([bytes("a"), bytes("1")].into_iter().collect()),
+                    None,
+                ),
+                (
+                    bytes("h"),
+                    Object::Hash([(bytes("f"), bytes("v"))].into_iter().collect()),
+                    None,
+                ),
+            ],
+            vec![],
+            vec![
+                (
+                    bytes("set"),
+                    Object::Set([bytes("1"), bytes("a")].into_iter().collect()),
+                    None,
+                ),
+                (
+                    bytes("z"),
+                    Object::SortedSet(
+                        [(bytes("a"), 1.5), (bytes("b"), f64::NEG_INFINITY)]
+                            .into_iter()
+                            .collect(),
+                    ),
+                    None,
+                ),
+                (bytes("stream"), Object::Stream(stream), None),
+            ],
+        ];
+
+        let mut buf = vec![];
+        save(&dbs, &mut buf).unwrap();
+        assert_eq!(b"REDIS0011", &buf[..9]);
+
+        let mut loaded = vec![vec![]; 3];
+        load(&buf[..], |db, entry| {
+            loaded[db].push(entry);
+            Ok(())
+        })
+        .unwrap();
+        let mut expected = dbs;
+        expected[0].remove(3);
+        assert_eq!(expected, loaded);
+
+        // any corruption is caught by the checksum.
+        let mut corrupted = buf.clone();
+        corrupted[20] ^= 1;
+        assert!(load(&corrupted[..], |_, _| Ok(())).is_err());
+        assert!(load(&buf[..buf.len() - 3], |_, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let value = Object::Hash([(bytes("f"), bytes("v"))].into_iter().collect());
+        let payload = dump(&value);
+        assert!(verify_dump(&payload));
+        assert_eq!(value, restore(&payload).unwrap());
+
+        let mut corrupted = payload.clone();
+        corrupted[2] ^= 1;
+        assert!(!verify_dump(&corrupted));
+        assert!(!verify_dump(&payload[1..]));
+    }
+
+    #[test]
+    fn test_load_compact_encodings() {
+        let mut intset = vec![2, 0, 0, 0, 2, 0, 0, 0];
+        intset.extend_from_slice(&(-3i16).to_le_bytes());
+        intset.extend_from_slice(&500i16.to_le_bytes());
+        let mut hash = ListpackWriter::new();
+        hash.push_str(b"f");
+        hash.push_int(7);
+        let mut zset = ListpackWriter::new();
+        zset.push_str(b"m");
+        zset.push_str(b"2.5");
+
+        let mut buf = vec![];
+        let mut rdb = RdbWriter {
+            writer: &mut buf,
+            crc: Crc64::default(),
+        };
+        rdb.write_raw(b"REDIS0011").unwrap();
+        for (kind, key, value) in [
+            (RDB_TYPE_SET_INTSET, "i", intset),
+            (RDB_TYPE_HASH_LISTPACK, "h", hash.finish()),
+            (RDB_TYPE_ZSET_LISTPACK, "z", zset.finish()),
+        ] {
+            rdb.write_raw(&[kind]).unwrap();
+            rdb.write_string(key.as_bytes()).unwrap();
+            rdb.write_string(&value).unwrap();
+        }
+        rdb.write_raw(&[RDB_OPCODE_EOF]).unwrap();
+        // checksums are optional.
+        buf.extend_from_slice(&[0; 8]);
+
+        let mut loaded = vec![];
+        load(&buf[..], |_, (key, value, _)| {
+            loaded.push((key, value));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    bytes("i"),
+                    Object::Set([bytes("-3"), bytes("500")].into_iter().collect())
+                ),
+                (
+                    bytes("h"),
+                    Object::Hash([(bytes("f"), bytes("7"))].into_iter().collect())
+                ),
+                (
+                    bytes("z"),
+                    Object::SortedSet([(bytes("m"), 2.5)].into_iter().collect())
+                ),
+            ],
+            loaded
+        );
+    }
+}
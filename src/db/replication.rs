@@ -0,0 +1,505 @@
+//! The state of the replication. A master sends the write commands to its replicas as a stream of
+//! RESP commands, and keeps the end of the stream in a backlog so that a replica that lost its
+//! connection can resume from where it was instead of loading a whole snapshot again. A replica
+//! keeps the link to its master, and forwards the stream it receives to its own replicas.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::config::Config;
+use crate::value::Value;
+
+use super::aof::{command, write_command, CommandStream};
+use super::rdb::{self, Entry};
+use super::Database;
+
+// How often the master pings its replicas, so that they can tell the link is still alive.
+const REPL_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Generates a new replication id, 40 random hexadecimal characters like redis.
+pub(super) fn new_replid() -> String {
+    let mut rng = rand::thread_rng();
+    (0..20).fold(String::new(), |mut id, _| {
+        let _ = write!(id, "{:02x}", rng.gen::<u8>());
+        id
+    })
+}
+
+/// The last bytes of the replication stream, up to a fixed size.
+#[derive(Debug)]
+pub struct Backlog {
+    buf: VecDeque<u8>,
+    size: usize,
+}
+
+impl Backlog {
+    pub fn new(size: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            size: size.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[bytes.len().saturating_sub(self.size)..];
+        let excess = (self.buf.len() + bytes.len()).saturating_sub(self.size);
+        self.buf.drain(..excess);
+        self.buf.extend(bytes);
+    }
+
+    /// Returns the bytes of the stream starting at `offset`, given the offset of the last byte
+    /// of the backlog. Returns `None` if some of them are no longer in the backlog.
+    pub fn since(&self, offset: u64, end: u64) -> Option<Vec<u8>> {
+        let start = end + 1 - self.buf.len() as u64;
+        if offset < start || offset > end + 1 {
+            return None;
+        }
+        Some(
+            self.buf
+                .range((offset - start) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+/// A replica connected to this server.
+#[derive(Debug)]
+pub struct Replica {
+    pub id: u64,
+    pub addr: String,
+    /// The port the replica listens on, announced with REPLCONF.
+    pub port: u16,
+    /// The offset of the stream the replica acknowledged.
+    pub ack_offset: u64,
+    feed: Sender<Arc<[u8]>>,
+}
+
+/// The state of the link of a replica with its master.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Waiting to connect to the master.
+    Connect,
+    /// Connected, and doing the handshake.
+    Connecting,
+    /// Receiving the snapshot of the master.
+    Sync,
+    /// Receiving the stream of commands.
+    Connected,
+}
+
+impl LinkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Connecting => "connecting",
+            Self::Sync => "sync",
+            Self::Connected => "connected",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    pub state: LinkState,
+    /// The connection to the master, kept to shut it down when the master changes.
+    connection: Option<TcpStream>,
+}
+
+pub struct Replication {
+    replid: String,
+    /// The id of the previous history, and the offset up to which it is shared with the current
+    /// one. A replica that gets promoted keeps the id of its former master, so that the other
+    /// replicas of that master can resume with it.
+    replid2: String,
+    second_replid_offset: Option<u64>,
+    /// The offset of the last byte of the stream.
+    offset: u64,
+    /// Created when the first replica connects.
+    backlog: Option<Backlog>,
+    backlog_size: usize,
+    stream: CommandStream,
+    replicas: Vec<Replica>,
+    next_replica_id: u64,
+    last_ping: Instant,
+    /// The master of this server, `None` when it's a master itself.
+    master: Option<MasterLink>,
+    /// Bumped every time the master changes, so that the link to the previous master knows it
+    /// has to stop.
+    generation: u64,
+    /// The port this server listens on, announced to the master.
+    port: u16,
+}
+
+/// The handshake of a replica with its master, resolved by PSYNC. The connection is then handed
+/// over to the replication: the master sends [`ReplicaSync::payload`], then the feed.
+pub struct ReplicaSync<'a> {
+    pub db: &'a Database,
+    pub id: u64,
+    pub(super) header: String,
+    /// The snapshot sent for a full resynchronization.
+    pub(super) snapshot: Option<Vec<Vec<Entry>>>,
+    /// The part of the backlog the replica is missing, for a partial resynchronization.
+    pub(super) backlog: Vec<u8>,
+    /// The stream of commands, starting right after the payload.
+    pub feed: Receiver<Arc<[u8]>>,
+}
+
+impl ReplicaSync<'_> {
+    /// Returns what is sent to the replica before the stream: the reply to PSYNC, followed by
+    /// either the snapshot in the RDB format or the missing part of the stream.
+    pub fn payload(&mut self) -> Vec<u8> {
+        let mut payload = self.header.clone().into_bytes();
+        if let Some(dbs) = self.snapshot.take() {
+            let mut rdb = vec![];
+            // writing to a vector can't fail.
+            rdb::save(&dbs, &mut rdb).unwrap();
+            // unlike a bulk string, the snapshot is not terminated by a CRLF.
+            payload.extend_from_slice(format!("${}\r\n", rdb.len()).as_bytes());
+            payload.extend_from_slice(&rdb);
+        }
+        payload.append(&mut self.backlog);
+        payload
+    }
+}
+
+impl Replication {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            replid: new_replid(),
+            replid2: "0".repeat(40),
+            second_replid_offset: None,
+            offset: 0,
+            backlog: None,
+            backlog_size: config.repl_backlog_size,
+            stream: CommandStream::default(),
+            replicas: vec![],
+            next_replica_id: 0,
+            last_ping: Instant::now(),
+            master: config.replicaof.as_ref().map(|(host, port)| MasterLink {
+                host: host.clone(),
+                port: *port,
+                state: LinkState::Connect,
+                connection: None,
+            }),
+            generation: 0,
+            port: config.port,
+        }
+    }
+
+    pub fn replid(&self) -> &str {
+        &self.replid
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn replicas(&self) -> &[Replica] {
+        &self.replicas
+    }
+
+    pub fn master(&self) -> Option<&MasterLink> {
+        self.master.as_ref()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns whether the write commands are sent to the replicas. A replica forwards the stream
+    /// of its master instead.
+    pub fn feeding(&self) -> bool {
+        self.master.is_none() && self.backlog.is_some()
+    }
+
+    /// Returns the number of replicas that acknowledged the stream up to `offset`.
+    pub fn acked(&self, offset: u64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count()
+    }
+
+    /// Sends the commands run against the database `db` to the replicas.
+    pub fn feed_commands(&mut self, db: usize, commands: &[Vec<Value>], in_exec: bool) {
+        if self.feeding() {
+            let buf = self.stream.encode(db, commands, in_exec);
+            self.feed(&buf);
+        }
+    }
+
+    /// Ends the transaction started by [`Replication::feed_commands`], if any.
+    pub fn end_exec(&mut self) {
+        if let Some(buf) = self.stream.end_exec() {
+            self.feed(&buf);
+        }
+    }
+
+    /// Sends a command that doesn't depend on the selected database, like PING.
+    fn feed_command(&mut self, args: &[&[u8]]) {
+        let mut buf = vec![];
+        write_command(&mut buf, &command(args));
+        self.feed(&buf);
+    }
+
+    /// Appends bytes to the stream, sending them to the replicas.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.push(bytes);
+        }
+        if !self.replicas.is_empty() {
+            let bytes: Arc<[u8]> = bytes.into();
+            self.replicas
+                .retain(|replica| replica.feed.send(bytes.clone()).is_ok());
+        }
+    }
+
+    /// Asks the replicas to acknowledge the stream received so far.
+    pub fn request_ack(&mut self) {
+        if self.feeding() {
+            self.feed_command(&[b"REPLCONF", b"GETACK", b"*"]);
+        }
+    }
+
+    /// Pings the replicas every once in a while.
+    pub fn cron(&mut self) {
+        if self.feeding()
+            && !self.replicas.is_empty()
+            && self.last_ping.elapsed() >= REPL_PING_INTERVAL
+        {
+            self.last_ping = Instant::now();
+            self.feed_command(&[b"PING"]);
+        }
+    }
+
+    /// Returns whether the replica asking to continue the stream at `offset` of the history
+    /// `replid` can do so.
+    pub fn can_continue(&self, replid: &str, offset: u64) -> bool {
+        let same_history = replid == self.replid
+            || (replid == self.replid2
+                && self
+                    .second_replid_offset
+                    .is_some_and(|second| offset <= second));
+        match &self.backlog {
+            Some(backlog) if same_history => {
+                offset + backlog.len() as u64 > self.offset && offset <= self.offset + 1
+            }
+            _ => false,
+        }
+    }
+
+    /// Registers a replica, returning its id and the receiving half of its feed. The backlog is
+    /// created with the first replica.
+    pub fn add_replica(&mut self, addr: String, port: u16) -> (u64, Receiver<Arc<[u8]>>) {
+        if self.backlog.is_none() {
+            self.backlog = Some(Backlog::new(self.backlog_size));
+        }
+        let (feed, receiver) = mpsc::channel();
+        let id = self.next_replica_id;
+        self.next_replica_id += 1;
+        self.replicas.push(Replica {
+            id,
+            addr,
+            port,
+            ack_offset: 0,
+            feed,
+        });
+        (id, receiver)
+    }
+
+    /// Returns the part of the backlog starting at `offset`, see [`Replication::can_continue`].
+    pub fn backlog_since(&self, offset: u64) -> Vec<u8> {
+        self.backlog
+            .as_ref()
+            .and_then(|backlog| backlog.since(offset, self.offset))
+            .unwrap_or_default()
+    }
+
+    /// Makes the next command sent to the replicas start with a SELECT, for a replica that loads
+    /// a snapshot.
+    pub fn reset_stream(&mut self) {
+        self.stream.reset();
+    }
+
+    /// Records the offset acknowledged by a replica. Returns false if it's no longer connected.
+    pub fn ack(&mut self, id: u64, offset: u64) -> bool {
+        match self.replicas.iter_mut().find(|replica| replica.id == id) {
+            Some(replica) => {
+                replica.ack_offset = replica.ack_offset.max(offset);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replicas.retain(|replica| replica.id != id);
+    }
+
+    /// Replicates another master, or stops replicating with `None`. Returns false if the master
+    /// didn't change.
+    pub fn set_master(&mut self, master: Option<(String, u16)>) -> bool {
+        let current = self
+            .master
+            .as_ref()
+            .map(|link| (link.host.clone(), link.port));
+        if current == master {
+            return false;
+        }
+
+        self.generation += 1;
+        if let Some(connection) = self.master.take().and_then(|link| link.connection) {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+        match master {
+            Some((host, port)) => {
+                // the replicas are disconnected, they resynchronize once this server is done
+                // with its new master.
+                self.replicas.clear();
+                self.master = Some(MasterLink {
+                    host,
+                    port,
+                    state: LinkState::Connect,
+                    connection: None,
+                });
+            }
+            None => {
+                // the stream continues with a new history, which the replicas can follow since
+                // it's shared up to now.
+                self.shift_replid(new_replid());
+                self.stream.reset();
+            }
+        }
+        true
+    }
+
+    /// Starts a new history, remembering the current one.
+    fn shift_replid(&mut self, replid: String) {
+        self.replid2 = std::mem::replace(&mut self.replid, replid);
+        self.second_replid_offset = Some(self.offset + 1);
+    }
+
+    /// Returns the master to connect to, if any, and the generation it belongs to.
+    pub fn master_to_connect(&self) -> Option<(String, u16, u64)> {
+        self.master
+            .as_ref()
+            .filter(|link| link.state == LinkState::Connect)
+            .map(|link| (link.host.clone(), link.port, self.generation))
+    }
+
+    /// Updates the state of the link with the master of the given generation. Returns false if
+    /// the master changed since.
+    pub fn set_link_state(
+        &mut self,
+        generation: u64,
+        state: LinkState,
+        connection: Option<TcpStream>,
+    ) -> bool {
+        match &mut self.master {
+            Some(link) if self.generation == generation => {
+                link.state = state;
+                if connection.is_some() || state == LinkState::Connect {
+                    link.connection = connection;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts following the stream of the master at `offset`, after loading its snapshot.
+    pub fn start_stream(&mut self, replid: String, offset: u64) {
+        self.replid = replid;
+        self.replid2 = "0".repeat(40);
+        self.second_replid_offset = None;
+        self.offset = offset;
+        self.backlog = Some(Backlog::new(self.backlog_size));
+        self.replicas.clear();
+    }
+
+    /// Continues the stream of the master, which may have started a new history since.
+    pub fn continue_stream(&mut self, replid: String) {
+        if replid != self.replid {
+            self.shift_replid(replid);
+            self.replicas.clear();
+        }
+        if self.backlog.is_none() {
+            self.backlog = Some(Backlog::new(self.backlog_size));
+        }
+    }
+
+    /// Forgets the history after failing to load a snapshot, so that the next synchronization
+    /// is a full one.
+    pub fn discard_history(&mut self) {
+        self.replid = new_replid();
+        self.second_replid_offset = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let mut backlog = Backlog::new(8);
+        backlog.push(b"abcde");
+        assert_eq!(Some(b"cde".to_vec()), backlog.since(3, 5));
+        assert_eq!(Some(vec![]), backlog.since(6, 5));
+        assert_eq!(None, backlog.since(7, 5));
+
+        backlog.push(b"fghij");
+        assert_eq!(8, backlog.len());
+        assert_eq!(None, backlog.since(2, 10));
+        assert_eq!(Some(b"cdefghij".to_vec()), backlog.since(3, 10));
+
+        backlog.push(b"0123456789");
+        assert_eq!(Some(b"23456789".to_vec()), backlog.since(13, 20));
+    }
+
+    #[test]
+    fn test_partial_resync() {
+        let mut replication = Replication::new(&Config::default());
+        assert!(!replication.can_continue(&replication.replid.clone(), 1));
+
+        replication.add_replica("127.0.0.1".to_string(), 6380);
+        let replid = replication.replid.clone();
+        replication.feed_commands(0, &[command(&[b"SET", b"a", b"1"])], false);
+        let offset = replication.offset();
+        assert!(replication.can_continue(&replid, 1));
+        assert!(replication.can_continue(&replid, offset + 1));
+        assert!(!replication.can_continue(&replid, offset + 2));
+        assert!(!replication.can_continue("unknown", 1));
+
+        // the replicas of the former master continue with the promoted replica.
+        replication.master = Some(MasterLink {
+            host: "localhost".to_string(),
+            port: 6381,
+            state: LinkState::Connected,
+            connection: None,
+        });
+        assert!(replication.set_master(None));
+        assert_ne!(replid, replication.replid);
+        assert!(replication.can_continue(&replid, offset + 1));
+        replication.feed_commands(0, &[command(&[b"SET", b"a", b"2"])], false);
+        assert!(!replication.can_continue(&replid, replication.offset() + 1));
+    }
+}
@@ -0,0 +1,516 @@
+//! Lua scripting: EVAL runs a script in an embedded Lua 5.1 interpreter, from which the commands
+//! are called with `redis.call` and `redis.pcall`. Like EXEC, a script runs holding the exec lock
+//! exclusively, so no other client sees its writes half done.
+//!
+//! Once a script runs for longer than `lua_time_limit`, the other clients are replied BUSY instead
+//! of waiting, and the script can be stopped with SCRIPT KILL as long as it didn't write yet.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Table, Variadic};
+
+use crate::config::Config;
+use crate::value::{Bytes, Value};
+
+use super::command::{CommandError, COMMAND_FLAG_DENYOOM, COMMAND_FLAG_WRITE};
+use super::Session;
+
+// The commands that can't be called from a script.
+const NOSCRIPT_COMMANDS: &[&str] = &[
+    "MULTI",
+    "EXEC",
+    "DISCARD",
+    "WATCH",
+    "UNWATCH",
+    "EVAL",
+    "EVALSHA",
+    "EVAL_RO",
+    "EVALSHA_RO",
+    "SCRIPT",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "PSYNC",
+    "REPLCONF",
+    "REPLICAOF",
+    "SLAVEOF",
+    "WAIT",
+    "ASKING",
+    "QUIT",
+    "SHUTDOWN",
+];
+
+// How often a running script checks whether it's killed, in Lua instructions.
+const KILL_CHECK_INTERVAL: u32 = 1000;
+
+/// Returns the SHA1 digest of a script, which identifies it in EVALSHA.
+fn script_sha(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+/// An error reply of a command called with `redis.call`, raised as a Lua error.
+#[derive(Debug)]
+struct ReplyError(Value);
+
+impl Display for ReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Value::Err(code, msg) => write!(f, "{} {}", code, msg),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+impl error::Error for ReplyError {}
+
+/// The script being run, shared with SCRIPT KILL.
+#[derive(Default)]
+struct RunningScript {
+    started: Option<Instant>,
+    wrote: bool,
+}
+
+pub struct Scripting {
+    lua: Mutex<Lua>,
+    /// The scripts run or loaded so far, by SHA1.
+    scripts: Mutex<HashMap<String, Bytes>>,
+    running: Mutex<RunningScript>,
+    /// Checked by the running script every few instructions.
+    kill: Arc<AtomicBool>,
+    /// In milliseconds, it can be changed with CONFIG SET.
+    time_limit: AtomicU64,
+}
+
+impl Scripting {
+    pub fn new(config: &Config) -> Self {
+        let kill = Arc::new(AtomicBool::new(false));
+        Self {
+            lua: Mutex::new(new_interpreter(kill.clone())),
+            scripts: Mutex::new(HashMap::new()),
+            running: Mutex::new(RunningScript::default()),
+            kill,
+            time_limit: AtomicU64::new(config.lua_time_limit),
+        }
+    }
+
+    /// Compiles the script and adds it to the cache, returning its SHA1.
+    pub fn load(&self, body: Bytes) -> Result<String, CommandError> {
+        let sha = script_sha(&body);
+        if !self.scripts.lock().unwrap().contains_key(&sha) {
+            let lua = self.lua.lock().unwrap();
+            lua.load(body.as_slice())
+                .set_name("@user_script")
+                .into_function()
+                .map_err(|err| {
+                    format!(
+                        "Error compiling script (new function): {}",
+                        error_message(&err)
+                    )
+                })?;
+            self.scripts.lock().unwrap().insert(sha.clone(), body);
+        }
+        Ok(sha)
+    }
+
+    pub fn get(&self, sha: &str) -> Option<Bytes> {
+        self.scripts
+            .lock()
+            .unwrap()
+            .get(&sha.to_ascii_lowercase())
+            .cloned()
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.get(sha).is_some()
+    }
+
+    pub fn flush(&self) {
+        self.scripts.lock().unwrap().clear();
+    }
+
+    /// Returns whether a script is running for longer than the time limit.
+    pub fn busy(&self) -> bool {
+        let running = self.running.lock().unwrap();
+        running.started.is_some_and(|started| {
+            started.elapsed() >= Duration::from_millis(self.time_limit.load(Ordering::Relaxed))
+        })
+    }
+
+    pub fn set_time_limit(&self, time_limit: u64) {
+        self.time_limit.store(time_limit, Ordering::Relaxed);
+    }
+
+    /// Stops the running script, unless it already wrote.
+    pub fn kill(&self) -> Result<(), CommandError> {
+        let running = self.running.lock().unwrap();
+        if running.started.is_none() {
+            return Err(CommandError::Code(
+                "NOTBUSY",
+                "No scripts in execution right now.".into(),
+            ));
+        }
+        if running.wrote {
+            return Err(CommandError::Code(
+                "UNKILLABLE",
+                "Sorry the script already executed write commands against the dataset. You can \
+                 either wait the script termination or kill the server in a hard way using the \
+                 SHUTDOWN NOSAVE command."
+                    .into(),
+            ));
+        }
+        self.kill.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Runs a script with its keys and arguments. With `read_only`, the script can't call the
+    /// write commands. The caller must hold the exec lock exclusively.
+    pub fn run(
+        &self,
+        session: &mut Session,
+        sha: &str,
+        body: &[u8],
+        keys: Vec<Bytes>,
+        argv: Vec<Bytes>,
+        read_only: bool,
+    ) -> Value {
+        let lua = self.lua.lock().unwrap();
+        self.kill.store(false, Ordering::SeqCst);
+        *self.running.lock().unwrap() = RunningScript {
+            started: Some(Instant::now()),
+            wrote: false,
+        };
+
+        // like in a transaction, the commands don't block and are propagated in a MULTI/EXEC.
+        let nested = session.executing;
+        session.executing = true;
+        let selected_db = session.selected_db.clone();
+        let session = RefCell::new(session);
+        let result = lua.scope(|scope| {
+            let redis: Table = lua.globals().get("redis")?;
+            for (name, raise) in [("call", true), ("pcall", false)] {
+                let session = &session;
+                let function = scope.create_function(move |lua, args: Variadic<mlua::Value>| {
+                    let reply = self.call(&mut session.borrow_mut(), args, read_only);
+                    match reply {
+                        Value::Err(..) if raise => Err(mlua::Error::external(ReplyError(reply))),
+                        reply => to_lua(lua, reply),
+                    }
+                })?;
+                redis.set(name, function)?;
+            }
+
+            let globals = lua.globals();
+            for (name, values) in [("KEYS", &keys), ("ARGV", &argv)] {
+                let values = values
+                    .iter()
+                    .map(|value| lua.create_string(value.as_slice()))
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                globals.raw_set(name, lua.create_sequence_from(values)?)?;
+            }
+            let function = lua.load(body).set_name("@user_script").into_function()?;
+            let pcall: mlua::Function = globals.get("pcall")?;
+            let (ok, result): (bool, mlua::Value) = pcall.call(function)?;
+            Ok(if ok {
+                from_lua(result)
+            } else {
+                script_error(sha, result)
+            })
+        });
+        let session = session.into_inner();
+        session.selected_db = selected_db;
+        session.executing = nested;
+        if !nested {
+            session.db.end_exec();
+        }
+        *self.running.lock().unwrap() = RunningScript::default();
+
+        result.unwrap_or_else(|err| {
+            Value::err(format!(
+                "Error running script (call to f_{}): {}",
+                sha,
+                error_message(&err)
+            ))
+        })
+    }
+
+    /// Runs a command called by the running script.
+    fn call(&self, session: &mut Session, args: Variadic<mlua::Value>, read_only: bool) -> Value {
+        let mut args = args.into_iter().map(|arg| match arg {
+            mlua::Value::String(s) => Some(Value::Blob(s.as_bytes().to_vec().into())),
+            mlua::Value::Integer(n) => Some(Value::Blob(n.to_string().as_str().into())),
+            mlua::Value::Number(n) => Some(Value::Blob(format_number(n).as_str().into())),
+            _ => None,
+        });
+        let command = match args.next() {
+            Some(Some(Value::Blob(command))) => String::from_utf8_lossy(&command).to_uppercase(),
+            Some(_) => {
+                return Value::err("Lua redis lib command arguments must be strings or integers")
+            }
+            None => {
+                return Value::err("Please specify at least one argument for this redis lib call")
+            }
+        };
+        let args = match args.collect::<Option<Vec<_>>>() {
+            Some(args) => args,
+            None => {
+                return Value::err("Lua redis lib command arguments must be strings or integers")
+            }
+        };
+
+        if let Err(err) = Self::check_call(session, &command, &args, read_only) {
+            session.db.stats().record_rejected(&command);
+            return err;
+        }
+        if session.handlers[&command]
+            .flags
+            .contains(&COMMAND_FLAG_WRITE)
+        {
+            self.running.lock().unwrap().wrote = true;
+        }
+        session.execute(&command, args)
+    }
+
+    /// Checks that the running script can call the command, like the clients calling it.
+    fn check_call(
+        session: &mut Session,
+        command: &str,
+        args: &[Value],
+        read_only: bool,
+    ) -> Result<(), Value> {
+        session.check_command(command, args)?;
+        if NOSCRIPT_COMMANDS.contains(&command) {
+            return Err(Value::err("This Redis command is not allowed from script"));
+        }
+        session.check_acl(command, args, "lua")?;
+        let write = session.handlers[command]
+            .flags
+            .contains(&COMMAND_FLAG_WRITE);
+        if write && read_only {
+            return Err(Value::err(
+                "Write commands are not allowed from read-only scripts.",
+            ));
+        }
+        if write && !session.master_link && session.db.is_replica() {
+            return Err(Value::Err(
+                "READONLY".to_string(),
+                "You can't write against a read only replica.".to_string(),
+            ));
+        }
+        session.check_cluster(command, args)?;
+        if session.oom
+            && session.handlers[command]
+                .flags
+                .contains(&COMMAND_FLAG_DENYOOM)
+        {
+            return Err(Value::Err(
+                "OOM".to_string(),
+                "command not allowed when used memory > 'maxmemory'.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Creates the interpreter shared by the scripts, with the `redis` library.
+fn new_interpreter(kill: Arc<AtomicBool>) -> Lua {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )
+    .unwrap();
+    let init = || -> mlua::Result<()> {
+        let globals = lua.globals();
+        // the scripts can't access the files.
+        globals.set("dofile", mlua::Value::Nil)?;
+        globals.set("loadfile", mlua::Value::Nil)?;
+
+        let redis = lua.create_table()?;
+        redis.set(
+            "error_reply",
+            lua.create_function(|lua, msg: mlua::String| {
+                let reply = lua.create_table()?;
+                reply.set("err", msg)?;
+                Ok(reply)
+            })?,
+        )?;
+        redis.set(
+            "status_reply",
+            lua.create_function(|lua, msg: mlua::String| {
+                let reply = lua.create_table()?;
+                reply.set("ok", msg)?;
+                Ok(reply)
+            })?,
+        )?;
+        redis.set(
+            "sha1hex",
+            lua.create_function(|_, body: mlua::String| Ok(script_sha(body.as_bytes())))?,
+        )?;
+        for (i, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+            .iter()
+            .enumerate()
+        {
+            redis.set(*level, i)?;
+        }
+        redis.set(
+            "log",
+            lua.create_function(|_, (level, msg): (u8, mlua::String)| {
+                let msg = msg.to_string_lossy();
+                match level {
+                    0 => log::debug!("{}", msg),
+                    1 | 2 => log::info!("{}", msg),
+                    _ => log::warn!("{}", msg),
+                }
+                Ok(())
+            })?,
+        )?;
+        globals.set("redis", redis)?;
+
+        // the scripts can't keep state in global variables, which would outlive them.
+        lua.load(
+            "setmetatable(_G, {__newindex = function(_, name) error(\"Script attempted to create \
+             global variable '\" .. tostring(name) .. \"'\", 2) end, __metatable = false})",
+        )
+        .exec()?;
+        Ok(())
+    };
+    init().unwrap();
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL),
+        move |_, _| {
+            if kill.load(Ordering::SeqCst) {
+                return Err(mlua::Error::RuntimeError(
+                    "Script killed by user with SCRIPT KILL...".into(),
+                ));
+            }
+            Ok(())
+        },
+    );
+    lua
+}
+
+/// Formats a Lua number passed to a command, the integers without a decimal part.
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e17 {
+        (n as i64).to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+/// Converts a reply to a Lua value: the status and error replies become tables with an `ok` or
+/// an `err` field, and a null reply becomes false.
+fn to_lua(lua: &Lua, value: Value) -> mlua::Result<mlua::Value<'_>> {
+    Ok(match value {
+        Value::Simple(s) => {
+            let reply = lua.create_table()?;
+            reply.set("ok", lua.create_string(s.as_slice())?)?;
+            mlua::Value::Table(reply)
+        }
+        Value::Blob(s) => mlua::Value::String(lua.create_string(s.as_slice())?),
+        Value::Number(n) => mlua::Value::Integer(n),
+        Value::Array(values) => {
+            let reply = lua.create_table()?;
+            for (i, value) in values.into_iter().enumerate() {
+                reply.raw_set(i + 1, to_lua(lua, value)?)?;
+            }
+            mlua::Value::Table(reply)
+        }
+        Value::Err(code, msg) => {
+            let reply = lua.create_table()?;
+            reply.set("err", format!("{} {}", code, msg))?;
+            mlua::Value::Table(reply)
+        }
+        Value::Null => mlua::Value::Boolean(false),
+        // the scripts speak RESP2.
+        value => to_lua(lua, value.into_resp2())?,
+    })
+}
+
+/// Converts the value returned by a script to a reply. The numbers are truncated to integers,
+/// and the tables are arrays up to their first nil, unless they have an `ok` or an `err` field.
+fn from_lua(value: mlua::Value) -> Value {
+    match value {
+        mlua::Value::Boolean(true) => Value::Number(1),
+        mlua::Value::Integer(n) => Value::Number(n),
+        mlua::Value::Number(n) => Value::Number(n as i64),
+        mlua::Value::String(s) => Value::Blob(s.as_bytes().to_vec().into()),
+        mlua::Value::Table(table) => {
+            if let Ok(mlua::Value::String(err)) = table.raw_get("err") {
+                return error_reply(&err.to_string_lossy());
+            }
+            if let Ok(mlua::Value::String(ok)) = table.raw_get("ok") {
+                return Value::Simple(ok.as_bytes().to_vec().into());
+            }
+            let mut values = vec![];
+            for i in 1.. {
+                match table.raw_get(i) {
+                    Ok(mlua::Value::Nil) | Err(_) => break,
+                    Ok(value) => values.push(from_lua(value)),
+                }
+            }
+            Value::Array(values)
+        }
+        mlua::Value::Error(err) => match reply_error(&err) {
+            Some(reply) => reply,
+            None => Value::err(error_message(&err)),
+        },
+        _ => Value::Null,
+    }
+}
+
+/// Splits an error message in its code, like `WRONGTYPE`, and the rest of the message.
+fn error_reply(err: &str) -> Value {
+    match err.split_once(' ') {
+        Some((code, msg)) if !code.is_empty() && code.bytes().all(|c| c.is_ascii_uppercase()) => {
+            Value::Err(code.to_string(), msg.to_string())
+        }
+        _ => Value::err(err),
+    }
+}
+
+/// Converts the error raised by a script to a reply. The errors of `redis.call` and the ones
+/// raised with a table like `redis.error_reply` are replied as they are.
+fn script_error(sha: &str, err: mlua::Value) -> Value {
+    let message = match err {
+        mlua::Value::Table(_) => return from_lua(err),
+        mlua::Value::Error(err) => match reply_error(&err) {
+            Some(reply) => return reply,
+            None => error_message(&err),
+        },
+        mlua::Value::String(s) => s.to_string_lossy().to_string(),
+        _ => "unknown error".to_string(),
+    };
+    Value::err(format!(
+        "Error running script (call to f_{}): {}",
+        sha, message
+    ))
+}
+
+/// Finds the error reply of `redis.call` behind a Lua error.
+fn reply_error(err: &mlua::Error) -> Option<Value> {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => reply_error(cause),
+        mlua::Error::ExternalError(err) => err.downcast_ref::<ReplyError>().map(|e| e.0.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the message of a Lua error, without the traceback.
+fn error_message(err: &mlua::Error) -> String {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(msg) => msg.clone(),
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        err => err.to_string(),
+    }
+}
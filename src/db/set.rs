@@ -0,0 +1,197 @@
+use rand::Rng;
+
+use crate::value::Bytes;
+
+use super::dict::Dict;
+
+// Sets with only integers are stored as a sorted array of integers, as long as they don't have
+// more than this number of members. Same as redis' default set-max-intset-entries.
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+
+/// An unordered set of strings. Small sets of integers use a compact sorted array, and are
+/// converted to a hash table once a non-integer member is added or the set grows too big.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashTable(Dict<Bytes, ()>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self::IntSet(Vec::new())
+    }
+}
+
+/// Parses the member as an integer, only if formatting the integer back gives the exact same
+/// string. This way, converting an intset back to strings doesn't change the members.
+fn as_int(member: &[u8]) -> Option<i64> {
+    let n: i64 = std::str::from_utf8(member).ok()?.parse().ok()?;
+    (n.to_string().as_bytes() == member).then_some(n)
+}
+
+fn int_to_bytes(n: i64) -> Bytes {
+    Bytes::from(&n.to_string())
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::IntSet(ints) => ints.len(),
+            Self::HashTable(dict) => dict.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the name of the internal representation, as reported by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::IntSet(_) => "intset",
+            Self::HashTable(_) => "hashtable",
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Self::IntSet(ints) => as_int(member)
+                .map(|n| ints.binary_search(&n).is_ok())
+                .unwrap_or(false),
+            Self::HashTable(dict) => dict.contains_key(member),
+        }
+    }
+
+    /// Adds the member to the set. Returns false if it is already a member.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let Self::IntSet(ints) = self {
+            if let Some(n) = as_int(&member) {
+                match ints.binary_search(&n) {
+                    Ok(_) => return false,
+                    Err(_) if ints.len() >= SET_MAX_INTSET_ENTRIES => (),
+                    Err(pos) => {
+                        ints.insert(pos, n);
+                        return true;
+                    }
+                }
+            }
+            self.convert_to_hashtable();
+        }
+
+        match self {
+            Self::HashTable(dict) => dict.insert(member, ()).is_none(),
+            Self::IntSet(_) => unreachable!(),
+        }
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Self::IntSet(ints) = self {
+            let dict = ints.iter().map(|n| (int_to_bytes(*n), ())).collect();
+            *self = Self::HashTable(dict);
+        }
+    }
+
+    /// Removes the member from the set. Returns false if it is not a member.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Self::IntSet(ints) => match as_int(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Self::HashTable(dict) => dict.remove(member).is_some(),
+        }
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<Bytes> {
+        match self {
+            Self::IntSet(ints) => ints.get(index).map(|n| int_to_bytes(*n)),
+            Self::HashTable(dict) => dict.get_index(index).map(|(k, _)| k.clone()),
+        }
+    }
+
+    pub fn random_member(&self) -> Option<Bytes> {
+        if self.is_empty() {
+            return None;
+        }
+        self.get_index(rand::thread_rng().gen_range(0..self.len()))
+    }
+
+    pub fn pop_random(&mut self) -> Option<Bytes> {
+        let member = self.random_member()?;
+        self.remove(&member);
+        Some(member)
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Self::IntSet(ints) => Box::new(ints.iter().map(|n| int_to_bytes(*n))),
+            Self::HashTable(dict) => Box::new(dict.keys().cloned()),
+        }
+    }
+
+    /// Scans the set incrementally, see [`Dict::scan`]. Intsets are small, so they are returned
+    /// in a single call.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        match self {
+            Self::IntSet(_) => (0, self.iter().collect()),
+            Self::HashTable(dict) => {
+                let (cursor, entries) = dict.scan(cursor, count);
+                (
+                    cursor,
+                    entries.into_iter().map(|(k, _)| k.clone()).collect(),
+                )
+            }
+        }
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intset_conversion() {
+        let mut set = Set::new();
+        assert!(set.insert(Bytes::from("3")));
+        assert!(set.insert(Bytes::from("-1")));
+        assert!(!set.insert(Bytes::from("3")));
+        assert_eq!("intset", set.encoding());
+
+        // not the canonical representation of an integer.
+        assert!(set.insert(Bytes::from("007")));
+        assert_eq!("hashtable", set.encoding());
+        assert!(set.contains(b"3"));
+        assert!(set.contains(b"007"));
+        assert!(!set.contains(b"7"));
+        assert_eq!(3, set.len());
+
+        let mut set: Set = (0..SET_MAX_INTSET_ENTRIES)
+            .map(|i| Bytes::from(&i.to_string()))
+            .collect();
+        assert_eq!("intset", set.encoding());
+        assert!(set.remove(b"10"));
+        assert!(!set.remove(b"10"));
+        assert!(set.insert(Bytes::from("10")));
+        assert_eq!("intset", set.encoding());
+        assert!(set.insert(Bytes::from("100000")));
+        assert_eq!("hashtable", set.encoding());
+        assert_eq!(SET_MAX_INTSET_ENTRIES + 1, set.len());
+    }
+}
@@ -0,0 +1,485 @@
+use std::cmp::Ordering;
+
+use rand::Rng;
+
+use crate::value::Bytes;
+
+// Same parameters as redis' zskiplist: enough levels for 2^64 elements with P = 1/4.
+const SKIPLIST_MAXLEVEL: usize = 32;
+const SKIPLIST_P: f64 = 0.25;
+
+// The header node is always stored at this position in the arena.
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    /// Number of elements between this node and `forward`, used to compute ranks.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    /// Orders elements by score, then by member. Scores are never NaN.
+    fn cmp(&self, score: f64, member: &[u8]) -> Ordering {
+        self.score
+            .partial_cmp(&score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.member[..].cmp(member))
+    }
+}
+
+/// A range of elements in a skiplist, like a score range or a lexicographical range.
+pub trait SkipListRange {
+    /// Returns true if the element is not below the start of the range.
+    fn after_min(&self, score: f64, member: &[u8]) -> bool;
+    /// Returns true if the element is not above the end of the range.
+    fn before_max(&self, score: f64, member: &[u8]) -> bool;
+    /// Returns true if no element can be in the range.
+    fn is_empty(&self) -> bool;
+}
+
+/// A skiplist of `(score, member)` pairs ordered by score and then by member, where each link
+/// also records how many elements it skips so ranks can be computed in logarithmic time. This is
+/// the same structure redis uses for sorted sets.
+///
+/// Nodes live in an arena and link each other by index. The skiplist doesn't check for
+/// duplicated members, that's up to the caller.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::from(""),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                SKIPLIST_MAXLEVEL
+            ],
+        };
+        Self {
+            nodes: vec![Some(head)],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+fn random_level() -> usize {
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < SKIPLIST_MAXLEVEL && rng.gen::<f64>() < SKIPLIST_P {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, index: usize) -> &Node {
+        self.nodes[index].as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node {
+        self.nodes[index].as_mut().unwrap()
+    }
+
+    fn forward(&self, index: usize, level: usize) -> Option<usize> {
+        self.node(index).levels[level].forward
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = Some(node);
+                index
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Finds, for each level, the last node that is ordered before the element.
+    fn find_predecessors(&self, score: f64, member: &[u8]) -> [usize; SKIPLIST_MAXLEVEL] {
+        let mut update = [HEAD; SKIPLIST_MAXLEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if self.node(next).cmp(score, member) != Ordering::Less {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        update
+    }
+
+    /// Inserts a new element. The member must not be in the skiplist already.
+    pub fn insert(&mut self, score: f64, member: Bytes) {
+        let mut update = [HEAD; SKIPLIST_MAXLEVEL];
+        let mut rank = [0usize; SKIPLIST_MAXLEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if self.node(next).cmp(score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += self.node(x).levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.node_mut(HEAD).levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let new = self.alloc(Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                level
+            ],
+        });
+        for i in 0..level {
+            let prev = self.node(update[i]).levels[i];
+            self.node_mut(new).levels[i] = Level {
+                forward: prev.forward,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            self.node_mut(update[i]).levels[i] = Level {
+                forward: Some(new),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.node_mut(prev).levels[i].span += 1;
+        }
+
+        match self.forward(new, 0) {
+            Some(next) => self.node_mut(next).backward = Some(new),
+            None => self.tail = Some(new),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element. Returns false if it's not in the skiplist.
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let update = self.find_predecessors(score, member);
+        match self.forward(update[0], 0) {
+            Some(x) if self.node(x).cmp(score, member) == Ordering::Equal => {
+                self.remove_node(x, &update);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn remove_node(&mut self, x: usize, update: &[usize; SKIPLIST_MAXLEVEL]) {
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == Some(x) {
+                let removed = self.node(x).levels[i];
+                let level = &mut self.node_mut(prev).levels[i];
+                level.span = level.span + removed.span - 1;
+                level.forward = removed.forward;
+            } else {
+                self.node_mut(prev).levels[i].span -= 1;
+            }
+        }
+
+        let backward = self.node(x).backward;
+        match self.forward(x, 0) {
+            Some(next) => self.node_mut(next).backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[x] = None;
+        self.free.push(x);
+        self.len -= 1;
+    }
+
+    /// Returns the 0-based rank of the element, or `None` if it's not in the skiplist.
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if self.node(next).cmp(score, member) == Ordering::Greater {
+                    break;
+                }
+                rank += self.node(x).levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.node(x).member[..] == *member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Finds the node at the 0-based rank.
+    fn node_by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.node(x).levels[i].span > target {
+                    break;
+                }
+                traversed += self.node(x).levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// Finds the first node in the range.
+    fn first_in_range(&self, range: &impl SkipListRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = self.node(next);
+                if range.after_min(node.score, &node.member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        let x = self.forward(x, 0)?;
+        let node = self.node(x);
+        range.before_max(node.score, &node.member).then_some(x)
+    }
+
+    /// Finds the last node in the range.
+    fn last_in_range(&self, range: &impl SkipListRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = self.node(next);
+                if !range.before_max(node.score, &node.member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        if x == HEAD {
+            return None;
+        }
+        let node = self.node(x);
+        range.after_min(node.score, &node.member).then_some(x)
+    }
+
+    fn node_rank(&self, x: usize) -> usize {
+        let node = self.node(x);
+        self.rank(node.score, &node.member).unwrap()
+    }
+
+    /// Iterates over the elements, starting at the 0-based rank. With `rev`, the iteration goes
+    /// towards the lowest scores, and the rank counts from the highest score.
+    pub fn iter_from_rank(&self, rank: usize, rev: bool) -> Iter<'_> {
+        let start = if rank >= self.len {
+            None
+        } else if rev {
+            self.node_by_rank(self.len - 1 - rank)
+        } else {
+            self.node_by_rank(rank)
+        };
+        Iter {
+            list: self,
+            next: start,
+            rev,
+        }
+    }
+
+    /// Iterates over the elements in the range, from the lowest to the highest, or in reverse
+    /// order with `rev`.
+    pub fn range<'a, R: SkipListRange>(
+        &'a self,
+        range: &'a R,
+        rev: bool,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> + 'a {
+        let start = if rev {
+            self.last_in_range(range)
+        } else {
+            self.first_in_range(range)
+        };
+        Iter {
+            list: self,
+            next: start,
+            rev,
+        }
+        .take_while(move |(member, score)| {
+            if rev {
+                range.after_min(*score, member)
+            } else {
+                range.before_max(*score, member)
+            }
+        })
+    }
+
+    /// Returns the number of elements in the range.
+    pub fn count_in_range(&self, range: &impl SkipListRange) -> usize {
+        match (self.first_in_range(range), self.last_in_range(range)) {
+            (Some(first), Some(last)) => self.node_rank(last) - self.node_rank(first) + 1,
+            _ => 0,
+        }
+    }
+
+    pub fn first(&self) -> Option<(&Bytes, f64)> {
+        self.forward(HEAD, 0).map(|x| self.entry(x))
+    }
+
+    pub fn last(&self) -> Option<(&Bytes, f64)> {
+        self.tail.map(|x| self.entry(x))
+    }
+
+    fn entry(&self, x: usize) -> (&Bytes, f64) {
+        let node = self.node(x);
+        (&node.member, node.score)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from_rank(0, false)
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.next?;
+        let node = self.list.node(x);
+        self.next = if self.rev {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scores(f64, f64);
+
+    impl SkipListRange for Scores {
+        fn after_min(&self, score: f64, _: &[u8]) -> bool {
+            score >= self.0
+        }
+
+        fn before_max(&self, score: f64, _: &[u8]) -> bool {
+            score <= self.1
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0 > self.1
+        }
+    }
+
+    #[test]
+    fn test_skiplist() {
+        let mut list = SkipList::default();
+        // insert in a scrambled order, each score is 10 times the final rank.
+        for i in (0..1000).map(|i| (i * 7919) % 1000) {
+            list.insert((i * 10) as f64, Bytes::from(&format!("m{}", i)));
+        }
+        assert_eq!(1000, list.len());
+
+        for i in 0..1000 {
+            let member = format!("m{}", i);
+            assert_eq!(Some(i), list.rank((i * 10) as f64, member.as_bytes()));
+        }
+        assert_eq!(None, list.rank(10.0, b"m2"));
+
+        let first: Vec<f64> = list.iter_from_rank(998, false).map(|(_, s)| s).collect();
+        assert_eq!(vec![9980.0, 9990.0], first);
+        let last: Vec<f64> = list.iter_from_rank(998, true).map(|(_, s)| s).collect();
+        assert_eq!(vec![10.0, 0.0], last);
+
+        let range = Scores(15.0, 50.0);
+        let scores: Vec<f64> = list.range(&range, false).map(|(_, s)| s).collect();
+        assert_eq!(vec![20.0, 30.0, 40.0, 50.0], scores);
+        let scores: Vec<f64> = list.range(&range, true).map(|(_, s)| s).collect();
+        assert_eq!(vec![50.0, 40.0, 30.0, 20.0], scores);
+        assert_eq!(4, list.count_in_range(&range));
+        assert_eq!(0, list.count_in_range(&Scores(11.0, 12.0)));
+
+        for i in (0..1000).step_by(2) {
+            let member = format!("m{}", i);
+            assert!(list.remove((i * 10) as f64, member.as_bytes()));
+        }
+        assert!(!list.remove(0.0, b"m0"));
+        assert_eq!(500, list.len());
+        assert_eq!(Some(3), list.rank(70.0, b"m7"));
+        assert_eq!(Some((&Bytes::from("m1"), 10.0)), list.first());
+        assert_eq!(Some((&Bytes::from("m999"), 9990.0)), list.last());
+
+        let scores: Vec<f64> = list.iter().map(|(_, s)| s).collect();
+        let mut sorted = scores.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(sorted, scores);
+    }
+}
@@ -0,0 +1,105 @@
+//! The slow log, which keeps the most recent commands that took longer than
+//! `slowlog-log-slower-than` microseconds to run.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::value::{Bytes, Value};
+
+use super::now_millis;
+
+// Like redis, only the first arguments of a command are logged, and the long arguments are
+// truncated.
+const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+
+struct SlowLogEntry {
+    id: u64,
+    /// Unix time in seconds when the command was logged.
+    timestamp: u64,
+    duration: Duration,
+    args: Vec<Bytes>,
+    client_addr: String,
+}
+
+#[derive(Default)]
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+}
+
+impl SlowLog {
+    /// Logs a command, the name included in `args`, keeping at most `max_len` entries.
+    pub fn push(
+        &mut self,
+        args: &[Value],
+        duration: Duration,
+        client_addr: String,
+        max_len: usize,
+    ) {
+        let mut logged: Vec<Bytes> = args
+            .iter()
+            .take(if args.len() > SLOWLOG_ENTRY_MAX_ARGC {
+                SLOWLOG_ENTRY_MAX_ARGC - 1
+            } else {
+                SLOWLOG_ENTRY_MAX_ARGC
+            })
+            .map(|arg| {
+                let arg = match arg {
+                    Value::Simple(arg) | Value::Blob(arg) => arg.to_vec(),
+                    arg => arg.to_string().into_bytes(),
+                };
+                if arg.len() <= SLOWLOG_ENTRY_MAX_STRING {
+                    return arg.into();
+                }
+                let mut truncated = arg[..SLOWLOG_ENTRY_MAX_STRING].to_vec();
+                truncated.extend_from_slice(
+                    format!("... ({} more bytes)", arg.len() - SLOWLOG_ENTRY_MAX_STRING).as_bytes(),
+                );
+                truncated.into()
+            })
+            .collect();
+        if args.len() > SLOWLOG_ENTRY_MAX_ARGC {
+            let more = format!("... ({} more arguments)", args.len() - logged.len());
+            logged.push(more.as_str().into());
+        }
+
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id,
+            timestamp: now_millis() / 1000,
+            duration,
+            args: logged,
+            client_addr,
+        });
+        self.next_id += 1;
+        self.entries.truncate(max_len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the most recent entries, as replied by SLOWLOG GET.
+    pub fn entries(&self, count: usize) -> Value {
+        Value::Array(
+            self.entries
+                .iter()
+                .take(count)
+                .map(|entry| {
+                    Value::Array(vec![
+                        Value::Number(entry.id as i64),
+                        Value::Number(entry.timestamp as i64),
+                        Value::Number(entry.duration.as_micros() as i64),
+                        Value::Array(entry.args.iter().cloned().map(Value::Blob).collect()),
+                        Value::Blob(entry.client_addr.as_str().into()),
+                        Value::Blob("".into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,261 @@
+use crate::value::Bytes;
+
+use super::dict::Dict;
+use super::skiplist::{Iter, SkipList, SkipListRange};
+
+/// A set of members ordered by a floating point score. The scores are kept in a hash table for
+/// O(1) lookups and the members are ordered in a skiplist for rank and range queries.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: Dict<Bytes, f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds the member or updates its score. Returns the previous score if it was a member.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), score);
+        match previous {
+            Some(previous) if previous == score => (),
+            Some(previous) => {
+                self.list.remove(previous, &member);
+                self.list.insert(score, member);
+            }
+            None => self.list.insert(score, member),
+        }
+        previous
+    }
+
+    /// Removes the member, returning its score.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        Some(score)
+    }
+
+    /// Returns the 0-based rank of the member, ordered from the lowest score, or from the
+    /// highest one with `rev`.
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let rank = self.list.rank(self.score(member)?, member)?;
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    /// Iterates over the members starting at the 0-based rank.
+    pub fn iter_from_rank(&self, rank: usize, rev: bool) -> Iter<'_> {
+        self.list.iter_from_rank(rank, rev)
+    }
+
+    /// Iterates over the members in the range.
+    pub fn range<'a, R: SkipListRange>(
+        &'a self,
+        range: &'a R,
+        rev: bool,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> + 'a {
+        self.list.range(range, rev)
+    }
+
+    pub fn count(&self, range: &impl SkipListRange) -> usize {
+        self.list.count_in_range(range)
+    }
+
+    /// Removes and returns the member with the lowest score, or the highest one with `rev`.
+    pub fn pop(&mut self, rev: bool) -> Option<(Bytes, f64)> {
+        let (member, score) = if rev {
+            self.list.last()
+        } else {
+            self.list.first()
+        }?;
+        let member = member.clone();
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// Iterates over the members, from the lowest score to the highest.
+    pub fn iter(&self) -> Iter<'_> {
+        self.list.iter()
+    }
+
+    /// Scans the members incrementally, see [`Dict::scan`].
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, f64)>) {
+        let (cursor, entries) = self.scores.scan(cursor, count);
+        (cursor, entries.into_iter().map(|(k, v)| (k, *v)).collect())
+    }
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+// Scores are never NaN, so the equality is reflexive.
+impl Eq for SortedSet {}
+
+impl FromIterator<(Bytes, f64)> for SortedSet {
+    fn from_iter<T: IntoIterator<Item = (Bytes, f64)>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for (member, score) in iter {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+/// A range of scores, as used by ZRANGEBYSCORE. Each end can be exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub max: f64,
+    pub min_exclusive: bool,
+    pub max_exclusive: bool,
+}
+
+impl SkipListRange for ScoreRange {
+    fn after_min(&self, score: f64, _: &[u8]) -> bool {
+        if self.min_exclusive {
+            score > self.min
+        } else {
+            score >= self.min
+        }
+    }
+
+    fn before_max(&self, score: f64, _: &[u8]) -> bool {
+        if self.max_exclusive {
+            score < self.max
+        } else {
+            score <= self.max
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max || (self.min == self.max && (self.min_exclusive || self.max_exclusive))
+    }
+}
+
+/// One end of a lexicographical range, as used by ZRANGEBYLEX.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    /// `-`, lower than any member.
+    Min,
+    /// `+`, greater than any member.
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+/// A lexicographical range of members. It's only meaningful when all the members have the same
+/// score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl SkipListRange for LexRange {
+    fn after_min(&self, _: f64, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+        }
+    }
+
+    fn before_max(&self, _: f64, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (LexBound::Max, _) | (_, LexBound::Min) => true,
+            (LexBound::Min, _) | (_, LexBound::Max) => false,
+            (LexBound::Inclusive(min), LexBound::Inclusive(max)) => min > max,
+            (LexBound::Inclusive(min), LexBound::Exclusive(max))
+            | (LexBound::Exclusive(min), LexBound::Inclusive(max))
+            | (LexBound::Exclusive(min), LexBound::Exclusive(max)) => min >= max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_set() {
+        let mut set: SortedSet = [("a", 3.0), ("b", 1.0), ("c", 2.0)]
+            .into_iter()
+            .map(|(m, s)| (Bytes::from(m), s))
+            .collect();
+        assert_eq!(Some(0), set.rank(b"b", false));
+        assert_eq!(Some(0), set.rank(b"a", true));
+
+        assert_eq!(Some(3.0), set.insert(Bytes::from("a"), 0.5));
+        assert_eq!(Some(0), set.rank(b"a", false));
+        assert_eq!(Some(1.0), set.remove(b"b"));
+        assert_eq!(None, set.rank(b"b", false));
+
+        let range = ScoreRange {
+            min: 0.5,
+            max: 2.0,
+            min_exclusive: true,
+            max_exclusive: false,
+        };
+        let members: Vec<&Bytes> = set.range(&range, false).map(|(m, _)| m).collect();
+        assert_eq!(vec![&Bytes::from("c")], members);
+        assert_eq!(1, set.count(&range));
+
+        assert_eq!(Some((Bytes::from("c"), 2.0)), set.pop(true));
+        assert_eq!(Some((Bytes::from("a"), 0.5)), set.pop(false));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_lex_range() {
+        let set: SortedSet = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|m| (Bytes::from(m), 0.0))
+            .collect();
+        let members = |min, max| {
+            let range = LexRange { min, max };
+            let members: Vec<Bytes> = set.range(&range, false).map(|(m, _)| m.clone()).collect();
+            members
+        };
+
+        assert_eq!(4, members(LexBound::Min, LexBound::Max).len());
+        assert_eq!(
+            vec![Bytes::from("b"), Bytes::from("c")],
+            members(
+                LexBound::Exclusive(Bytes::from("a")),
+                LexBound::Inclusive(Bytes::from("c"))
+            )
+        );
+        assert!(members(LexBound::Max, LexBound::Max).is_empty());
+        assert!(members(
+            LexBound::Inclusive(Bytes::from("c")),
+            LexBound::Exclusive(Bytes::from("c"))
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! The counters of the server, reset with CONFIG RESETSTAT.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::command::get_commands;
+
+// The number of samples averaged by the instantaneous metrics, taken by the server cron every
+// 100 milliseconds like redis.
+const INSTANTANEOUS_METRICS_SAMPLES: usize = 16;
+
+/// The counters of a command.
+#[derive(Default)]
+pub struct CommandStats {
+    pub calls: AtomicU64,
+    /// The total time spent running the command, in microseconds.
+    pub usec: AtomicU64,
+    /// The calls rejected before running, like with the wrong number of arguments, or denied by
+    /// the ACL rules. They are not counted in `calls`.
+    pub rejected_calls: AtomicU64,
+    /// The calls that replied an error.
+    pub failed_calls: AtomicU64,
+}
+
+pub struct Stats {
+    /// The number of connected clients. Unlike the other counters, it's not reset.
+    pub connected_clients: AtomicUsize,
+    pub connections_received: AtomicU64,
+    /// The connections refused because of `maxclients`.
+    pub rejected_connections: AtomicU64,
+    pub commands_processed: AtomicU64,
+    pub error_replies: AtomicU64,
+    /// The keys deleted because they expired.
+    pub expired_keys: AtomicU64,
+    /// The keys deleted to free memory once `maxmemory` was reached.
+    pub evicted_keys: AtomicU64,
+    /// The keys found, and not found, by the read commands.
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    /// The highest memory used by the keys, see [`super::Database::used_memory`].
+    pub used_memory_peak: AtomicUsize,
+    ops: Mutex<InstantaneousMetric>,
+    /// The counters of every command, by name. The commands are known in advance, so the map
+    /// doesn't need a lock.
+    commands: HashMap<String, CommandStats>,
+}
+
+/// The rate of a counter over the last samples.
+struct InstantaneousMetric {
+    last_sample: Instant,
+    last_count: u64,
+    /// Per second rates.
+    samples: [u64; INSTANTANEOUS_METRICS_SAMPLES],
+    next: usize,
+}
+
+impl Default for InstantaneousMetric {
+    fn default() -> Self {
+        Self {
+            last_sample: Instant::now(),
+            last_count: 0,
+            samples: [0; INSTANTANEOUS_METRICS_SAMPLES],
+            next: 0,
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            connected_clients: AtomicUsize::new(0),
+            connections_received: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            error_replies: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            used_memory_peak: AtomicUsize::new(0),
+            ops: Mutex::default(),
+            commands: get_commands()
+                .into_iter()
+                .map(|spec| (spec.name.to_uppercase(), CommandStats::default()))
+                .collect(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn record_command(&self, name: &str, duration: Duration, failed: bool) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+        if let Some(stats) = self.commands.get(name) {
+            stats.calls.fetch_add(1, Ordering::Relaxed);
+            stats
+                .usec
+                .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+            if failed {
+                stats.failed_calls.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if failed {
+            self.error_replies.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a command rejected before running, which replied an error.
+    pub fn record_rejected(&self, name: &str) {
+        if let Some(stats) = self.commands.get(name) {
+            stats.rejected_calls.fetch_add(1, Ordering::Relaxed);
+        }
+        self.error_replies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Samples the number of commands processed, for [`Stats::instantaneous_ops_per_sec`]. It's
+    /// meant to be called periodically.
+    pub fn track_instantaneous_metrics(&self) {
+        let mut ops = self.ops.lock().unwrap();
+        let now = Instant::now();
+        let count = self.commands_processed.load(Ordering::Relaxed);
+        let elapsed = now.duration_since(ops.last_sample).as_millis() as u64;
+        let rate = count.saturating_sub(ops.last_count) * 1000 / elapsed.max(1);
+        let next = ops.next;
+        ops.samples[next] = rate;
+        ops.next = (next + 1) % INSTANTANEOUS_METRICS_SAMPLES;
+        ops.last_sample = now;
+        ops.last_count = count;
+    }
+
+    /// Returns the number of commands processed per second, averaged over the last samples.
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        let ops = self.ops.lock().unwrap();
+        ops.samples.iter().sum::<u64>() / INSTANTANEOUS_METRICS_SAMPLES as u64
+    }
+
+    /// Returns the counters of the commands that were called or rejected, sorted by name.
+    pub fn command_stats(&self) -> Vec<(&str, &CommandStats)> {
+        let mut stats: Vec<_> = self
+            .commands
+            .iter()
+            .filter(|(_, stats)| {
+                stats.calls.load(Ordering::Relaxed) > 0
+                    || stats.rejected_calls.load(Ordering::Relaxed) > 0
+            })
+            .map(|(name, stats)| (name.as_str(), stats))
+            .collect();
+        stats.sort_by_key(|(name, _)| *name);
+        stats
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.connections_received,
+            &self.rejected_connections,
+            &self.commands_processed,
+            &self.error_replies,
+            &self.expired_keys,
+            &self.evicted_keys,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.used_memory_peak.store(0, Ordering::Relaxed);
+        *self.ops.lock().unwrap() = InstantaneousMetric {
+            last_count: self.commands_processed.load(Ordering::Relaxed),
+            ..InstantaneousMetric::default()
+        };
+        for stats in self.commands.values() {
+            stats.calls.store(0, Ordering::Relaxed);
+            stats.usec.store(0, Ordering::Relaxed);
+            stats.rejected_calls.store(0, Ordering::Relaxed);
+            stats.failed_calls.store(0, Ordering::Relaxed);
+        }
+    }
+}
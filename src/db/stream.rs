@@ -0,0 +1,602 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::value::Bytes;
+
+/// The ID of a stream entry: the creation time in milliseconds, and a sequence number to tell
+/// apart the entries created in the same millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Returns the smallest ID greater than this one.
+    pub fn next(self) -> Option<Self> {
+        match (self.ms, self.seq) {
+            (ms, u64::MAX) => Some(Self::new(ms.checked_add(1)?, 0)),
+            (ms, seq) => Some(Self::new(ms, seq + 1)),
+        }
+    }
+
+    /// Returns the greatest ID smaller than this one.
+    pub fn prev(self) -> Option<Self> {
+        match (self.ms, self.seq) {
+            (ms, 0) => Some(Self::new(ms.checked_sub(1)?, u64::MAX)),
+            (ms, seq) => Some(Self::new(ms, seq - 1)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The field-value pairs of a stream entry.
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+
+/// How to trim a stream, as used by XADD and XTRIM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keeps at most this number of entries.
+    MaxLen(u64),
+    /// Removes the entries with an ID lower than this one.
+    MinId(StreamId),
+}
+
+/// An entry delivered to a consumer that hasn't been acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consumer {
+    /// Unix time in milliseconds of the last interaction of the consumer.
+    pub seen_time: u64,
+    /// Unix time in milliseconds of the last successful read or claim, if any.
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: u64) -> Self {
+        Self {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    /// ID of the last entry delivered to the group.
+    pub last_id: StreamId,
+    /// Logical number of entries read by the group, if it is known. It's used to compute the
+    /// lag of the group.
+    pub entries_read: Option<u64>,
+    /// Pending entries list of the group.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Bytes, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the consumer. Returns false if it already exists.
+    pub fn create_consumer(&mut self, name: &Bytes, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(name.clone(), Consumer::new(now));
+        true
+    }
+
+    /// Returns the consumer, creating it if needed, and marks it as seen.
+    pub fn touch_consumer(&mut self, name: &Bytes, now: u64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.clone())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Deletes the consumer along with its pending entries. Returns the number of pending
+    /// entries it had, or `None` if it doesn't exist.
+    pub fn delete_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in consumer.pending.iter() {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Acknowledges the entry, removing it from the pending entries. Returns false if it wasn't
+    /// pending.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let entry = match self.pending.remove(&id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+
+    /// Assigns the entry to the consumer, which must exist, moving it from its previous owner.
+    /// Returns the pending entry to update its delivery time and count.
+    fn assign(&mut self, id: StreamId, consumer: &Bytes, now: u64) -> &mut PendingEntry {
+        let previous = self.pending.get(&id).map(|entry| entry.consumer.clone());
+        if let Some(previous) = previous.filter(|previous| previous != consumer) {
+            if let Some(previous) = self.consumers.get_mut(&previous) {
+                previous.pending.remove(&id);
+            }
+        }
+        self.consumers.get_mut(consumer).unwrap().pending.insert(id);
+
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.clone(),
+            delivery_time: now,
+            delivery_count: 0,
+        });
+        entry.consumer = consumer.clone();
+        entry
+    }
+}
+
+/// Options of XCLAIM that change the pending entry of the claimed entries.
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    /// Only claim entries that have been idle for at least this number of milliseconds.
+    pub min_idle_time: u64,
+    /// Unix time in milliseconds to set as the delivery time. Defaults to now.
+    pub delivery_time: Option<u64>,
+    /// Delivery count to set. By default it's incremented, unless `justid` is set.
+    pub retry_count: Option<u64>,
+    /// Creates the pending entry if it doesn't exist, as long as the entry is in the stream.
+    pub force: bool,
+    pub justid: bool,
+}
+
+/// The result of claiming a single entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimResult {
+    Claimed(StreamFields),
+    /// The entry was pending but it's not in the stream anymore, so it was removed from the
+    /// pending entries.
+    Deleted,
+    /// The entry is not pending or it hasn't been idle long enough.
+    Skipped,
+}
+
+/// An append-only log of entries with increasing IDs, consumed either directly or through
+/// consumer groups which track which entries have been delivered and acknowledged.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    last_id: StreamId,
+    /// The greatest ID removed with XDEL.
+    max_deleted_id: StreamId,
+    /// Number of entries added over the stream lifetime.
+    entries_added: u64,
+    groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Restores the metadata of the stream once its entries are inserted, when it's loaded from a
+    /// snapshot or rebuilt by XSETID.
+    pub fn restore(&mut self, last_id: StreamId, max_deleted_id: StreamId, entries_added: u64) {
+        self.last_id = last_id;
+        self.max_deleted_id = max_deleted_id;
+        self.entries_added = entries_added;
+    }
+
+    /// Returns the ID of the first entry, or 0-0 if the stream is empty.
+    pub fn first_id(&self) -> StreamId {
+        self.first_entry().map(|(id, _)| id).unwrap_or_default()
+    }
+
+    pub fn first_entry(&self) -> Option<(StreamId, &StreamFields)> {
+        self.entries.iter().next().map(|(id, f)| (*id, f))
+    }
+
+    pub fn last_entry(&self) -> Option<(StreamId, &StreamFields)> {
+        self.entries.iter().next_back().map(|(id, f)| (*id, f))
+    }
+
+    /// Appends an entry. The ID must be greater than the last ID of the stream.
+    pub fn insert(&mut self, id: StreamId, fields: StreamFields) {
+        debug_assert!(id > self.last_id);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&StreamFields> {
+        self.entries.get(&id)
+    }
+
+    /// Iterates over the entries with IDs between `start` and `end`, both inclusive.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (StreamId, &StreamFields)> {
+        let range = if start <= end {
+            Some(self.entries.range(start..=end))
+        } else {
+            None
+        };
+        range.into_iter().flatten().map(|(id, f)| (*id, f))
+    }
+
+    /// Removes the entry. Returns false if it's not in the stream.
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        if self.entries.remove(&id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Removes the oldest entries according to the strategy, removing no more than `limit`
+    /// entries. Returns the number of removed entries.
+    pub fn trim(&mut self, strategy: TrimStrategy, limit: Option<usize>) -> usize {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let (id, _) = match self.first_entry() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let trim = match strategy {
+                TrimStrategy::MaxLen(max) => self.len() as u64 > max,
+                TrimStrategy::MinId(min) => id < min,
+            };
+            if !trim {
+                break;
+            }
+            self.entries.remove(&id);
+            removed += 1;
+        }
+        removed
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&Bytes, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a consumer group that starts delivering after `last_id`. Returns false if the
+    /// group already exists.
+    pub fn create_group(
+        &mut self,
+        name: Bytes,
+        last_id: StreamId,
+        entries_read: Option<u64>,
+    ) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups
+            .insert(name, ConsumerGroup::new(last_id, entries_read));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Returns true if an entry between `start` and `end` has been deleted with XDEL. Only the
+    /// greatest deleted ID is tracked, so this may give false positives.
+    fn has_tombstones(&self, start: StreamId, end: StreamId) -> bool {
+        if self.is_empty() || self.max_deleted_id == StreamId::MIN {
+            return false;
+        }
+        start <= self.max_deleted_id && self.max_deleted_id <= end
+    }
+
+    /// Estimates the logical number of entries that come before the ID, plus one if the ID
+    /// itself is an entry, since the stream was created. Returns `None` if it can't be computed
+    /// because of deleted entries.
+    pub fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+
+        let first_id = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            // there are no deleted entries in the middle of the stream.
+            let before_first = self.entries_added - self.len() as u64;
+            if id < first_id {
+                return Some(before_first);
+            } else if id == first_id {
+                return Some(before_first + 1);
+            }
+        }
+        None
+    }
+
+    /// Returns the number of entries in the stream that are yet to be delivered to the group,
+    /// or `None` if it can't be computed because of deleted entries.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones(group.last_id, StreamId::MAX) => Some(read),
+            _ => self.estimate_entries_read(group.last_id),
+        };
+        entries_read.map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// Delivers up to `count` entries that have never been delivered to the group, adding them
+    /// to the pending entries of the consumer unless `noack` is set.
+    pub fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Vec<(StreamId, StreamFields)> {
+        let start = match self.groups[group].last_id.next() {
+            Some(start) => start,
+            None => return vec![],
+        };
+        let entries: Vec<(StreamId, StreamFields)> = self
+            .range(start, StreamId::MAX)
+            .take(count)
+            .map(|(id, fields)| (id, fields.clone()))
+            .collect();
+
+        for (id, _) in entries.iter() {
+            let entries_read = {
+                let group = &self.groups[group];
+                match group.entries_read {
+                    Some(read) if !self.has_tombstones(*id, StreamId::MAX) => Some(read + 1),
+                    _ => self.estimate_entries_read(*id),
+                }
+            };
+            let group = self.groups.get_mut(group).unwrap();
+            group.last_id = *id;
+            group.entries_read = entries_read;
+            group.touch_consumer(consumer, now);
+            if !noack {
+                let entry = group.assign(*id, consumer, now);
+                entry.delivery_time = now;
+                entry.delivery_count = 1;
+            }
+        }
+
+        let consumer = self
+            .groups
+            .get_mut(group)
+            .unwrap()
+            .touch_consumer(consumer, now);
+        if !entries.is_empty() {
+            consumer.active_time = Some(now);
+        }
+        entries
+    }
+
+    /// Delivers again up to `count` pending entries of the consumer with an ID greater than
+    /// `after`. Entries that have been deleted from the stream are returned without fields.
+    pub fn read_pending(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        after: StreamId,
+        count: usize,
+        now: u64,
+    ) -> Vec<(StreamId, Option<StreamFields>)> {
+        let group = self.groups.get_mut(group).unwrap();
+        let ids: Vec<StreamId> = match after.next() {
+            Some(start) => group
+                .touch_consumer(consumer, now)
+                .pending
+                .range(start..)
+                .take(count)
+                .copied()
+                .collect(),
+            None => vec![],
+        };
+
+        ids.into_iter()
+            .map(|id| {
+                if let Some(entry) = group.pending.get_mut(&id) {
+                    entry.delivery_time = now;
+                    entry.delivery_count += 1;
+                }
+                (id, self.entries.get(&id).cloned())
+            })
+            .collect()
+    }
+
+    /// Transfers the ownership of a pending entry of the group to the consumer, which is created
+    /// if needed.
+    pub fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        id: StreamId,
+        options: &ClaimOptions,
+        now: u64,
+    ) -> ClaimResult {
+        let group = self.groups.get_mut(group).unwrap();
+        let fields = self.entries.get(&id);
+        match (group.pending.get(&id), fields) {
+            (None, Some(_)) if options.force => (),
+            (None, _) => return ClaimResult::Skipped,
+            (Some(_), None) => {
+                group.ack(id);
+                return ClaimResult::Deleted;
+            }
+            (Some(entry), Some(_)) => {
+                let idle = now.saturating_sub(entry.delivery_time);
+                if options.min_idle_time > 0 && idle < options.min_idle_time {
+                    return ClaimResult::Skipped;
+                }
+            }
+        }
+
+        let claiming = group.touch_consumer(consumer, now);
+        claiming.active_time = Some(now);
+        let entry = group.assign(id, consumer, now);
+        entry.delivery_time = options.delivery_time.unwrap_or(now);
+        if let Some(count) = options.retry_count {
+            entry.delivery_count = count;
+        } else if !options.justid {
+            entry.delivery_count += 1;
+        }
+        ClaimResult::Claimed(fields.unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> StreamFields {
+        vec![(Bytes::from("f"), Bytes::from(value))]
+    }
+
+    #[test]
+    fn test_trim_and_range() {
+        let mut stream = Stream::new();
+        for i in 1..=10 {
+            stream.insert(StreamId::new(i, 0), fields(&i.to_string()));
+        }
+        assert_eq!(2, stream.trim(TrimStrategy::MaxLen(8), None));
+        assert_eq!(StreamId::new(3, 0), stream.first_id());
+        assert_eq!(
+            1,
+            stream.trim(TrimStrategy::MinId(StreamId::new(5, 0)), Some(1))
+        );
+        assert_eq!(7, stream.len());
+
+        let ids: Vec<StreamId> = stream
+            .range(StreamId::new(5, 0), StreamId::new(6, 5))
+            .rev()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(vec![StreamId::new(6, 0), StreamId::new(5, 0)], ids);
+        assert_eq!(0, stream.range(StreamId::MAX, StreamId::MIN).count());
+        assert_eq!(Some(StreamId::new(1, 0)), StreamId::new(0, u64::MAX).next());
+        assert_eq!(None, StreamId::MAX.next());
+    }
+
+    #[test]
+    fn test_consumer_group() {
+        let mut stream = Stream::new();
+        for i in 1..=3 {
+            stream.insert(StreamId::new(i, 0), fields(&i.to_string()));
+        }
+        assert!(stream.create_group(Bytes::from("g"), StreamId::MIN, None));
+        assert!(!stream.create_group(Bytes::from("g"), StreamId::MIN, None));
+        let group = stream.group(b"g").unwrap();
+        assert_eq!(Some(3), stream.lag(group));
+
+        let (alice, bob) = (Bytes::from("alice"), Bytes::from("bob"));
+        let read = stream.read_group(b"g", &alice, 2, false, 1000);
+        assert_eq!(2, read.len());
+        let group = stream.group(b"g").unwrap();
+        assert_eq!(StreamId::new(2, 0), group.last_id);
+        assert_eq!(Some(1), stream.lag(group));
+        assert_eq!(2, group.consumers[&alice].pending.len());
+
+        let options = ClaimOptions {
+            min_idle_time: 500,
+            ..Default::default()
+        };
+        let id = StreamId::new(1, 0);
+        assert_eq!(
+            ClaimResult::Skipped,
+            stream.claim(b"g", &bob, id, &options, 1200)
+        );
+        assert_eq!(
+            ClaimResult::Claimed(fields("1")),
+            stream.claim(b"g", &bob, id, &options, 2000)
+        );
+        let group = stream.group(b"g").unwrap();
+        assert_eq!(bob, group.pending[&id].consumer);
+        assert_eq!(2, group.pending[&id].delivery_count);
+        assert_eq!(1, group.consumers[&alice].pending.len());
+
+        stream.remove(StreamId::new(2, 0));
+        let pending = stream.read_pending(b"g", &alice, StreamId::MIN, 10, 3000);
+        assert_eq!(vec![(StreamId::new(2, 0), None)], pending);
+        assert_eq!(
+            ClaimResult::Deleted,
+            stream.claim(b"g", &bob, StreamId::new(2, 0), &options, 5000)
+        );
+        assert!(stream.group_mut(b"g").unwrap().ack(id));
+        assert!(stream.group(b"g").unwrap().pending.is_empty());
+    }
+}
@@ -7,6 +7,16 @@ pub enum Error {
     Io(io::Error),
     Eof,
     ParseError,
+    /// The peer sent something that is not valid RESP, like a malformed length.
+    Protocol(String),
+    /// The RDB file can't be loaded.
+    InvalidRdb(String),
+    /// The AOF can't be replayed.
+    InvalidAof(String),
+    /// The configuration file or the command line is invalid.
+    InvalidConfig(String),
+    /// The ACL file can't be loaded.
+    InvalidAcl(String),
 }
 
 impl Display for Error {
@@ -15,6 +25,11 @@ impl Display for Error {
             Self::Io(err) => err.fmt(f),
             Self::Eof => write!(f, "Client disconnected"),
             Self::ParseError => write!(f, "Cannot parse the binary value"),
+            Self::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            Self::InvalidRdb(msg) => write!(f, "Invalid RDB file: {}", msg),
+            Self::InvalidAof(msg) => write!(f, "Invalid AOF: {}", msg),
+            Self::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            Self::InvalidAcl(msg) => write!(f, "Invalid ACL file: {}", msg),
         }
     }
 }
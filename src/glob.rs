@@ -0,0 +1,139 @@
+/// Matches `string` against a glob-style `pattern`, using the same rules as redis:
+///
+/// - `*` matches any sequence of characters, including an empty one.
+/// - `?` matches exactly one character.
+/// - `[abc]`, `[^abc]` and `[a-z]` match one character from (or not from) the set.
+/// - `\` escapes the next character.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // position in the pattern right after the last `*`, and the position in the string that
+    // `*` is currently matched up to. On mismatch, the `*` consumes one more character.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            if pattern[p] == b'*' {
+                while p < pattern.len() && pattern[p] == b'*' {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                backtrack = Some((p, s));
+                continue;
+            }
+
+            if let Some(consumed) = match_one(&pattern[p..], string[s], nocase) {
+                p += consumed;
+                s += 1;
+                continue;
+            }
+        }
+
+        match backtrack {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                backtrack = Some((star_p, star_s + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Matches a single character against the start of the pattern, which must not be `*`. Returns
+/// the length of the consumed pattern if it matches.
+fn match_one(pattern: &[u8], c: u8, nocase: bool) -> Option<usize> {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    match pattern[0] {
+        b'?' => Some(1),
+        b'\\' if pattern.len() >= 2 => eq(pattern[1], c).then_some(2),
+        b'[' => {
+            let mut i = 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    i += 1;
+                    matched |= eq(pattern[i], c);
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' {
+                    let (mut start, mut end) = (pattern[i], pattern[i + 2]);
+                    if start > end {
+                        std::mem::swap(&mut start, &mut end);
+                    }
+                    let c = if nocase { c.to_ascii_lowercase() } else { c };
+                    let (start, end) = if nocase {
+                        (start.to_ascii_lowercase(), end.to_ascii_lowercase())
+                    } else {
+                        (start, end)
+                    };
+                    matched |= start <= c && c <= end;
+                    i += 2;
+                } else {
+                    matched |= eq(pattern[i], c);
+                }
+                i += 1;
+            }
+
+            // an unterminated class extends until the end of the pattern.
+            let consumed = (i + 1).min(pattern.len());
+            (matched != negate).then_some(consumed)
+        }
+        other => eq(other, c).then_some(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        let testcases = vec![
+            ("*", "", true),
+            ("*", "anything", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "hllo", true),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "heeeellox", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("*.user:*:name", "app.user:42:name", true),
+            ("a*b*c", "aXXbYYbZZc", true),
+            ("a*b*c", "aXXbYYbZZ", false),
+        ];
+
+        for (pattern, string, expected) in testcases {
+            assert_eq!(
+                expected,
+                glob_match(pattern.as_bytes(), string.as_bytes(), false),
+                "matching {:?} against {:?}",
+                string,
+                pattern
+            );
+        }
+        assert!(glob_match(b"HELLO*", b"hello world", true));
+        assert!(glob_match(b"h[A-Z]llo", b"hello", true));
+    }
+}
@@ -1,6 +1,9 @@
 pub mod bufstream;
+pub mod client;
 pub mod config;
+pub mod db;
+pub mod error;
+pub mod glob;
+pub mod replication;
 pub mod server;
 pub mod value;
-pub mod error;
-pub mod db;
@@ -0,0 +1,324 @@
+//! The connections of the replication. A replica connects to its master like a client, and once
+//! PSYNC resolves the handshake, the master sends the snapshot or the missing part of the stream,
+//! then the stream of write commands. The replica runs the commands as they come, and
+//! acknowledges the offset of the stream it reached every second.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::db::{LinkState, ReplicaSync, Session, SessionFactory};
+use crate::error::{Error, Result};
+use crate::value::{Value, ValueRead, ValueWrite};
+
+// How often a replica acknowledges the stream, and how long it waits before connecting again to
+// its master after losing the link.
+const REPL_ACK_INTERVAL: Duration = Duration::from_secs(1);
+const REPL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn protocol_error(msg: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Returns the arguments of the request if it's `REPLCONF <option> ...`.
+fn replconf_args(request: &Value, option: &str) -> Option<Vec<Value>> {
+    match request {
+        Value::Array(args)
+            if args.len() >= 2
+                && matches!(&args[0], Value::Blob(name) if name.eq_ignore_ascii_case(b"REPLCONF"))
+                && matches!(&args[1], Value::Blob(name) if name.eq_ignore_ascii_case(option.as_bytes())) =>
+        {
+            Some(args[2..].to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Serves a replica whose PSYNC was resolved, until the connection is lost. The reader is the
+/// buffered reader of the connection, which may already hold the acknowledgements of the
+/// replica.
+pub fn serve_replica(
+    mut sync: ReplicaSync,
+    connection: TcpStream,
+    mut reader: BufReader<TcpStream>,
+    addr: &str,
+) {
+    let db = sync.db;
+    let id = sync.id;
+    let payload = sync.payload();
+    let feed = sync.feed;
+    log::info!("Synchronization with replica {} started", addr);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut writer = &connection;
+            let result = writer.write_all(&payload).and_then(|()| {
+                for bytes in feed {
+                    writer.write_all(&bytes)?;
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                log::error!("Error writing to replica {}: {}", addr, err);
+            }
+            // the feed ends when the replica is removed, which stops the reader as well.
+            let _ = connection.shutdown(Shutdown::Both);
+        });
+
+        while let Ok(request) = reader.read_value() {
+            let offset = replconf_args(&request, "ACK")
+                .and_then(|args| args.into_iter().next())
+                .and_then(|offset| match offset {
+                    Value::Blob(offset) => std::str::from_utf8(&offset).ok()?.parse().ok(),
+                    _ => None,
+                });
+            if let Some(offset) = offset {
+                if !db.replica_ack(id, offset) {
+                    break;
+                }
+            }
+        }
+        log::info!("Connection with replica {} lost", addr);
+        db.remove_replica(id);
+        let _ = connection.shutdown(Shutdown::Both);
+    });
+}
+
+/// Keeps this server in sync with its master, whenever it has one. It's meant to run in its own
+/// thread for the whole life of the server.
+pub fn run_master_link(session_factory: &SessionFactory) {
+    let database = session_factory.database();
+    // the session is kept across the connections, so that a partial resynchronization continues
+    // with the database selected by the stream.
+    let mut session = session_factory.create_session();
+    session.master_link = true;
+    loop {
+        let (host, port, generation) = database.wait_for_master();
+        log::info!("Connecting to MASTER {}:{}", host, port);
+        let result = sync_with_master(&mut session, &host, port, generation);
+        if database.set_link_state(generation, LinkState::Connect, None) {
+            match result {
+                Ok(()) => log::info!("Connection with MASTER {}:{} lost", host, port),
+                Err(err) => log::error!("Error with MASTER {}:{}: {}", host, port, err),
+            }
+            thread::sleep(REPL_RETRY_DELAY);
+        }
+    }
+}
+
+fn sync_with_master(session: &mut Session, host: &str, port: u16, generation: u64) -> Result<()> {
+    let database = session.db;
+    let connection = TcpStream::connect((host, port))?;
+    if !database.set_link_state(
+        generation,
+        LinkState::Connecting,
+        Some(connection.try_clone()?),
+    ) {
+        return Ok(());
+    }
+    let mut reader = BufReader::new(connection.try_clone()?);
+    let writer = Mutex::new(connection);
+    let send = |args: &[&str]| -> Result<()> {
+        let request = Value::Array(args.iter().map(|arg| Value::Blob((*arg).into())).collect());
+        let mut buf = vec![];
+        buf.write_value(&request)?;
+        Ok(writer.lock().unwrap().write_all(&buf)?)
+    };
+
+    send(&["PING"])?;
+    read_status(&mut reader)?;
+    send(&[
+        "REPLCONF",
+        "listening-port",
+        &database.announced_port().to_string(),
+    ])?;
+    read_status(&mut reader)?;
+    send(&["REPLCONF", "capa", "psync2"])?;
+    read_status(&mut reader)?;
+
+    let (replid, offset) = database.psync_args();
+    send(&["PSYNC", &replid, &offset.to_string()])?;
+    let reply = read_status(&mut reader)?;
+    let mut words = reply.split_whitespace();
+    let synced = match words.next() {
+        Some("FULLRESYNC") => {
+            let (replid, offset) = match (words.next(), words.next().map(str::parse)) {
+                (Some(replid), Some(Ok(offset))) => (replid.to_string(), offset),
+                _ => return Err(protocol_error(format!("Invalid PSYNC reply: {}", reply))),
+            };
+            database.set_link_state(generation, LinkState::Sync, None);
+            log::info!("Full resynchronization with MASTER {}:{}", host, port);
+            let rdb = read_snapshot(&mut reader)?;
+            database.full_sync(generation, &rdb, replid, offset)?
+        }
+        Some("CONTINUE") => {
+            log::info!("Partial resynchronization with MASTER {}:{}", host, port);
+            let replid = words.next().map_or(replid, str::to_string);
+            database.continue_sync(generation, replid)
+        }
+        _ => return Err(protocol_error(format!("Invalid PSYNC reply: {}", reply))),
+    };
+    if !synced {
+        return Ok(());
+    }
+    log::info!("MASTER <-> REPLICA sync succeeded");
+
+    let send_ack = || {
+        send(&[
+            "REPLCONF",
+            "ACK",
+            &database.replication_offset().to_string(),
+        ])
+    };
+    thread::scope(|scope| {
+        let (stop, stopped) = mpsc::channel::<()>();
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REPL_ACK_INTERVAL) {
+                if send_ack().is_err() {
+                    break;
+                }
+            }
+        });
+
+        let result = loop {
+            let request = match reader.read_value() {
+                Ok(request) => request,
+                Err(Error::Eof) => break Ok(()),
+                Err(err) => break Err(err),
+            };
+            // the offset of the stream counts the bytes of the commands as the master sent them.
+            let mut bytes = vec![];
+            // writing to a vector can't fail.
+            bytes.write_value(&request).unwrap();
+            if replconf_args(&request, "GETACK").is_some() {
+                if let Err(err) = send_ack() {
+                    break Err(err);
+                }
+            } else {
+                session.handle_request(request);
+            }
+            if !database.forward_stream(generation, &bytes) {
+                break Ok(());
+            }
+        };
+        drop(stop);
+        result
+    })
+}
+
+/// Reads a status reply, failing on an error reply.
+fn read_status(reader: &mut impl BufRead) -> Result<String> {
+    let line = read_line(reader)?;
+    match line.strip_prefix('+') {
+        Some(status) => Ok(status.to_string()),
+        None => Err(protocol_error(format!(
+            "Unexpected reply from MASTER: {}",
+            line
+        ))),
+    }
+}
+
+/// Reads a line, skipping the empty lines sent by a master to keep the connection alive while it
+/// prepares a snapshot.
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::Eof);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if !line.is_empty() {
+            return Ok(line.to_string());
+        }
+    }
+}
+
+/// Reads the snapshot sent for a full resynchronization, in the RDB format.
+fn read_snapshot(reader: &mut impl BufRead) -> Result<Vec<u8>> {
+    let line = read_line(reader)?;
+    let len: usize = line
+        .strip_prefix('$')
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| protocol_error(format!("Invalid snapshot header: {}", line)))?;
+    let mut rdb = vec![0; len];
+    reader.read_exact(&mut rdb)?;
+    Ok(rdb)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::tests::{start_server, TestClient};
+    use crate::value::Value;
+
+    #[test]
+    fn test_replication() {
+        let master_port = start_server(21000 + (std::process::id() % 1000) as u16 * 4, |_| ());
+        let replica_port = start_server(master_port + 1, |_| ());
+        let mut master = TestClient::connect(master_port);
+        let mut replica = TestClient::connect(replica_port);
+
+        master.call(&["SET", "a", "1"]);
+        master.call(&["SELECT", "2"]);
+        master.call(&["RPUSH", "l", "x", "y"]);
+
+        let port = master_port.to_string();
+        assert_eq!(
+            Value::Simple("OK".into()),
+            replica.call(&["REPLICAOF", "127.0.0.1", &port])
+        );
+        assert_eq!(
+            Value::Simple("OK Already connected to specified master".into()),
+            replica.call(&["REPLICAOF", "127.0.0.1", &port])
+        );
+        replica.wait_for(&["GET", "a"], Value::Blob("1".into()));
+        replica.call(&["SELECT", "2"]);
+        assert_eq!(Value::Number(2), replica.call(&["LLEN", "l"]));
+        assert_eq!(
+            Value::Err(
+                "READONLY".to_string(),
+                "You can't write against a read only replica.".to_string()
+            ),
+            replica.call(&["SET", "b", "2"])
+        );
+
+        // the commands are sent as they run, in the database they run against.
+        master.call(&["LPUSH", "l", "w"]);
+        master.call(&["SELECT", "0"]);
+        master.call(&["SET", "b", "2"]);
+        assert_eq!(Value::Number(1), master.call(&["WAIT", "1", "5000"]));
+        assert_eq!(Value::Blob("w".into()), replica.call(&["LINDEX", "l", "0"]));
+        replica.call(&["SELECT", "0"]);
+        assert_eq!(Value::Blob("2".into()), replica.call(&["GET", "b"]));
+        assert_eq!(Value::Number(1), master.call(&["WAIT", "2", "10"]));
+
+        let offset = match master.call(&["ROLE"]) {
+            Value::Array(role) => {
+                assert_eq!(Value::Blob("master".into()), role[0]);
+                assert!(matches!(&role[2], Value::Array(replicas) if replicas.len() == 1));
+                role[1].clone()
+            }
+            role => panic!("unexpected role {:?}", role),
+        };
+        assert_eq!(
+            Value::Array(vec![
+                Value::Blob("slave".into()),
+                Value::Blob("127.0.0.1".into()),
+                Value::Number(master_port as i64),
+                Value::Blob("connected".into()),
+                offset,
+            ]),
+            replica.call(&["ROLE"])
+        );
+
+        assert_eq!(
+            Value::Simple("OK".into()),
+            replica.call(&["REPLICAOF", "NO", "ONE"])
+        );
+        assert_eq!(Value::Simple("OK".into()), replica.call(&["SET", "b", "3"]));
+        master.wait_for(&["WAIT", "0", "0"], Value::Number(0));
+        assert_eq!(Value::Blob("2".into()), master.call(&["GET", "b"]));
+    }
+}
@@ -1,90 +1,1121 @@
-use crate::bufstream::BufStream;
 use crate::config::Config;
-use crate::db::{Session, SessionFactory};
+use crate::db::{AbortHandle, ReplicaSync, Session, SessionFactory};
 use crate::error::Error;
-use crate::value::{ValueRead, ValueWrite};
+use crate::replication;
+use crate::value::{ProtocolLimits, RequestParser, Value, ValueWrite};
 use log;
-use std::io;
-use std::net::{TcpListener, TcpStream};
-use std::thread;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::net;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+// How long the replies held for the rest of a pipeline wait when nothing else comes.
+const MAX_HOLD_TIME: Duration = Duration::from_millis(10);
+
+// How often the background tasks like active expiration are run, the same as redis' default hz.
+const SERVER_CRON_INTERVAL: Duration = Duration::from_millis(100);
+
+// How many workers are kept waiting for the next requests, the other ones exit once idle.
+const MAX_IDLE_WORKERS: usize = 16;
+
+// How long a batch of requests waits for a busy worker before another worker is spawned.
+const MAX_QUEUE_TIME: Duration = Duration::from_millis(10);
+
+// How many workers are spawned at most. Once they are all busy, like with as many clients running
+// WAIT or slow commands, the other clients wait for one of them to be done.
+const MAX_WORKERS: usize = 128;
+
+// How much is read from a connection at once. The buffers growing beyond it are released once
+// empty, so that the idle connections take little memory.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+// The length of the queue of the connections waiting to be accepted, the same as redis' default
+// tcp-backlog.
+const TCP_BACKLOG: i32 = 511;
+
+const WAKER: Token = Token(usize::MAX);
 
 pub struct Server<'a> {
-    addr: String,
+    bind: Vec<String>,
+    port: u16,
     session_factory: &'a mut SessionFactory,
 }
 
 impl<'a> Server<'a> {
     pub fn new(config: &Config, session_factory: &'a mut SessionFactory) -> Self {
         Self {
-            addr: format!("{}:{}", config.host, config.port),
+            bind: config.bind.clone(),
+            port: config.port,
             session_factory,
         }
     }
 
+    /// Serves the clients from a single event loop, the connections being non-blocking. Only the
+    /// commands run on other threads, the workers, so that a slow command doesn't block the other
+    /// clients. The clients blocked on keys, like by BLPOP, are parked in the event loop instead
+    /// of holding a worker.
     pub fn run(&self) -> io::Result<()> {
+        let listeners = self
+            .bind
+            .iter()
+            .map(|host| {
+                log::info!("Starting server at {}:{}", host, self.port);
+                let listener = net::TcpListener::bind((host.as_str(), self.port))?;
+                listener.set_nonblocking(true)?;
+                // listening again only changes the backlog, too small by default for the
+                // clients connecting at once.
+                // SAFETY: the file descriptor is the listener's own, valid while it lives.
+                if unsafe { libc::listen(listener.as_raw_fd(), TCP_BACKLOG) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(TcpListener::from_std(listener))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let session_factory: &SessionFactory = self.session_factory;
+        let mut event_loop = EventLoop::new(session_factory, listeners)?;
+
         thread::scope(|server_scope| {
-            log::info!("Starting server at {}", &self.addr);
+            let database = session_factory.database();
+            server_scope.spawn(move || loop {
+                thread::sleep(SERVER_CRON_INTERVAL);
+                database.active_expire_cycle();
+                database.save_if_needed();
+                database.aof_cron();
+                database.replication_cron();
+                database.track_metrics();
+            });
 
-            let server = TcpListener::bind(&self.addr)?;
-            for client in server.incoming() {
-                let connection = match client {
-                    Ok(conn) => conn,
-                    Err(err) => {
-                        log::error!("Cannot accept connection: {:?}", err);
-                        continue;
+            server_scope.spawn(move || replication::run_master_link(session_factory));
+
+            event_loop.run(server_scope)
+        })
+    }
+}
+
+/// What the connection of a client shares with the threads sending to its output.
+#[derive(Default)]
+struct Output {
+    /// Set while a batch of requests is handled, for the replies to be written at once.
+    held: AtomicBool,
+    /// The number of bytes sent to the output and not taken by the event loop yet.
+    pending: AtomicUsize,
+}
+
+struct Connection<'a> {
+    stream: TcpStream,
+    addr: String,
+    /// `None` while a worker handles a batch of requests.
+    session: Option<Session<'a>>,
+    messages: Receiver<Vec<u8>>,
+    shared: Arc<Output>,
+    /// Aborts the command the session is blocked on, when the client is gone during a batch.
+    abort_handle: Arc<AbortHandle>,
+    input: Vec<u8>,
+    parser: RequestParser,
+    /// The bytes to write to the connection, the ones before `written` being written already.
+    output: Vec<u8>,
+    written: usize,
+    /// Set until reading would block. Nothing is read while a batch is handled, the next
+    /// requests waiting in the socket like they would for a thread busy with a command.
+    readable: bool,
+    /// The protocol error replied once the requests before it are handled.
+    protocol_error: Option<String>,
+    /// Set when the connection is to be closed, once the output is written.
+    closing: bool,
+    last_interaction: Instant,
+    /// The requests received after the command the client is parked on, handled once it
+    /// replies.
+    pending: Vec<Value>,
+}
+
+/// The requests of a client received at once, handled by a worker.
+struct Batch<'a> {
+    token: Token,
+    session: Session<'a>,
+    requests: Vec<Value>,
+    /// Set to retry the command the client is parked on before the requests.
+    retry: bool,
+}
+
+/// A batch handled by a worker, giving the session back to the event loop.
+struct Handled<'a> {
+    token: Token,
+    session: Session<'a>,
+    replica_sync: Option<ReplicaSync<'a>>,
+    /// The requests not handled yet, when the client was parked.
+    pending: Vec<Value>,
+}
+
+/// The batches waiting for a worker.
+struct Queue<'a> {
+    batches: VecDeque<(Instant, Batch<'a>)>,
+    /// The number of workers waiting for a batch.
+    idle: usize,
+    /// The number of workers spawned, but not waiting for a batch yet.
+    starting: usize,
+    workers: usize,
+}
+
+/// The threads running the commands, so that a slow command only blocks its own client. Up to
+/// one worker per core is spawned as the batches come. Beyond that, a batch waits for a worker to
+/// be done, another one being spawned only when the batch waited for too long, like when the
+/// workers are busy with slow commands or with WAIT, up to [`MAX_WORKERS`]. The clients blocked on keys don't keep
+/// their worker: the command returns once the client is parked, and is retried by another batch
+/// once the client is woken up.
+struct Workers<'a> {
+    queue: Arc<(Mutex<Queue<'a>>, Condvar)>,
+    cores: usize,
+    handled: Sender<Handled<'a>>,
+    waker: Arc<Waker>,
+}
+
+impl<'a> Workers<'a> {
+    fn run<'scope>(&self, scope: &'scope Scope<'scope, '_>, batch: Batch<'a>)
+    where
+        'a: 'scope,
+    {
+        let (queue, ready) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        queue.batches.push_back((Instant::now(), batch));
+        if queue.idle > 0 {
+            ready.notify_one();
+        }
+        if queue.batches.len() > queue.idle + queue.starting && queue.workers < self.cores {
+            self.spawn(scope, &mut queue);
+        }
+    }
+
+    /// Spawns a worker for each batch waiting for longer than [`MAX_QUEUE_TIME`], as long as
+    /// there are less than [`MAX_WORKERS`]. Returns how long until the next batch waits for too
+    /// long, if any.
+    fn unstall<'scope>(&self, scope: &'scope Scope<'scope, '_>) -> Option<Duration>
+    where
+        'a: 'scope,
+    {
+        let mut queue = self.queue.0.lock().unwrap();
+        let stalled = queue
+            .batches
+            .iter()
+            .take_while(|(queued, _)| queued.elapsed() >= MAX_QUEUE_TIME)
+            .count();
+        let waiting = stalled.saturating_sub(queue.idle + queue.starting);
+        for _ in 0..waiting.min(MAX_WORKERS.saturating_sub(queue.workers)) {
+            self.spawn(scope, &mut queue);
+        }
+        let (queued, _) = queue.batches.get(stalled)?;
+        Some(MAX_QUEUE_TIME.saturating_sub(queued.elapsed()))
+    }
+
+    fn spawn<'scope>(&self, scope: &'scope Scope<'scope, '_>, queue: &mut Queue<'a>)
+    where
+        'a: 'scope,
+    {
+        queue.workers += 1;
+        queue.starting += 1;
+        let shared = self.queue.clone();
+        let handled = self.handled.clone();
+        let waker = self.waker.clone();
+        scope.spawn(move || {
+            let (queue, ready) = &*shared;
+            queue.lock().unwrap().starting -= 1;
+            loop {
+                let batch = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if let Some((_, batch)) = queue.batches.pop_front() {
+                            break batch;
+                        }
+                        if queue.idle >= MAX_IDLE_WORKERS {
+                            queue.workers -= 1;
+                            return;
+                        }
+                        queue.idle += 1;
+                        queue = ready.wait(queue).unwrap();
+                        queue.idle -= 1;
                     }
                 };
+                if handled.send(Self::handle(batch)).is_err() {
+                    return;
+                }
+                let _ = waker.wake();
+            }
+        });
+    }
 
-                let session = self.session_factory.create_session();
-                server_scope.spawn(move || {
-                    Self::handle_connection(session, connection);
-                });
+    /// Handles the requests of a batch in order, sending their replies to the client's output.
+    fn handle(batch: Batch<'a>) -> Handled<'a> {
+        let Batch {
+            token,
+            mut session,
+            requests,
+            retry,
+        } = batch;
+        let mut requests = requests.into_iter();
+        let mut replica_sync = None;
+        let retried = if retry { session.retry_blocked() } else { None };
+        let sent = match retried {
+            Some(response) => session.subscriber.send(response),
+            None => !session.is_blocked(),
+        };
+        if sent {
+            for request in requests.by_ref() {
+                let response = session.handle_request(request);
+
+                // the replication takes the connection over, the replica waiting for its payload
+                // before sending anything else.
+                replica_sync = session.take_replica_sync();
+                if replica_sync.is_some() {
+                    break;
+                }
+
+                // the client is parked, the next requests wait for the command to reply.
+                if session.is_blocked() {
+                    break;
+                }
+                if !session.subscriber.send(response) || session.quit {
+                    break;
+                }
             }
+        }
+        let pending = if session.is_blocked() {
+            requests.collect()
+        } else {
+            vec![]
+        };
+        Handled {
+            token,
+            session,
+            replica_sync,
+            pending,
+        }
+    }
+}
+
+/// Waits for the events of the listeners and of the connections, reading the requests and
+/// writing the replies without blocking.
+struct EventLoop<'a> {
+    session_factory: &'a SessionFactory,
+    poll: Poll,
+    listeners: Vec<TcpListener>,
+    connections: HashMap<Token, Connection<'a>>,
+    next_token: usize,
+    workers: Workers<'a>,
+    handled: Receiver<Handled<'a>>,
+    /// The connections whose output received something to write.
+    notified: Arc<Mutex<Vec<Token>>>,
+    /// The parked clients woken up, whose command is to be retried.
+    unblocked: Arc<Mutex<Vec<Token>>>,
+    /// When the commands of the parked clients time out, in order.
+    deadlines: BTreeSet<(Instant, Token)>,
+    waker: Arc<Waker>,
+    /// When the outputs held for a batch are to be written anyway, in order.
+    holds: VecDeque<(Instant, Token)>,
+    buffer: Vec<u8>,
+}
 
-            Ok(())
+impl<'a> EventLoop<'a> {
+    fn new(
+        session_factory: &'a SessionFactory,
+        mut listeners: Vec<TcpListener>,
+    ) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        for (i, listener) in listeners.iter_mut().enumerate() {
+            poll.registry()
+                .register(listener, Token(i), Interest::READABLE)?;
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (handled_sender, handled) = mpsc::channel();
+        Ok(Self {
+            session_factory,
+            poll,
+            next_token: listeners.len(),
+            listeners,
+            connections: HashMap::new(),
+            workers: Workers {
+                queue: Arc::new((
+                    Mutex::new(Queue {
+                        batches: VecDeque::new(),
+                        idle: 0,
+                        starting: 0,
+                        workers: 0,
+                    }),
+                    Condvar::new(),
+                )),
+                cores: thread::available_parallelism().map_or(4, |cores| cores.get()),
+                handled: handled_sender,
+                waker: waker.clone(),
+            },
+            handled,
+            notified: Arc::default(),
+            unblocked: Arc::default(),
+            deadlines: BTreeSet::new(),
+            waker,
+            holds: VecDeque::new(),
+            buffer: vec![0; READ_CHUNK_SIZE],
         })
     }
 
-    fn handle_connection(mut session: Session, connection: TcpStream) {
-        let addr = match connection.local_addr() {
-            Ok(addr) => addr.to_string(),
-            Err(err) => {
-                log::error!("Cannot get client address: {}", err);
-                "unknown_address".to_string()
+    fn run<'scope>(&mut self, scope: &'scope Scope<'scope, '_>) -> io::Result<()>
+    where
+        'a: 'scope,
+    {
+        let mut events = Events::with_capacity(1024);
+        let mut last_cron = Instant::now();
+        loop {
+            let hold = self
+                .holds
+                .front()
+                .map(|&(deadline, _)| deadline.saturating_duration_since(Instant::now()));
+            let deadline = self
+                .deadlines
+                .first()
+                .map(|&(deadline, _)| deadline.saturating_duration_since(Instant::now()));
+            let timeout = [hold, deadline, self.workers.unstall(scope)]
+                .into_iter()
+                .flatten()
+                .fold(SERVER_CRON_INTERVAL, Duration::min);
+            if let Err(err) = self.poll.poll(&mut events, Some(timeout)) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
             }
-        };
 
-        log::info!("Client connected: {}", addr);
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => {}
+                    Token(listener) if listener < self.listeners.len() => self.accept(listener),
+                    token => {
+                        if event.is_writable() {
+                            self.flush(token);
+                        }
+                        if event.is_readable() || event.is_read_closed() || event.is_error() {
+                            let busy = match self.connections.get_mut(&token) {
+                                Some(conn) => {
+                                    conn.readable = true;
+                                    conn.session.as_ref().is_none_or(Session::is_blocked)
+                                }
+                                None => false,
+                            };
+                            // nothing is read during a batch or while the client is parked, so
+                            // a client gone while blocked in a command is only noticed from the
+                            // event.
+                            if busy && (event.is_read_closed() || event.is_error()) {
+                                log::info!(
+                                    "Client disconnected: {}",
+                                    self.connections[&token].addr
+                                );
+                                self.close(token);
+                            } else {
+                                self.process(scope, token);
+                            }
+                        }
+                    }
+                }
+            }
 
-        let mut stream = BufStream::new(connection);
-        loop {
-            let val = stream.read_value();
-            let val = match val {
-                Ok(val) => val,
-                Err(Error::Eof) => {
-                    log::info!("Client disconnected: {}", addr);
+            while let Ok(handled) = self.handled.try_recv() {
+                self.finish(scope, handled);
+            }
+            let unblocked = mem::take(&mut *self.unblocked.lock().unwrap());
+            for token in unblocked {
+                self.retry(scope, token);
+            }
+            let now = Instant::now();
+            while let Some(&(deadline, token)) = self.deadlines.first() {
+                if deadline > now {
                     break;
                 }
+                self.deadlines.pop_first();
+                self.retry(scope, token);
+            }
+            let notified = mem::take(&mut *self.notified.lock().unwrap());
+            for token in notified {
+                self.flush(token);
+            }
+            self.release_holds();
+
+            if last_cron.elapsed() >= SERVER_CRON_INTERVAL {
+                last_cron = Instant::now();
+                self.close_idle_clients();
+            }
+        }
+    }
+
+    fn accept(&mut self, listener: usize) {
+        loop {
+            let (mut stream, addr) = match self.listeners[listener].accept() {
+                Ok(client) => client,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    log::error!("Cannot accept connection: {:?}", err);
+                    return;
+                }
+            };
+
+            let database = self.session_factory.database();
+            let stats = database.stats();
+            stats.connections_received.fetch_add(1, Ordering::Relaxed);
+            let maxclients = database.config().maxclients;
+            if stats.connected_clients.fetch_add(1, Ordering::Relaxed) >= maxclients {
+                stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+                stats.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                let _ = stream.write_value(&Value::err("max number of clients reached"));
+                continue;
+            }
+
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            let interest = Interest::READABLE | Interest::WRITABLE;
+            if let Err(err) = self.poll.registry().register(&mut stream, token, interest) {
+                log::error!("Cannot register the connection of client {}: {}", addr, err);
+                stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+
+            let mut session = self.session_factory.create_client_session();
+            session.peer_addr = Some(addr);
+            let messages = session.take_messages().unwrap();
+            let abort_handle = session.abort_handle.clone();
+            let shared = Arc::new(Output::default());
+            let output = shared.clone();
+            let notified = self.notified.clone();
+            let waker = self.waker.clone();
+            let ceiling = database.config().output_buffer_ceiling;
+            session.subscriber.set_notify(move |len| {
+                // the event loop may take the message before it's counted, the counter wrapping
+                // below zero meanwhile.
+                let pending = output
+                    .pending
+                    .fetch_add(len, Ordering::AcqRel)
+                    .wrapping_add(len);
+                // the replies held are written once the batch is handled, unless they reach the
+                // ceiling before.
+                if output.held.load(Ordering::Acquire) && pending < ceiling {
+                    return;
+                }
+                let mut notified = notified.lock().unwrap();
+                notified.push(token);
+                // the event loop takes all of them at once after waking up.
+                if notified.len() == 1 {
+                    let _ = waker.wake();
+                }
+            });
+
+            let unblocked = self.unblocked.clone();
+            let waker = self.waker.clone();
+            session.set_on_ready(Arc::new(move || {
+                let mut unblocked = unblocked.lock().unwrap();
+                unblocked.push(token);
+                if unblocked.len() == 1 {
+                    let _ = waker.wake();
+                }
+            }));
+
+            log::info!("Client connected: {}", addr);
+            self.connections.insert(
+                token,
+                Connection {
+                    stream,
+                    addr: addr.to_string(),
+                    session: Some(session),
+                    messages,
+                    shared,
+                    abort_handle,
+                    input: vec![],
+                    parser: RequestParser::default(),
+                    output: vec![],
+                    written: 0,
+                    readable: false,
+                    protocol_error: None,
+                    closing: false,
+                    last_interaction: Instant::now(),
+                    pending: vec![],
+                },
+            );
+        }
+    }
+
+    /// Reads the requests of a client until some of them are complete, and hands them to a
+    /// worker. Nothing else is read until they are handled.
+    fn process<'scope>(&mut self, scope: &'scope Scope<'scope, '_>, token: Token)
+    where
+        'a: 'scope,
+    {
+        loop {
+            let conn = match self.connections.get_mut(&token) {
+                Some(conn) => conn,
+                None => return,
+            };
+            let session = match &conn.session {
+                Some(session) if !conn.closing && !session.is_blocked() => session,
+                _ => return,
+            };
+
+            if let Some(msg) = conn.protocol_error.take() {
+                // like redis, the client is told what was wrong before being disconnected.
+                log::info!("Protocol error from client {}: {}", conn.addr, msg);
+                session
+                    .subscriber
+                    .send(Value::err(format!("Protocol error: {}", msg)));
+                conn.closing = true;
+                return;
+            }
+
+            let limits = ProtocolLimits {
+                max_bulk_len: session.db.config().proto_max_bulk_len,
+                ..ProtocolLimits::default()
+            };
+            let mut requests = vec![];
+            let mut parsed = 0;
+            loop {
+                match conn.parser.parse(&conn.input[parsed..], &limits) {
+                    Ok((used, request)) => {
+                        parsed += used;
+                        match request {
+                            Some(request) => requests.push(request),
+                            None => break,
+                        }
+                    }
+                    Err(err) => {
+                        conn.protocol_error = Some(match err {
+                            Error::Protocol(msg) => msg,
+                            err => err.to_string(),
+                        });
+                        break;
+                    }
+                }
+            }
+            conn.input.drain(..parsed);
+            if conn.input.is_empty() && conn.input.capacity() > READ_CHUNK_SIZE {
+                conn.input = vec![];
+            }
+
+            if !requests.is_empty() {
+                let session = conn.session.take().unwrap();
+                conn.shared.held.store(true, Ordering::Release);
+                self.holds
+                    .push_back((Instant::now() + MAX_HOLD_TIME, token));
+                let batch = Batch {
+                    token,
+                    session,
+                    requests,
+                    retry: false,
+                };
+                self.workers.run(scope, batch);
+                return;
+            }
+            if conn.protocol_error.is_some() {
+                continue;
+            }
+            if !conn.readable {
+                return;
+            }
+
+            match conn.stream.read(&mut self.buffer) {
+                Ok(0) => {
+                    log::info!("Client disconnected: {}", conn.addr);
+                    self.close(token);
+                    return;
+                }
+                Ok(len) => {
+                    conn.input.extend_from_slice(&self.buffer[..len]);
+                    conn.last_interaction = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    conn.readable = false;
+                    return;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
                 Err(err) => {
                     log::error!(
                         "Error reading command from client {}: {}. Disconnecting",
-                        addr,
+                        conn.addr,
                         err
                     );
-                    break;
+                    self.close(token);
+                    return;
                 }
-            };
+            }
+        }
+    }
 
-            let response = session.handle_request(val);
+    /// Takes the session back from a worker, writing the replies of the batch and going on with
+    /// the next requests.
+    fn finish<'scope>(&mut self, scope: &'scope Scope<'scope, '_>, handled: Handled<'a>)
+    where
+        'a: 'scope,
+    {
+        let token = handled.token;
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.shared.held.store(false, Ordering::Release);
+        conn.last_interaction = Instant::now();
+        conn.closing |= handled.session.quit;
+        conn.session = Some(handled.session);
 
-            if let Err(err) = stream.write_value(&response) {
+        if let Some(sync) = handled.replica_sync {
+            self.serve_replica(scope, token, sync);
+            return;
+        }
+        self.flush(token);
+
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let session = match &conn.session {
+            Some(session) if session.is_blocked() => session,
+            _ => return self.process(scope, token),
+        };
+        conn.pending = handled.pending;
+        if let Some(deadline) = session.blocked_deadline() {
+            self.deadlines.insert((deadline, token));
+        }
+        // the client may have been woken up before being parked.
+        if session.is_ready() {
+            self.retry(scope, token);
+        }
+    }
+
+    /// Retries the command a parked client is blocked on, once it was woken up or the command
+    /// timed out, and goes on with the requests received meanwhile.
+    fn retry<'scope>(&mut self, scope: &'scope Scope<'scope, '_>, token: Token)
+    where
+        'a: 'scope,
+    {
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        match &conn.session {
+            Some(session) if !conn.closing && session.is_blocked() => {}
+            _ => return,
+        }
+        let session = conn.session.take().unwrap();
+        if let Some(deadline) = session.blocked_deadline() {
+            self.deadlines.remove(&(deadline, token));
+        }
+        conn.shared.held.store(true, Ordering::Release);
+        self.holds
+            .push_back((Instant::now() + MAX_HOLD_TIME, token));
+        let batch = Batch {
+            token,
+            session,
+            requests: mem::take(&mut conn.pending),
+            retry: true,
+        };
+        self.workers.run(scope, batch);
+    }
+
+    /// Writes the output of a client, as much of it as the connection takes without blocking.
+    /// The rest is written once the connection is writable again.
+    fn flush(&mut self, token: Token) {
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        for message in conn.messages.try_iter() {
+            conn.shared
+                .pending
+                .fetch_sub(message.len(), Ordering::AcqRel);
+            conn.output.extend_from_slice(&message);
+        }
+
+        while conn.written < conn.output.len() {
+            match conn.stream.write(&conn.output[conn.written..]) {
+                Ok(0) => {
+                    log::error!("Cannot write to client {}. Disconnecting", conn.addr);
+                    conn.output.clear();
+                    conn.closing = true;
+                }
+                Ok(len) => conn.written += len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::error!(
+                        "Error writing response to client {}: {}. Disconnecting",
+                        conn.addr,
+                        err
+                    );
+                    conn.output.clear();
+                    conn.closing = true;
+                }
+            }
+        }
+        conn.output.clear();
+        conn.written = 0;
+        if conn.output.capacity() > READ_CHUNK_SIZE {
+            conn.output = vec![];
+        }
+        if conn.closing {
+            self.close(token);
+        }
+    }
+
+    /// Writes the outputs held for longer than [`MAX_HOLD_TIME`], like when a command of the
+    /// batch blocks. The next replies of the batch are then written as they come.
+    fn release_holds(&mut self) {
+        let now = Instant::now();
+        while let Some(&(deadline, token)) = self.holds.front() {
+            if deadline > now {
+                break;
+            }
+            self.holds.pop_front();
+            let held = self
+                .connections
+                .get(&token)
+                .is_some_and(|conn| conn.shared.held.swap(false, Ordering::AcqRel));
+            if held {
+                self.flush(token);
+            }
+        }
+    }
+
+    /// Closes the connections idle for longer than the `timeout` configuration, unless they
+    /// are waiting for messages.
+    fn close_idle_clients(&mut self) {
+        let timeout = self.session_factory.database().config().timeout;
+        if timeout == 0 {
+            return;
+        }
+        let timeout = Duration::from_secs(timeout);
+        let idle = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| {
+                conn.session.as_ref().is_some_and(|session| {
+                    !session.subscriptions.is_subscribed() && !session.is_blocked()
+                }) && conn.last_interaction.elapsed() > timeout
+            })
+            .map(|(&token, _)| token)
+            .collect::<Vec<_>>();
+        for token in idle {
+            log::info!("Closing idle client {}", self.connections[&token].addr);
+            self.close(token);
+        }
+    }
+
+    /// Closes the connection of a client. While a worker handles a batch of its requests, the
+    /// connection is only closed once the batch is handled, the command the client is blocked on
+    /// being aborted.
+    fn close(&mut self, token: Token) {
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        if conn.session.is_none() {
+            conn.closing = true;
+            conn.abort_handle.abort();
+            return;
+        }
+        let mut conn = self.connections.remove(&token).unwrap();
+        let _ = self.poll.registry().deregister(&mut conn.stream);
+        drop(conn);
+        let stats = self.session_factory.database().stats();
+        stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Hands the connection of a replica over to the replication, on a thread of its own, once
+    /// the replies sent before are written.
+    fn serve_replica<'scope>(
+        &mut self,
+        scope: &'scope Scope<'scope, '_>,
+        token: Token,
+        sync: ReplicaSync<'a>,
+    ) where
+        'a: 'scope,
+    {
+        let mut conn = self.connections.remove(&token).unwrap();
+        let _ = self.poll.registry().deregister(&mut conn.stream);
+        drop(conn.session.take());
+        for message in conn.messages.try_iter() {
+            conn.output.extend_from_slice(&message);
+        }
+
+        let stats = self.session_factory.database().stats();
+        let addr = conn.addr;
+        let connection = net::TcpStream::from(conn.stream);
+        let reader = connection
+            .set_nonblocking(false)
+            .and_then(|()| (&connection).write_all(&conn.output[conn.written..]))
+            .and_then(|()| connection.try_clone());
+        match reader {
+            Ok(reader) => {
+                scope.spawn(move || {
+                    replication::serve_replica(sync, connection, BufReader::new(reader), &addr);
+                    stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Err(err) => {
                 log::error!(
-                    "Error writing response to client{}: {}. Disconnecting",
+                    "Cannot hand the connection of replica {} over: {}",
                     addr,
                     err
                 );
-                break;
+                stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
             }
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::db::Database;
+    use std::net::{TcpListener, TcpStream};
+
+    /// Starts a server on a free port from `base`, returning the port. The configuration can be
+    /// changed with `configure`.
+    pub fn start_server(base: u16, configure: impl FnOnce(&mut Config)) -> u16 {
+        let port = (base..base + 1000)
+            .find(|&port| TcpListener::bind(("127.0.0.1", port)).is_ok())
+            .unwrap();
+        let mut config = Config {
+            port,
+            save: vec![],
+            ..Config::default()
+        };
+        configure(&mut config);
+        let session_factory = Box::leak(Box::new(SessionFactory::new(Database::new(&config))));
+        thread::spawn(move || Server::new(&config, session_factory).run().unwrap());
+        port
+    }
+
+    pub struct TestClient(Client);
+
+    impl TestClient {
+        /// Connects to a server, waiting for it to listen.
+        pub fn connect(port: u16) -> Self {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                match Client::connect("127.0.0.1", port, Duration::from_secs(10)) {
+                    Ok(client) => return Self(client),
+                    Err(err) if Instant::now() > deadline => panic!("{}", err),
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        }
+
+        pub fn call(&mut self, args: &[&str]) -> Value {
+            self.0.call(args).unwrap()
+        }
+
+        /// Calls the command until it replies `expected`.
+        pub fn wait_for(&mut self, args: &[&str], expected: Value) {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while self.call(args) != expected {
+                assert!(
+                    Instant::now() < deadline,
+                    "timed out waiting for {:?}",
+                    args
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[test]
+    fn test_inline_commands() {
+        use std::io::{BufRead, Read};
+
+        let port = start_server(23000 + (std::process::id() % 1000) as u16 * 2, |_| ());
+        TestClient::connect(port);
+        let mut connection = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        connection
+            .write_all(b"PING\r\nSET k \"a b\"\nGET k\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(connection.try_clone().unwrap());
+        let mut reply = [0; 21];
+        reader.read_exact(&mut reply).unwrap();
+        assert_eq!(b"+PONG\r\n+OK\r\n$3\r\na b\r\n", &reply);
+
+        // a malformed request is replied with an error, and the connection is closed.
+        connection.write_all(b"*1\r\n$x\r\n").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!("-ERR Protocol error: invalid bulk length\r\n", line);
+        assert_eq!(0, reader.read(&mut [0]).unwrap());
+    }
+
+    #[test]
+    fn test_pipelining() {
+        use std::io::BufRead;
+
+        let port = start_server(29000 + (std::process::id() % 1000) as u16 * 2, |_| ());
+        TestClient::connect(port);
+        let mut connection = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = BufReader::new(connection.try_clone().unwrap());
+        let mut read_line = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line
+        };
+
+        let rpush = Value::Array(vec![
+            Value::Blob("RPUSH".into()),
+            Value::Blob("n".into()),
+            Value::Blob("x".into()),
+        ]);
+        let mut requests = vec![];
+        for _ in 0..1000 {
+            requests.write_value(&rpush).unwrap();
+        }
+        connection.write_all(&requests).unwrap();
+        for n in 1..=1000 {
+            assert_eq!(format!(":{}\r\n", n), read_line());
+        }
+
+        // the replies before a blocking command are not held while it blocks.
+        connection
+            .write_all(b"SET a 1\r\nBLPOP list 0\r\n")
+            .unwrap();
+        assert_eq!("+OK\r\n", read_line());
+        TestClient::connect(port).call(&["RPUSH", "list", "x"]);
+        assert_eq!("*2\r\n", read_line());
+    }
+
+    #[test]
+    fn test_blocked_client_disconnect() {
+        let port = start_server(37000 + (std::process::id() % 1000) as u16 * 2, |_| ());
+        let mut client = TestClient::connect(port);
+        let mut blocked = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        blocked.write_all(b"BLPOP lost 0\r\n").unwrap();
+        client.wait_for(&["LLEN", "lost"], Value::Number(0));
+        thread::sleep(Duration::from_millis(50));
+        drop(blocked);
+
+        // the element pushed once the blocked client is gone stays in the list.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(Value::Number(1), client.call(&["RPUSH", "lost", "a"]));
+        assert_eq!(Value::Number(1), client.call(&["LLEN", "lost"]));
+        match client.call(&["INFO", "clients"]) {
+            Value::Blob(info) => {
+                assert!(String::from_utf8_lossy(&info).contains("blocked_clients:0"))
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn test_many_connections() {
+        use std::io::Read;
+
+        let port = start_server(33000 + (std::process::id() % 1000) as u16 * 2, |_| ());
+        TestClient::connect(port);
+        let connections = (0..1000)
+            .map(|_| TcpStream::connect(("127.0.0.1", port)).unwrap())
+            .collect::<Vec<_>>();
+        // a request received in parts is handled once complete.
+        let mut connection = &connections[0];
+        for part in b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n".chunks(4) {
+            connection.write_all(part).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut reply = [0; 5];
+        connection.read_exact(&mut reply).unwrap();
+        assert_eq!(b"+OK\r\n", &reply);
+
+        for mut connection in &connections {
+            connection.write_all(b"GET key\r\n").unwrap();
+        }
+        for mut connection in &connections {
+            let mut reply = [0; 11];
+            connection.read_exact(&mut reply).unwrap();
+            assert_eq!(b"$5\r\nvalue\r\n", &reply);
+        }
+
+        // the connections don't take a thread each.
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let threads = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Threads:"))
+            .unwrap();
+        assert!(threads.trim().parse::<usize>().unwrap() < 500);
+    }
+
+    #[test]
+    fn test_many_blocked_clients() {
+        use std::io::Read;
+
+        let wait_blocked = |client: &mut TestClient, blocked: usize| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let expected = format!("blocked_clients:{}\r\n", blocked);
+            loop {
+                match client.call(&["INFO", "clients"]) {
+                    Value::Blob(info) if String::from_utf8_lossy(&info).contains(&expected) => {
+                        return
+                    }
+                    _ => assert!(
+                        Instant::now() < deadline,
+                        "timed out waiting for the clients"
+                    ),
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let port = start_server(41000 + (std::process::id() % 1000) as u16 * 2, |_| ());
+        let mut client = TestClient::connect(port);
+        let connections = (0..1000)
+            .map(|i| {
+                let mut connection = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                let request = format!("BLPOP list:{:03} 0\r\nGET after\r\n", i);
+                connection.write_all(request.as_bytes()).unwrap();
+                connection
+            })
+            .collect::<Vec<_>>();
+        wait_blocked(&mut client, 1000);
+
+        // the blocked clients don't take a thread each.
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let threads = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Threads:"))
+            .unwrap();
+        assert!(threads.trim().parse::<usize>().unwrap() < 500);
+
+        // the requests after the blocking command are handled once it replies.
+        client.call(&["SET", "after", "1"]);
+        for i in 0..1000 {
+            client.call(&["RPUSH", &format!("list:{:03}", i), "x"]);
+        }
+        for (i, mut connection) in connections.iter().enumerate() {
+            let expected = format!("*2\r\n$8\r\nlist:{:03}\r\n$1\r\nx\r\n$1\r\n1\r\n", i);
+            let mut reply = vec![0; expected.len()];
+            connection.read_exact(&mut reply).unwrap();
+            assert_eq!(expected.as_bytes(), &reply);
+        }
+
+        // the parked commands time out, and read from the IDs of when they blocked.
+        let mut connection = &connections[0];
+        connection.write_all(b"BLPOP list:000 0.05\r\n").unwrap();
+        let mut reply = [0; 5];
+        connection.read_exact(&mut reply).unwrap();
+        assert_eq!(b"$-1\r\n", &reply);
+
+        client.call(&["XADD", "stream", "1-0", "a", "1"]);
+        connection
+            .write_all(b"XREAD BLOCK 0 STREAMS stream $\r\n")
+            .unwrap();
+        wait_blocked(&mut client, 1);
+        client.call(&["XADD", "stream", "2-0", "b", "2"]);
+        let expected =
+            b"*1\r\n*2\r\n$6\r\nstream\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n";
+        let mut reply = vec![0; expected.len()];
+        connection.read_exact(&mut reply).unwrap();
+        assert_eq!(&expected[..], &reply);
+    }
+}
@@ -1,13 +1,22 @@
 use std::{
     borrow::Borrow,
     fmt::Display,
-    io,
+    hash::{Hash, Hasher},
+    io, mem,
     ops::{Deref, DerefMut},
 };
 
-use crate::error::Result;
+use crate::config::split_raw_args;
+use crate::error::{Error, Result};
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+/// The version of the protocol spoken with a client, negotiated with HELLO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Simple(Bytes),
     Blob(Bytes),
@@ -15,12 +24,92 @@ pub enum Value {
     Array(Vec<Value>),
     Err(String, String),
     Null,
+    // The RESP3 types. They are written as the RESP2 type closest to them to the RESP2 clients,
+    // see [`Value::into_resp2`].
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    Double(f64),
+    Boolean(bool),
+    /// An integer too large for 64 bits, in decimal.
+    BigNumber(String),
+    /// A string along with its three letters format, like `txt` or `mkd`.
+    Verbatim(String, Bytes),
+    /// Out of band data, like the pub/sub messages.
+    Push(Vec<Value>),
+    /// Auxiliary data about the value.
+    Attribute(Vec<(Value, Value)>, Box<Value>),
+}
+
+// The doubles compared by the tests are never NaN.
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Self::Simple(buff) | Self::Blob(buff) => buff.hash(state),
+            Self::Number(num) => num.hash(state),
+            Self::Array(values) | Self::Set(values) | Self::Push(values) => values.hash(state),
+            Self::Err(code, msg) => (code, msg).hash(state),
+            Self::Null => {}
+            Self::Map(pairs) => pairs.hash(state),
+            Self::Double(value) => value.to_bits().hash(state),
+            Self::Boolean(value) => value.hash(state),
+            Self::BigNumber(value) => value.hash(state),
+            Self::Verbatim(format, text) => (format, text).hash(state),
+            Self::Attribute(attributes, value) => (attributes, value).hash(state),
+        }
+    }
 }
 
 impl Value {
     pub fn err(message: impl Into<String>) -> Self {
         Self::Err("ERR".to_string(), message.into())
     }
+
+    /// Converts the RESP3 types to the RESP2 types they are written as to the RESP2 clients: the
+    /// maps are flattened into arrays, the doubles, big numbers and verbatim strings become bulk
+    /// strings, the booleans become integers and the attributes are dropped.
+    pub fn into_resp2(self) -> Self {
+        match self {
+            Self::Array(values) | Self::Set(values) | Self::Push(values) => {
+                Self::Array(values.into_iter().map(Self::into_resp2).collect())
+            }
+            Self::Map(pairs) => Self::Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| [key.into_resp2(), value.into_resp2()])
+                    .collect(),
+            ),
+            Self::Double(value) => Self::Blob(format_double(value).as_str().into()),
+            Self::Boolean(value) => Self::Number(value as i64),
+            Self::BigNumber(value) => Self::Blob(value.as_str().into()),
+            Self::Verbatim(_, text) => Self::Blob(text),
+            Self::Attribute(_, value) => value.into_resp2(),
+            value => value,
+        }
+    }
+}
+
+/// Formats a double the way redis replies them: the shortest representation that parses back
+/// to the same value, switching to exponent notation for very large or small values.
+pub fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    let abs = value.abs();
+    if abs != 0.0 && !(1e-5..1e17).contains(&abs) {
+        let formatted = format!("{:e}", value);
+        return match formatted.split_once('e') {
+            Some((mantissa, exp)) if !exp.starts_with('-') => format!("{}e+{}", mantissa, exp),
+            _ => formatted,
+        };
+    }
+    format!("{}", value)
 }
 
 impl Display for Value {
@@ -30,7 +119,7 @@ impl Display for Value {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash)]
 pub struct Bytes(Vec<u8>);
 
 impl Bytes {
@@ -44,7 +133,19 @@ where
     S: Borrow<str> + ?Sized,
 {
     fn from(s: &S) -> Self {
-        Self(s.borrow().bytes().into_iter().collect())
+        Self(s.borrow().bytes().collect())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(v: Vec<u8>) -> Self {
+        Self(v)
+    }
+}
+
+impl Borrow<[u8]> for Bytes {
+    fn borrow(&self) -> &[u8] {
+        &self.0
     }
 }
 
@@ -62,109 +163,503 @@ impl DerefMut for Bytes {
 }
 
 pub trait ValueWrite: io::Write {
+    /// Writes a value with the RESP2 encoding.
     fn write_value(&mut self, value: &Value) -> Result<()> {
+        self.write_value_as(value, Protocol::Resp2)
+    }
+
+    /// Writes a value with the encoding of the protocol, downgrading the RESP3 types for RESP2.
+    /// The writer is not flushed, so that many values can be written at once.
+    fn write_value_as(&mut self, value: &Value, protocol: Protocol) -> Result<()> {
+        self.encode_value(value, protocol)?;
+        Ok(())
+    }
+}
+
+impl<W: io::Write + ?Sized> ValueWrite for W {}
+
+trait ValueWriteExt: io::Write {
+    fn encode_value(&mut self, value: &Value, protocol: Protocol) -> io::Result<()> {
+        let resp3 = protocol == Protocol::Resp3;
         match value {
             Value::Simple(buff) => {
-                self.write("+".as_bytes())?;
-                self.write(buff.as_slice())?;
-                self.write("\r\n".as_bytes())?;
-            }
-            Value::Blob(buff) => {
-                self.write("$".as_bytes())?;
-                self.write(format!("{}", buff.len()).as_bytes())?;
-                self.write("\r\n".as_bytes())?;
-                self.write(buff.as_slice())?;
-                self.write("\r\n".as_bytes())?;
-            }
-            Value::Number(num) => {
-                self.write(":".as_bytes())?;
-                self.write(format!("{}", num).as_bytes())?;
-                self.write("\r\n".as_bytes())?;
-            }
-            Value::Array(slice) => {
-                self.write("*".as_bytes())?;
-                self.write(format!("{}", slice.len()).as_bytes())?;
-                self.write("\r\n".as_bytes())?;
-                for elem in slice.iter() {
-                    self.write_value(elem)?;
-                }
+                self.write_all(b"+")?;
+                self.write_all(buff)?;
+                self.write_all(b"\r\n")
+            }
+            Value::Blob(buff) => self.encode_blob(b'$', buff),
+            Value::Number(num) => write!(self, ":{}\r\n", num),
+            Value::Array(values) => self.encode_aggregate(b'*', values, protocol),
+            // an error spanning several lines can only be sent as a blob error.
+            Value::Err(code, msg) if resp3 && msg.contains(['\r', '\n']) => {
+                self.encode_blob(b'!', format!("{} {}", code, msg).as_bytes())
             }
             Value::Err(code, msg) => {
-                self.write("-".as_bytes())?;
-                self.write(code.as_bytes())?;
-                self.write(" ".as_bytes())?;
-                self.write(msg.as_bytes())?;
-                self.write("\r\n".as_bytes())?;
+                write!(self, "-{} {}\r\n", code, msg.replace(['\r', '\n'], " "))
+            }
+            Value::Null if resp3 => self.write_all(b"_\r\n"),
+            Value::Null => self.write_all(b"$-1\r\n"),
+            Value::Map(pairs) => {
+                if resp3 {
+                    write!(self, "%{}\r\n", pairs.len())?;
+                } else {
+                    write!(self, "*{}\r\n", pairs.len() * 2)?;
+                }
+                self.encode_pairs(pairs, protocol)
+            }
+            Value::Set(values) => {
+                self.encode_aggregate(if resp3 { b'~' } else { b'*' }, values, protocol)
+            }
+            Value::Push(values) => {
+                self.encode_aggregate(if resp3 { b'>' } else { b'*' }, values, protocol)
             }
-            Value::Null => {
-                self.write("$-1\r\n".as_bytes())?;
+            Value::Double(value) if resp3 => write!(self, ",{}\r\n", format_double(*value)),
+            Value::Double(value) => self.encode_blob(b'$', format_double(*value).as_bytes()),
+            Value::Boolean(value) if resp3 => {
+                write!(self, "#{}\r\n", if *value { 't' } else { 'f' })
             }
+            Value::Boolean(value) => write!(self, ":{}\r\n", *value as i64),
+            Value::BigNumber(value) if resp3 => write!(self, "({}\r\n", value),
+            Value::BigNumber(value) => self.encode_blob(b'$', value.as_bytes()),
+            Value::Verbatim(format, text) if resp3 => {
+                write!(self, "={}\r\n{}:", format.len() + 1 + text.len(), format)?;
+                self.write_all(text)?;
+                self.write_all(b"\r\n")
+            }
+            Value::Verbatim(_, text) => self.encode_blob(b'$', text),
+            Value::Attribute(attributes, value) => {
+                if resp3 {
+                    write!(self, "|{}\r\n", attributes.len())?;
+                    self.encode_pairs(attributes, protocol)?;
+                }
+                self.encode_value(value, protocol)
+            }
+        }
+    }
+
+    fn encode_blob(&mut self, kind: u8, buff: &[u8]) -> io::Result<()> {
+        self.write_all(&[kind])?;
+        write!(self, "{}\r\n", buff.len())?;
+        self.write_all(buff)?;
+        self.write_all(b"\r\n")
+    }
+
+    fn encode_aggregate(
+        &mut self,
+        kind: u8,
+        values: &[Value],
+        protocol: Protocol,
+    ) -> io::Result<()> {
+        self.write_all(&[kind])?;
+        write!(self, "{}\r\n", values.len())?;
+        for value in values {
+            self.encode_value(value, protocol)?;
+        }
+        Ok(())
+    }
+
+    fn encode_pairs(&mut self, pairs: &[(Value, Value)], protocol: Protocol) -> io::Result<()> {
+        for (key, value) in pairs {
+            self.encode_value(key, protocol)?;
+            self.encode_value(value, protocol)?;
         }
-        self.flush()?;
         Ok(())
     }
 }
 
-impl<W: io::Write + ?Sized> ValueWrite for W {}
+impl<W: io::Write + ?Sized> ValueWriteExt for W {}
+
+/// The limits checked while reading values, so that a peer can't exhaust the memory or the stack
+/// with the lengths it declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolLimits {
+    /// The maximum length of a bulk string, set with `proto-max-bulk-len`.
+    pub max_bulk_len: usize,
+    /// The maximum number of elements of an aggregate, like an array or a map.
+    pub max_multibulk_len: usize,
+    /// The maximum nesting of the aggregates.
+    pub max_depth: usize,
+    /// The maximum length of a line, like an inline command or a simple string.
+    pub max_line_len: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_depth: 32,
+            max_line_len: 64 * 1024,
+        }
+    }
+}
+
+fn protocol_error(msg: impl Into<String>) -> Error {
+    Error::Protocol(msg.into())
+}
 
 trait ValueReadExt: io::BufRead {
-    fn read_number(&mut self) -> Result<i64> {
+    /// Reads a line, without its line ending. The CR is optional, like in the inline commands.
+    /// The lines longer than `max_len` fail, `what` describing the line in the error.
+    fn read_crlf_line(&mut self, max_len: usize, what: &str) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            let available = self.fill_buf()?;
+            // the connection was closed in the middle of the line.
+            if available.is_empty() {
+                return Err(Error::Eof);
+            }
+            let end = available.iter().position(|&c| c == b'\n');
+            let consumed = end.map(|end| end + 1).unwrap_or(available.len());
+            line.extend_from_slice(&available[..end.unwrap_or(consumed)]);
+            self.consume(consumed);
+            if line.len() > max_len + 1 {
+                return Err(protocol_error(format!("too big {}", what)));
+            }
+            if end.is_some() {
+                break;
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.len() > max_len {
+            return Err(protocol_error(format!("too big {}", what)));
+        }
+        Ok(line)
+    }
+
+    /// Reads the length of a bulk string or of an aggregate, `None` for the null ones declared
+    /// with a negative length.
+    fn read_length(
+        &mut self,
+        limits: &ProtocolLimits,
+        max: usize,
+        what: &str,
+    ) -> Result<Option<usize>> {
+        let line = self.read_crlf_line(limits.max_line_len, &format!("{} count string", what))?;
+        match parse_length(&line) {
+            Some(len) if len < 0 => Ok(None),
+            Some(len) if len as u64 <= max as u64 => Ok(Some(len as usize)),
+            _ => Err(protocol_error(format!("invalid {} length", what))),
+        }
+    }
+
+    /// Reads the content of a bulk string of a known length, followed by its CRLF. The buffer
+    /// grows as the content arrives, instead of being allocated at the declared length.
+    fn read_bulk(&mut self, len: usize) -> Result<Vec<u8>> {
         let mut buff = Vec::new();
-        self.read_until('\n' as u8, &mut buff)?;
-        buff.pop();
-        buff.pop();
+        while buff.len() < len + 2 {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                return Err(Error::Eof);
+            }
+            let consumed = available.len().min(len + 2 - buff.len());
+            buff.extend_from_slice(&available[..consumed]);
+            self.consume(consumed);
+        }
+        if !buff.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF"));
+        }
+        buff.truncate(len);
+        Ok(buff)
+    }
 
-        Ok(String::from_utf8(buff)?.parse()?)
+    /// Reads a blob, of a length read first. `None` for a null blob.
+    fn read_blob(&mut self, limits: &ProtocolLimits) -> Result<Option<Vec<u8>>> {
+        match self.read_length(limits, limits.max_bulk_len, "bulk")? {
+            Some(len) => Ok(Some(self.read_bulk(len)?)),
+            None => Ok(None),
+        }
     }
-}
 
-impl<R: io::BufRead + ?Sized> ValueReadExt for R {}
+    /// Reads the elements of an aggregate nested at `depth`, of a length read first. `None` for
+    /// a null aggregate.
+    fn read_values(&mut self, limits: &ProtocolLimits, depth: usize) -> Result<Option<Vec<Value>>> {
+        if depth >= limits.max_depth {
+            return Err(protocol_error("too deeply nested aggregate"));
+        }
+        let len = match self.read_length(limits, limits.max_multibulk_len, "multibulk")? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut values = vec![];
+        for _ in 0..len {
+            values.push(self.read_nested(limits, depth + 1)?);
+        }
+        Ok(Some(values))
+    }
 
-pub trait ValueRead: io::BufRead {
-    fn read_value(self: &mut Self) -> Result<Value> {
+    fn read_pairs(&mut self, limits: &ProtocolLimits, depth: usize) -> Result<Vec<(Value, Value)>> {
+        if depth >= limits.max_depth {
+            return Err(protocol_error("too deeply nested aggregate"));
+        }
+        let len = self
+            .read_length(limits, limits.max_multibulk_len, "multibulk")?
+            .unwrap_or(0);
+        let mut pairs = vec![];
+        for _ in 0..len {
+            pairs.push((
+                self.read_nested(limits, depth + 1)?,
+                self.read_nested(limits, depth + 1)?,
+            ));
+        }
+        Ok(pairs)
+    }
+
+    /// Reads a value nested in `depth` aggregates.
+    fn read_nested(&mut self, limits: &ProtocolLimits, depth: usize) -> Result<Value> {
         let mut buff: [u8; 1] = [0];
         self.read_exact(&mut buff)?;
 
+        let mut line = |what: &str| self.read_crlf_line(limits.max_line_len, what);
         let value = match buff[0] as char {
-            '+' => {
-                let mut buff = Vec::new();
-                self.read_until('\n' as u8, &mut buff)?;
-                buff.pop();
-                buff.pop();
-                Value::Simple(Bytes(buff))
-            }
-            '$' => {
-                let num = self.read_number()?;
-                if num < 0 {
-                    Value::Null
-                } else {
-                    let mut buff = vec![0u8; num as usize];
-                    self.read_exact(&mut buff)?;
-                    self.consume(2);
-                    Value::Blob(Bytes(buff))
+            '+' => Value::Simple(Bytes(line("simple string")?)),
+            '-' => parse_error(line("error")?)?,
+            '$' => match self.read_blob(limits)? {
+                Some(buff) => Value::Blob(Bytes(buff)),
+                None => Value::Null,
+            },
+            ':' => Value::Number(
+                parse_length(&line("integer")?).ok_or_else(|| protocol_error("invalid integer"))?,
+            ),
+            // the null arrays of RESP2 are read as the RESP3 null.
+            '*' => match self.read_values(limits, depth)? {
+                Some(values) => Value::Array(values),
+                None => Value::Null,
+            },
+            '_' => {
+                line("null")?;
+                Value::Null
+            }
+            '%' => Value::Map(self.read_pairs(limits, depth)?),
+            '~' => Value::Set(self.read_values(limits, depth)?.unwrap_or_default()),
+            '>' => Value::Push(self.read_values(limits, depth)?.unwrap_or_default()),
+            '|' => {
+                let attributes = self.read_pairs(limits, depth)?;
+                Value::Attribute(attributes, Box::new(self.read_nested(limits, depth)?))
+            }
+            ',' => {
+                let line = String::from_utf8(line("double")?)?;
+                Value::Double(line.parse().map_err(|_| protocol_error("invalid double"))?)
+            }
+            '#' => match line("boolean")?.as_slice() {
+                b"t" => Value::Boolean(true),
+                b"f" => Value::Boolean(false),
+                _ => return Err(protocol_error("invalid boolean")),
+            },
+            '(' => {
+                let line = String::from_utf8(line("big number")?)?;
+                let digits = line.strip_prefix('-').unwrap_or(&line);
+                if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+                    return Err(protocol_error("invalid big number"));
                 }
+                Value::BigNumber(line)
             }
-            ':' => {
-                let num = self.read_number()?;
-                Value::Number(num)
+            '!' => {
+                let buff = self
+                    .read_blob(limits)?
+                    .ok_or_else(|| protocol_error("invalid bulk length"))?;
+                parse_error(buff)?
             }
-            '*' => {
-                let num = self.read_number()?;
-                let mut values = vec![];
-                for _ in 0..num {
-                    values.push(self.read_value()?);
+            '=' => {
+                let buff = self
+                    .read_blob(limits)?
+                    .ok_or_else(|| protocol_error("invalid bulk length"))?;
+                if buff.len() < 4 || buff[3] != b':' {
+                    return Err(protocol_error("invalid verbatim string"));
                 }
-                Value::Array(values)
+                let format = String::from_utf8(buff[..3].to_vec())?;
+                Value::Verbatim(format, Bytes(buff[4..].to_vec()))
+            }
+            c => {
+                return Err(protocol_error(format!(
+                    "unexpected type byte '{}'",
+                    c.escape_default()
+                )))
             }
-            _ => todo!(),
         };
 
         Ok(value)
     }
 }
 
+impl<R: io::BufRead + ?Sized> ValueReadExt for R {}
+
+fn parse_length(line: &[u8]) -> Option<i64> {
+    std::str::from_utf8(line).ok()?.parse().ok()
+}
+
+/// Splits an error line in its code, like `WRONGTYPE`, and its message.
+fn parse_error(line: Vec<u8>) -> Result<Value> {
+    let line = String::from_utf8(line)?;
+    let (code, msg) = line.split_once(' ').unwrap_or((&line, ""));
+    Ok(Value::Err(code.to_string(), msg.to_string()))
+}
+
+pub trait ValueRead: io::BufRead {
+    fn read_value(&mut self) -> Result<Value> {
+        self.read_value_with(&ProtocolLimits::default())
+    }
+
+    /// Reads a value, failing with [`Error::Protocol`] if it goes beyond the limits.
+    fn read_value_with(&mut self, limits: &ProtocolLimits) -> Result<Value> {
+        self.read_nested(limits, 0)
+    }
+}
+
 impl<R: io::BufRead + ?Sized> ValueRead for R {}
 
+/// Parses the requests of a client as their bytes arrive. A request is either a multibulk
+/// request, an array of bulk strings, or an inline command: the arguments separated by spaces on
+/// a single line, as typed in telnet.
+///
+/// When only a part of a request arrived, the parser keeps the arguments it already parsed and
+/// resumes from there once more bytes arrive, instead of parsing the request again.
+#[derive(Debug, Default)]
+pub struct RequestParser {
+    /// The arguments of the multibulk request being parsed, and the number still missing.
+    multibulk: Option<(Vec<Bytes>, usize)>,
+    /// The length of the bulk string being parsed, once its length is parsed.
+    bulk_len: Option<usize>,
+}
+
+impl RequestParser {
+    /// Parses the next request from `input`, returning the number of bytes used with the
+    /// request. The request is `None` when it's not complete yet: the bytes used then are the
+    /// ones already parsed, the rest of `input` being given again, with more bytes, to the next
+    /// call. The empty requests are skipped. Malformed requests, or the ones beyond the limits,
+    /// fail with [`Error::Protocol`].
+    pub fn parse(
+        &mut self,
+        input: &[u8],
+        limits: &ProtocolLimits,
+    ) -> Result<(usize, Option<Value>)> {
+        let mut pos = 0;
+        loop {
+            let (args, missing) = match &mut self.multibulk {
+                Some(multibulk) => multibulk,
+                None => {
+                    let first = match input.get(pos) {
+                        Some(&c) => c,
+                        None => return Ok((pos, None)),
+                    };
+                    if first == b'*' {
+                        let (line, used) = match parse_line(
+                            &input[pos + 1..],
+                            limits.max_line_len,
+                            "multibulk count string",
+                        )? {
+                            Some(line) => line,
+                            None => return Ok((pos, None)),
+                        };
+                        let len = match parse_length(line) {
+                            Some(len) if len <= 0 => 0,
+                            Some(len) if len as u64 <= limits.max_multibulk_len as u64 => {
+                                len as usize
+                            }
+                            _ => return Err(protocol_error("invalid multibulk length")),
+                        };
+                        pos += 1 + used;
+                        if len > 0 {
+                            // the arguments are only allocated as they arrive.
+                            self.multibulk = Some((Vec::with_capacity(len.min(1024)), len));
+                        }
+                        continue;
+                    }
+
+                    let (line, used) =
+                        match parse_line(&input[pos..], limits.max_line_len, "inline request")? {
+                            Some(line) => line,
+                            None => return Ok((pos, None)),
+                        };
+                    let args = split_raw_args(line)
+                        .ok_or_else(|| protocol_error("unbalanced quotes in request"))?;
+                    pos += used;
+                    if !args.is_empty() {
+                        let args = args.into_iter().map(|arg| Value::Blob(Bytes(arg)));
+                        return Ok((pos, Some(Value::Array(args.collect()))));
+                    }
+                    continue;
+                }
+            };
+
+            while *missing > 0 {
+                let len = match self.bulk_len {
+                    Some(len) => len,
+                    None => {
+                        let kind = match input.get(pos) {
+                            Some(&c) => c,
+                            None => return Ok((pos, None)),
+                        };
+                        if kind != b'$' {
+                            return Err(protocol_error(format!(
+                                "expected '$', got '{}'",
+                                (kind as char).escape_default()
+                            )));
+                        }
+                        let (line, used) = match parse_line(
+                            &input[pos + 1..],
+                            limits.max_line_len,
+                            "bulk count string",
+                        )? {
+                            Some(line) => line,
+                            None => return Ok((pos, None)),
+                        };
+                        let len = match parse_length(line) {
+                            Some(len) if len >= 0 && len as u64 <= limits.max_bulk_len as u64 => {
+                                len as usize
+                            }
+                            _ => return Err(protocol_error("invalid bulk length")),
+                        };
+                        pos += 1 + used;
+                        self.bulk_len = Some(len);
+                        len
+                    }
+                };
+                // the bulk string is taken at once, when all of it arrived.
+                if input.len() - pos < len + 2 {
+                    return Ok((pos, None));
+                }
+                if &input[pos + len..pos + len + 2] != b"\r\n" {
+                    return Err(protocol_error("bulk string not terminated by CRLF"));
+                }
+                args.push(Bytes(input[pos..pos + len].to_vec()));
+                pos += len + 2;
+                self.bulk_len = None;
+                *missing -= 1;
+            }
+
+            let (args, _) = self.multibulk.take().unwrap_or_default();
+            let args = args.into_iter().map(Value::Blob).collect();
+            return Ok((pos, Some(Value::Array(args))));
+        }
+    }
+}
+
+/// Finds a line at the start of `input`, returning it without its line ending, with the number of
+/// bytes it takes. `None` if the line is not complete yet. The CR is optional, like in the inline
+/// commands. The lines longer than `max_len` fail, `what` describing the line in the error.
+fn parse_line<'i>(
+    input: &'i [u8],
+    max_len: usize,
+    what: &str,
+) -> Result<Option<(&'i [u8], usize)>> {
+    let end = match input.iter().take(max_len + 2).position(|&c| c == b'\n') {
+        Some(end) => end,
+        None if input.len() > max_len + 1 => {
+            return Err(protocol_error(format!("too big {}", what)))
+        }
+        None => return Ok(None),
+    };
+    let line = &input[..end];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.len() > max_len {
+        return Err(protocol_error(format!("too big {}", what)));
+    }
+    Ok(Some((line, end + 1)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -200,6 +695,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_resp3_serialization() {
+        let testcases = vec![
+            (Value::Null, "$-1\r\n", "_\r\n"),
+            (
+                Value::Map(vec![(Value::Blob("a".into()), Value::Number(1))]),
+                "*2\r\n$1\r\na\r\n:1\r\n",
+                "%1\r\n$1\r\na\r\n:1\r\n",
+            ),
+            (
+                Value::Set(vec![Value::Null]),
+                "*1\r\n$-1\r\n",
+                "~1\r\n_\r\n",
+            ),
+            (Value::Double(1.5), "$3\r\n1.5\r\n", ",1.5\r\n"),
+            (
+                Value::Double(f64::NEG_INFINITY),
+                "$4\r\n-inf\r\n",
+                ",-inf\r\n",
+            ),
+            (Value::Boolean(true), ":1\r\n", "#t\r\n"),
+            (
+                Value::BigNumber("3492890328409238509324850943850943825024385".to_string()),
+                "$43\r\n3492890328409238509324850943850943825024385\r\n",
+                "(3492890328409238509324850943850943825024385\r\n",
+            ),
+            (
+                Value::Verbatim("txt".to_string(), "Some string".into()),
+                "$11\r\nSome string\r\n",
+                "=15\r\ntxt:Some string\r\n",
+            ),
+            (
+                Value::Push(vec![Value::Blob("message".into())]),
+                "*1\r\n$7\r\nmessage\r\n",
+                ">1\r\n$7\r\nmessage\r\n",
+            ),
+            (
+                Value::Attribute(
+                    vec![(Value::Simple("ttl".into()), Value::Number(3))],
+                    Box::new(Value::Number(7)),
+                ),
+                ":7\r\n",
+                "|1\r\n+ttl\r\n:3\r\n:7\r\n",
+            ),
+            (
+                Value::Err("SYNTAX".to_string(), "invalid\r\nsyntax".to_string()),
+                "-SYNTAX invalid  syntax\r\n",
+                "!22\r\nSYNTAX invalid\r\nsyntax\r\n",
+            ),
+        ];
+
+        for (value, resp2, resp3) in testcases {
+            let mut buffer: Vec<u8> = vec![];
+            buffer.write_value_as(&value, Protocol::Resp2).unwrap();
+            assert_eq!(resp2, String::from_utf8(buffer).unwrap().as_str());
+
+            let mut buffer: Vec<u8> = vec![];
+            buffer.write_value_as(&value, Protocol::Resp3).unwrap();
+            assert_eq!(resp3, String::from_utf8(buffer).unwrap().as_str());
+            // the RESP3 encoding reads back as the value, and the RESP2 one as the downgraded value.
+            let mut resp2_value = value.clone().into_resp2();
+            if let Value::Err(_, msg) = &mut resp2_value {
+                *msg = msg.replace(['\r', '\n'], " ");
+            }
+            assert_eq!(resp2_value, resp2.as_bytes().read_value().unwrap());
+            assert_eq!(value, resp3.as_bytes().read_value().unwrap());
+        }
+    }
+
     #[test]
     fn test_deserialization() {
         let testcases = vec![
@@ -211,6 +775,13 @@ mod tests {
                 "$16\r\nsomesimplestring\r\n",
                 Value::Blob("somesimplestring".into()),
             ),
+            (
+                "-WRONGTYPE Operation against a key\r\n",
+                Value::Err(
+                    "WRONGTYPE".to_string(),
+                    "Operation against a key".to_string(),
+                ),
+            ),
             (":-1\r\n", Value::Number(-1)),
             (":0\r\n", Value::Number(0)),
             (":12912\r\n", Value::Number(12912)),
@@ -229,4 +800,126 @@ mod tests {
             assert_eq!(expected, val);
         }
     }
+
+    /// Parses a request from `input`, given to the parser one byte at a time, for the parser to
+    /// resume the partial request at each byte.
+    fn parse_request(input: &str, limits: &ProtocolLimits) -> Result<Option<Value>> {
+        let mut parser = RequestParser::default();
+        let mut buffer = vec![];
+        for &c in input.as_bytes() {
+            buffer.push(c);
+            let (used, request) = parser.parse(&buffer, limits)?;
+            buffer.drain(..used);
+            if request.is_some() {
+                assert!(buffer.is_empty());
+                return Ok(request);
+            }
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn test_read_request() {
+        let limits = ProtocolLimits::default();
+        let request = |args: &[&str]| {
+            Value::Array(args.iter().map(|arg| Value::Blob((*arg).into())).collect())
+        };
+        let testcases = vec![
+            ("*2\r\n$3\r\nGET\r\n$1\r\na\r\n", request(&["GET", "a"])),
+            ("PING\r\n", request(&["PING"])),
+            ("\r\n\n  set  a   b\n", request(&["set", "a", "b"])),
+            ("SET \"a b\" 'it s'\r\n", request(&["SET", "a b", "it s"])),
+            (
+                "SET 'it\\'s' \"\\x41\\t\"\r\n",
+                request(&["SET", "it's", "A\t"]),
+            ),
+        ];
+        for (input, expected) in testcases {
+            assert_eq!(Some(expected), parse_request(input, &limits).unwrap());
+        }
+
+        // the requests received at once are parsed one after the other.
+        let input = b"*1\r\n$4\r\nPING\r\n\r\nGET a\r\n*2\r\n$3\r\nGET\r\n$1\r";
+        let mut parser = RequestParser::default();
+        let (used, ping) = parser.parse(input, &limits).unwrap();
+        assert_eq!(Some(request(&["PING"])), ping);
+        let (more, get) = parser.parse(&input[used..], &limits).unwrap();
+        assert_eq!(Some(request(&["GET", "a"])), get);
+        let (partial, incomplete) = parser.parse(&input[used + more..], &limits).unwrap();
+        assert_eq!(None, incomplete);
+        let rest = [&input[used + more + partial..], b"\nb\r\n"].concat();
+        assert_eq!(
+            (rest.len(), Some(request(&["GET", "b"]))),
+            parser.parse(&rest, &limits).unwrap()
+        );
+
+        let errors = vec![
+            ("SET \"a\r\n", "unbalanced quotes in request"),
+            ("SET \"a\"b\r\n", "unbalanced quotes in request"),
+            ("*x\r\n", "invalid multibulk length"),
+            ("*1\r\n:1\r\n", "expected '$', got ':'"),
+            ("*1\r\n$-2\r\n", "invalid bulk length"),
+            ("*1\r\n$1\r\nab\r\n", "bulk string not terminated by CRLF"),
+        ];
+        for (input, expected) in errors {
+            match parse_request(input, &limits) {
+                Err(Error::Protocol(msg)) => assert_eq!(expected, msg),
+                result => panic!("unexpected result {:?} for {:?}", result, input),
+            }
+        }
+        assert_eq!(None, parse_request("", &limits).unwrap());
+        assert_eq!(None, parse_request("PING", &limits).unwrap());
+        assert!(matches!(
+            "?".as_bytes().read_value(),
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn test_protocol_limits() {
+        let limits = ProtocolLimits {
+            max_bulk_len: 4,
+            max_multibulk_len: 2,
+            max_depth: 2,
+            max_line_len: 8,
+        };
+        let requests = vec![
+            ("*1\r\n$5\r\nhello\r\n", "invalid bulk length"),
+            ("*1\r\n$99999999\r\n", "invalid bulk length"),
+            ("*1\r\n$-1\r\n", "invalid bulk length"),
+            ("*3\r\n", "invalid multibulk length"),
+            ("*1\r\n$0000000001\r\n", "too big bulk count string"),
+            ("SET key value\r\n", "too big inline request"),
+        ];
+        for (input, expected) in requests {
+            match parse_request(input, &limits) {
+                Err(Error::Protocol(msg)) => assert_eq!(expected, msg),
+                result => panic!("unexpected result {:?} for {:?}", result, input),
+            }
+        }
+        assert_eq!(
+            Value::Array(vec![Value::Blob("GET".into()), Value::Blob("k".into())]),
+            parse_request("GET k\r\n", &limits).unwrap().unwrap()
+        );
+
+        let values = vec![
+            ("*1\r\n*1\r\n*0\r\n", "too deeply nested aggregate"),
+            ("%1\r\n*1\r\n~0\r\n:1\r\n", "too deeply nested aggregate"),
+            ("$2\r\nab\n\n", "bulk string not terminated by CRLF"),
+            (":1.5\r\n", "invalid integer"),
+            (",x\r\n", "invalid double"),
+            ("(12a\r\n", "invalid big number"),
+        ];
+        for (input, expected) in values {
+            match input.as_bytes().read_value_with(&limits) {
+                Err(Error::Protocol(msg)) => assert_eq!(expected, msg),
+                result => panic!("unexpected result {:?} for {:?}", result, input),
+            }
+        }
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![])]),
+            "*1\r\n*0\r\n".as_bytes().read_value_with(&limits).unwrap()
+        );
+        assert_eq!(Value::Null, "*-1\r\n".as_bytes().read_value().unwrap());
+    }
 }